@@ -0,0 +1,34 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simpleftp::{parse_reply_bytes, ReplyParser, Response};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(reply) = parse_reply_bytes(data) {
+        check(&reply);
+    }
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    // a parser reporting a complete reply must be able to finish it
+    let mut parser = ReplyParser::new();
+    for line in text.split_inclusive('\n') {
+        match parser.push_line(line) {
+            Ok(true) => check(&parser.clone().finish().expect("complete reply")),
+            Ok(false) => {}
+            Err(_) => break,
+        }
+    }
+    // replies made up by middleware are split into lines the same way
+    let reply = Response::new(200, text);
+    assert!(!reply.lines().is_empty());
+    assert_eq!(reply.lines().len(), reply.line_codes().len());
+});
+
+fn check(reply: &Response) {
+    assert!((100..=599).contains(&reply.code()), "{}", reply.code());
+    assert!(!reply.lines().is_empty());
+    assert_eq!(reply.lines().len(), reply.line_codes().len());
+    // the reply ends on a line carrying its code
+    assert_eq!(reply.line_codes().last(), Some(&Some(reply.code())));
+}
@@ -0,0 +1,470 @@
+//! Remote directory trees streamed to and from tar archives.
+
+use std::collections::HashSet;
+use std::io::Read;
+use std::io::Write;
+use std::time::UNIX_EPOCH;
+
+use crate::Command;
+use crate::EntryKind;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::RemotePath;
+use crate::Result;
+use crate::WalkEntry;
+use crate::WalkOptions;
+
+/// Size of tar headers and of the unit data is padded to.
+const BLOCK: usize = 512;
+
+/// Largest value the 11 octal digits of a ustar size or time hold, larger
+/// sizes are written in the GNU base-256 form.
+const MAX_OCTAL: u64 = (1 << 33) - 1;
+
+/// Type flags of ustar headers.
+const REGULAR: u8 = b'0';
+const SYMLINK: u8 = b'2';
+const DIRECTORY: u8 = b'5';
+const CONTIGUOUS: u8 = b'7';
+/// GNU long name of the next member.
+const LONG_NAME: u8 = b'L';
+/// pax extended attributes of the next member.
+const PAX: u8 = b'x';
+/// Largest long name or pax member read, a few times the longest path any
+/// server accepts.
+const MAX_EXTENDED: u64 = 16 * 1024;
+
+/// A ustar header block.
+struct Header([u8; BLOCK]);
+
+impl Header {
+    /// Header of the entry `name`, split between the name and prefix fields
+    /// when longer than 100 bytes.
+    fn new(name: &str, kind: u8, size: u64, mode: u32, modified: u64) -> Result<Self> {
+        let mut header = Header([0; BLOCK]);
+        let (prefix, name) = split_name(name)?;
+        header.0[..name.len()].copy_from_slice(name.as_bytes());
+        header.octal(100..108, u64::from(mode & 0o7777));
+        header.octal(108..116, 0);
+        header.octal(116..124, 0);
+        if size <= MAX_OCTAL {
+            header.octal(124..136, size);
+        } else {
+            header.0[124] = 0x80;
+            header.0[128..136].copy_from_slice(&size.to_be_bytes());
+        }
+        header.octal(136..148, modified.min(MAX_OCTAL));
+        header.0[156] = kind;
+        header.0[257..263].copy_from_slice(b"ustar\0");
+        header.0[263..265].copy_from_slice(b"00");
+        header.0[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        Ok(header)
+    }
+
+    /// Store the target of a symbolic link.
+    fn link(mut self, target: &str) -> Result<Self> {
+        if target.len() > 100 {
+            return Err(FtpError::InvalidArgument(format!(
+                "Link target too long for tar: {}",
+                target
+            )));
+        }
+        self.0[157..157 + target.len()].copy_from_slice(target.as_bytes());
+        Ok(self)
+    }
+
+    /// Write `value` in octal, zero padded and NUL terminated, in `field`.
+    fn octal(&mut self, field: std::ops::Range<usize>, value: u64) {
+        let digits = field.len() - 1;
+        let text = format!("{:0width$o}", value, width = digits);
+        self.0[field.start..field.end - 1].copy_from_slice(text.as_bytes());
+    }
+
+    /// The header bytes with their checksum filled in.
+    fn finish(mut self) -> [u8; BLOCK] {
+        self.0[148..156].fill(b' ');
+        let sum: u64 = self.0.iter().map(|&byte| u64::from(byte)).sum();
+        self.0[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        self.0
+    }
+}
+
+/// Name, type flag and data size of an archive member read from its header.
+struct Member {
+    name: String,
+    kind: u8,
+    size: u64,
+}
+
+impl Member {
+    /// Parse a header block, `None` for the zero blocks ending the archive.
+    fn parse(block: &[u8; BLOCK]) -> Result<Option<Self>> {
+        if block.iter().all(|&byte| byte == 0) {
+            return Ok(None);
+        }
+        let sum: u64 = block
+            .iter()
+            .enumerate()
+            .map(|(index, &byte)| match index {
+                148..=155 => u64::from(b' '),
+                _ => u64::from(byte),
+            })
+            .sum();
+        if number(&block[148..156]) != Some(sum) {
+            return Err(FtpError::InvalidArgument(
+                "Invalid tar header checksum".into(),
+            ));
+        }
+        let mut name = text(&block[..100]);
+        if &block[257..262] == b"ustar" && block[345] != 0 {
+            name = format!("{}/{}", text(&block[345..500]), name);
+        }
+        let size = number(&block[124..136])
+            .ok_or_else(|| FtpError::InvalidArgument(format!("Invalid tar size for {}", name)))?;
+        Ok(Some(Self {
+            name,
+            kind: block[156],
+            size,
+        }))
+    }
+}
+
+/// A NUL terminated header field.
+fn text(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// An octal header field, or a big-endian one in the GNU base-256 form.
+fn number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7f), |value, &byte| {
+                value.checked_mul(256).map(|value| value | u64::from(byte))
+            });
+    }
+    let digits = text(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// The `path` record of pax extended attributes, `<length> path=<name>\n`.
+fn pax_path(attributes: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(attributes)
+        .lines()
+        .find_map(|record| {
+            let (_, field) = record.split_once(' ')?;
+            field.strip_prefix("path=").map(str::to_string)
+        })
+}
+
+/// Remote path of the member `name` below `root`. Leading `/` and `.`
+/// components are dropped as tar does.
+fn member_path(root: &RemotePath, name: &str) -> Result<RemotePath> {
+    let mut path = root.clone();
+    for component in name.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                return Err(FtpError::InvalidArgument(format!(
+                    "Archive member escapes the target directory: {}",
+                    name
+                )))
+            }
+            _ => path = path.join(component),
+        }
+    }
+    Ok(path)
+}
+
+/// Read and drop `count` bytes of `reader`.
+fn skip(reader: &mut impl Read, count: u64) -> Result<()> {
+    let skipped = std::io::copy(&mut reader.take(count), &mut std::io::sink())?;
+    if skipped != count {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(())
+}
+
+/// Split `name` into the prefix and name fields of a ustar header, at a `/`
+/// leaving at most 155 bytes before it and 100 after it.
+fn split_name(name: &str) -> Result<(&str, &str)> {
+    if name.len() <= 100 {
+        return Ok(("", name));
+    }
+    name.match_indices('/')
+        .map(|(index, _)| (&name[..index], &name[index + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= 155 && rest.len() <= 100 && !rest.is_empty())
+        .ok_or_else(|| FtpError::InvalidArgument(format!("Name too long for tar: {}", name)))
+}
+
+/// Zero bytes bringing an entry of `size` bytes to a whole number of blocks.
+fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+impl FtpClient {
+    /// Write the tree below `remote_dir` to `writer` as a tar archive, streaming
+    /// each file from its data connection into the archive without storing it
+    /// locally, e.g. to pipe a backup to object storage. Names in the archive
+    /// are relative to `remote_dir`; symbolic links are archived as links and
+    /// other special files left out.
+    ///
+    /// File sizes must be known before their data, so they're asked with
+    /// `SIZE` in binary mode, or taken from the listing when the server lacks it.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let archive = std::fs::File::create("site.tar").unwrap();
+    /// let files = client.get_dir_to_tar("/www", &mut std::io::BufWriter::new(archive)).unwrap();
+    /// println!("{} files archived", files);
+    /// ```
+    ///
+    /// # Returns
+    /// The number of files archived.
+    ///
+    /// # Errors
+    /// On connection failure, when a directory can't be listed, a file can't
+    /// be retrieved or doesn't have the expected size, a name doesn't fit in a
+    /// tar header or `writer` fails. The archive is incomplete then.
+    pub fn get_dir_to_tar(
+        &mut self,
+        remote_dir: impl AsRef<str>,
+        writer: &mut impl Write,
+    ) -> Result<usize> {
+        let root = RemotePath::new(remote_dir.as_ref());
+        let entries = self.walk(&root, &WalkOptions::new())?;
+        self.binary_mode()?;
+        let mut files = 0;
+        for found in entries {
+            let Some(name) = found.path.strip_prefix(&root) else {
+                continue;
+            };
+            if self.archive_entry(&found, name.as_str(), writer)? {
+                files += 1;
+            }
+        }
+        writer.write_all(&[0; 2 * BLOCK])?;
+        writer.flush()?;
+        Ok(files)
+    }
+
+    /// Write `found` to the archive under `name`, returning whether it was a file.
+    fn archive_entry(
+        &mut self,
+        found: &WalkEntry,
+        name: &str,
+        writer: &mut impl Write,
+    ) -> Result<bool> {
+        let entry = &found.entry;
+        let mode = entry.permissions().map(|permissions| permissions.mode());
+        let modified = entry
+            .modified()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let header = match entry.kind() {
+            EntryKind::Directory => Header::new(
+                &format!("{}/", name),
+                DIRECTORY,
+                0,
+                mode.unwrap_or(0o755),
+                modified,
+            )?,
+            EntryKind::Symlink => {
+                let target = entry.link_target().unwrap_or_default();
+                Header::new(name, SYMLINK, 0, mode.unwrap_or(0o777), modified)?.link(target)?
+            }
+            EntryKind::File => {
+                let size = match self.size(&found.path) {
+                    Ok(size) => size,
+                    Err(error) => entry.size().ok_or(error)?,
+                };
+                let header = Header::new(name, REGULAR, size, mode.unwrap_or(0o644), modified)?;
+                writer.write_all(&header.finish())?;
+                let received = self.retrieve(&found.path, writer)?;
+                if received != size {
+                    return Err(FtpError::FileError(format!(
+                        "Size of {} changed while archiving: {} bytes received, {} expected",
+                        found.path, received, size
+                    )));
+                }
+                writer.write_all(&[0; BLOCK][..padding(size)])?;
+                return Ok(true);
+            }
+            EntryKind::Other => return Ok(false),
+        };
+        writer.write_all(&header.finish())?;
+        Ok(false)
+    }
+
+    /// Unpack the tar archive read from `reader` into `remote_dir`, streaming
+    /// each file to the server as its data comes, e.g. to deploy one artifact
+    /// archive. Directories are created as needed. Links and special files are
+    /// skipped since FTP can't create them.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let archive = std::fs::File::open("site.tar").unwrap();
+    /// let files = client.put_from_tar(&mut std::io::BufReader::new(archive), "/www").unwrap();
+    /// println!("{} files deployed", files);
+    /// ```
+    ///
+    /// # Returns
+    /// The number of files uploaded.
+    ///
+    /// # Errors
+    /// When the archive is malformed or truncated, a member would land outside
+    /// `remote_dir`, on connection failure or when an upload fails. Members
+    /// before the failure stay on the server.
+    pub fn put_from_tar(
+        &mut self,
+        reader: &mut impl Read,
+        remote_dir: impl AsRef<str>,
+    ) -> Result<usize> {
+        let root = RemotePath::new(remote_dir.as_ref());
+        self.binary_mode()?;
+        self.create_dir_all(&root)?;
+        let mut created = HashSet::from([root.normalize()]);
+        let mut long_name = None;
+        let mut files = 0;
+        loop {
+            let mut block = [0; BLOCK];
+            reader.read_exact(&mut block)?;
+            let Some(member) = Member::parse(&block)? else {
+                break;
+            };
+            let name = long_name.take().unwrap_or(member.name);
+            let padding = padding(member.size) as u64;
+            match member.kind {
+                LONG_NAME | PAX => {
+                    if member.size > MAX_EXTENDED {
+                        return Err(FtpError::InvalidArgument(format!(
+                            "Tar extended header of {} bytes is too large",
+                            member.size
+                        )));
+                    }
+                    let mut data = vec![0; member.size as usize];
+                    reader.read_exact(&mut data)?;
+                    long_name = match member.kind {
+                        LONG_NAME => Some(text(&data)),
+                        _ => pax_path(&data),
+                    };
+                }
+                DIRECTORY => {
+                    let path = member_path(&root, &name)?;
+                    self.create_dir_all(&path)?;
+                    created.insert(path.normalize());
+                    skip(reader, member.size)?;
+                }
+                REGULAR | CONTIGUOUS | 0 => {
+                    let path = member_path(&root, &name)?;
+                    if let Some(parent) = path.parent() {
+                        if created.insert(parent.normalize()) {
+                            self.create_dir_all(&parent)?;
+                        }
+                    }
+                    self.unpack_file(reader, &path, member.size)?;
+                    files += 1;
+                }
+                _ => skip(reader, member.size)?,
+            }
+            skip(reader, padding)?;
+        }
+        Ok(files)
+    }
+
+    /// Upload the next `size` bytes of `reader` to `path`.
+    fn unpack_file(&mut self, reader: &mut impl Read, path: &RemotePath, size: u64) -> Result<()> {
+        let mut data = reader.take(size);
+        self.store_with(
+            path.as_str(),
+            |file| Command::Stor(file),
+            |client, stream, socket| client.transfer_data(&mut data, stream, socket),
+        )?;
+        if data.limit() != 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::testing::served_client;
+
+    #[test]
+    fn directories_stream_through_tar_archives() {
+        let files: &[(&str, &[u8])] = &[
+            ("www/index.html", b"<html>"),
+            ("www/css/site.css", &[b'x'; 600]),
+        ];
+        let (mut client, root) = served_client("tar", files);
+        let mut archive = Vec::new();
+        assert_eq!(client.get_dir_to_tar("/www", &mut archive).unwrap(), 2);
+        assert_eq!(client.put_from_tar(&mut &archive[..], "/copy").unwrap(), 2);
+        assert_eq!(
+            std::fs::read(root.join("copy/css/site.css")).unwrap(),
+            vec![b'x'; 600]
+        );
+        assert_eq!(
+            std::fs::read(root.join("copy/index.html")).unwrap(),
+            b"<html>"
+        );
+        assert!(client.put_from_tar(&mut &archive[..1000], "/cut").is_err());
+
+        let mut members = Vec::new();
+        let mut rest = &archive[..];
+        while rest[0] != 0 {
+            let (header, data) = rest.split_at(512);
+            let name = std::str::from_utf8(&header[..100]).unwrap();
+            let size = std::str::from_utf8(&header[124..135]).unwrap();
+            let size = usize::from_str_radix(size, 8).unwrap();
+            members.push((
+                name.trim_end_matches('\0').to_string(),
+                data[..size].to_vec(),
+            ));
+            rest = &data[size.div_ceil(512) * 512..];
+        }
+        assert_eq!(rest, [0; 1024]);
+        members.sort();
+        assert_eq!(members[0], ("css/".to_string(), Vec::new()));
+        assert_eq!(members[1], ("css/site.css".to_string(), vec![b'x'; 600]));
+        assert_eq!(members[2], ("index.html".to_string(), b"<html>".to_vec()));
+    }
+
+    #[test]
+    fn extended_headers_are_bounded() {
+        use super::{Header, LONG_NAME, MAX_EXTENDED, PAX};
+        use crate::FtpError;
+
+        let (mut client, _) = served_client("tar-extended", &[]);
+        for kind in [LONG_NAME, PAX] {
+            // a name cut short by the end of the archive
+            let header = Header::new("././@LongLink", kind, 700, 0o644, 0).unwrap();
+            let mut archive = header.finish().to_vec();
+            archive.extend_from_slice(&[b'a'; 100]);
+            let error = client.put_from_tar(&mut &archive[..], "/cut").unwrap_err();
+            assert!(matches!(error, FtpError::FileError(_)), "{:?}", error);
+
+            // a size no name needs isn't allocated
+            let header = Header::new("././@LongLink", kind, MAX_EXTENDED + 1, 0o644, 0).unwrap();
+            let error = client
+                .put_from_tar(&mut &header.finish()[..], "/huge")
+                .unwrap_err();
+            assert!(matches!(error, FtpError::InvalidArgument(_)), "{:?}", error);
+        }
+    }
+}
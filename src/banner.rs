@@ -0,0 +1,103 @@
+//! Checks of the texts a server greets and logs in with.
+
+use std::sync::Arc;
+
+use crate::Command;
+use crate::FtpClient;
+use crate::LoginInfo;
+use crate::Response;
+use crate::Result;
+
+/// Check of the 220 greeting and 230 login replies, given the stage and the
+/// lines of the reply, see [`crate::FtpClientBuilder::banner_policy`]. An
+/// error ends the session before any transfer.
+pub type BannerPolicy = Arc<dyn Fn(BannerStage, &[String]) -> Result<()> + Send + Sync>;
+
+/// Which reply a [`BannerPolicy`] is given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BannerStage {
+    /// The 220 reply greeting a new connection.
+    Greeting,
+    /// The 230 reply accepting the login.
+    Login,
+}
+
+impl FtpClient {
+    /// Run the banner policy, if any, on `response`, closing the session
+    /// when the policy refuses it.
+    pub(crate) fn check_banner(&mut self, stage: BannerStage, response: &Response) -> Result<()> {
+        let Some(policy) = self.config.banner_policy.clone() else {
+            return Ok(());
+        };
+        if let Err(error) = policy(stage, response.lines()) {
+            // the session is given up either way
+            let _ = self.write_cmd(Command::Quit);
+            self.disconnected = true;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// The outcome of a login the server accepted with `response`, once the
+    /// banner policy let it through.
+    pub(crate) fn logged_in(
+        &mut self,
+        response: &Response,
+        account_required: bool,
+    ) -> Result<LoginInfo> {
+        self.check_banner(BannerStage::Login, response)?;
+        Ok(LoginInfo::new(response, account_required))
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::testing::start_server;
+    use crate::FtpClient;
+
+    #[test]
+    fn banner_policy_ends_sessions() {
+        use super::BannerStage;
+        use crate::{AnonymousAccess, FtpError, FtpServer};
+        use std::sync::{Arc, Mutex};
+
+        let refuse_maintenance = |_: BannerStage, lines: &[String]| {
+            if lines.iter().any(|line| line.contains("maintenance")) {
+                return Err(FtpError::ConnectionError("server in maintenance".into()));
+            }
+            Ok(())
+        };
+        let address =
+            start_server(FtpServer::new(AnonymousAccess).greeting("Down for maintenance"));
+        let result = FtpClient::builder()
+            .banner_policy(refuse_maintenance)
+            .connect(address.to_string());
+        assert!(matches!(result, Err(FtpError::ConnectionError(_))));
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let address = start_server(FtpServer::new(AnonymousAccess).greeting("Welcome"));
+        let mut client = FtpClient::builder()
+            .banner_policy(move |stage, lines| {
+                recorded.lock().unwrap().push((stage, lines.to_vec()));
+                match stage {
+                    BannerStage::Greeting => Ok(()),
+                    BannerStage::Login => Err(FtpError::LoginError("read-only mode".into())),
+                }
+            })
+            .connect(address.to_string())
+            .unwrap();
+        assert!(matches!(
+            client.login("anonymous", ""),
+            Err(FtpError::LoginError(message)) if message == "read-only mode"
+        ));
+        assert!(matches!(client.noop(), Err(FtpError::SessionExpired(_))));
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(
+            seen[0],
+            (BannerStage::Greeting, vec!["Welcome".to_string()])
+        );
+        assert_eq!(seen[1].0, BannerStage::Login);
+    }
+}
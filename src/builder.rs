@@ -0,0 +1,555 @@
+//! Builder used to configure a [`FtpClient`] before connecting.
+
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::encoding::Codec;
+use crate::pool::BufferPool;
+use crate::stream::Stream;
+use crate::BannerPolicy;
+use crate::BannerStage;
+#[cfg(feature = "chaos")]
+use crate::ChaosConfig;
+use crate::DecodePolicy;
+use crate::Encoding;
+use crate::EncodingMode;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::ListFormat;
+use crate::ListStyle;
+use crate::Metrics;
+use crate::ProtocolEvent;
+use crate::QuirksRegistry;
+use crate::ReplyPolicy;
+use crate::Result;
+use crate::TlsConfig;
+
+/// Name resolution hook: maps a host name to the list of socket addresses to try.
+/// The port of the returned addresses is ignored and replaced by the requested one.
+pub type Resolver = Arc<dyn Fn(&str) -> std::io::Result<Vec<SocketAddr>> + Send + Sync>;
+
+/// Settings shared by the control and data connections of a client.
+#[derive(Clone, Default)]
+pub(crate) struct Config {
+    pub(crate) resolver: Option<Resolver>,
+    pub(crate) interleave_families: bool,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) events: Option<Sender<ProtocolEvent>>,
+    pub(crate) metrics: Option<Arc<dyn Metrics>>,
+    pub(crate) data_read_timeout: Option<Duration>,
+    pub(crate) data_write_timeout: Option<Duration>,
+    pub(crate) transfer_deadline: Option<Duration>,
+    pub(crate) stall_timeout: Option<Duration>,
+    pub(crate) max_transfer_size: Option<u64>,
+    pub(crate) max_session_bytes: Option<u64>,
+    pub(crate) check_free_space: bool,
+    pub(crate) allocate_uploads: bool,
+    pub(crate) verify_uploads: bool,
+    pub(crate) record_size: Option<usize>,
+    pub(crate) history_size: Option<usize>,
+    pub(crate) latency_window: Option<usize>,
+    pub(crate) cache_ttl: Option<Duration>,
+    pub(crate) buffers: BufferPool,
+    pub(crate) tls: Option<TlsConfig>,
+    pub(crate) tls_domain: String,
+    pub(crate) list_format: ListFormat,
+    pub(crate) list_style: Option<ListStyle>,
+    pub(crate) codec: Codec,
+    pub(crate) list_decode: Option<DecodePolicy>,
+    pub(crate) quirks: Option<QuirksRegistry>,
+    pub(crate) nat_safe_pasv: bool,
+    pub(crate) disable_mlsd: bool,
+    pub(crate) list_via_stat: bool,
+    pub(crate) reply_policy: ReplyPolicy,
+    pub(crate) banner_policy: Option<BannerPolicy>,
+    #[cfg(feature = "chaos")]
+    pub(crate) chaos: Option<ChaosConfig>,
+}
+
+impl Config {
+    /// Resolve `host` and `port` into socket addresses, through the custom resolver if any.
+    pub(crate) fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+        let mut addresses = match &self.resolver {
+            Some(resolver) => resolver(host)?,
+            None => (host, port).to_socket_addrs()?.collect(),
+        };
+        for address in addresses.iter_mut() {
+            address.set_port(port);
+        }
+        if addresses.is_empty() {
+            return Err(FtpError::ConnectionError(format!(
+                "Could not resolve address: {}",
+                host
+            )));
+        }
+        Ok(addresses)
+    }
+
+    /// Wrap a freshly opened socket with the configured transport layers.
+    pub(crate) fn wrap(&self, stream: TcpStream) -> Box<dyn Stream> {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            return Box::new(chaos.wrap(stream));
+        }
+        Box::new(stream)
+    }
+
+    /// Open a TCP connection to `host` and `port`, giving up at `deadline`.
+    pub(crate) fn open(
+        &self,
+        host: &str,
+        port: u16,
+        deadline: Option<Instant>,
+    ) -> Result<TcpStream> {
+        let addresses = self.resolve(host, port)?;
+        self.open_any(addresses, deadline)
+    }
+
+    /// Try each address in turn until one accepts the connection, giving up at
+    /// `deadline`.
+    /// When families are interleaved, IPv6 and IPv4 candidates alternate so a
+    /// broken stack for one family doesn't delay the other (RFC 8305 ordering).
+    pub(crate) fn open_any(
+        &self,
+        addresses: Vec<SocketAddr>,
+        deadline: Option<Instant>,
+    ) -> Result<TcpStream> {
+        let addresses = if self.interleave_families {
+            interleave(addresses)
+        } else {
+            addresses
+        };
+        let mut failures = Vec::new();
+        for address in addresses {
+            let mut timeout = self.connect_timeout;
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    failures.push(format!("{} (deadline exceeded)", address));
+                    break;
+                }
+                timeout = Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)));
+            }
+            let stream = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&address, timeout),
+                None => TcpStream::connect(address),
+            };
+            match stream {
+                Ok(stream) => {
+                    #[cfg(feature = "debug")]
+                    println!("Connected to {}", address);
+                    return Ok(stream);
+                }
+                Err(error) => failures.push(format!("{} ({})", address, error)),
+            }
+        }
+        Err(FtpError::ConnectionError(format!(
+            "Could not connect to any address: {}",
+            failures.join(", ")
+        )))
+    }
+}
+
+/// Reorder addresses alternating families, keeping the first address family first.
+fn interleave(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addresses.first().map(|a| a.is_ipv6()).unwrap_or(false);
+    let (mut primary, mut secondary): (Vec<_>, Vec<_>) = addresses
+        .into_iter()
+        .partition(|address| address.is_ipv6() == first_v6);
+    let mut ordered = Vec::with_capacity(primary.len() + secondary.len());
+    primary.reverse();
+    secondary.reverse();
+    while !primary.is_empty() || !secondary.is_empty() {
+        ordered.extend(primary.pop());
+        ordered.extend(secondary.pop());
+    }
+    ordered
+}
+
+/// Builder for [`FtpClient`] allowing to customize how connections are made.
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::FtpClient;
+/// use std::net::ToSocketAddrs;
+///
+/// fn main() -> simpleftp::Result<()> {
+///     let mut client = FtpClient::builder()
+///         .resolver(|host| Ok((host, 0).to_socket_addrs()?.collect()))
+///         .connect("test.rebex.net:21")?;
+///     client.login("demo", "password")?;
+///     client.logout()?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Default)]
+pub struct FtpClientBuilder {
+    config: Config,
+}
+
+impl FtpClientBuilder {
+    /// Create a builder with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use a custom name resolver for the control connection and for the
+    /// addresses offered by the server for data connections.
+    ///
+    /// # Arguments
+    /// `resolver`  closure mapping a host name to the addresses to connect to
+    pub fn resolver<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&str) -> std::io::Result<Vec<SocketAddr>> + Send + Sync + 'static,
+    {
+        self.config.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Alternate IPv6 and IPv4 addresses when a host resolves to both,
+    /// instead of trying them in the order returned by the resolver.
+    pub fn interleave_families(mut self, interleave: bool) -> Self {
+        self.config.interleave_families = interleave;
+        self
+    }
+
+    /// Send a [`ProtocolEvent`] for every command, reply and data transfer step.
+    ///
+    /// # Arguments
+    /// `sender`    channel end receiving the events
+    pub fn events(mut self, sender: Sender<ProtocolEvent>) -> Self {
+        self.config.events = Some(sender);
+        self
+    }
+
+    /// Report commands, errors and transfers to `metrics`.
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.config.metrics = Some(metrics);
+        self
+    }
+
+    /// Inject faults in the control and data connections, for resilience testing.
+    #[cfg(feature = "chaos")]
+    pub fn chaos(mut self, chaos: ChaosConfig) -> Self {
+        self.config.chaos = Some(chaos);
+        self
+    }
+
+    /// Give up on each address after `timeout` when connecting, and on servers
+    /// not sending their greeting within `timeout` once connected.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Read timeout of data connections, independent of the control connection.
+    pub fn data_read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.data_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Write timeout of data connections, independent of the control connection.
+    pub fn data_write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.data_write_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum time a single transfer may take on its data connection,
+    /// regardless of how steadily data flows.
+    pub fn transfer_deadline(mut self, deadline: Duration) -> Self {
+        self.config.transfer_deadline = Some(deadline);
+        self
+    }
+
+    /// Abort transfers with [`crate::FtpError::TransferStalled`] when no byte moves on the
+    /// data connection for `timeout`, e.g. after a NAT mapping silently died.
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.config.stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Abort any single transfer moving more than `bytes` bytes with
+    /// [`crate::FtpError::SizeLimitExceeded`].
+    pub fn max_transfer_size(mut self, bytes: u64) -> Self {
+        self.config.max_transfer_size = Some(bytes);
+        self
+    }
+
+    /// Abort transfers once the whole session moved more than `bytes` bytes with
+    /// [`crate::FtpError::SizeLimitExceeded`].
+    pub fn max_session_bytes(mut self, bytes: u64) -> Self {
+        self.config.max_session_bytes = Some(bytes);
+        self
+    }
+
+    /// Before downloading to a path, compare the remote SIZE with the space
+    /// available on the local disk and fail without transferring when it won't fit.
+    /// Only effective on Unix platforms.
+    pub fn check_free_space(mut self, check: bool) -> Self {
+        self.config.check_free_space = check;
+        self
+    }
+
+    /// Announce the length of uploads from memory with `ALLO` before sending
+    /// them, as [`FtpClient::put_from_path`] always does for files.
+    pub fn allocate_uploads(mut self, allocate: bool) -> Self {
+        self.config.allocate_uploads = allocate;
+        self
+    }
+
+    /// After each upload, compare the `SIZE` of the stored file with the bytes
+    /// sent and fail with [`FtpError::IntegrityError`] when they differ, e.g.
+    /// after an upload in ASCII mode or cut short. Uploads to names the
+    /// server picks, with [`FtpClient::put_unique`], aren't checked.
+    pub fn verify_uploads(mut self, verify: bool) -> Self {
+        self.config.verify_uploads = verify;
+        self
+    }
+
+    /// Maximum record or page size sent with the `ALLO` announcing uploads,
+    /// required by servers storing record-structured files.
+    pub fn record_size(mut self, size: usize) -> Self {
+        self.config.record_size = Some(size);
+        self
+    }
+
+    /// Number of commands and replies kept for [`FtpClient::history`].
+    /// Defaults to [`crate::DEFAULT_HISTORY_SIZE`], 0 disables the history.
+    pub fn history_size(mut self, size: usize) -> Self {
+        self.config.history_size = Some(size);
+        self
+    }
+
+    /// Number of round trips [`FtpClient::latency`] is computed over.
+    /// Defaults to [`crate::DEFAULT_LATENCY_WINDOW`], 0 disables the tracking.
+    pub fn latency_window(mut self, size: usize) -> Self {
+        self.config.latency_window = Some(size);
+        self
+    }
+
+    /// Cache directory listings, sizes and modification times for `ttl`, so
+    /// repeated queries during one run don't reach the server. Entries are
+    /// dropped when the client changes the paths they describe.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.config.cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Copy transfer data through buffers of `size` bytes and keep up to `count`
+    /// of them for reuse, so jobs moving many small files don't allocate a buffer
+    /// per transfer. Defaults to [`crate::DEFAULT_BUFFER_COUNT`] buffers of
+    /// [`crate::DEFAULT_BUFFER_SIZE`] bytes.
+    pub fn transfer_buffers(mut self, count: usize, size: usize) -> Self {
+        self.config.buffers = BufferPool::new(count, size);
+        self
+    }
+
+    /// Secure the control and data connections with TLS (FTPS).
+    /// Requires the `rustls` or `native-tls` feature to create the [`TlsConfig`].
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    /// Read the dates of `LIST` replies with `format`, e.g. to accept localized
+    /// month names. See [`ListFormat`].
+    pub fn list_format(mut self, format: ListFormat) -> Self {
+        self.config.list_format = format;
+        self
+    }
+
+    /// Read `LIST` lines in `style` rather than in the one the server's `SYST`
+    /// reply tells.
+    pub fn list_style(mut self, style: ListStyle) -> Self {
+        self.config.list_style = Some(style);
+        self
+    }
+
+    /// Send paths and read replies and listings in `encoding` rather than
+    /// UTF-8, for servers without the `UTF8` feature. In `mode`
+    /// [`EncodingMode::Strict`], names the encoding can't represent fail with
+    /// an error instead of being altered. Not applied to GSSAPI protected commands.
+    pub fn encoding(mut self, encoding: Encoding, mode: EncodingMode) -> Self {
+        self.config.codec = Codec { encoding, mode };
+        self
+    }
+
+    /// Handle listing lines that aren't valid in the encoding with `policy`,
+    /// instead of as the encoding mode says.
+    pub fn list_decode(mut self, policy: DecodePolicy) -> Self {
+        self.config.list_decode = Some(policy);
+        self
+    }
+
+    /// Turn on the workarounds `registry` has for the host connected to, and
+    /// for the server's `SYST` reply.
+    pub fn quirks(mut self, registry: QuirksRegistry) -> Self {
+        self.config.quirks = Some(registry);
+        self
+    }
+
+    /// Check replies against `policy`, [`ReplyPolicy::Lenient`] by default.
+    /// [`ReplyPolicy::Strict`] fails on any reply code the standards don't
+    /// specify for the command, even when the server meant success.
+    pub fn reply_policy(mut self, policy: ReplyPolicy) -> Self {
+        self.config.reply_policy = policy;
+        self
+    }
+
+    /// Check the server greeting and the reply accepting each login with
+    /// `policy`, given the lines of the reply. An error from `policy` ends the
+    /// session and is returned by the connection or login, e.g. to stay off a
+    /// server in maintenance or one not identifying as expected.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use simpleftp::{BannerStage, FtpClient, FtpError};
+    ///
+    /// let mut client = FtpClient::builder()
+    ///     .banner_policy(|stage, lines| {
+    ///         let text = lines.join("\n").to_lowercase();
+    ///         if text.contains("maintenance") || text.contains("read-only") {
+    ///             return Err(FtpError::ConnectionError(format!("{:?}: {}", stage, text)));
+    ///         }
+    ///         if stage == BannerStage::Greeting && !text.contains("vsftpd") {
+    ///             return Err(FtpError::ConnectionError("unexpected server".into()));
+    ///         }
+    ///         Ok(())
+    ///     })
+    ///     .connect("127.0.0.1:21")
+    ///     .unwrap();
+    /// client.login("user", "password").unwrap();
+    /// ```
+    pub fn banner_policy<F>(mut self, policy: F) -> Self
+    where
+        F: Fn(BannerStage, &[String]) -> Result<()> + Send + Sync + 'static,
+    {
+        self.config.banner_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Open a FTP connection using the configured settings.
+    ///
+    /// # Arguments
+    /// `address`     Server address in the form `host:port`
+    ///
+    /// # Errors
+    /// When the address is malformed, cannot be resolved or the server is not ready.
+    pub fn connect(self, address: impl AsRef<str>) -> Result<FtpClient> {
+        let (host, port) = split_host_port(address.as_ref())?;
+        let mut config = self.config;
+        let registry = config.quirks.take();
+        if let Some(quirks) = registry
+            .as_ref()
+            .and_then(|registry| registry.for_host(host))
+        {
+            quirks.apply(&mut config);
+        }
+        let stream = config.open(host, port, None)?;
+        config.tls_domain = host.to_string();
+        let mut client = FtpClient::with_config(stream, config)?;
+        if let Some(registry) = registry.filter(QuirksRegistry::has_systems) {
+            client.apply_system_quirks(registry)?;
+        }
+        Ok(client)
+    }
+}
+
+/// Split an address in the form `host:port` or `[v6]:port`.
+fn split_host_port(address: &str) -> Result<(&str, u16)> {
+    let invalid = || FtpError::ConnectionError(format!("Invalid address: {}", address));
+    let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = port.parse::<u16>().map_err(|_| invalid())?;
+    Ok((host, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::interleave;
+    use super::Config;
+    use super::FtpClientBuilder;
+    use crate::testing::scripted_server;
+    use crate::FtpError;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    #[test]
+    fn resolver_addresses_take_the_requested_port() {
+        let address = scripted_server(|_, _| "200 OK\r\n".to_string());
+        // the stub answers with a port of its own, which is replaced
+        let resolver = |host: &str| match host {
+            "ftp.example.test" => Ok(vec!["127.0.0.1:9".parse().unwrap()]),
+            _ => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        };
+        let config = Config {
+            resolver: Some(Arc::new(resolver)),
+            ..Config::default()
+        };
+        let resolved = config.resolve("ftp.example.test", 2121).unwrap();
+        assert_eq!(resolved, ["127.0.0.1:2121".parse::<SocketAddr>().unwrap()]);
+        assert!(config.resolve("other.test", 21).is_err());
+        let config = Config {
+            resolver: Some(Arc::new(|_: &str| Ok(Vec::new()))),
+            ..Config::default()
+        };
+        assert!(matches!(
+            config.resolve("ftp.example.test", 21),
+            Err(FtpError::ConnectionError(_))
+        ));
+
+        let mut client = FtpClientBuilder::new()
+            .resolver(resolver)
+            .connect(format!("ftp.example.test:{}", address.port()))
+            .unwrap();
+        assert_eq!(client.peer_addr().unwrap(), address);
+        client.noop().unwrap();
+    }
+
+    #[test]
+    fn address_families_alternate() {
+        let addresses: Vec<SocketAddr> = [
+            "[::1]:21",
+            "[::2]:21",
+            "10.0.0.1:21",
+            "[::3]:21",
+            "10.0.0.2:21",
+            "10.0.0.3:21",
+            "10.0.0.4:21",
+        ]
+        .iter()
+        .map(|address| address.parse().unwrap())
+        .collect();
+        let order = |addresses: Vec<SocketAddr>| {
+            interleave(addresses)
+                .iter()
+                .map(SocketAddr::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            order(addresses.clone()),
+            [
+                "[::1]:21",
+                "10.0.0.1:21",
+                "[::2]:21",
+                "10.0.0.2:21",
+                "[::3]:21",
+                "10.0.0.3:21",
+                "10.0.0.4:21"
+            ]
+        );
+        // the family of the first address leads
+        let mut addresses = addresses;
+        addresses.rotate_left(2);
+        assert_eq!(
+            order(addresses)[..4],
+            ["10.0.0.1:21", "[::3]:21", "10.0.0.2:21", "[::1]:21"]
+        );
+        assert!(interleave(Vec::new()).is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+//! Time limited cache of listings and file metadata.
+//!
+//! Entries are dropped when the client sends a command changing the paths they
+//! describe. Changes made by other clients are only noticed once entries expire.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use crate::RemotePath;
+
+#[derive(Clone)]
+pub(crate) enum Cached {
+    Lines(Vec<String>),
+    Size(u64),
+    Modified(SystemTime),
+}
+
+pub(crate) struct Cache {
+    ttl: Option<Duration>,
+    entries: HashMap<(&'static str, RemotePath), (Instant, Cached)>,
+}
+
+impl Cache {
+    /// A cache keeping entries for `ttl`, disabled without one.
+    pub(crate) fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Cached result of `command` for `path`, if still fresh.
+    pub(crate) fn get(&mut self, command: &'static str, path: &str) -> Option<Cached> {
+        let ttl = self.ttl?;
+        let key = (command, RemotePath::new(path).normalize());
+        match self.entries.get(&key) {
+            Some((stored, value)) if stored.elapsed() < ttl => Some(value.clone()),
+            Some(_) => {
+                self.entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, command: &'static str, path: &str, value: Cached) {
+        if self.ttl.is_some() {
+            let key = (command, RemotePath::new(path).normalize());
+            self.entries.insert(key, (Instant::now(), value));
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drop the entries a command about to be sent may invalidate.
+    pub(crate) fn observe(&mut self, command: &str) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let (verb, argument) = command.split_once(' ').unwrap_or((command, ""));
+        match verb.to_ascii_uppercase().as_str() {
+            "STOR" | "APPE" | "DELE" | "MKD" | "XMKD" | "RMD" | "XRMD" | "RNFR" | "RNTO"
+            | "MFMT" | "MFF" => self.invalidate(argument),
+            // relative paths now point elsewhere
+            "CWD" | "XCWD" | "CDUP" | "XCUP" => {
+                self.entries.retain(|(_, path), _| path.is_absolute())
+            }
+            "STOU" | "SITE" | "SMNT" | "USER" | "REIN" => self.clear(),
+            _ => {}
+        }
+    }
+
+    /// Forget `path`, its content and the listing of its parent. Relative paths
+    /// can't be matched against absolute ones, so they are dropped as well.
+    pub(crate) fn invalidate(&mut self, path: &str) {
+        let path = RemotePath::new(path).normalize();
+        if !path.is_absolute() {
+            self.clear();
+            return;
+        }
+        let parent = path.parent();
+        let below = format!("{}/", path.as_str().trim_end_matches('/'));
+        self.entries.retain(|(_, cached), _| {
+            cached.is_absolute()
+                && *cached != path
+                && Some(cached) != parent.as_ref()
+                && !cached.as_str().starts_with(&below)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use super::Cached;
+    use std::time::Duration;
+
+    /// A cache holding the sizes of `paths`.
+    fn filled(paths: &[&str]) -> Cache {
+        let mut cache = Cache::new(Some(Duration::from_secs(60)));
+        for path in paths {
+            cache.insert("SIZE", path, Cached::Size(1));
+        }
+        cache
+    }
+
+    fn cached(cache: &mut Cache, paths: &[&str]) -> Vec<bool> {
+        paths
+            .iter()
+            .map(|path| cache.get("SIZE", path).is_some())
+            .collect()
+    }
+
+    #[test]
+    fn entries_expire() {
+        let mut cache = Cache::new(Some(Duration::from_millis(50)));
+        cache.insert("LIST", "/pub/", Cached::Lines(vec!["a.txt".into()]));
+        assert!(
+            matches!(cache.get("LIST", "/pub"), Some(Cached::Lines(lines)) if lines == ["a.txt"])
+        );
+        assert!(cache.get("NLST", "/pub").is_none());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.get("LIST", "/pub").is_none());
+        assert!(cache.entries.is_empty());
+
+        // without a ttl nothing is kept
+        let mut cache = Cache::new(None);
+        cache.insert("SIZE", "/a.txt", Cached::Size(1));
+        assert!(cache.get("SIZE", "/a.txt").is_none());
+    }
+
+    #[test]
+    fn changes_drop_entries() {
+        let paths = ["/pub", "/pub/a.txt", "/pub/a.txt/x", "/pub/b.txt", "/other"];
+        for command in ["STOR /pub/a.txt", "dele /pub/a.txt", "RNFR /pub/./a.txt"] {
+            let mut cache = filled(&paths);
+            cache.observe(command);
+            assert_eq!(
+                cached(&mut cache, &paths),
+                [false, false, false, true, true]
+            );
+        }
+        let mut cache = filled(&paths);
+        cache.observe("RETR /pub/a.txt");
+        cache.observe("NOOP");
+        assert_eq!(cached(&mut cache, &paths), [true; 5]);
+        // a relative path could be any of them
+        cache.observe("DELE a.txt");
+        assert_eq!(cached(&mut cache, &paths), [false; 5]);
+    }
+
+    #[test]
+    fn directory_changes_keep_absolute_entries() {
+        let paths = ["/pub/a.txt", "a.txt", "docs/b.txt"];
+        for command in ["CWD /pub", "CDUP", "xcwd docs"] {
+            let mut cache = filled(&paths);
+            cache.observe(command);
+            assert_eq!(cached(&mut cache, &paths), [true, false, false]);
+        }
+    }
+
+    #[test]
+    fn session_changes_clear_the_cache() {
+        let paths = ["/pub/a.txt", "/other"];
+        for command in [
+            "STOU",
+            "SITE CHMOD 644 /x",
+            "USER other",
+            "REIN",
+            "SMNT /mnt",
+        ] {
+            let mut cache = filled(&paths);
+            cache.observe(command);
+            assert_eq!(cached(&mut cache, &paths), [false, false], "{}", command);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Fault injection for resilience testing (`chaos` feature).
+//!
+//! Wrap a client's connections with [`ChaosTransport`] through
+//! [`crate::FtpClientBuilder::chaos`] to exercise retry and error handling
+//! against latency, connection resets, short reads and bogus reply codes.
+//! Faults are driven by a seeded generator so a failing scenario can be replayed.
+//!
+//! # Example
+//! ```no_run
+//! use simpleftp::{ChaosConfig, FtpClient};
+//! use std::time::Duration;
+//!
+//! let chaos = ChaosConfig::new(42)
+//!     .latency(Duration::from_millis(50))
+//!     .reset_probability(0.01)
+//!     .bogus_reply_probability(0.05);
+//! let mut client = FtpClient::builder().chaos(chaos).connect("127.0.0.1:21").unwrap();
+//! client.login("user", "password").unwrap();
+//! ```
+
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::stream::Stream;
+
+/// Reply code written over genuine ones when a bogus reply is injected.
+pub const BOGUS_REPLY_CODE: &[u8; 3] = b"599";
+
+/// Kinds and rates of faults to inject. Probabilities are in the `0.0..=1.0` range
+/// and are evaluated on every read or write call.
+#[derive(Debug, Clone)]
+pub struct ChaosConfig {
+    seed: u64,
+    latency: Option<Duration>,
+    reset_probability: f64,
+    short_read_probability: f64,
+    bogus_reply_probability: f64,
+    streams: Arc<AtomicU64>,
+}
+
+impl ChaosConfig {
+    /// No faults, with the generator seeded by `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            latency: None,
+            reset_probability: 0.0,
+            short_read_probability: 0.0,
+            bogus_reply_probability: 0.0,
+            streams: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Delay every read and write by `latency`.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Chance for an operation to fail with `ConnectionReset`.
+    /// A reset connection keeps failing afterwards.
+    pub fn reset_probability(mut self, probability: f64) -> Self {
+        self.reset_probability = probability;
+        self
+    }
+
+    /// Chance for a read to return fewer bytes than available.
+    pub fn short_read_probability(mut self, probability: f64) -> Self {
+        self.short_read_probability = probability;
+        self
+    }
+
+    /// Chance for a reply line to have its code replaced by [`BOGUS_REPLY_CODE`].
+    pub fn bogus_reply_probability(mut self, probability: f64) -> Self {
+        self.bogus_reply_probability = probability;
+        self
+    }
+
+    /// Wrap `inner`. Every wrapped stream gets its own generator derived from the
+    /// seed and the order in which streams were wrapped.
+    pub fn wrap<S>(&self, inner: S) -> ChaosTransport<S> {
+        let index = self.streams.fetch_add(1, Ordering::Relaxed);
+        let seed = self.seed ^ index.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        ChaosTransport {
+            inner,
+            config: self.clone(),
+            state: seed | 1,
+            at_line_start: true,
+            reset: false,
+        }
+    }
+}
+
+/// A stream wrapper injecting the faults described by a [`ChaosConfig`].
+pub struct ChaosTransport<S> {
+    inner: S,
+    config: ChaosConfig,
+    state: u64,
+    at_line_start: bool,
+    reset: bool,
+}
+
+impl<S> ChaosTransport<S> {
+    /// Give back the wrapped stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// xorshift64*, returns a value in `0.0..1.0`.
+    fn next(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next() < probability
+    }
+
+    /// Apply latency and resets common to reads and writes.
+    fn before_io(&mut self) -> std::io::Result<()> {
+        if let Some(latency) = self.config.latency {
+            std::thread::sleep(latency);
+        }
+        if !self.reset && self.chance(self.config.reset_probability) {
+            self.reset = true;
+        }
+        if self.reset {
+            return Err(std::io::Error::new(
+                ErrorKind::ConnectionReset,
+                "connection reset (injected)",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read> Read for ChaosTransport<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.before_io()?;
+        let mut len = buf.len();
+        if len > 1 && self.chance(self.config.short_read_probability) {
+            len = 1 + (self.next() * (len - 1) as f64) as usize;
+        }
+        let count = self.inner.read(&mut buf[..len])?;
+        let data = &mut buf[..count];
+        if self.at_line_start
+            && data.len() >= 3
+            && data[..3].iter().all(u8::is_ascii_digit)
+            && self.chance(self.config.bogus_reply_probability)
+        {
+            data[..3].copy_from_slice(BOGUS_REPLY_CODE);
+        }
+        if let Some(last) = data.last() {
+            self.at_line_start = *last == b'\n';
+        }
+        Ok(count)
+    }
+}
+
+impl<S: Write> Write for ChaosTransport<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.before_io()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Stream> Stream for ChaosTransport<S> {
+    fn socket(&self) -> &TcpStream {
+        self.inner.socket()
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        self.inner.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChaosConfig;
+    use super::BOGUS_REPLY_CODE;
+    use std::io::ErrorKind;
+    use std::io::Read;
+    use std::io::Write;
+
+    /// Sizes of the reads needed to drain `data` through a fresh transport.
+    fn read_sizes(config: &ChaosConfig, data: &[u8]) -> Vec<usize> {
+        let mut transport = config.wrap(data);
+        let mut sizes = Vec::new();
+        let mut buf = [0; 64];
+        loop {
+            match transport.read(&mut buf).unwrap() {
+                0 => return sizes,
+                count => sizes.push(count),
+            }
+        }
+    }
+
+    #[test]
+    fn faults_replay_from_the_seed() {
+        let data = [b'x'; 4096];
+        let config = ChaosConfig::new(42).short_read_probability(0.5);
+        let first = read_sizes(&config, &data);
+        assert_eq!(first.iter().sum::<usize>(), data.len());
+        assert!(first.iter().any(|&size| size < 64));
+        // each stream of a config gets its own generator, replayed by the same seed
+        let second = read_sizes(&config, &data);
+        assert_ne!(first, second);
+        let replay = ChaosConfig::new(42).short_read_probability(0.5);
+        assert_eq!(read_sizes(&replay, &data), first);
+        assert_eq!(read_sizes(&replay, &data), second);
+        let other = ChaosConfig::new(7).short_read_probability(0.5);
+        assert_ne!(read_sizes(&other, &data), first);
+        assert_eq!(read_sizes(&ChaosConfig::new(42), &data), [64; 64]);
+    }
+
+    #[test]
+    fn resets_and_bogus_replies_are_injected() {
+        let mut transport = ChaosConfig::new(1).reset_probability(1.0).wrap(Vec::new());
+        for _ in 0..2 {
+            let error = transport.write(b"NOOP\r\n").unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ConnectionReset);
+        }
+        assert!(transport.into_inner().is_empty());
+
+        let replies = b"220 Ready\r\n331 Password 200\r\n";
+        let mut transport = ChaosConfig::new(1)
+            .bogus_reply_probability(1.0)
+            .wrap(&replies[..]);
+        // codes are replaced at the start of a line, not digits within one
+        let mut read = Vec::new();
+        for size in [11, 13, 5] {
+            let mut buf = vec![0; size];
+            assert_eq!(transport.read(&mut buf).unwrap(), size);
+            read.extend_from_slice(&buf);
+        }
+        let mut expected = BOGUS_REPLY_CODE.to_vec();
+        expected.extend_from_slice(b" Ready\r\n");
+        expected.extend_from_slice(BOGUS_REPLY_CODE);
+        expected.extend_from_slice(b" Password 200\r\n");
+        assert_eq!(read, expected);
+    }
+}
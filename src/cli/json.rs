@@ -0,0 +1,133 @@
+//! JSON output of the command line client (`--json`).
+
+use std::fmt::Write;
+use std::time::SystemTime;
+
+pub(crate) use simpleftp::json::string;
+use simpleftp::EntryKind;
+use simpleftp::FtpEntry;
+
+/// A JSON object, written field by field.
+pub(crate) struct Object(String);
+
+impl Object {
+    pub(crate) fn new() -> Self {
+        Self(String::new())
+    }
+
+    fn key(&mut self, key: &str) -> &mut String {
+        self.0.push(if self.0.is_empty() { '{' } else { ',' });
+        self.0.push_str(&string(key));
+        self.0.push(':');
+        &mut self.0
+    }
+
+    pub(crate) fn string(mut self, key: &str, value: &str) -> Self {
+        let value = string(value);
+        self.key(key).push_str(&value);
+        self
+    }
+
+    pub(crate) fn number(mut self, key: &str, value: impl Into<f64>) -> Self {
+        let value = value.into();
+        let _ = write!(self.key(key), "{}", value);
+        self
+    }
+
+    pub(crate) fn bool(mut self, key: &str, value: bool) -> Self {
+        let _ = write!(self.key(key), "{}", value);
+        self
+    }
+
+    /// A raw JSON value, `null` if `None`.
+    pub(crate) fn raw(mut self, key: &str, value: Option<String>) -> Self {
+        let value = value.unwrap_or_else(|| "null".into());
+        self.key(key).push_str(&value);
+        self
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+        if self.0.is_empty() {
+            self.0.push('{');
+        }
+        self.0.push('}');
+        self.0
+    }
+}
+
+/// A JSON array of already encoded values.
+pub(crate) fn array(values: impl IntoIterator<Item = String>) -> String {
+    format!("[{}]", values.into_iter().collect::<Vec<_>>().join(","))
+}
+
+/// An unsigned integer, `null` if `None`. Kept exact instead of going through
+/// `f64`.
+pub(crate) fn integer(value: Option<u64>) -> Option<String> {
+    value.map(|value| value.to_string())
+}
+
+pub(crate) fn entry(entry: &FtpEntry) -> String {
+    let kind = match entry.kind() {
+        EntryKind::File => "file",
+        EntryKind::Directory => "dir",
+        EntryKind::Symlink => "symlink",
+        EntryKind::Other => "other",
+    };
+    Object::new()
+        .string("name", entry.name())
+        .string("kind", kind)
+        .raw("size", integer(entry.size()))
+        .raw(
+            "permissions",
+            entry
+                .permissions()
+                .map(|permissions| string(&permissions.octal())),
+        )
+        .raw("target", entry.link_target().map(string))
+        .raw("modified", timestamp(entry.modified()))
+        .finish()
+}
+
+/// Seconds since the Unix epoch, `null` if unknown.
+pub(crate) fn timestamp(time: Option<SystemTime>) -> Option<String> {
+    let seconds = time?.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_secs();
+    integer(Some(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("plain"), "\"plain\"");
+        assert_eq!(string("say \"hi\""), r#""say \"hi\"""#);
+        assert_eq!(string("C:\\temp"), r#""C:\\temp""#);
+        assert_eq!(string("a\nb\r\tc"), r#""a\nb\r\tc""#);
+        assert_eq!(string("\u{0}\u{1b}\u{7f}"), "\"\\u0000\\u001b\u{7f}\"");
+        assert_eq!(string("é ✓"), "\"é ✓\"");
+        let parsed: String = serde_json::from_str(&string("\"\\\u{8}\u{1f}/")).unwrap();
+        assert_eq!(parsed, "\"\\\u{8}\u{1f}/");
+    }
+
+    #[test]
+    fn writes_objects() {
+        assert_eq!(Object::new().finish(), "{}");
+        let object = Object::new()
+            .string("name", "a\"b")
+            .number("ratio", 0.5)
+            .bool("done", true)
+            .raw("size", integer(Some(u64::MAX)))
+            .raw("modified", timestamp(None))
+            .finish();
+        assert_eq!(
+            object,
+            r#"{"name":"a\"b","ratio":0.5,"done":true,"size":18446744073709551615,"modified":null}"#
+        );
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(timestamp(Some(time)).as_deref(), Some("1700000000"));
+    }
+}
@@ -0,0 +1,443 @@
+//! The `simpleftp` command line client.
+
+mod json;
+mod profile;
+mod progress;
+mod script;
+
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+
+use simpleftp::FtpClient;
+use simpleftp::FtpClientBuilder;
+use simpleftp::FtpError;
+use simpleftp::RemotePath;
+use simpleftp::Result;
+
+use profile::Profile;
+use progress::Progress;
+use progress::Summary;
+
+const USAGE: &str = "\
+Usage: simpleftp [options] <command> [arguments]
+
+Commands:
+  ls [dir]                  List a directory
+  stat <path>               Show the size and modification time of a file
+  get <remote> [local]      Download a file
+  put <local> [remote]      Upload a file
+  mget <pattern> [dir]      Download the files matching a pattern, e.g. /logs/*.txt
+  run <script>              Run the commands of a script file, one per line:
+                            open, login, cd, lcd, ls, get, put, mget, mkdir,
+                            delete and close
+
+Options:
+  -H, --host <host[:port]>  Server to connect to
+  -u, --user <name>         User name, anonymous by default
+  -p, --password <secret>   Password, also read from SIMPLEFTP_PASSWORD
+      --tls                 Secure the connection with AUTH TLS
+  -P, --profile <name>      Connect with the settings of a profile
+      --config <file>       Read profiles from this file instead of
+                            ~/.config/simpleftp/config.toml
+  -k, --keep-going          Let scripts go on after a failed command
+  -q, --quiet               Don't draw progress bars
+      --json                Print listings, file details and transfer summaries
+                            as JSON on the standard output
+  -h, --help                Show this help
+
+Options given on the command line take precedence over the profile.
+";
+
+/// Settings given on the command line.
+struct Options {
+    host: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    tls: bool,
+    profile: Option<String>,
+    config: Option<PathBuf>,
+    quiet: bool,
+    keep_going: bool,
+    json: bool,
+    command: Vec<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Self>> {
+        let mut options = Options {
+            host: None,
+            user: None,
+            password: std::env::var("SIMPLEFTP_PASSWORD").ok(),
+            tls: false,
+            profile: None,
+            config: None,
+            quiet: false,
+            keep_going: false,
+            json: false,
+            command: Vec::new(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| usage_error(format!("{} needs a value", name)))
+            };
+            match arg.as_str() {
+                "-H" | "--host" => options.host = Some(value(&arg)?),
+                "-u" | "--user" => options.user = Some(value(&arg)?),
+                "-p" | "--password" => options.password = Some(value(&arg)?),
+                "--tls" => options.tls = true,
+                "-P" | "--profile" => options.profile = Some(value(&arg)?),
+                "--config" => options.config = Some(value(&arg)?.into()),
+                "-q" | "--quiet" => options.quiet = true,
+                "-k" | "--keep-going" => options.keep_going = true,
+                "--json" => options.json = true,
+                "-h" | "--help" => return Ok(None),
+                option if option.starts_with('-') && options.command.is_empty() => {
+                    return Err(usage_error(format!("Unknown option {}", option)))
+                }
+                _ => options.command.push(arg),
+            }
+        }
+        Ok(Some(options))
+    }
+
+    /// The profile named by `--profile`, or an empty one.
+    fn profile(&self) -> Result<Profile> {
+        let Some(name) = &self.profile else {
+            return Ok(Profile::default());
+        };
+        let path = self
+            .config
+            .clone()
+            .or_else(profile::default_path)
+            .ok_or_else(|| usage_error("No configuration file, use --config".into()))?;
+        profile::load(&path, name)
+    }
+
+    fn connect(&self) -> Result<FtpClient> {
+        let profile = self.profile()?;
+        let host = self
+            .host
+            .clone()
+            .or(profile.host)
+            .ok_or_else(|| usage_error("No host given, use --host or --profile".into()))?;
+        let address = match profile.port {
+            _ if host.contains(':') => host,
+            Some(port) => format!("{}:{}", host, port),
+            None => format!("{}:21", host),
+        };
+        if profile.passive == Some(false) {
+            return Err(FtpError::InvalidArgument(
+                "Active mode is not supported, only passive".into(),
+            ));
+        }
+        let mut client = open(&address, self.tls || profile.tls == Some(true))?;
+        let user = self.user.as_deref().or(profile.user.as_deref());
+        let password = self.password.as_deref().or(profile.password.as_deref());
+        client.login(user.unwrap_or("anonymous"), password.unwrap_or_default())?;
+        if let Some(dir) = &profile.dir {
+            client.change_dir(dir)?;
+        }
+        Ok(client)
+    }
+}
+
+/// Run the command line `args`, returning whether every transfer succeeded.
+pub fn run(args: impl Iterator<Item = String>) -> Result<bool> {
+    let Some(options) = Options::parse(args)? else {
+        print!("{}", USAGE);
+        return Ok(true);
+    };
+    let (command, arguments) = options
+        .command
+        .split_first()
+        .ok_or_else(|| usage_error("No command given".into()))?;
+    let mut summary = Summary::default();
+    let ok = match (command.as_str(), arguments) {
+        ("run", [script]) => script::run(Path::new(script), &options, &mut summary)?,
+        _ => {
+            let mut client = options.connect()?;
+            if !execute(&mut client, command, arguments, &options, &mut summary)? {
+                return Err(usage_error(format!(
+                    "Invalid command: {}",
+                    options.command.join(" ")
+                )));
+            }
+            // the work is done, a failing QUIT changes nothing
+            let _ = client.logout();
+            true
+        }
+    };
+    if options.json && !summary.is_empty() {
+        summary.print_json();
+    } else if !summary.is_empty() {
+        summary.print();
+    }
+    Ok(ok && summary.failures() == 0)
+}
+
+/// Connect to `address` (`host:port`), with `AUTH TLS` if `tls`.
+fn open(address: &str, tls: bool) -> Result<FtpClient> {
+    let mut builder = FtpClientBuilder::new();
+    if tls {
+        builder = builder.tls(tls::config()?);
+    }
+    builder.connect(address)
+}
+
+/// Run a listing or transfer command, returning `false` when `command` isn't
+/// one of them or has the wrong arguments. Failed transfers are recorded in
+/// `summary` rather than returned.
+fn execute(
+    client: &mut FtpClient,
+    command: &str,
+    arguments: &[String],
+    options: &Options,
+    summary: &mut Summary,
+) -> Result<bool> {
+    match (command, arguments) {
+        ("ls", dir) if dir.len() <= 1 && options.json => {
+            let dir = dir.first().map(String::as_str).unwrap_or(".");
+            let entries = client.list_entries(dir)?;
+            println!("{}", json::array(entries.iter().map(json::entry)));
+        }
+        ("ls", dir) if dir.len() <= 1 => {
+            let dir = dir.first().map(String::as_str).unwrap_or(".");
+            for line in client.list(dir)? {
+                println!("{}", line);
+            }
+        }
+        ("stat", [path]) => stat(client, path, options)?,
+        ("get", [remote, local @ ..]) if local.len() <= 1 => {
+            let remote = RemotePath::new(remote.as_str());
+            let local = match local.first() {
+                Some(local) => PathBuf::from(local),
+                None => PathBuf::from(file_name(&remote)?),
+            };
+            get(client, &remote, &local, options, summary);
+        }
+        ("put", [local, remote @ ..]) if remote.len() <= 1 => {
+            let local = Path::new(local);
+            let remote = match remote.first() {
+                Some(remote) => RemotePath::new(remote.as_str()),
+                None => RemotePath::new(local_name(local)?),
+            };
+            put(client, local, &remote, options, summary);
+        }
+        ("mget", [pattern, dir @ ..]) if dir.len() <= 1 => {
+            let dir = Path::new(dir.first().map(String::as_str).unwrap_or("."));
+            mget(client, pattern, dir, options, summary)?;
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn stat(client: &mut FtpClient, path: &str, options: &Options) -> Result<()> {
+    let size = client.size(path)?;
+    let modified = client.modified_time(path).ok();
+    if options.json {
+        let details = json::Object::new()
+            .string("path", path)
+            .raw("size", json::integer(Some(size)))
+            .raw("modified", json::timestamp(modified));
+        println!("{}", details.finish());
+    } else {
+        println!("{}", path);
+        println!(
+            "  size      {} ({} bytes)",
+            progress::format_bytes(size),
+            size
+        );
+        if let Some(modified) = modified {
+            println!("  modified  {}", format_time(modified));
+        }
+    }
+    Ok(())
+}
+
+/// A time as `YYYY-MM-DD HH:MM:SS UTC`.
+fn format_time(time: std::time::SystemTime) -> String {
+    let seconds = time
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let time_of_day = seconds % 86_400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
+
+fn get(
+    client: &mut FtpClient,
+    remote: &RemotePath,
+    local: &Path,
+    options: &Options,
+    summary: &mut Summary,
+) {
+    let total = client.size(remote).ok();
+    let mut progress = Progress::new(remote.as_str(), total, options.quiet || options.json);
+    let result = File::create(local)
+        .map_err(FtpError::from)
+        .and_then(|file| {
+            let result = client.get(remote, &mut progress.track(file));
+            if result.is_err() {
+                // don't leave an empty or truncated file behind
+                let _ = std::fs::remove_file(local);
+            }
+            result
+        });
+    progress.finish(result.is_ok());
+    summary.record(&progress, result);
+}
+
+fn put(
+    client: &mut FtpClient,
+    local: &Path,
+    remote: &RemotePath,
+    options: &Options,
+    summary: &mut Summary,
+) {
+    let name = local.display().to_string();
+    let total = std::fs::metadata(local).map(|metadata| metadata.len()).ok();
+    let mut progress = Progress::new(&name, total, options.quiet || options.json);
+    let result = File::open(local)
+        .map_err(FtpError::from)
+        .and_then(|file| client.put(remote, &mut progress.track(file)));
+    progress.finish(result.is_ok());
+    summary.record(&progress, result);
+}
+
+/// Download the files of a directory whose names match the wildcards (`*`,
+/// `?`) of the last component of `pattern`.
+fn mget(
+    client: &mut FtpClient,
+    pattern: &str,
+    dir: &Path,
+    options: &Options,
+    summary: &mut Summary,
+) -> Result<()> {
+    let pattern = RemotePath::new(pattern);
+    let wildcard = file_name(&pattern)?;
+    let parent = pattern.parent().unwrap_or_else(|| RemotePath::new("."));
+    for name in client.name_list_matching(&parent, &wildcard)? {
+        get(
+            client,
+            &parent.join(&name),
+            &dir.join(&name),
+            options,
+            summary,
+        );
+    }
+    Ok(())
+}
+
+fn file_name(path: &RemotePath) -> Result<String> {
+    path.file_name()
+        .map(str::to_string)
+        .ok_or_else(|| usage_error(format!("No file name in {}", path)))
+}
+
+fn local_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_string)
+        .ok_or_else(|| usage_error(format!("No file name in {}", path.display())))
+}
+
+fn usage_error(message: String) -> FtpError {
+    FtpError::InvalidArgument(format!("{}\n\n{}", message, USAGE))
+}
+
+/// TLS with the certificates trusted by the system.
+mod tls {
+    use simpleftp::FtpError;
+    use simpleftp::Result;
+    use simpleftp::TlsConfig;
+
+    #[cfg(feature = "native-tls")]
+    pub(super) fn config() -> Result<TlsConfig> {
+        let connector = native_tls::TlsConnector::new()
+            .map_err(|error| FtpError::ConnectionError(error.to_string()))?;
+        Ok(TlsConfig::native_tls(connector))
+    }
+
+    /// Trust the certificates of `SSL_CERT_FILE` or of the usual system bundle.
+    #[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+    pub(super) fn config() -> Result<TlsConfig> {
+        use rustls::pki_types::pem::PemObject;
+        use rustls::pki_types::CertificateDer;
+        use std::sync::Arc;
+
+        const BUNDLES: [&str; 3] = [
+            "/etc/ssl/certs/ca-certificates.crt",
+            "/etc/pki/tls/certs/ca-bundle.crt",
+            "/etc/ssl/cert.pem",
+        ];
+        let bundle = std::env::var("SSL_CERT_FILE")
+            .ok()
+            .into_iter()
+            .chain(BUNDLES.map(String::from))
+            .find(|path| std::path::Path::new(path).exists())
+            .ok_or_else(|| FtpError::ConnectionError("No trusted certificates found".into()))?;
+        let mut roots = rustls::RootCertStore::empty();
+        let certificates = CertificateDer::pem_file_iter(&bundle)
+            .map_err(|error| FtpError::ConnectionError(format!("{}: {}", bundle, error)))?;
+        roots.add_parsable_certificates(certificates.flatten());
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(TlsConfig::rustls(Arc::new(config)))
+    }
+
+    #[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+    pub(super) fn config() -> Result<TlsConfig> {
+        Err(FtpError::InvalidArgument(
+            "TLS needs a build with the rustls or native-tls feature".into(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use super::format_time;
+
+    #[test]
+    fn formats_times_in_utc() {
+        let at = |seconds| format_time(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(at(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(at(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(at(1_700_000_000), "2023-11-14 22:13:20 UTC");
+        assert_eq!(at(4_107_542_399), "2100-02-28 23:59:59 UTC");
+        assert_eq!(at(4_107_542_400), "2100-03-01 00:00:00 UTC");
+        // before the epoch shows the epoch
+        assert_eq!(
+            format_time(SystemTime::UNIX_EPOCH - Duration::from_secs(1)),
+            "1970-01-01 00:00:00 UTC"
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Named connection profiles of the configuration file.
+//!
+//! The file holds one table per profile, in a subset of TOML: strings,
+//! integers and booleans.
+//!
+//! ```toml
+//! [prod]
+//! host = "ftp.example.com"
+//! port = 21
+//! user = "deploy"
+//! tls = true
+//! passive = true
+//! dir = "/releases"
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use simpleftp::FtpError;
+use simpleftp::Result;
+
+/// Connection settings of a profile, each one optional.
+#[derive(Debug, Default)]
+pub(crate) struct Profile {
+    pub(crate) host: Option<String>,
+    pub(crate) port: Option<u16>,
+    pub(crate) user: Option<String>,
+    pub(crate) password: Option<String>,
+    pub(crate) tls: Option<bool>,
+    pub(crate) passive: Option<bool>,
+    pub(crate) dir: Option<String>,
+}
+
+enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+}
+
+/// The configuration file: `$SIMPLEFTP_CONFIG`, or `simpleftp/config.toml` in
+/// `$XDG_CONFIG_HOME` or `~/.config`.
+pub(crate) fn default_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("SIMPLEFTP_CONFIG") {
+        return Some(path.into());
+    }
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("simpleftp").join("config.toml"))
+}
+
+/// Read the profile `name` of the configuration file at `path`.
+pub(crate) fn load(path: &Path, name: &str) -> Result<Profile> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| config_error(path, format!("can't be read: {}", error)))?;
+    let mut tables = parse(path, &text)?;
+    let table = tables
+        .remove(name)
+        .ok_or_else(|| config_error(path, format!("no profile named {}", name)))?;
+    let mut profile = Profile::default();
+    for (key, value) in table {
+        let invalid = || config_error(path, format!("invalid value for {}.{}", name, key));
+        match (key.as_str(), value) {
+            ("host", Value::String(host)) => profile.host = Some(host),
+            ("port", Value::Integer(port)) => {
+                profile.port = Some(u16::try_from(port).map_err(|_| invalid())?)
+            }
+            ("user", Value::String(user)) => profile.user = Some(user),
+            ("password", Value::String(password)) => profile.password = Some(password),
+            ("tls", Value::Bool(tls)) => profile.tls = Some(tls),
+            ("passive", Value::Bool(passive)) => profile.passive = Some(passive),
+            ("dir", Value::String(dir)) => profile.dir = Some(dir),
+            ("host" | "port" | "user" | "password" | "tls" | "passive" | "dir", _) => {
+                return Err(invalid())
+            }
+            _ => {
+                return Err(config_error(
+                    path,
+                    format!("unknown setting {}.{}", name, key),
+                ))
+            }
+        }
+    }
+    Ok(profile)
+}
+
+fn parse(path: &Path, text: &str) -> Result<HashMap<String, HashMap<String, Value>>> {
+    let mut tables: HashMap<String, HashMap<String, Value>> = HashMap::new();
+    let mut current = None;
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            let name = unquote(name.trim()).unwrap_or(name.trim()).to_string();
+            tables.entry(name.clone()).or_default();
+            current = Some(name);
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(line_error(path, number, "expected key = value".into()));
+        };
+        let Some(table) = current.as_ref().and_then(|name| tables.get_mut(name)) else {
+            return Err(line_error(
+                path,
+                number,
+                "setting outside of a profile".into(),
+            ));
+        };
+        let value = value.trim();
+        let value = if let Some(text) = unquote(value) {
+            Value::String(text.replace("\\\"", "\"").replace("\\\\", "\\"))
+        } else if let Ok(flag) = value.parse() {
+            Value::Bool(flag)
+        } else if let Ok(number) = value.parse() {
+            Value::Integer(number)
+        } else {
+            return Err(line_error(path, number, format!("invalid value {}", value)));
+        };
+        table.insert(key.trim().to_string(), value);
+    }
+    Ok(tables)
+}
+
+/// The line without a `#` comment, unless the `#` is quoted.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, character) in line.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn unquote(value: &str) -> Option<&str> {
+    value
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|_| value.len() >= 2)
+}
+
+fn config_error(path: &Path, message: String) -> FtpError {
+    FtpError::InvalidArgument(format!("{}: {}", path.display(), message))
+}
+
+fn line_error(path: &Path, line: usize, message: String) -> FtpError {
+    FtpError::InvalidArgument(format!("{}:{}: {}", path.display(), line, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+# shared by the team
+[prod]
+host = "ftp.example.com"  # the mirror
+port = 21
+user = "deploy"
+password = "say \"hi\" # not a comment"
+tls = true
+passive = false
+dir = "/releases"
+
+["staging box"]
+host = "10.0.0.7"
+"#;
+
+    /// Write `text` to a configuration file named after `name`.
+    fn config(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "simpleftp-profile-{}-{}.toml",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_tables_values_and_comments() {
+        let tables = parse(Path::new("config.toml"), CONFIG).unwrap();
+        assert_eq!(tables.len(), 2);
+        let prod = &tables["prod"];
+        assert!(matches!(&prod["host"], Value::String(host) if host == "ftp.example.com"));
+        assert!(matches!(prod["port"], Value::Integer(21)));
+        assert!(matches!(prod["tls"], Value::Bool(true)));
+        assert!(
+            matches!(&prod["password"], Value::String(password) if password == "say \"hi\" # not a comment")
+        );
+        assert!(tables["staging box"].contains_key("host"));
+    }
+
+    #[test]
+    fn reports_the_faulty_line() {
+        let path = Path::new("config.toml");
+        for (text, line) in [
+            ("host = \"a\"", 1),
+            ("[prod]\nhost", 2),
+            ("[prod]\n\nport = 21x", 3),
+        ] {
+            let Err(FtpError::InvalidArgument(message)) = parse(path, text) else {
+                panic!("{:?} parsed", text);
+            };
+            assert!(
+                message.starts_with(&format!("config.toml:{}:", line)),
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn loads_a_profile() {
+        let path = config("load", CONFIG);
+        let profile = load(&path, "prod").unwrap();
+        assert_eq!(profile.host.as_deref(), Some("ftp.example.com"));
+        assert_eq!(profile.port, Some(21));
+        assert_eq!(profile.user.as_deref(), Some("deploy"));
+        assert_eq!(profile.tls, Some(true));
+        assert_eq!(profile.passive, Some(false));
+        assert_eq!(profile.dir.as_deref(), Some("/releases"));
+        let staging = load(&path, "staging box").unwrap();
+        assert_eq!(staging.host.as_deref(), Some("10.0.0.7"));
+        assert_eq!(staging.port, None);
+        assert!(load(&path, "test").is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn refuses_invalid_settings() {
+        for (name, text) in [
+            ("range", "[prod]\nport = 70000"),
+            ("type", "[prod]\ntls = \"yes\""),
+            ("unknown", "[prod]\nmode = \"active\""),
+        ] {
+            let path = config(name, text);
+            assert!(matches!(
+                load(&path, "prod"),
+                Err(FtpError::InvalidArgument(_))
+            ));
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Progress bars and transfer summaries of the command line client.
+
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Write;
+use std::time::Duration;
+use std::time::Instant;
+
+use simpleftp::Result;
+
+use super::json;
+
+/// Width of the bar itself, in characters.
+const BAR_WIDTH: usize = 24;
+/// Shortest time between two redraws.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Progress of a file transfer, drawn on the standard error when it's a
+/// terminal.
+pub(crate) struct Progress {
+    name: String,
+    total: Option<u64>,
+    bytes: u64,
+    started: Instant,
+    drawn: Option<Instant>,
+    visible: bool,
+}
+
+impl Progress {
+    pub(crate) fn new(name: &str, total: Option<u64>, quiet: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            total,
+            bytes: 0,
+            started: Instant::now(),
+            drawn: None,
+            visible: !quiet && std::io::stderr().is_terminal(),
+        }
+    }
+
+    /// Wrap the source or destination of the transfer to follow its progress.
+    pub(crate) fn track<T>(&mut self, inner: T) -> Tracked<'_, T> {
+        Tracked {
+            inner,
+            progress: self,
+        }
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        if self
+            .drawn
+            .is_none_or(|drawn| drawn.elapsed() >= REDRAW_INTERVAL)
+        {
+            self.draw("");
+        }
+    }
+
+    /// Draw the final state of the bar and move to the next line.
+    pub(crate) fn finish(&mut self, ok: bool) {
+        if ok {
+            self.total = Some(self.bytes);
+        }
+        self.draw(if ok { "\n" } else { " failed\n" });
+    }
+
+    fn draw(&mut self, end: &str) {
+        if !self.visible {
+            return;
+        }
+        self.drawn = Some(Instant::now());
+        let speed = self.bytes as f64 / self.started.elapsed().as_secs_f64().max(0.001);
+        let (bar, percent, eta) = match self.total {
+            Some(total) if total > 0 => {
+                let ratio = (self.bytes as f64 / total as f64).min(1.0);
+                let filled = (ratio * BAR_WIDTH as f64) as usize;
+                let eta = if speed > 0.0 {
+                    format_duration(Duration::from_secs_f64(
+                        total.saturating_sub(self.bytes) as f64 / speed,
+                    ))
+                } else {
+                    "--:--".into()
+                };
+                (
+                    format!("{}{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled)),
+                    format!("{:3.0}%", ratio * 100.0),
+                    eta,
+                )
+            }
+            _ => ("?".repeat(BAR_WIDTH), "   ?".into(), "--:--".into()),
+        };
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r{:<24.24} [{}] {} {:>10} {:>10}/s ETA {}{}",
+            self.name,
+            bar,
+            percent,
+            format_bytes(self.bytes),
+            format_bytes(speed as u64),
+            eta,
+            end
+        );
+        let _ = stderr.flush();
+    }
+}
+
+/// A reader or writer counting the bytes of a transfer.
+pub(crate) struct Tracked<'a, T> {
+    inner: T,
+    progress: &'a mut Progress,
+}
+
+impl<T: Read> Read for Tracked<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.advance(read);
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for Tracked<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.progress.advance(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Outcome of a transfer, a row of the summary.
+struct Row {
+    name: String,
+    bytes: u64,
+    elapsed: Duration,
+    error: Option<String>,
+}
+
+/// The transfers of a command, printed as a table once done.
+#[derive(Default)]
+pub(crate) struct Summary {
+    rows: Vec<Row>,
+}
+
+impl Summary {
+    pub(crate) fn record(&mut self, progress: &Progress, result: Result<()>) {
+        self.rows.push(Row {
+            name: progress.name.clone(),
+            bytes: progress.bytes,
+            elapsed: progress.started.elapsed(),
+            error: result.err().map(|error| error.to_string()),
+        });
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub(crate) fn failures(&self) -> usize {
+        self.rows.iter().filter(|row| row.error.is_some()).count()
+    }
+
+    /// Print the transfers as a JSON object on the standard output.
+    pub(crate) fn print_json(&self) {
+        let transfers = self.rows.iter().map(|row| {
+            json::Object::new()
+                .string("name", &row.name)
+                .raw("bytes", json::integer(Some(row.bytes)))
+                .number("seconds", row.elapsed.as_secs_f64())
+                .bool("ok", row.error.is_none())
+                .raw("error", row.error.as_deref().map(json::string))
+                .finish()
+        });
+        let bytes: u64 = self.rows.iter().map(|row| row.bytes).sum();
+        let summary = json::Object::new()
+            .raw("transfers", Some(json::array(transfers)))
+            .raw("files", json::integer(Some(self.rows.len() as u64)))
+            .raw("bytes", json::integer(Some(bytes)))
+            .raw("failed", json::integer(Some(self.failures() as u64)))
+            .finish();
+        println!("{}", summary);
+    }
+
+    /// Print the table on the standard error, keeping the output for data.
+    pub(crate) fn print(&self) {
+        let width = self
+            .rows
+            .iter()
+            .map(|row| row.name.chars().count())
+            .chain([4])
+            .max()
+            .unwrap_or(4);
+        eprintln!(
+            "{:<width$}  {:>10}  {:>8}  Status",
+            "File",
+            "Bytes",
+            "Time",
+            width = width
+        );
+        for row in &self.rows {
+            eprintln!(
+                "{:<width$}  {:>10}  {:>8}  {}",
+                row.name,
+                format_bytes(row.bytes),
+                format!("{:.1}s", row.elapsed.as_secs_f64()),
+                row.error.as_deref().unwrap_or("ok"),
+                width = width
+            );
+        }
+        let bytes: u64 = self.rows.iter().map(|row| row.bytes).sum();
+        eprintln!(
+            "{} files, {}, {} failed",
+            self.rows.len(),
+            format_bytes(bytes),
+            self.failures()
+        );
+    }
+}
+
+/// A size in binary units, e.g. `1.5 MiB`.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
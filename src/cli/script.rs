@@ -0,0 +1,170 @@
+//! Batch scripts: a file of commands run one after the other, like the input
+//! of `ftp -n`.
+//!
+//! ```text
+//! # nightly upload
+//! open ftp.example.com
+//! login deploy "secret password"
+//! cd /incoming
+//! put build/release.zip
+//! close
+//! ```
+
+use std::path::Path;
+
+use simpleftp::FtpClient;
+use simpleftp::FtpError;
+use simpleftp::Result;
+
+use super::Options;
+use super::Summary;
+
+/// Run the script at `path`, stopping at the first failure unless
+/// `--keep-going` was given. Returns whether every command succeeded.
+pub(crate) fn run(path: &Path, options: &Options, summary: &mut Summary) -> Result<bool> {
+    let script = std::fs::read_to_string(path).map_err(|error| {
+        FtpError::InvalidArgument(format!("{}: can't be read: {}", path.display(), error))
+    })?;
+    let mut client = None;
+    let mut ok = true;
+    for (index, line) in script.lines().enumerate() {
+        let words = split(line).map_err(|message| line_error(path, index + 1, message))?;
+        let Some((command, arguments)) = words.split_first() else {
+            continue;
+        };
+        let failures = summary.failures();
+        let result = step(&mut client, command, arguments, options, summary);
+        let failed = match result {
+            Ok(()) => summary.failures() > failures,
+            Err(error) => {
+                eprintln!("{}:{}: {}: {}", path.display(), index + 1, command, error);
+                true
+            }
+        };
+        if failed {
+            ok = false;
+            if !options.keep_going {
+                break;
+            }
+        }
+    }
+    if let Some(mut client) = client {
+        let _ = client.logout();
+    }
+    Ok(ok)
+}
+
+/// Run a command of the script.
+fn step(
+    client: &mut Option<FtpClient>,
+    command: &str,
+    arguments: &[String],
+    options: &Options,
+    summary: &mut Summary,
+) -> Result<()> {
+    match (command, arguments) {
+        ("open", []) => *client = Some(options.connect()?),
+        ("open", [address]) => {
+            let address = if address.contains(':') {
+                address.clone()
+            } else {
+                format!("{}:21", address)
+            };
+            *client = Some(super::open(&address, options.tls)?);
+        }
+        ("lcd", [dir]) => std::env::set_current_dir(dir)?,
+        ("close" | "bye" | "quit", []) => {
+            if let Some(mut client) = client.take() {
+                client.logout()?;
+            }
+        }
+        _ => {
+            let client = client
+                .as_mut()
+                .ok_or_else(|| FtpError::InvalidArgument("Not connected, use open first".into()))?;
+            match (command, arguments) {
+                ("login" | "user", [user]) => {
+                    let password = options.password.as_deref().unwrap_or_default();
+                    client.login(user, password)?;
+                }
+                ("login" | "user", [user, password]) => {
+                    client.login(user, password)?;
+                }
+                ("cd", [dir]) => client.change_dir(dir)?,
+                ("mkdir", [dir]) => client.makedir(dir)?,
+                ("delete", [file]) => client.delete(file)?,
+                _ => {
+                    if !super::execute(client, command, arguments, options, summary)? {
+                        return Err(FtpError::InvalidArgument("Invalid command".into()));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The words of a script line, which may be double quoted, without the `#`
+/// comment ending it.
+fn split(line: &str) -> std::result::Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut characters = line.chars();
+    let mut word: Option<String> = None;
+    while let Some(character) = characters.next() {
+        match character {
+            '#' if word.is_none() => break,
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match characters.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(characters.next()),
+                        Some(character) => word.push(character),
+                        None => return Err("unterminated quote".into()),
+                    }
+                }
+            }
+            character if character.is_whitespace() => words.extend(word.take()),
+            character => word.get_or_insert_with(String::new).push(character),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+fn line_error(path: &Path, line: usize, message: String) -> FtpError {
+    FtpError::InvalidArgument(format!("{}:{}: {}", path.display(), line, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split;
+
+    #[test]
+    fn splits_words_and_quotes() {
+        let words = |line: &str| split(line).unwrap();
+        assert_eq!(
+            words("  put  build/release.zip\t/incoming "),
+            ["put", "build/release.zip", "/incoming"]
+        );
+        assert_eq!(
+            words("login deploy \"secret password\""),
+            ["login", "deploy", "secret password"]
+        );
+        assert_eq!(
+            words("get \"a \\\"quoted\\\" name\" x\"y z\""),
+            ["get", "a \"quoted\" name", "xy z"]
+        );
+        assert_eq!(words("cd \"\""), ["cd", ""]);
+        assert_eq!(words("cd \"#dir\" # comment"), ["cd", "#dir"]);
+        assert_eq!(words("get file#1"), ["get", "file#1"]);
+        assert!(words("# comment").is_empty());
+        assert!(words("").is_empty());
+    }
+
+    #[test]
+    fn refuses_unterminated_quotes() {
+        assert!(split("login deploy \"secret").is_err());
+        assert!(split("get \"name\\\"").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! Credentials kept out of memory dumps.
+
+use std::sync::atomic::compiler_fence;
+use std::sync::atomic::Ordering;
+
+use crate::Result;
+
+/// A string wiped from memory when dropped. Its `Debug` output is redacted.
+pub struct Secret(String);
+
+impl Secret {
+    /// Take ownership of `value`. Copies made before this call are not wiped.
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Create an empty secret able to hold `capacity` bytes without reallocating,
+    /// since a reallocation would leave an unwiped copy behind.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self(String::with_capacity(capacity))
+    }
+
+    pub(crate) fn push_str(&mut self, value: &str) {
+        debug_assert!(self.0.capacity() - self.0.len() >= value.len());
+        self.0.push_str(value);
+    }
+
+    /// Access the secret value.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // SAFETY: zero bytes are valid UTF-8, the string stays well formed.
+        wipe(unsafe { self.0.as_mut_vec() });
+    }
+}
+
+/// Overwrite `bytes` with zeroes the compiler can't optimize away.
+pub(crate) fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        // SAFETY: `byte` is a valid, aligned reference.
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret(****)")
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+/// Login information. Password and account are wiped once dropped.
+#[derive(Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret,
+    pub account: Option<Secret>,
+}
+
+impl Credentials {
+    /// Credentials without account information.
+    pub fn new(username: impl Into<String>, password: impl Into<Secret>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+            account: None,
+        }
+    }
+
+    /// Also send `account` with ACCT after the password.
+    pub fn with_account(mut self, account: impl Into<Secret>) -> Self {
+        self.account = Some(account.into());
+        self
+    }
+}
+
+/// Source of credentials, asked again whenever the client needs to log in,
+/// so they don't have to stay in memory between logins.
+///
+/// Closures returning [`Credentials`] implement this trait.
+pub trait CredentialProvider: Send {
+    fn credentials(&self) -> Result<Credentials>;
+}
+
+impl<F> CredentialProvider for F
+where
+    F: Fn() -> Result<Credentials> + Send,
+{
+    fn credentials(&self) -> Result<Credentials> {
+        self()
+    }
+}
@@ -0,0 +1,312 @@
+//! Character encodings of paths and listings, for servers predating the RFC 2640
+//! `UTF8` feature which send file names in a legacy code page.
+
+use std::borrow::Cow;
+
+use crate::credentials::wipe;
+use crate::FtpError;
+use crate::Result;
+
+/// Telnet "interpret as command" byte, doubled when it occurs in a command line.
+const IAC: u8 = 0xFF;
+
+/// Character set of the file names exchanged with the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// UTF-8 (RFC 2640), the default.
+    #[default]
+    Utf8,
+    /// ISO-8859-1, common on older Unix and Windows servers in Western Europe.
+    Latin1,
+    /// Shift-JIS, used by Japanese servers. Requires the `encoding` feature.
+    #[cfg(feature = "encoding")]
+    ShiftJis,
+}
+
+/// What to do with text the [`Encoding`] can't represent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncodingMode {
+    /// Replace unencodable characters with `?` and undecodable bytes with U+FFFD.
+    #[default]
+    Lossy,
+    /// Fail with an error rather than alter a name.
+    Strict,
+}
+
+/// What to do with listing lines the [`Encoding`] can't decode, e.g. names
+/// written by a client using another code page.
+///
+/// Unless set on the builder, listings follow the [`EncodingMode`]: lossy
+/// decoding in [`EncodingMode::Lossy`], an error in [`EncodingMode::Strict`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodePolicy {
+    /// Leave the line out of the listing.
+    SkipInvalid,
+    /// Keep the line with undecodable bytes replaced by U+FFFD.
+    LossyDecode,
+    /// Fail the whole listing.
+    Error,
+}
+
+/// Converts between strings and the bytes on the control and listing connections.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Codec {
+    pub(crate) encoding: Encoding,
+    pub(crate) mode: EncodingMode,
+}
+
+impl Codec {
+    /// Bytes of a command line, with Telnet IAC bytes doubled as RFC 959 asks.
+    /// The line may hold a password, so intermediate copies are wiped and
+    /// buffers are sized up front rather than reallocated.
+    ///
+    /// # Errors
+    /// In strict mode, when `line` holds characters the encoding lacks.
+    pub(crate) fn encode<'a>(&self, line: &'a str) -> Result<Cow<'a, [u8]>> {
+        if self.encoding == Encoding::Utf8 || line.is_ascii() {
+            // IAC never occurs in UTF-8
+            return Ok(Cow::Borrowed(line.as_bytes()));
+        }
+        let mut bytes = Vec::new();
+        let encoded = match self.encoding {
+            Encoding::Utf8 => unreachable!("handled above"),
+            Encoding::Latin1 => self.encode_latin1(line, &mut bytes),
+            #[cfg(feature = "encoding")]
+            Encoding::ShiftJis => self.encode_with(encoding_rs::SHIFT_JIS, line, &mut bytes),
+        };
+        if let Err(error) = encoded {
+            wipe(&mut bytes);
+            return Err(error);
+        }
+        let doubled = bytes.iter().filter(|byte| **byte == IAC).count();
+        if doubled == 0 {
+            return Ok(Cow::Owned(bytes));
+        }
+        let mut escaped = Vec::with_capacity(bytes.len() + doubled);
+        for byte in &bytes {
+            escaped.push(*byte);
+            if *byte == IAC {
+                escaped.push(IAC);
+            }
+        }
+        wipe(&mut bytes);
+        Ok(Cow::Owned(escaped))
+    }
+
+    fn encode_latin1(&self, line: &str, bytes: &mut Vec<u8>) -> Result<()> {
+        // never more characters than UTF-8 bytes
+        bytes.reserve_exact(line.len());
+        for c in line.chars() {
+            let byte = match u8::try_from(c) {
+                Ok(byte) => byte,
+                Err(_) => self.unencodable(c, line)?,
+            };
+            bytes.push(byte);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "encoding")]
+    fn encode_with(
+        &self,
+        encoding: &'static encoding_rs::Encoding,
+        line: &str,
+        bytes: &mut Vec<u8>,
+    ) -> Result<()> {
+        use encoding_rs::EncoderResult;
+
+        let mut encoder = encoding.new_encoder();
+        let needed = encoder
+            .max_buffer_length_from_utf8_without_replacement(line.len())
+            .unwrap_or(line.len() * 2);
+        bytes.reserve_exact(needed);
+        let mut input = line;
+        loop {
+            let (result, read) =
+                encoder.encode_from_utf8_to_vec_without_replacement(input, bytes, true);
+            input = &input[read..];
+            match result {
+                EncoderResult::InputEmpty => return Ok(()),
+                EncoderResult::OutputFull => grow(bytes, input.len() * 2 + 16),
+                EncoderResult::Unmappable(c) => {
+                    let byte = self.unencodable(c, line)?;
+                    if bytes.len() == bytes.capacity() {
+                        grow(bytes, input.len() * 2 + 16);
+                    }
+                    bytes.push(byte);
+                }
+            }
+        }
+    }
+
+    /// The byte standing for `c`, which the encoding lacks, in lossy mode.
+    fn unencodable(&self, c: char, line: &str) -> Result<u8> {
+        match self.mode {
+            EncodingMode::Lossy => Ok(b'?'),
+            EncodingMode::Strict => Err(FtpError::InvalidArgument(format!(
+                "{:?} can't be encoded in {:?}: {}",
+                c,
+                self.encoding,
+                line.escape_debug()
+            ))),
+        }
+    }
+
+    /// Text of a reply or listing line.
+    ///
+    /// # Errors
+    /// In strict mode, when `bytes` aren't valid in the encoding.
+    pub(crate) fn decode<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, str>> {
+        let (text, valid) = self.try_decode(bytes);
+        if !valid && self.mode == EncodingMode::Strict {
+            return Err(self.undecodable(bytes));
+        }
+        Ok(text)
+    }
+
+    /// Text of a listing line, `None` when `policy` skips it.
+    ///
+    /// # Errors
+    /// With [`DecodePolicy::Error`], when `bytes` aren't valid in the encoding.
+    pub(crate) fn decode_listing<'a>(
+        &self,
+        bytes: &'a [u8],
+        policy: Option<DecodePolicy>,
+    ) -> Result<Option<Cow<'a, str>>> {
+        let (text, valid) = self.try_decode(bytes);
+        if valid {
+            return Ok(Some(text));
+        }
+        let policy = policy.unwrap_or(match self.mode {
+            EncodingMode::Lossy => DecodePolicy::LossyDecode,
+            EncodingMode::Strict => DecodePolicy::Error,
+        });
+        match policy {
+            DecodePolicy::SkipInvalid => Ok(None),
+            DecodePolicy::LossyDecode => Ok(Some(text)),
+            DecodePolicy::Error => Err(self.undecodable(bytes)),
+        }
+    }
+
+    /// Text of `bytes` with undecodable bytes replaced, and whether there were
+    /// none.
+    fn try_decode<'a>(&self, bytes: &'a [u8]) -> (Cow<'a, str>, bool) {
+        match self.encoding {
+            Encoding::Utf8 => match std::str::from_utf8(bytes) {
+                Ok(text) => (Cow::Borrowed(text), true),
+                Err(_) => (String::from_utf8_lossy(bytes), false),
+            },
+            Encoding::Latin1 => (
+                Cow::Owned(bytes.iter().map(|&b| char::from(b)).collect()),
+                true,
+            ),
+            #[cfg(feature = "encoding")]
+            Encoding::ShiftJis => {
+                let (text, errors) = encoding_rs::SHIFT_JIS.decode_without_bom_handling(bytes);
+                (text, !errors)
+            }
+        }
+    }
+
+    fn undecodable(&self, bytes: &[u8]) -> FtpError {
+        FtpError::ResponseError(format!(
+            "Response is not valid {:?}: {}",
+            self.encoding,
+            String::from_utf8_lossy(bytes).escape_debug()
+        ))
+    }
+}
+
+/// Make room for `additional` more bytes without leaving a copy of `bytes`
+/// behind in the old allocation.
+#[cfg(feature = "encoding")]
+fn grow(bytes: &mut Vec<u8>, additional: usize) {
+    let mut larger = Vec::with_capacity(bytes.len() + additional);
+    larger.extend_from_slice(bytes);
+    wipe(bytes);
+    *bytes = larger;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::FtpError;
+
+    #[test]
+    fn filename_encodings() {
+        use super::Codec;
+        use super::{Encoding, EncodingMode};
+
+        let latin1 = Codec {
+            encoding: Encoding::Latin1,
+            mode: EncodingMode::Lossy,
+        };
+        assert_eq!(&*latin1.encode("CWD café").unwrap(), b"CWD caf\xe9");
+        assert_eq!(&*latin1.encode("CWD ÿ").unwrap(), b"CWD \xff\xff");
+        assert_eq!(&*latin1.encode("CWD 日本").unwrap(), b"CWD ??");
+        assert_eq!(latin1.decode(b"caf\xe9").unwrap(), "café");
+        let strict = Codec {
+            mode: EncodingMode::Strict,
+            ..latin1
+        };
+        assert!(matches!(
+            strict.encode("CWD 日本"),
+            Err(FtpError::InvalidArgument(_))
+        ));
+        let utf8 = Codec {
+            encoding: Encoding::Utf8,
+            ..strict
+        };
+        assert!(utf8.decode(b"caf\xe9").is_err());
+        let utf8 = Codec {
+            mode: EncodingMode::Lossy,
+            ..utf8
+        };
+        assert_eq!(utf8.decode(b"caf\xe9").unwrap(), "caf\u{fffd}");
+        #[cfg(feature = "encoding")]
+        {
+            let shift_jis = Codec {
+                encoding: Encoding::ShiftJis,
+                mode: EncodingMode::Strict,
+            };
+            let encoded = shift_jis.encode("日本.txt").unwrap();
+            assert_eq!(&*encoded, b"\x93\xfa\x96\x7b.txt");
+            assert_eq!(shift_jis.decode(&encoded).unwrap(), "日本.txt");
+            let lossy = Codec {
+                mode: EncodingMode::Lossy,
+                ..shift_jis
+            };
+            assert_eq!(&*lossy.encode("CWD é日").unwrap(), b"CWD ?\x93\xfa");
+        }
+    }
+
+    #[test]
+    fn listing_decode_policies() {
+        use super::Codec;
+        use super::{DecodePolicy, EncodingMode};
+
+        let lossy = Codec::default();
+        let line = b"-rw-r--r-- 1 ftp ftp 4 Jan 1 2024 caf\xe9.txt";
+        let decoded = lossy.decode_listing(line, None).unwrap().unwrap();
+        assert!(decoded.ends_with("caf\u{fffd}.txt"));
+        assert!(lossy
+            .decode_listing(line, Some(DecodePolicy::SkipInvalid))
+            .unwrap()
+            .is_none());
+        assert!(lossy
+            .decode_listing(line, Some(DecodePolicy::Error))
+            .is_err());
+        let strict = Codec {
+            mode: EncodingMode::Strict,
+            ..lossy
+        };
+        assert!(strict.decode_listing(line, None).is_err());
+        let kept = strict
+            .decode_listing(line, Some(DecodePolicy::LossyDecode))
+            .unwrap();
+        assert!(kept.is_some());
+        assert_eq!(
+            strict.decode_listing(b"ok.txt", None).unwrap().unwrap(),
+            "ok.txt"
+        );
+    }
+}
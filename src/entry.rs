@@ -0,0 +1,1058 @@
+//! Structured directory entries parsed from `LIST` and `MLSD` listings.
+
+use std::fmt;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::status::reply_lines;
+use crate::time;
+use crate::Command;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::Result;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// How far ahead of the current time a `LIST` date without a year may be before
+/// it is taken as last year's, to absorb clock skew and time zones.
+const FUTURE_SLACK: Duration = Duration::from_secs(86_400);
+
+/// Number of listing lines [`ListStyle::detect`] tries each style on.
+const DETECT_SAMPLE: usize = 20;
+
+/// Settings for reading the dates of `LIST` lines, which have no standard format.
+///
+/// Unix listings show `Mmm dd HH:MM` for files changed within about six months
+/// and `Mmm dd YYYY` for older ones. A date without a year is taken in the
+/// current year, or in the previous one when that would put it in the future.
+/// Some servers localize the month names, extra tables can be added next to
+/// the English one. Listed times are read as UTC, the server time zone is unknown.
+///
+/// # Example
+/// ```
+/// use simpleftp::{FtpEntry, ListFormat};
+///
+/// let french = ListFormat::new().months([
+///     "janv", "févr", "mars", "avr", "mai", "juin", "juil", "août", "sept", "oct", "nov", "déc",
+/// ]);
+/// let entry = FtpEntry::parse_list_with("-rw-r--r-- 1 ftp ftp 12 févr. 3 2021 a.txt", &french);
+/// assert!(entry.unwrap().modified().is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ListFormat {
+    /// Month name tables, lowercase and without trailing dots.
+    months: Vec<[String; 12]>,
+    now: Option<SystemTime>,
+}
+
+impl Default for ListFormat {
+    fn default() -> Self {
+        Self {
+            months: vec![MONTHS.map(String::from)],
+            now: None,
+        }
+    }
+}
+
+impl ListFormat {
+    /// English month names, years guessed from the system clock.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also accept the month names of `names`, January first. Names are
+    /// matched ignoring case and trailing dots.
+    pub fn months(mut self, names: [&str; 12]) -> Self {
+        self.months.push(names.map(month_key));
+        self
+    }
+
+    /// Guess the year of recent dates relative to `now` rather than the system
+    /// clock, e.g. when it is off or to read old listings.
+    pub fn now(mut self, now: SystemTime) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// Month number, 1 to 12, of a month name.
+    fn month(&self, token: &str) -> Option<u32> {
+        let token = month_key(token);
+        self.months
+            .iter()
+            .find_map(|table| table.iter().position(|name| *name == token))
+            .map(|index| index as u32 + 1)
+    }
+
+    /// Time of a Unix listing date: day and either `HH:MM` or a year.
+    fn unix_date(&self, month: u32, day: &str, time_or_year: &str) -> Option<SystemTime> {
+        let day = day.parse().ok()?;
+        let Some((hour, minute)) = time_or_year.split_once(':') else {
+            return time::from_utc(time_or_year.parse().ok()?, month, day, 0, 0, 0);
+        };
+        let (hour, minute) = (hour.parse().ok()?, minute.parse().ok()?);
+        let now = self.now.unwrap_or_else(SystemTime::now);
+        let year = time::to_utc(now).0;
+        let date = time::from_utc(year, month, day, hour, minute, 0)?;
+        if date > now + FUTURE_SLACK {
+            time::from_utc(year - 1, month, day, hour, minute, 0)
+        } else {
+            Some(date)
+        }
+    }
+}
+
+fn month_key(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// Time of a DOS listing date, `MM-DD-YY` or `YYYY-MM-DD`, and time, `HH:MM`
+/// optionally followed by `AM` or `PM`.
+fn dos_date(date: &str, clock: &str) -> Option<SystemTime> {
+    let fields: Vec<&str> = date.split(['-', '/']).collect();
+    let [first, second, third] = fields[..] else {
+        return None;
+    };
+    let (year, month, day) = if first.len() == 4 {
+        (first, second, third)
+    } else {
+        (third, first, second)
+    };
+    let two_digits = year.len() == 2;
+    let year: i64 = year.parse().ok()?;
+    let year = match year {
+        0..=69 if two_digits => year + 2000,
+        70..=99 if two_digits => year + 1900,
+        _ => year,
+    };
+    let upper = clock.to_ascii_uppercase();
+    let (clock, afternoon) = match upper.strip_suffix("PM") {
+        Some(clock) => (clock, Some(true)),
+        None => match upper.strip_suffix("AM") {
+            Some(clock) => (clock, Some(false)),
+            None => (upper.as_str(), None),
+        },
+    };
+    let (hour, minute) = clock.split_once(':')?;
+    let (hour, minute): (u32, u32) = (hour.parse().ok()?, minute.parse().ok()?);
+    let hour = match afternoon {
+        Some(true) if hour < 12 => hour + 12,
+        Some(false) if hour == 12 => 0,
+        _ => hour,
+    };
+    time::from_utc(
+        year,
+        month.parse().ok()?,
+        day.parse().ok()?,
+        hour,
+        minute,
+        0,
+    )
+}
+
+/// Time of a VMS listing date, `DD-MMM-YYYY`, and time, `HH:MM` optionally
+/// followed by seconds and hundredths.
+fn vms_date(date: &str, clock: &str, format: &ListFormat) -> Option<SystemTime> {
+    let mut fields = date.split('-');
+    let (day, month, year) = (fields.next()?, fields.next()?, fields.next()?);
+    let mut clock = clock.split(['.', ':']);
+    let (hour, minute) = (clock.next()?.parse().ok()?, clock.next()?.parse().ok()?);
+    let second = clock.next().map_or(Some(0), |second| second.parse().ok())?;
+    time::from_utc(
+        year.parse().ok()?,
+        format.month(month)?,
+        day.parse().ok()?,
+        hour,
+        minute,
+        second,
+    )
+}
+
+/// Unix permissions of a VMS protection, `(system,owner,group,world)` with
+/// `R`, `W`, `E` and `D` rights. Delete has no Unix equivalent.
+fn vms_protection(protection: &str) -> Option<FtpPermissions> {
+    let classes: Vec<&str> = protection
+        .strip_prefix('(')?
+        .strip_suffix(')')?
+        .split(',')
+        .collect();
+    let [_, owner, group, world] = classes[..] else {
+        return None;
+    };
+    let bits = |rights: &str| {
+        rights.chars().fold(0, |bits, right| match right {
+            'R' => bits | 4,
+            'W' => bits | 2,
+            'E' => bits | 1,
+            _ => bits,
+        })
+    };
+    Some(FtpPermissions::from_mode(
+        bits(owner) << 6 | bits(group) << 3 | bits(world),
+    ))
+}
+
+/// Time of an MVS listing date, `YYYY/MM/DD`, and time, `HH:MM` or `HH:MM:SS`.
+fn mvs_date(date: &str, clock: &str) -> Option<SystemTime> {
+    let mut fields = date.split('/');
+    let (year, month, day) = (fields.next()?, fields.next()?, fields.next()?);
+    let mut clock = clock.split(':');
+    let (hour, minute) = (clock.next()?.parse().ok()?, clock.next()?.parse().ok()?);
+    let second = clock.next().map_or(Some(0), |second| second.parse().ok())?;
+    time::from_utc(
+        year.parse().ok()?,
+        month.parse().ok()?,
+        day.parse().ok()?,
+        hour,
+        minute,
+        second,
+    )
+}
+
+/// Whether `name` is a member name of a partitioned data set: up to 8
+/// letters, digits or national characters, not starting with a digit.
+fn is_member_name(name: &str) -> bool {
+    let national = |b: u8| matches!(b, b'#' | b'@' | b'$');
+    (1..=8).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || national(b))
+        && !name.as_bytes()[0].is_ascii_digit()
+}
+
+/// Layout of `LIST` lines, which depends on the operating system of the
+/// server.
+///
+/// # Example
+/// ```
+/// use simpleftp::{FtpEntry, ListFormat, ListStyle};
+///
+/// let style = ListStyle::from_system("VMS system type.").unwrap();
+/// let line = "LOGIN.COM;2   1/3   10-JAN-2024 09:15:42  [SMITH]  (RWED,RWED,RE,)";
+/// let entry = FtpEntry::parse_list_as(line, style, &ListFormat::new()).unwrap();
+/// assert_eq!(entry.name(), "LOGIN.COM");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ListStyle {
+    /// `ls -l` lines.
+    Unix,
+    /// DOS and IIS lines: `MM-DD-YY HH:MMPM <DIR> name` or a size for files.
+    Dos,
+    /// OpenVMS lines: `NAME.EXT;version blocks date time [owner] (protection)`.
+    Vms,
+    /// z/OS and MVS lines, listing data sets or the members of a partitioned
+    /// data set.
+    Mvs,
+    /// `MLSD` lines, which a few servers also send for `LIST`.
+    Mlsd,
+}
+
+impl ListStyle {
+    /// Style of the listings of a server from its `SYST` reply, `None` when
+    /// it doesn't tell. Windows servers are left out, IIS can list either way.
+    pub fn from_system(system: &str) -> Option<Self> {
+        let name = system.split_whitespace().next()?.to_ascii_uppercase();
+        match name.as_str() {
+            "UNIX" => Some(ListStyle::Unix),
+            "VMS" | "OPENVMS" => Some(ListStyle::Vms),
+            "MVS" | "OS/390" | "Z/OS" => Some(ListStyle::Mvs),
+            _ => None,
+        }
+    }
+
+    /// Style parsing the most of the first lines of a listing, `None` when
+    /// none parse. For servers whose `SYST` reply doesn't tell.
+    ///
+    /// # Example
+    /// ```
+    /// use simpleftp::{ListFormat, ListStyle};
+    ///
+    /// let lines = ["01-15-20  03:04PM       <DIR>          docs"];
+    /// assert_eq!(ListStyle::detect(&lines, &ListFormat::new()), Some(ListStyle::Dos));
+    /// ```
+    pub fn detect<S: AsRef<str>>(lines: &[S], format: &ListFormat) -> Option<Self> {
+        let sample: Vec<&str> = lines
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|line| !line.trim().is_empty())
+            .take(DETECT_SAMPLE)
+            .collect();
+        // on a tie the earlier, stricter style wins: MVS takes any bare name
+        [
+            ListStyle::Mlsd,
+            ListStyle::Unix,
+            ListStyle::Dos,
+            ListStyle::Vms,
+            ListStyle::Mvs,
+        ]
+        .into_iter()
+        .map(|style| {
+            let parsed = sample
+                .iter()
+                .filter(|line| FtpEntry::parse_list_as(line, style, format).is_some())
+                .count();
+            (style, parsed)
+        })
+        .filter(|&(_, parsed)| parsed > 0)
+        .rev()
+        .max_by_key(|&(_, parsed)| parsed)
+        .map(|(style, _)| style)
+    }
+}
+
+/// Unix permission bits of a remote file.
+///
+/// # Example
+/// ```
+/// use simpleftp::FtpPermissions;
+///
+/// let permissions = FtpPermissions::parse("rwxr-x---").unwrap();
+/// assert_eq!(permissions.octal(), "750");
+/// assert!(permissions.is_writable());
+/// assert_eq!(FtpPermissions::parse("0640"), Some(FtpPermissions::from_mode(0o640)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FtpPermissions(u32);
+
+impl FtpPermissions {
+    /// Permissions from mode bits, file type bits are ignored.
+    pub fn from_mode(mode: u32) -> Self {
+        Self(mode & 0o7777)
+    }
+
+    /// Parse a symbolic mode as in `LIST` (`rwxr-xr-x`, optionally preceded by the
+    /// file type character) or an octal mode as in the MLSD `UNIX.mode` fact (`0755`).
+    pub fn parse(text: &str) -> Option<Self> {
+        if !text.is_empty() && text.len() <= 4 && text.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+            return u32::from_str_radix(text, 8).ok().map(Self::from_mode);
+        }
+        let symbols = text.as_bytes();
+        let symbols = match symbols.len() {
+            9 => symbols,
+            10 => &symbols[1..],
+            _ => return None,
+        };
+        let mut mode = 0;
+        for (index, symbol) in symbols.iter().enumerate() {
+            let bit = 1 << (8 - index);
+            let special = match index {
+                2 => 0o4000,
+                5 => 0o2000,
+                _ => 0o1000,
+            };
+            mode |= match (index % 3, symbol) {
+                (_, b'-') => 0,
+                (0, b'r') | (1, b'w') | (2, b'x') => bit,
+                (2, b's') if index < 8 => bit | special,
+                (2, b'S') if index < 8 => special,
+                (2, b't') if index == 8 => bit | special,
+                (2, b'T') if index == 8 => special,
+                _ => return None,
+            };
+        }
+        Some(Self(mode))
+    }
+
+    /// Mode bits, including setuid, setgid and sticky bits.
+    pub fn mode(&self) -> u32 {
+        self.0
+    }
+
+    /// Mode in octal as expected by `SITE CHMOD`, e.g. `644`.
+    pub fn octal(&self) -> String {
+        format!("{:03o}", self.0)
+    }
+
+    /// Whether the owner may read the file.
+    pub fn is_readable(&self) -> bool {
+        self.0 & 0o400 != 0
+    }
+
+    /// Whether the owner may write the file.
+    pub fn is_writable(&self) -> bool {
+        self.0 & 0o200 != 0
+    }
+
+    /// Whether the owner may execute the file or enter the directory.
+    pub fn is_executable(&self) -> bool {
+        self.0 & 0o100 != 0
+    }
+}
+
+impl fmt::Display for FtpPermissions {
+    /// Symbolic form, `rwxr-xr-x`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, symbol) in "rwxrwxrwx".chars().enumerate() {
+            let set = self.0 & (1 << (8 - index)) != 0;
+            let special = match index {
+                2 => self.0 & 0o4000 != 0,
+                5 => self.0 & 0o2000 != 0,
+                8 => self.0 & 0o1000 != 0,
+                _ => false,
+            };
+            let symbol = match (special, set, index) {
+                (true, true, 8) => 't',
+                (true, false, 8) => 'T',
+                (true, true, _) => 's',
+                (true, false, _) => 'S',
+                (false, true, _) => symbol,
+                (false, false, _) => '-',
+            };
+            write!(f, "{}", symbol)?;
+        }
+        Ok(())
+    }
+}
+
+/// Kind of a directory entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EntryKind {
+    File,
+    Directory,
+    /// Symbolic link, see [`FtpEntry::link_target`].
+    Symlink,
+    /// Devices, sockets and other special files.
+    Other,
+}
+
+/// A file or directory from a structured listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FtpEntry {
+    name: String,
+    kind: EntryKind,
+    size: Option<u64>,
+    permissions: Option<FtpPermissions>,
+    target: Option<String>,
+    modified: Option<SystemTime>,
+    facts: Vec<(String, String)>,
+}
+
+impl FtpEntry {
+    /// Parse a `LIST` line in Unix (`ls -l`) or DOS/IIS format.
+    /// Returns `None` for lines that don't describe a file, like `total 42`.
+    pub fn parse_list(line: &str) -> Option<Self> {
+        Self::parse_list_with(line, &ListFormat::default())
+    }
+
+    /// Parse a `LIST` line like [`FtpEntry::parse_list`], reading dates with
+    /// the month names and reference time of `format`.
+    pub fn parse_list_with(line: &str, format: &ListFormat) -> Option<Self> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let tokens = tokens(line);
+        Self::parse_unix(line, &tokens, format).or_else(|| Self::parse_dos(line, &tokens))
+    }
+
+    /// Parse a `LIST` line in `style`, reading dates with `format`.
+    pub fn parse_list_as(line: &str, style: ListStyle, format: &ListFormat) -> Option<Self> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        match style {
+            ListStyle::Unix => Self::parse_unix(line, &tokens(line), format),
+            ListStyle::Dos => Self::parse_dos(line, &tokens(line)),
+            ListStyle::Vms => Self::parse_vms(line, format),
+            ListStyle::Mvs => Self::parse_mvs(line),
+            ListStyle::Mlsd => Self::parse_mlsd(line),
+        }
+    }
+
+    fn parse_unix(line: &str, tokens: &[(usize, &str)], format: &ListFormat) -> Option<Self> {
+        let mode = tokens.first()?.1;
+        let kind = match mode.chars().next()? {
+            '-' => EntryKind::File,
+            'd' => EntryKind::Directory,
+            'l' => EntryKind::Symlink,
+            'b' | 'c' | 'p' | 's' => EntryKind::Other,
+            _ => return None,
+        };
+        // owner and group columns vary, the date is the anchor
+        let (month, number) = (3..tokens.len().saturating_sub(3)).find_map(|index| {
+            let number = format.month(tokens[index].1)?;
+            let date = tokens[index + 1].1.parse::<u8>().is_ok_and(|day| day <= 31)
+                && tokens[index + 2]
+                    .1
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || b == b':');
+            date.then_some((index, number))
+        })?;
+        let name = &line[tokens[month + 3].0..];
+        let (name, target) = match name.split_once(" -> ") {
+            Some((name, target)) if kind == EntryKind::Symlink => (name, Some(target.to_string())),
+            _ => (name, None),
+        };
+        Some(Self {
+            name: name.to_string(),
+            kind,
+            size: tokens[month - 1].1.parse().ok(),
+            permissions: mode.get(..10).and_then(FtpPermissions::parse),
+            target,
+            modified: format.unix_date(number, tokens[month + 1].1, tokens[month + 2].1),
+            facts: Vec::new(),
+        })
+    }
+
+    fn parse_dos(line: &str, tokens: &[(usize, &str)]) -> Option<Self> {
+        if tokens.len() < 4
+            || !tokens[0]
+                .1
+                .bytes()
+                .all(|b| b.is_ascii_digit() || b == b'-' || b == b'/')
+            || !tokens[1].1.contains(':')
+        {
+            return None;
+        }
+        let (kind, size) = match tokens[2].1 {
+            "<DIR>" => (EntryKind::Directory, None),
+            size => (EntryKind::File, Some(size.parse().ok()?)),
+        };
+        Some(Self {
+            name: line[tokens[3].0..].to_string(),
+            kind,
+            size,
+            permissions: None,
+            target: None,
+            modified: dos_date(tokens[0].1, tokens[1].1),
+            facts: Vec::new(),
+        })
+    }
+
+    /// Parse an OpenVMS line. The version is left out of the name, and `.DIR`
+    /// files are directories named without the extension. Sizes are counted
+    /// in 512 byte blocks, so they are rounded up.
+    fn parse_vms(line: &str, format: &ListFormat) -> Option<Self> {
+        let tokens = tokens(line);
+        let (name, version) = tokens.first()?.1.rsplit_once(';')?;
+        if name.is_empty() || version.is_empty() || !version.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let (kind, name) = match name.len().checked_sub(4) {
+            Some(stem) if name[stem..].eq_ignore_ascii_case(".DIR") => {
+                (EntryKind::Directory, &name[..stem])
+            }
+            _ => (EntryKind::File, name),
+        };
+        let blocks = tokens
+            .get(1)
+            .and_then(|(_, blocks)| blocks.split('/').next()?.parse::<u64>().ok());
+        let modified = match (tokens.get(2), tokens.get(3)) {
+            (Some((_, date)), Some((_, clock))) => vms_date(date, clock, format),
+            _ => None,
+        };
+        Some(Self {
+            name: name.to_string(),
+            kind,
+            size: blocks.map(|blocks| blocks * 512),
+            permissions: tokens.iter().find_map(|(_, token)| vms_protection(token)),
+            target: None,
+            modified,
+            facts: Vec::new(),
+        })
+    }
+
+    /// Parse an MVS line, of a data set (`volume unit referred ext used recfm
+    /// lrecl blksize dsorg name`, or `Migrated name`) or of a member of a
+    /// partitioned data set (`name vv.mm created changed time size init mod
+    /// id`, or the name alone). Partitioned data sets are directories. Data set
+    /// sizes are listed in tracks, not bytes, and left out.
+    fn parse_mvs(line: &str) -> Option<Self> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let is_version = |token: &str| {
+            token
+                .split_once('.')
+                .is_some_and(|(version, modification)| {
+                    [version, modification]
+                        .iter()
+                        .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+                })
+        };
+        let (name, kind, modified) = match tokens[..] {
+            ["Migrated", name] => (name, EntryKind::File, None),
+            ["Pseudo", "Directory", name] => (name, EntryKind::Directory, None),
+            [.., dsorg, name] if matches!(dsorg, "PS" | "PO" | "PO-E" | "VS" | "DA") => {
+                let kind = if dsorg.starts_with("PO") {
+                    EntryKind::Directory
+                } else {
+                    EntryKind::File
+                };
+                (name, kind, None)
+            }
+            [name] if is_member_name(name) => (name, EntryKind::File, None),
+            [name, version, _, changed, clock, ..]
+                if is_member_name(name) && is_version(version) =>
+            {
+                (name, EntryKind::File, mvs_date(changed, clock))
+            }
+            _ => return None,
+        };
+        Some(Self {
+            name: name.to_string(),
+            kind,
+            size: None,
+            permissions: None,
+            target: None,
+            modified,
+            facts: Vec::new(),
+        })
+    }
+
+    /// Parse an `MLSD` line: `fact=value;...; name`. Fact names are case-insensitive.
+    /// Returns `None` for lines without facts.
+    pub fn parse_mlsd(line: &str) -> Option<Self> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (facts, name) = line.split_once(' ')?;
+        let facts: Vec<(String, String)> = facts
+            .split(';')
+            .filter_map(|fact| fact.split_once('='))
+            .map(|(key, value)| (key.to_lowercase(), value.to_string()))
+            .collect();
+        if facts.is_empty() {
+            return None;
+        }
+        let mut entry = Self {
+            name: name.to_string(),
+            kind: EntryKind::Other,
+            size: None,
+            permissions: None,
+            target: None,
+            modified: None,
+            facts,
+        };
+        // links are `OS.unix=slink`, some servers append `:target`
+        let (kind, target) = match entry.fact("type").unwrap_or_default().split_once(':') {
+            Some((kind, target)) => (kind.to_lowercase(), Some(target.to_string())),
+            None => (entry.fact("type").unwrap_or_default().to_lowercase(), None),
+        };
+        entry.kind = match kind.as_str() {
+            "file" => EntryKind::File,
+            "dir" | "cdir" | "pdir" => EntryKind::Directory,
+            "os.unix=slink" | "os.unix=symlink" => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        };
+        entry.target = target;
+        entry.size = entry.fact("size").and_then(|size| size.parse().ok());
+        entry.permissions = entry.fact("unix.mode").and_then(FtpPermissions::parse);
+        entry.modified = entry.fact("modify").and_then(time::parse_timestamp);
+        Some(entry)
+    }
+
+    /// File name, without directory.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Directory
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == EntryKind::File
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == EntryKind::Symlink
+    }
+
+    /// Target of a symbolic link, when listed.
+    pub fn link_target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    /// Size in bytes, when listed.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// Unix permissions, when listed.
+    pub fn permissions(&self) -> Option<FtpPermissions> {
+        self.permissions
+    }
+
+    /// Last modification time, when listed. `LIST` dates are read as UTC and
+    /// lack seconds, or the time of day for older files.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// Value of an MLSD fact, e.g. `modify` or `unique`.
+    pub fn fact(&self, name: &str) -> Option<&str> {
+        self.facts
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Whitespace separated tokens with their byte offsets.
+fn tokens(line: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, c) in line.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(begin)) => {
+                tokens.push((begin, &line[begin..index]));
+                start = None;
+            }
+            (false, None) => start = Some(index),
+            _ => {}
+        }
+    }
+    if let Some(begin) = start {
+        tokens.push((begin, &line[begin..]));
+    }
+    tokens
+}
+
+/// Entries of the lines of a `LIST` listing in `style`, or in Unix or DOS
+/// format when unknown. VMS servers wrap the details of long names onto the
+/// next line, such lines are joined first.
+fn parse_listing<S: AsRef<str>>(
+    lines: &[S],
+    style: Option<ListStyle>,
+    format: &ListFormat,
+) -> Vec<FtpEntry> {
+    let mut entries = Vec::new();
+    let mut lines = lines.iter().map(AsRef::as_ref).peekable();
+    while let Some(line) = lines.next() {
+        let entry = match style {
+            Some(ListStyle::Vms) => match lines.peek() {
+                Some(next)
+                    if line.contains(';')
+                        && !line.trim().contains(' ')
+                        && next.starts_with(' ') =>
+                {
+                    let joined = format!("{}{}", line, lines.next().unwrap_or_default());
+                    FtpEntry::parse_list_as(&joined, ListStyle::Vms, format)
+                }
+                _ => FtpEntry::parse_list_as(line, ListStyle::Vms, format),
+            },
+            Some(style) => FtpEntry::parse_list_as(line, style, format),
+            None => FtpEntry::parse_list_with(line, format),
+        };
+        entries.extend(entry);
+    }
+    entries
+}
+
+impl FtpClient {
+    /// Layout of the server's `LIST` lines: the one set on the builder, or else
+    /// the one its `SYST` reply tells, asked once per connection, or else the
+    /// one detected from the first listing with parsable lines. `None` while
+    /// unknown, listings are then read as Unix or DOS lines.
+    pub fn list_style(&mut self) -> Option<ListStyle> {
+        if let Some(style) = self.config.list_style {
+            return Some(style);
+        }
+        if let Some(style) = self.list_style {
+            return style;
+        }
+        // servers without SYST are read as Unix or DOS, like before asking
+        let style = self
+            .system()
+            .ok()
+            .and_then(|system| ListStyle::from_system(&system));
+        self.list_style = Some(style);
+        style
+    }
+
+    /// Layout to read `lines` in, detecting it from them when the server
+    /// didn't tell.
+    fn listing_style(&mut self, lines: &[String]) -> Option<ListStyle> {
+        if let Some(style) = self.list_style() {
+            return Some(style);
+        }
+        let style = ListStyle::detect(lines, &self.config.list_format);
+        if style.is_some() {
+            self.list_style = Some(style);
+        }
+        style
+    }
+
+    /// List a directory with LIST and parse the entries, in the layout of
+    /// [`FtpClient::list_style`]. Lines in an unknown format are skipped.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// for entry in client.list_entries("/pub").unwrap() {
+    ///     println!("{} {:?} {:?}", entry.name(), entry.size(), entry.permissions());
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// Errors on connection failure or improper response from server
+    pub fn list_entries(&mut self, dir: impl AsRef<str>) -> Result<Vec<FtpEntry>> {
+        if self.config.list_via_stat {
+            return self.list_via_stat(dir);
+        }
+        let lines = self.list_cmd(|dir| Command::List(dir), dir.as_ref())?;
+        let style = self.listing_style(&lines);
+        Ok(parse_listing(&lines, style, &self.config.list_format))
+    }
+
+    /// List a directory with `STAT <dir>`, which carries the listing in its reply
+    /// on the control connection. A last resort for networks blocking data
+    /// connections; servers may cap the size of such replies.
+    ///
+    /// # Errors
+    /// Errors when the server refuses STAT with a path, on connection failure or
+    /// improper response from server
+    pub fn list_via_stat(&mut self, dir: impl AsRef<str>) -> Result<Vec<FtpEntry>> {
+        let response = self.expect(Command::Stat(dir.as_ref()), FtpError::CommandError)?;
+        let lines = reply_lines(&response).get(1..).unwrap_or_default();
+        let style = self.listing_style(lines);
+        Ok(parse_listing(lines, style, &self.config.list_format))
+    }
+
+    /// List a directory with MLSD (RFC 3659), which has a standard format.
+    /// The entries for the directory itself and its parent are left out.
+    ///
+    /// # Errors
+    /// [`FtpError::Unsupported`] when the server doesn't support MLSD. Errors
+    /// on connection failure or improper response from server
+    pub fn mlsd(&mut self, dir: impl AsRef<str>) -> Result<Vec<FtpEntry>> {
+        let lines = self.list_cmd(|dir| Command::Mlsd(dir), dir.as_ref())?;
+        Ok(lines
+            .iter()
+            .filter_map(|line| FtpEntry::parse_mlsd(line))
+            .filter(|entry| {
+                !matches!(
+                    entry.fact("type").map(str::to_lowercase).as_deref(),
+                    Some("cdir" | "pdir")
+                )
+            })
+            .collect())
+    }
+
+    /// Change the permissions of a remote file with `SITE CHMOD`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, FtpPermissions};
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// client.site_chmod("/www/index.html", FtpPermissions::from_mode(0o644)).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When the server doesn't support SITE CHMOD or refuses the change.
+    pub fn site_chmod(&mut self, path: impl AsRef<str>, permissions: FtpPermissions) -> Result<()> {
+        let arguments = format!("CHMOD {} {}", permissions.octal(), path.as_ref());
+        let command = Command::Site(&arguments);
+        let response = self.write_cmd(command)?;
+        if !self.accepts(&command, &response) {
+            return Err(self.refused(FtpError::CommandError(format!(
+                "Could not change permissions of {}: {}",
+                path.as_ref(),
+                response.message
+            ))));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EntryKind;
+    use super::FtpEntry;
+    use super::FtpPermissions;
+    use super::ListFormat;
+
+    #[test]
+    fn list_entries_unix() {
+        let entry =
+            FtpEntry::parse_list("-rw-r--r--   1 owner group     1200 Jan 01 12:00 read me.txt")
+                .unwrap();
+        assert_eq!(entry.name(), "read me.txt");
+        assert_eq!(entry.kind(), EntryKind::File);
+        assert_eq!(entry.size(), Some(1200));
+        assert_eq!(entry.permissions().unwrap().octal(), "644");
+        // no group column
+        let entry = FtpEntry::parse_list("drwxr-sr-t 2 ftp 4096 Mar  3  2019 pub").unwrap();
+        assert!(entry.is_dir());
+        assert_eq!(entry.permissions().unwrap().mode(), 0o3755);
+        assert_eq!(FtpEntry::parse_list("total 12"), None);
+    }
+
+    #[test]
+    fn list_entries_dos() {
+        let entry = FtpEntry::parse_list("01-15-20  03:04PM       <DIR>          docs").unwrap();
+        assert!(entry.is_dir());
+        let entry = FtpEntry::parse_list("01-15-20  03:04PM             1200 a b.txt").unwrap();
+        assert_eq!((entry.name(), entry.size()), ("a b.txt", Some(1200)));
+    }
+
+    #[test]
+    fn list_entries_vms_and_mvs() {
+        use super::{ListFormat, ListStyle};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let format = ListFormat::new();
+        assert_eq!(ListStyle::from_system(" VMS"), Some(ListStyle::Vms));
+        assert_eq!(
+            ListStyle::from_system("MVS is the operating system of this server."),
+            Some(ListStyle::Mvs)
+        );
+        assert_eq!(
+            ListStyle::from_system("UNIX Type: L8"),
+            Some(ListStyle::Unix)
+        );
+        assert_eq!(ListStyle::from_system("Windows_NT"), None);
+
+        let line = "LOGIN.COM;12   3/6   10-JAN-2024 09:15:42.50  [SMITH]  (RWED,RWED,RE,)";
+        let entry = FtpEntry::parse_list_as(line, ListStyle::Vms, &format).unwrap();
+        assert_eq!(entry.name(), "LOGIN.COM");
+        assert_eq!(entry.size(), Some(1536));
+        assert_eq!(entry.permissions().unwrap().octal(), "750");
+        assert_eq!(
+            entry.modified(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_704_878_142))
+        );
+        let line = "DATA.DIR;1  1/3  10-JAN-2024 09:15:42  [SMITH]  (RWE,RWE,RE,E)";
+        let entry = FtpEntry::parse_list_as(line, ListStyle::Vms, &format).unwrap();
+        assert!(entry.is_dir());
+        assert_eq!(entry.name(), "DATA");
+        let total = "Total of 2 files, 4/9 blocks.";
+        assert_eq!(
+            FtpEntry::parse_list_as(total, ListStyle::Vms, &format),
+            None
+        );
+
+        let line = "WYNK02 3390   2024/01/10  2   30  FB      80 27920  PO  SRC.COBOL";
+        let entry = FtpEntry::parse_list_as(line, ListStyle::Mvs, &format).unwrap();
+        assert!(entry.is_dir());
+        assert_eq!(entry.name(), "SRC.COBOL");
+        let line = "WYNK01 3390   2024/01/15  1   15  FB      80  3120  PS  DATA.SET";
+        let entry = FtpEntry::parse_list_as(line, ListStyle::Mvs, &format).unwrap();
+        assert!(entry.is_file());
+        let entry = FtpEntry::parse_list_as("Migrated  OLD.DATA", ListStyle::Mvs, &format);
+        assert_eq!(entry.unwrap().name(), "OLD.DATA");
+        let line = " PAYROLL   01.03 2023/12/01 2024/01/15 10:22    45    40     0 USER01";
+        let entry = FtpEntry::parse_list_as(line, ListStyle::Mvs, &format).unwrap();
+        assert_eq!(entry.name(), "PAYROLL");
+        assert_eq!(
+            entry.modified(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_705_314_120))
+        );
+        let header = "Volume Unit    Referred Ext Used Recfm Lrecl BlkSz Dsorg Dsname";
+        assert_eq!(
+            FtpEntry::parse_list_as(header, ListStyle::Mvs, &format),
+            None
+        );
+        let header = " Name     VV.MM   Created       Changed      Size  Init   Mod   Id";
+        assert_eq!(
+            FtpEntry::parse_list_as(header, ListStyle::Mvs, &format),
+            None
+        );
+    }
+
+    #[test]
+    fn list_styles_are_detected() {
+        use super::{ListFormat, ListStyle};
+
+        let format = ListFormat::new();
+        let unix = [
+            "total 8",
+            "-rw-r--r--   1 owner group     1200 Jan 01 12:00 a.txt",
+            "drwxr-xr-x   2 owner group     4096 Mar  3  2019 pub",
+        ];
+        assert_eq!(ListStyle::detect(&unix, &format), Some(ListStyle::Unix));
+        let dos = ["01-15-20  03:04PM       <DIR>          docs"];
+        assert_eq!(ListStyle::detect(&dos, &format), Some(ListStyle::Dos));
+        let vms = [
+            "Directory DISK$USER:[SMITH]",
+            "",
+            "LOGIN.COM;2   1/3   10-JAN-2024 09:15:42  [SMITH]  (RWED,RWED,RE,)",
+            "Total of 1 file, 1/3 blocks.",
+        ];
+        assert_eq!(ListStyle::detect(&vms, &format), Some(ListStyle::Vms));
+        let mvs = [
+            "Volume Unit    Referred Ext Used Recfm Lrecl BlkSz Dsorg Dsname",
+            "WYNK01 3390   2024/01/15  1   15  FB      80  3120  PS  DATA.SET",
+        ];
+        assert_eq!(ListStyle::detect(&mvs, &format), Some(ListStyle::Mvs));
+        let mlsd = ["type=file;size=42; a.txt"];
+        assert_eq!(ListStyle::detect(&mlsd, &format), Some(ListStyle::Mlsd));
+        assert_eq!(ListStyle::detect(&["total 0"], &format), None);
+    }
+
+    #[test]
+    fn mlsd_entries() {
+        let entry =
+            FtpEntry::parse_mlsd("Type=file;Size=42;Modify=20200101120000;UNIX.mode=0640; a.txt")
+                .unwrap();
+        assert_eq!(entry.name(), "a.txt");
+        assert!(entry.is_file());
+        assert_eq!(entry.size(), Some(42));
+        assert_eq!(entry.fact("modify"), Some("20200101120000"));
+        assert_eq!(entry.permissions(), Some(FtpPermissions::from_mode(0o640)));
+    }
+
+    #[test]
+    fn symlink_entries() {
+        let entry =
+            FtpEntry::parse_list("lrwxrwxrwx 1 root root 7 Jan 01 12:00 latest -> v1.2.3").unwrap();
+        assert!(entry.is_symlink());
+        assert_eq!(entry.name(), "latest");
+        assert_eq!(entry.link_target(), Some("v1.2.3"));
+        let entry = FtpEntry::parse_mlsd("type=OS.unix=slink:/pub/v1; latest").unwrap();
+        assert_eq!(entry.kind(), EntryKind::Symlink);
+        assert_eq!(entry.link_target(), Some("/pub/v1"));
+        // an arrow in a regular file name is part of the name
+        let entry = FtpEntry::parse_list("-rw-r--r-- 1 u g 7 Jan 01 12:00 a -> b").unwrap();
+        assert_eq!(entry.name(), "a -> b");
+    }
+
+    #[test]
+    fn permissions_round_trip() {
+        for symbolic in ["rwxr-xr-x", "rw-------", "rwsr-S--T", "r-xr-s--t"] {
+            let permissions = FtpPermissions::parse(symbolic).unwrap();
+            assert_eq!(permissions.to_string(), symbolic);
+        }
+        let permissions = FtpPermissions::parse("r--r--r--").unwrap();
+        assert!(permissions.is_readable() && !permissions.is_writable());
+        assert_eq!(FtpPermissions::parse("rwxrwxrwq"), None);
+    }
+
+    #[test]
+    fn list_dates() {
+        let utc = crate::time::from_utc;
+        let now = utc(2024, 3, 10, 12, 0, 0).unwrap();
+        let format = ListFormat::new().now(now).months([
+            "Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez",
+        ]);
+        let modified = |line| FtpEntry::parse_list_with(line, &format).unwrap().modified();
+        assert_eq!(
+            modified("-rw-r--r-- 1 ftp ftp 5 Mar 10 13:30 a"),
+            utc(2024, 3, 10, 13, 30, 0)
+        );
+        assert_eq!(
+            modified("-rw-r--r-- 1 ftp ftp 5 Dec 24 18:00 a"),
+            utc(2023, 12, 24, 18, 0, 0)
+        );
+        assert_eq!(
+            modified("-rw-r--r-- 1 ftp ftp 5 Okt. 3 2019 a"),
+            utc(2019, 10, 3, 0, 0, 0)
+        );
+        assert_eq!(
+            modified("-rw-r--r-- 1 ftp ftp 5 MÄR 1 08:15 a"),
+            utc(2024, 3, 1, 8, 15, 0)
+        );
+        assert_eq!(
+            modified("01-15-20  12:04AM             1200 a"),
+            utc(2020, 1, 15, 0, 4, 0)
+        );
+        assert_eq!(
+            modified("2019-07-30  15:04       <DIR>          docs"),
+            utc(2019, 7, 30, 15, 4, 0)
+        );
+        assert!(FtpEntry::parse_list("-rw-r--r-- 1 ftp ftp 5 Okt 3 2019 a").is_none());
+        let entry = FtpEntry::parse_mlsd("type=file;modify=20200101120000; a").unwrap();
+        assert_eq!(entry.modified(), utc(2020, 1, 1, 12, 0, 0));
+    }
+}
@@ -0,0 +1,101 @@
+//! Typed notifications about client activity.
+
+use std::net::SocketAddr;
+
+/// Events emitted by a [`crate::FtpClient`] when an event sender is attached
+/// through [`crate::FtpClientBuilder::events`].
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::{FtpClient, ProtocolEvent};
+///
+/// let (sender, receiver) = std::sync::mpsc::channel();
+/// std::thread::spawn(move || {
+///     for event in receiver {
+///         if let ProtocolEvent::TransferProgress { bytes } = event {
+///             println!("{} bytes", bytes);
+///         }
+///     }
+/// });
+/// let mut client = FtpClient::builder().events(sender).connect("127.0.0.1:21").unwrap();
+/// client.login("user", "password").unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolEvent {
+    /// A command was written to the control connection. Secrets are redacted.
+    CommandSent(String),
+    /// A complete reply was read from the control connection.
+    ReplyReceived { code: usize, message: String },
+    /// A data connection was opened to the given address.
+    DataOpened(SocketAddr),
+    /// Bytes moved so far on the current data connection.
+    TransferProgress { bytes: u64 },
+    /// The data connection finished after moving `bytes` bytes.
+    TransferComplete { bytes: u64 },
+}
+
+/// Hide the argument of commands carrying credentials.
+pub(crate) fn redact(command: &str) -> String {
+    let verb = command.split(' ').next().unwrap_or_default();
+    match verb.to_ascii_uppercase().as_str() {
+        "PASS" | "ACCT" => format!("{} ****", verb),
+        _ => command.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::redact;
+    use super::ProtocolEvent;
+    use crate::testing::scripted_server;
+    use crate::FtpClient;
+    use std::io::Write;
+
+    #[test]
+    fn credentials_are_redacted() {
+        assert_eq!(redact("PASS hunter2"), "PASS ****");
+        assert_eq!(redact("pass hunter2"), "pass ****");
+        assert_eq!(redact("ACCT billing"), "ACCT ****");
+        assert_eq!(redact("USER anonymous"), "USER anonymous");
+        assert_eq!(redact("PASSIVE"), "PASSIVE");
+    }
+
+    #[test]
+    fn events_follow_the_session() {
+        let address = scripted_server(|command, data| match command {
+            "USER user" => "331 Password required\r\n".to_string(),
+            "PASS hunter2" | "ACCT billing" => "230 Logged in\r\n".to_string(),
+            "RETR /a.txt" => {
+                data.accept().unwrap().0.write_all(b"hello").unwrap();
+                "150 Opening\r\n226 Done\r\n".to_string()
+            }
+            _ => "200 OK\r\n".to_string(),
+        });
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut client = FtpClient::builder()
+            .events(sender)
+            .connect(address.to_string())
+            .unwrap();
+        client.login("user", "hunter2").unwrap();
+        client.account("billing").unwrap();
+        client.get("/a.txt", &mut Vec::new()).unwrap();
+        drop(client);
+
+        let events: Vec<_> = receiver.iter().collect();
+        let sent = |command: &str| events.contains(&ProtocolEvent::CommandSent(command.into()));
+        assert!(sent("USER user") && sent("PASS ****") && sent("ACCT ****"));
+        assert!(sent("RETR /a.txt"));
+        assert!(events.iter().all(|event| {
+            let text = format!("{:?}", event);
+            !text.contains("hunter2") && !text.contains("billing")
+        }));
+        assert!(events.contains(&ProtocolEvent::ReplyReceived {
+            code: 331,
+            message: " Password required\r\n".into()
+        }));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, ProtocolEvent::DataOpened(_))));
+        assert!(events.contains(&ProtocolEvent::TransferComplete { bytes: 5 }));
+    }
+}
@@ -0,0 +1,450 @@
+//! Random access to remote files, reading and writing from any offset with
+//! `REST`.
+
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+use crate::probe::lists_feature;
+use crate::stream::Stream;
+use crate::trace::Span;
+use crate::Command;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::Result;
+
+/// A remote file opened with [`FtpClient::open`], readable from any offset so
+/// it can be handed to code expecting [`Read`] + [`Seek`], e.g. a zip reader.
+///
+/// Data is retrieved with `RETR`, started on the first read after opening or
+/// seeking, after a `REST` to the position when it isn't the start of the
+/// file. Seeking while data is retrieved aborts the transfer. The file size
+/// is the one `SIZE` gave on opening.
+pub struct FtpFile<'a> {
+    client: &'a mut FtpClient,
+    path: String,
+    size: u64,
+    position: u64,
+    retrieval: Option<Retrieval>,
+}
+
+/// A running `RETR` of an [`FtpFile`].
+struct Retrieval {
+    stream: Box<dyn Stream>,
+    /// Whether the reply completing the transfer is still to come.
+    pending: bool,
+    span: Span,
+}
+
+impl FtpFile<'_> {
+    /// Remote path of the file.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Size of the file when it was opened.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Offset the next read starts at.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Stop the running transfer, if any, so the client can send commands
+    /// again.
+    fn stop(&mut self) {
+        if let Some(retrieval) = self.retrieval.take() {
+            self.client.abort_retrieval(retrieval);
+        }
+    }
+}
+
+impl Read for FtpFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut retrieval = match self.retrieval.take() {
+            Some(retrieval) => retrieval,
+            None if self.position >= self.size => return Ok(0),
+            None => self
+                .client
+                .start_retrieval(&self.path, self.position)
+                .map_err(io_error)?,
+        };
+        match retrieval.stream.read(buf) {
+            Ok(0) => {
+                self.client.end_retrieval(retrieval).map_err(io_error)?;
+                Ok(0)
+            }
+            Ok(count) => {
+                self.position += count as u64;
+                self.client.session_bytes += count as u64;
+                self.retrieval = Some(retrieval);
+                Ok(count)
+            }
+            Err(error) => {
+                self.retrieval = Some(retrieval);
+                self.stop();
+                Err(error)
+            }
+        }
+    }
+}
+
+impl Seek for FtpFile<'_> {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(ErrorKind::InvalidInput, "seek before the start of the file")
+        })?;
+        if target != self.position {
+            self.stop();
+            self.position = target;
+        }
+        Ok(target)
+    }
+}
+
+impl Drop for FtpFile<'_> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn io_error(error: FtpError) -> std::io::Error {
+    std::io::Error::other(error.to_string())
+}
+
+impl FtpClient {
+    /// Open a remote file for reading at any offset, which needs a server
+    /// supporting `REST STREAM` and `SIZE`. The client is held until the file
+    /// is dropped.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// use std::io::{Read, Seek, SeekFrom};
+    ///
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let mut file = client.open("/backups/archive.zip").unwrap();
+    /// // the end of central directory record of a zip file without comment
+    /// let mut record = [0; 22];
+    /// file.seek(SeekFrom::End(-22)).unwrap();
+    /// file.read_exact(&mut record).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Errors on connection failure or when the size of `file` can't be
+    /// told, e.g. for a missing file.
+    pub fn open(&mut self, file: impl AsRef<str>) -> Result<FtpFile<'_>> {
+        let path = file.as_ref().to_string();
+        // offsets count the bytes of the binary form
+        self.binary_mode()?;
+        let size = self.size(&path)?;
+        Ok(FtpFile {
+            client: self,
+            path,
+            size,
+            position: 0,
+            retrieval: None,
+        })
+    }
+
+    /// Read up to `buf.len()` bytes of `file` from `offset` on, like `pread`,
+    /// retrieving only those: the transfer is aborted once `buf` is full.
+    /// Needs a server supporting `REST STREAM`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let mut header = [0; 512];
+    /// let read = client.read_at("/images/disk.img", 4096, &mut header).unwrap();
+    /// ```
+    ///
+    /// # Returns
+    /// The number of bytes read, short of `buf.len()` only when the file ends
+    /// first.
+    ///
+    /// # Errors
+    /// Errors on connection failure, when `file` can't be retrieved, or
+    /// [`FtpError::Unsupported`] when the server lacks `REST`.
+    pub fn read_at(&mut self, file: impl AsRef<str>, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // offsets count the bytes of the binary form
+        self.binary_mode()?;
+        let mut retrieval = self.start_retrieval(file.as_ref(), offset)?;
+        let mut filled = 0;
+        while filled < buf.len() {
+            match retrieval.stream.read(&mut buf[filled..]) {
+                Ok(0) => {
+                    self.end_retrieval(retrieval)?;
+                    self.session_bytes += filled as u64;
+                    return Ok(filled);
+                }
+                Ok(count) => filled += count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    self.abort_retrieval(retrieval);
+                    return Err(error.into());
+                }
+            }
+        }
+        // the rest of the file isn't wanted
+        self.abort_retrieval(retrieval);
+        self.session_bytes += filled as u64;
+        Ok(filled)
+    }
+
+    /// Overwrite the bytes of the existing `file` from `offset` on with `data`,
+    /// like `pwrite`, sending only those with `STOR` after a `REST`. The file
+    /// grows when `data` goes past its end.
+    ///
+    /// A server ignoring the `REST` would truncate the file, so `FEAT` must
+    /// list `REST STREAM` first. A server listing it yet storing from the
+    /// start still loses the rest of the file, which is only detected
+    /// afterwards by comparing sizes.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// // fix a header without uploading the whole image again
+    /// client.write_at("/images/disk.img", 510, &[0x55, 0xaa]).unwrap();
+    /// ```
+    ///
+    /// # Returns
+    /// The size of the file once written.
+    ///
+    /// # Errors
+    /// [`FtpError::Unsupported`] when `FEAT` doesn't list `REST STREAM`, or
+    /// the server refuses `REST` or a `STOR` after it. [`FtpError::InvalidArgument`] when `offset` is past the end
+    /// of the file, [`FtpError::IntegrityError`] when the server stored
+    /// another size than expected, e.g. truncating the file instead. Errors
+    /// from the transfer and from `SIZE`, which the server must support.
+    pub fn write_at(&mut self, file: impl AsRef<str>, offset: u64, data: &[u8]) -> Result<u64> {
+        let file = file.as_ref();
+        // offsets count the bytes of the binary form
+        self.binary_mode()?;
+        if !lists_feature(&self.features()?, "REST STREAM") {
+            return Err(FtpError::Unsupported("REST STREAM".into()));
+        }
+        self.cache.invalidate(file);
+        let size = self.size(file)?;
+        if offset > size {
+            return Err(FtpError::InvalidArgument(format!(
+                "offset {} is past the end of {}, {} bytes",
+                offset, file, size
+            )));
+        }
+        self.store_from(
+            file,
+            |file| Command::Stor(file),
+            offset,
+            |client, stream, socket| client.transfer_data(&mut &data[..], stream, socket),
+        )?;
+        let expected = size.max(offset + data.len() as u64);
+        match self.size(file)? {
+            written if written == expected => Ok(written),
+            written => Err(FtpError::IntegrityError(format!(
+                "{} holds {} bytes after writing {} at {}, expected {}",
+                file,
+                written,
+                data.len(),
+                offset,
+                expected
+            ))),
+        }
+    }
+
+    /// Start retrieving `file` from `offset`, holding the data connection
+    /// until the retrieval ends.
+    fn start_retrieval(&mut self, file: &str, offset: u64) -> Result<Retrieval> {
+        self.begin_transfer()?;
+        let span = Span::transfer("RETR", file);
+        let started = span.in_scope(|| {
+            let stream = self.data_connection()?;
+            if offset > 0 {
+                self.expect(Command::Rest(offset), FtpError::CommandError)?;
+            }
+            let (_, pending) = self.start_transfer(Command::Retr(file), |_| {
+                FtpError::CommandError("Could not process file retrieve".into())
+            })?;
+            self.secure_data(stream).map(|stream| (stream, pending))
+        });
+        match started {
+            Ok((stream, pending)) => Ok(Retrieval {
+                stream,
+                pending,
+                span,
+            }),
+            Err(error) => {
+                self.transferring = false;
+                Err(error)
+            }
+        }
+    }
+
+    /// Abort a retrieval before the end of the data.
+    fn abort_retrieval(&mut self, retrieval: Retrieval) {
+        self.abort_transfer(retrieval.stream);
+        self.transferring = false;
+    }
+
+    /// Close the data connection of a retrieval that read all the data.
+    fn end_retrieval(&mut self, retrieval: Retrieval) -> Result<()> {
+        let Retrieval {
+            mut stream,
+            pending,
+            span,
+        } = retrieval;
+        // errors are irrelevant once all the data arrived
+        let _ = stream.close();
+        let result = if pending {
+            span.in_scope(|| self.complete_transfer(&span))
+        } else {
+            Ok(())
+        };
+        self.transferring = false;
+        result
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::testing::served_client;
+
+    #[test]
+    fn remote_files_seek() {
+        use crate::{Next, Response};
+        use std::io::{Read, Seek, SeekFrom};
+
+        let content = (0..5000).map(|byte| byte as u8).collect::<Vec<_>>();
+        let (mut client, _root) = served_client("seek", &[("data.bin", &content)]);
+
+        let mut file = client.open("/data.bin").unwrap();
+        assert_eq!(file.size(), 5000);
+        let mut tail = [0; 10];
+        assert_eq!(file.seek(SeekFrom::End(-10)).unwrap(), 4990);
+        file.read_exact(&mut tail).unwrap();
+        assert_eq!(tail, content[4990..]);
+        assert_eq!(file.read(&mut tail).unwrap(), 0);
+        // seeking back in the middle of a transfer
+        file.seek(SeekFrom::Start(100)).unwrap();
+        let mut chunk = [0; 5];
+        file.read_exact(&mut chunk).unwrap();
+        assert_eq!(chunk, content[100..105]);
+        file.seek(SeekFrom::Current(-105)).unwrap();
+        let mut all = Vec::new();
+        file.read_to_end(&mut all).unwrap();
+        assert_eq!(all, content);
+        assert!(file.seek(SeekFrom::Current(-5001)).is_err());
+        file.seek(SeekFrom::Start(3000)).unwrap();
+        file.read_exact(&mut chunk).unwrap();
+        drop(file);
+        // the session is still usable after dropping a file in a transfer
+        client.noop().unwrap();
+        assert!(client.open("/missing.bin").is_err());
+
+        client.add_layer(|command: &str, next: &mut Next| {
+            if command.starts_with("REST") {
+                return Ok(Response::new(502, " Not implemented\r\n"));
+            }
+            next.run(command)
+        });
+        let mut file = client.open("/data.bin").unwrap();
+        file.read_exact(&mut chunk).unwrap();
+        file.seek(SeekFrom::Start(10)).unwrap();
+        let error = file.read(&mut chunk).unwrap_err();
+        assert!(error.to_string().contains("REST"));
+        drop(file);
+        client.noop().unwrap();
+    }
+
+    #[test]
+    fn ranges_are_read_at_offsets() {
+        let content = (0..100_000)
+            .map(|byte| (byte % 251) as u8)
+            .collect::<Vec<_>>();
+        let (mut client, _root) = served_client("pread", &[("large.bin", &content)]);
+
+        let mut buf = [0; 64];
+        assert_eq!(client.read_at("/large.bin", 0, &mut buf).unwrap(), 64);
+        assert_eq!(buf, content[..64]);
+        assert_eq!(client.read_at("/large.bin", 50_000, &mut buf).unwrap(), 64);
+        assert_eq!(buf, content[50_000..50_064]);
+        // short read at the end of the file
+        assert_eq!(client.read_at("/large.bin", 99_990, &mut buf).unwrap(), 10);
+        assert_eq!(buf[..10], content[99_990..]);
+        assert_eq!(client.read_at("/large.bin", 200_000, &mut buf).unwrap(), 0);
+        assert!(client.read_at("/missing.bin", 0, &mut buf).is_err());
+        assert_eq!(client.size("/large.bin").unwrap(), 100_000);
+    }
+
+    #[test]
+    fn regions_are_written_at_offsets() {
+        use crate::{FtpError, HistoryEntry, Next, Response};
+
+        let (mut client, root) = served_client("pwrite", &[("disk.img", b"0123456789")]);
+
+        assert_eq!(client.write_at("/disk.img", 2, b"ab").unwrap(), 10);
+        assert_eq!(std::fs::read(root.join("disk.img")).unwrap(), b"01ab456789");
+        // growing past the end
+        assert_eq!(client.write_at("/disk.img", 8, b"xyz").unwrap(), 11);
+        assert_eq!(
+            std::fs::read(root.join("disk.img")).unwrap(),
+            b"01ab4567xyz"
+        );
+        assert!(matches!(
+            client.write_at("/disk.img", 20, b"!"),
+            Err(FtpError::InvalidArgument(_))
+        ));
+        assert!(client.write_at("/missing.img", 0, b"!").is_err());
+
+        client.add_layer(|command: &str, next: &mut Next| {
+            if command.starts_with("REST") {
+                return Ok(Response::new(502, " Not implemented\r\n"));
+            }
+            next.run(command)
+        });
+        assert!(matches!(
+            client.write_at("/disk.img", 1, b"!"),
+            Err(FtpError::Unsupported(verb)) if verb == "REST"
+        ));
+        assert_eq!(
+            std::fs::read(root.join("disk.img")).unwrap(),
+            b"01ab4567xyz"
+        );
+        client.noop().unwrap();
+
+        // a server that may ignore REST isn't sent the STOR at all
+        let (mut client, root) = served_client("pwrite-feat", &[("disk.img", b"0123456789")]);
+        client.add_layer(|command: &str, next: &mut Next| {
+            if command == "FEAT" {
+                return Ok(Response::new(211, "-Features:\r\n SIZE\r\n211 End\r\n"));
+            }
+            next.run(command)
+        });
+        assert!(matches!(
+            client.write_at("/disk.img", 2, b"ab"),
+            Err(FtpError::Unsupported(feature)) if feature == "REST STREAM"
+        ));
+        assert!(!client.history().any(
+            |entry| matches!(entry, HistoryEntry::Command(command) if command.starts_with("STOR"))
+        ));
+        assert_eq!(std::fs::read(root.join("disk.img")).unwrap(), b"0123456789");
+    }
+}
@@ -0,0 +1,279 @@
+//! RFC 2228 security exchange for Kerberos single sign-on (`gssapi` feature).
+//!
+//! The crate drives the FTP side of `AUTH GSSAPI`: the ADAT token exchange and
+//! the integrity protection of commands (`MIC`) and replies (`631`–`633`).
+//! The GSSAPI mechanism itself is supplied by the application through
+//! [`SecurityContext`], typically backed by the platform GSSAPI library, so this
+//! crate doesn't link against any Kerberos implementation.
+//! Data connections are not protected (`PROT C`).
+
+use crate::Command;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::Result;
+
+/// Reply to AUTH accepting the mechanism, ADAT must follow.
+pub const SECURITY_DATA_NEEDED: usize = 334;
+/// ADAT accepted, security exchange complete.
+pub const SECURITY_DATA_COMPLETE: usize = 235;
+/// ADAT accepted, more security data needed.
+pub const SECURITY_DATA_CONTINUE: usize = 335;
+/// Integrity protected reply.
+pub const PROTECTED_REPLY: usize = 631;
+/// Confidentiality and integrity protected reply.
+pub const PRIVATE_REPLY: usize = 632;
+/// Confidentiality protected reply.
+pub const CONFIDENTIAL_REPLY: usize = 633;
+
+/// A GSSAPI security context established with the server's `ftp@host` service.
+pub trait SecurityContext: Send {
+    /// Process the token received from the server (`None` on the first call) and
+    /// return the token to send, if any.
+    fn step(&mut self, token: Option<&[u8]>) -> Result<Option<Vec<u8>>>;
+
+    /// Whether the context is fully established.
+    fn is_complete(&self) -> bool;
+
+    /// Protect `data` for integrity (`gss_wrap` without confidentiality).
+    fn wrap(&mut self, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// Verify and extract data protected by the server (`gss_unwrap`).
+    fn unwrap(&mut self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl FtpClient {
+    /// Authenticate with `AUTH GSSAPI` before logging in. Once the exchange
+    /// completes every command is sent integrity protected with `MIC` and
+    /// protected replies are verified.
+    ///
+    /// # Arguments
+    /// `context`   security context for the server's `ftp@host` service
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, SecurityContext};
+    /// # fn kerberos_context(service: &str) -> Box<dyn SecurityContext> { unimplemented!() }
+    /// let mut client = FtpClient::connect("ftp.example.gov:21").unwrap();
+    /// client.authenticate_gssapi(kerberos_context("ftp@ftp.example.gov")).unwrap();
+    /// client.login("jdoe", "").unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When the server doesn't support GSSAPI or the exchange fails.
+    pub fn authenticate_gssapi(&mut self, mut context: Box<dyn SecurityContext>) -> Result<()> {
+        let response = self.write_cmd(Command::Auth("GSSAPI"))?;
+        if response.code != SECURITY_DATA_NEEDED {
+            return Err(self.refused(FtpError::LoginError(format!(
+                "GSSAPI not accepted: {}",
+                response.code
+            ))));
+        }
+        let mut server_token: Option<Vec<u8>> = None;
+        loop {
+            let token = context.step(server_token.as_deref())?;
+            if context.is_complete() && token.is_none() {
+                break;
+            }
+            let token = token.unwrap_or_default();
+            let response = self.write_cmd(Command::Adat(&encode(&token)))?;
+            server_token = adat_token(&response.message)?;
+            match response.code {
+                SECURITY_DATA_COMPLETE => {
+                    if let Some(token) = server_token.take() {
+                        context.step(Some(&token))?;
+                    }
+                    break;
+                }
+                SECURITY_DATA_CONTINUE => {}
+                code => {
+                    return Err(FtpError::LoginError(format!(
+                        "GSSAPI exchange failed: {}",
+                        code
+                    )))
+                }
+            }
+        }
+        if !context.is_complete() {
+            return Err(FtpError::LoginError(
+                "GSSAPI context not established".into(),
+            ));
+        }
+        self.security = Some(context);
+        Ok(())
+    }
+}
+
+/// Wrap a command line (without line terminator) into a `MIC` command.
+pub(crate) fn protect(context: &mut Box<dyn SecurityContext>, command: &str) -> Result<String> {
+    let wrapped = context.wrap(command.as_bytes())?;
+    Ok(format!("MIC {}\r\n", encode(&wrapped)))
+}
+
+/// Replace protected reply lines by the reply lines they carry.
+pub(crate) fn unprotect(context: &mut Box<dyn SecurityContext>, reply: String) -> Result<String> {
+    let mut plain = String::new();
+    for line in reply.split_inclusive('\n') {
+        let code = line.get(0..3).and_then(|code| code.parse::<usize>().ok());
+        match code {
+            Some(PROTECTED_REPLY | PRIVATE_REPLY | CONFIDENTIAL_REPLY) => {
+                let token = line.get(4..).unwrap_or_default().trim();
+                if token.is_empty() {
+                    return Err(FtpError::ResponseError(
+                        "Protected reply carries no data".into(),
+                    ));
+                }
+                let token = decode(token)?;
+                let inner = context.unwrap(&token)?;
+                let inner = String::from_utf8(inner).map_err(|_| {
+                    FtpError::ResponseError("Protected reply is not valid UTF-8".into())
+                })?;
+                plain.push_str(inner.trim_end_matches(['\r', '\n']));
+                plain.push_str("\r\n");
+            }
+            _ => plain.push_str(line),
+        }
+    }
+    Ok(plain)
+}
+
+/// Extract the `ADAT=base64` token of a 235/335 reply.
+fn adat_token(message: &str) -> Result<Option<Vec<u8>>> {
+    match message.find("ADAT=") {
+        Some(start) => {
+            let token = message[start + 5..]
+                .split_whitespace()
+                .next()
+                .unwrap_or_default();
+            Ok(Some(decode(token)?))
+        }
+        None => Ok(None),
+    }
+}
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with padding, as used by RFC 2228.
+pub(crate) fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * index)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decode base64, refusing misplaced or excess padding and truncated groups.
+/// Unpadded input is accepted as long as its length is possible.
+pub(crate) fn decode(text: &str) -> Result<Vec<u8>> {
+    let invalid = || FtpError::ResponseError(format!("Invalid base64 data: {}", text));
+    let data = text.trim_end_matches('=');
+    let padding = text.len() - data.len();
+    if data.len() % 4 == 1 || padding > 2 || (padding > 0 && !text.len().is_multiple_of(4)) {
+        return Err(invalid());
+    }
+    let mut decoded = Vec::with_capacity(data.len() * 3 / 4);
+    let mut group = 0u32;
+    let mut bits = 0;
+    for byte in data.bytes() {
+        let value = ALPHABET
+            .iter()
+            .position(|c| *c == byte)
+            .ok_or_else(invalid)?;
+        group = (group << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((group >> bits) as u8);
+        }
+    }
+    // the bits left over from the last character must be zero
+    if group & ((1 << bits) - 1) != 0 {
+        return Err(invalid());
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::FtpError;
+
+    #[test]
+    fn gssapi_base64() {
+        use super::{decode, encode};
+
+        for data in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+            assert_eq!(decode(&encode(data)).unwrap(), data);
+        }
+        assert_eq!(encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(encode(b"fooba"), "Zm9vYmE=");
+        assert_eq!(encode(&[0xfb, 0xff]), "+/8=");
+        // unpadded tokens from lenient servers
+        assert_eq!(decode("Zm9vYmE").unwrap(), b"fooba");
+        for malformed in [
+            "Zm9vY",
+            "Zm9vYmE==",
+            "Zm9v=YmE=",
+            "Zg===",
+            "Zm9=",
+            "Zh==",
+            "Zm9v!",
+        ] {
+            assert!(decode(malformed).is_err(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn gssapi_replies_are_unprotected() {
+        use super::SecurityContext;
+        use super::{encode, unprotect};
+
+        /// Prefixes protected data with a tag the peer checks.
+        struct Tagged;
+        impl SecurityContext for Tagged {
+            fn step(&mut self, _token: Option<&[u8]>) -> crate::Result<Option<Vec<u8>>> {
+                Ok(None)
+            }
+            fn is_complete(&self) -> bool {
+                true
+            }
+            fn wrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+                Ok([b"MIC:", data].concat())
+            }
+            fn unwrap(&mut self, data: &[u8]) -> crate::Result<Vec<u8>> {
+                match data.strip_prefix(b"MIC:") {
+                    Some(inner) => Ok(inner.to_vec()),
+                    None => Err(FtpError::ResponseError("bad MIC".into())),
+                }
+            }
+        }
+        let mut context: Box<dyn SecurityContext> = Box::new(Tagged);
+        let protected = |line: &[u8]| encode(&[b"MIC:", line].concat());
+
+        let reply = format!(
+            "631 {}\r\n200 plain\r\n",
+            protected(b"250 Directory changed\r\n")
+        );
+        assert_eq!(
+            unprotect(&mut context, reply).unwrap(),
+            "250 Directory changed\r\n200 plain\r\n"
+        );
+        // cut in the middle of a base64 group
+        let truncated = format!("631 {}\r\n", &protected(b"250 Directory changed")[..13]);
+        assert!(unprotect(&mut context, truncated).is_err());
+        assert!(unprotect(&mut context, "631\r\n".into()).is_err());
+        assert!(unprotect(&mut context, "632 bm90IHRhZ2dlZA==\r\n".into()).is_err());
+        let invalid = format!("633 {}\r\n", protected(&[0xff, 0xfe]));
+        assert!(unprotect(&mut context, invalid).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+//! Ring buffer of the latest control connection traffic.
+
+use std::collections::VecDeque;
+
+use crate::events::redact;
+
+/// Default number of entries kept by [`crate::FtpClient::history`].
+pub const DEFAULT_HISTORY_SIZE: usize = 32;
+
+/// A line of control connection traffic. Secrets in commands are redacted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryEntry {
+    Command(String),
+    Reply { code: usize, message: String },
+}
+
+impl std::fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryEntry::Command(command) => write!(f, "> {}", command),
+            HistoryEntry::Reply { code, message } => write!(f, "< {}{}", code, message.trim_end()),
+        }
+    }
+}
+
+pub(crate) struct History {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+}
+
+impl History {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(crate) fn command(&mut self, command: &str) {
+        self.push(HistoryEntry::Command(redact(command)));
+    }
+
+    pub(crate) fn reply(&mut self, code: usize, message: &str) {
+        self.push(HistoryEntry::Reply {
+            code,
+            message: message.to_string(),
+        });
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    fn push(&mut self, entry: HistoryEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
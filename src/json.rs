@@ -0,0 +1,133 @@
+//! The bits of JSON written and read by the crate and its command line
+//! client. Not part of the API.
+
+use std::fmt::Write;
+
+/// `value` as a JSON string literal.
+pub fn string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for character in value.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            character if character < ' ' => {
+                let _ = write!(quoted, "\\u{:04x}", character as u32);
+            }
+            character => quoted.push(character),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Reads the strings, unsigned integers and punctuation of a JSON text, for
+/// documents of a known shape.
+pub(crate) struct Reader<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        Self { rest: text }
+    }
+
+    /// Skip whitespace, then `expected` if it comes next.
+    pub(crate) fn consume(&mut self, expected: char) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(expected) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether only whitespace is left.
+    pub(crate) fn at_end(&self) -> bool {
+        self.rest.trim_start().is_empty()
+    }
+
+    /// The next value, a string literal.
+    pub(crate) fn string(&mut self) -> Option<String> {
+        if !self.consume('"') {
+            return None;
+        }
+        let text = self.rest;
+        let mut value = String::new();
+        let mut characters = text.char_indices();
+        loop {
+            match characters.next()? {
+                (index, '"') => {
+                    self.rest = &text[index + 1..];
+                    return Some(value);
+                }
+                (_, '\\') => {
+                    let escaped = match characters.next()?.1 {
+                        'u' => {
+                            let unit = hex_unit(&mut characters)?;
+                            match unit {
+                                0xD800..=0xDBFF => {
+                                    let (_, '\\') = characters.next()? else {
+                                        return None;
+                                    };
+                                    let (_, 'u') = characters.next()? else {
+                                        return None;
+                                    };
+                                    let low = hex_unit(&mut characters)?;
+                                    if !(0xDC00..=0xDFFF).contains(&low) {
+                                        return None;
+                                    }
+                                    char::from_u32(
+                                        0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00),
+                                    )?
+                                }
+                                unit => char::from_u32(unit)?,
+                            }
+                        }
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        character @ ('"' | '\\' | '/') => character,
+                        _ => return None,
+                    };
+                    value.push(escaped);
+                }
+                (_, character) if character < ' ' => return None,
+                (_, character) => value.push(character),
+            }
+        }
+    }
+
+    /// The next value, an unsigned integer.
+    pub(crate) fn integer(&mut self) -> Option<u64> {
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        let (digits, rest) = self.rest.split_at(end);
+        // JSON numbers have no leading zeroes
+        if digits.is_empty() || (digits.len() > 1 && digits.starts_with('0')) {
+            return None;
+        }
+        let value = digits.parse().ok()?;
+        self.rest = rest;
+        Some(value)
+    }
+}
+
+/// The four hexadecimal digits of a `\u` escape.
+fn hex_unit(characters: &mut std::str::CharIndices) -> Option<u32> {
+    let mut unit = 0;
+    for _ in 0..4 {
+        unit = unit * 16 + characters.next()?.1.to_digit(16)?;
+    }
+    Some(unit)
+}
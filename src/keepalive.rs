@@ -0,0 +1,119 @@
+//! Keeping idle sessions open with `NOOP`, as often as the server needs.
+
+use std::time::Duration;
+
+use crate::FtpClient;
+use crate::Result;
+
+/// Idle timeout assumed for servers that don't advertise theirs, the default
+/// of vsftpd and ProFTPD.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Shortest interval between keep-alive commands, whatever the server says.
+const MIN_KEEP_ALIVE: Duration = Duration::from_secs(1);
+
+impl FtpClient {
+    /// How long the session may stay idle before [`FtpClient::keep_alive`]
+    /// sends a `NOOP`: a third short of the idle timeout the server
+    /// advertises, or [`DEFAULT_IDLE_TIMEOUT`], and at least four slow round
+    /// trips short of it, so the command arrives in time even when the server
+    /// is slow. The advertised timeout is asked once per session.
+    ///
+    /// # Errors
+    /// On connection failure.
+    pub fn keep_alive_interval(&mut self) -> Result<Duration> {
+        let limit = match self.idle_limit {
+            Some(limit) => limit,
+            None => {
+                let limit = self.idle_timeout()?.unwrap_or(DEFAULT_IDLE_TIMEOUT);
+                self.idle_limit = Some(limit);
+                limit
+            }
+        };
+        let slow = self.latency().map_or(Duration::ZERO, |latency| latency.p95);
+        let margin = (limit / 3).max(slow * 4);
+        Ok(limit.saturating_sub(margin).max(MIN_KEEP_ALIVE))
+    }
+
+    /// Time since the last command was sent.
+    pub fn idle_time(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    /// Send a `NOOP` if the session has been idle for the
+    /// [`FtpClient::keep_alive_interval`], and return how long to wait before
+    /// calling again. Meant for a loop in a thread owning the client, or
+    /// locking a [`crate::FtpSession`] between other users.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, FtpSession};
+    /// let session = FtpSession::new(|| {
+    ///     let mut client = FtpClient::connect("127.0.0.1:21")?;
+    ///     client.login("user", "password")?;
+    ///     Ok(client)
+    /// })
+    /// .unwrap();
+    /// let keeper = session.clone();
+    /// std::thread::spawn(move || loop {
+    ///     match keeper.run(|client| client.keep_alive()) {
+    ///         Ok(wait) => std::thread::sleep(wait),
+    ///         Err(_) => break,
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// # Errors
+    /// On connection failure or when the server refuses `NOOP`.
+    pub fn keep_alive(&mut self) -> Result<Duration> {
+        let interval = self.keep_alive_interval()?;
+        if self.idle_time() >= interval {
+            self.noop()?;
+        }
+        Ok(interval.saturating_sub(self.idle_time()))
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::testing::start_server;
+    use crate::FtpClient;
+
+    #[test]
+    fn keep_alive_follows_the_idle_timeout() {
+        use crate::{AnonymousAccess, FtpServer, HistoryEntry, Next, Response};
+        use std::time::Duration;
+
+        let mut client = FtpClient::connect(start_server(FtpServer::new(AnonymousAccess))).unwrap();
+        client.login("anonymous", "").unwrap();
+        client.add_layer(|command: &str, next: &mut Next| match command {
+            "SITE IDLE" => Ok(Response::new(200, " Session timeout in seconds is 1\r\n")),
+            _ if command.starts_with("SITE IDLE ") => Ok(Response::new(200, " Timeout set\r\n")),
+            _ => next.run(command),
+        });
+        // a third of the timeout is kept as margin, but never below a second
+        assert_eq!(
+            client.keep_alive_interval().unwrap(),
+            Duration::from_secs(1)
+        );
+        client.set_idle_timeout(30).unwrap();
+        assert_eq!(
+            client.keep_alive_interval().unwrap(),
+            Duration::from_secs(20)
+        );
+        let wait = client.keep_alive().unwrap();
+        assert!(wait > Duration::from_secs(19) && wait <= Duration::from_secs(20));
+        assert!(!client
+            .history()
+            .any(|entry| *entry == HistoryEntry::Command("NOOP".into())));
+
+        client.set_idle_timeout(1).unwrap();
+        std::thread::sleep(Duration::from_millis(1050));
+        assert!(client.idle_time() >= Duration::from_secs(1));
+        client.keep_alive().unwrap();
+        assert!(client.idle_time() < Duration::from_secs(1));
+        assert!(client
+            .history()
+            .any(|entry| *entry == HistoryEntry::Command("NOOP".into())));
+    }
+}
@@ -0,0 +1,86 @@
+//! Round-trip times of the commands sent on the control connection.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Default number of round trips [`crate::FtpClient::latency`] is computed over.
+pub const DEFAULT_LATENCY_WINDOW: usize = 64;
+
+/// Statistics over the latest command round trips, from sending a command to
+/// reading its first reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// The latest round trip.
+    pub last: Duration,
+    /// Median round trip.
+    pub p50: Duration,
+    /// Round trip 95% of the others didn't exceed.
+    pub p95: Duration,
+    pub max: Duration,
+    /// Number of round trips the statistics cover.
+    pub samples: usize,
+}
+
+pub(crate) struct LatencyWindow {
+    samples: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl LatencyWindow {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(crate) fn record(&mut self, round_trip: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(round_trip);
+    }
+
+    pub(crate) fn stats(&self) -> Option<LatencyStats> {
+        let last = *self.samples.back()?;
+        let mut sorted = self.samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        // nearest rank
+        let percentile = |percent: usize| sorted[(sorted.len() * percent).div_ceil(100) - 1];
+        Some(LatencyStats {
+            last,
+            p50: percentile(50),
+            p95: percentile(95),
+            max: sorted[sorted.len() - 1],
+            samples: sorted.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn latency_percentiles() {
+        use super::LatencyWindow;
+        use std::time::Duration;
+
+        let mut window = LatencyWindow::new(20);
+        assert!(window.stats().is_none());
+        // the first five fall out of the window
+        for millis in (1..=25).rev() {
+            window.record(Duration::from_millis(millis));
+        }
+        let stats = window.stats().unwrap();
+        assert_eq!(stats.samples, 20);
+        assert_eq!(stats.last, Duration::from_millis(1));
+        assert_eq!(stats.p50, Duration::from_millis(10));
+        assert_eq!(stats.p95, Duration::from_millis(19));
+        assert_eq!(stats.max, Duration::from_millis(20));
+        let mut disabled = LatencyWindow::new(0);
+        disabled.record(Duration::from_millis(1));
+        assert!(disabled.stats().is_none());
+    }
+}
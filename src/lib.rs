@@ -1,6 +1,10 @@
 //! [crate] A simple and naive implementation of the FTP protocol.
 //! This library doesn't support all FTP commands. See [README.md].
-//! This library doesn't provide encripted data transmission.
+//! Encrypted connections (FTPS) are available with the `rustls` or `native-tls` feature.
+//! The `encoding` feature adds legacy file name encodings such as Shift-JIS.
+//! With the `tracing` feature every command and data transfer runs in a span.
+//! The `server` feature adds an embeddable server, `FtpServer`.
+//! The `serde` feature makes snapshots of remote trees serializable.
 //! # Example:
 //! ```no_run
 //! use simpleftp::FtpClient;
@@ -21,18 +25,131 @@
 //! }
 //!```
 
+use std::borrow::Cow;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Read;
 use std::io::Write;
 use std::net::Shutdown;
+use std::net::SocketAddr;
 use std::net::TcpStream;
 use std::net::ToSocketAddrs;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 
 use std::io::ErrorKind;
-#[allow(dead_code)]
+use std::io::IoSlice;
+
+mod archive;
+mod banner;
+mod builder;
+mod cache;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod credentials;
+mod encoding;
+mod entry;
+mod events;
+mod file;
+#[cfg(feature = "gssapi")]
+mod gssapi;
+mod history;
+#[doc(hidden)]
+pub mod json;
+mod keepalive;
+mod latency;
+mod listing;
+mod local;
+mod manifest;
+mod metrics;
+mod middleware;
+mod mirror;
+mod path;
+mod policy;
+mod poll;
+mod pool;
+mod probe;
+mod protocol;
+mod quirks;
+mod reply;
+#[cfg(feature = "server")]
+mod server;
+mod session;
+mod site;
+mod snapshot;
+mod status;
+mod stream;
+#[cfg(test)]
+mod testing;
+mod time;
+mod tls;
+mod trace;
+mod transcript;
+mod walk;
+#[cfg(all(feature = "zero-copy", target_os = "linux"))]
+mod zerocopy;
+
+pub use banner::{BannerPolicy, BannerStage};
+pub use builder::FtpClientBuilder;
+pub use builder::Resolver;
+#[cfg(feature = "chaos")]
+pub use chaos::{ChaosConfig, ChaosTransport, BOGUS_REPLY_CODE};
+pub use credentials::{CredentialProvider, Credentials, Secret};
+pub use encoding::{DecodePolicy, Encoding, EncodingMode};
+pub use entry::{EntryKind, FtpEntry, FtpPermissions, ListFormat, ListStyle};
+pub use events::ProtocolEvent;
+pub use file::FtpFile;
+#[cfg(feature = "gssapi")]
+pub use gssapi::{
+    SecurityContext, CONFIDENTIAL_REPLY, PRIVATE_REPLY, PROTECTED_REPLY, SECURITY_DATA_COMPLETE,
+    SECURITY_DATA_CONTINUE, SECURITY_DATA_NEEDED,
+};
+pub use history::{HistoryEntry, DEFAULT_HISTORY_SIZE};
+pub use keepalive::DEFAULT_IDLE_TIMEOUT;
+pub use latency::{LatencyStats, DEFAULT_LATENCY_WINDOW};
+pub use listing::{ListOptions, ListOrder};
+pub use local::UploadOutcome;
+pub use manifest::{Manifest, ManifestEntry};
+pub use metrics::Metrics;
+pub use middleware::{Middleware, Next};
+pub use mirror::{
+    Collision, MirrorAction, MirrorCollision, MirrorFile, MirrorOptions, MirrorProgress,
+    MirrorReport,
+};
+pub use path::RemotePath;
+pub use policy::ReplyPolicy;
+pub use poll::PollScheduler;
+pub use pool::{DEFAULT_BUFFER_COUNT, DEFAULT_BUFFER_SIZE};
+pub use probe::ServerReport;
+pub use protocol::{Command, DataType};
+pub use quirks::{Quirks, QuirksRegistry};
+pub use reply::{parse_reply, parse_reply_bytes, ReplyParser};
+#[cfg(feature = "server")]
+pub use server::{
+    AnonymousAccess, AuditAction, AuditEvent, AuthResult, Authenticator, FileInfo, FtpServer,
+    LocalStorage, StaticUsers, StorageBackend,
+};
+pub use session::{FtpSession, SessionGuard};
+pub use snapshot::{ChangeSet, RemoteSnapshot, SnapshotEntry};
+pub use status::SessionStatus;
+#[cfg(feature = "server")]
+pub use tls::ServerTlsConfig;
+pub use tls::TlsConfig;
+pub use walk::{SymlinkPolicy, WalkEntry, WalkOptions};
+
+use builder::Config;
+use cache::Cache;
+use cache::Cached;
+use history::History;
+use latency::LatencyWindow;
+use path::WorkingDir;
+use stream::Stream;
+use trace::Span;
+use transcript::Transcript;
 
 /// A generic FTP representation enum
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum FtpError {
     LoginError(String),
@@ -40,6 +157,26 @@ pub enum FtpError {
     FileError(String),
     CommandError(String),
     ResponseError(String),
+    TransferStalled(String),
+    SizeLimitExceeded(String),
+    InvalidArgument(String),
+    /// The server closed the session, e.g. with a 421 reply after an idle
+    /// timeout, and the command could not be retried on a new one.
+    SessionExpired(String),
+    /// A rename was refused because its destination already exists.
+    DestinationExists(String),
+    /// A transfer was started while another one still holds the data
+    /// connection, e.g. from the closure moving the data of the first.
+    TransferInProgress(String),
+    /// The server holds other data than sent, e.g. a file of another size.
+    IntegrityError(String),
+    /// A remote file isn't in the state an operation expected, e.g. another
+    /// writer appended to it first.
+    Conflict(String),
+    /// The server lacks the command or extension an operation needs, named
+    /// in the error, e.g. `MLSD` or `MDTM`. Callers may fall back on another
+    /// way to do the same.
+    Unsupported(String),
 }
 impl From<std::io::Error> for FtpError {
     fn from(error: std::io::Error) -> Self {
@@ -52,12 +189,37 @@ impl From<std::io::Error> for FtpError {
             | ErrorKind::NotConnected => {
                 Self::ConnectionError("IO resource connection failed".into())
             }
-            ErrorKind::TimedOut => Self::ConnectionError("connection timed out".into()),
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => {
+                Self::ConnectionError("connection timed out".into())
+            }
             _ => Self::FileError("Error accessing file/reader/writer".into()),
         }
     }
 }
 
+impl FtpError {
+    /// Short label of the error variant, e.g. `connection`, to group errors in
+    /// metrics.
+    pub fn class(&self) -> &'static str {
+        match self {
+            FtpError::LoginError(_) => "login",
+            FtpError::ConnectionError(_) => "connection",
+            FtpError::FileError(_) => "file",
+            FtpError::CommandError(_) => "command",
+            FtpError::ResponseError(_) => "response",
+            FtpError::TransferStalled(_) => "stalled",
+            FtpError::SizeLimitExceeded(_) => "size_limit",
+            FtpError::InvalidArgument(_) => "invalid_argument",
+            FtpError::SessionExpired(_) => "session_expired",
+            FtpError::DestinationExists(_) => "destination_exists",
+            FtpError::TransferInProgress(_) => "transfer_in_progress",
+            FtpError::IntegrityError(_) => "integrity",
+            FtpError::Conflict(_) => "conflict",
+            FtpError::Unsupported(_) => "unsupported",
+        }
+    }
+}
+
 impl std::fmt::Display for FtpError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -66,18 +228,153 @@ impl std::fmt::Display for FtpError {
             FtpError::FileError(error) => write!(f, "File Error: {}", error),
             FtpError::CommandError(error) => write!(f, "Command Error: {}", error),
             FtpError::ResponseError(error) => write!(f, "Response Error: {}", error),
+            FtpError::TransferStalled(error) => write!(f, "Transfer Stalled: {}", error),
+            FtpError::SizeLimitExceeded(error) => write!(f, "Size Limit Exceeded: {}", error),
+            FtpError::InvalidArgument(error) => write!(f, "Invalid Argument: {}", error),
+            FtpError::SessionExpired(error) => write!(f, "Session Expired: {}", error),
+            FtpError::DestinationExists(error) => write!(f, "Destination Exists: {}", error),
+            FtpError::TransferInProgress(error) => write!(f, "Transfer In Progress: {}", error),
+            FtpError::IntegrityError(error) => write!(f, "Integrity Error: {}", error),
+            FtpError::Conflict(error) => write!(f, "Conflict: {}", error),
+            FtpError::Unsupported(feature) => write!(f, "Unsupported: {}", feature),
         }
     }
 }
 /// Generic Result type defaults to Result<T, FtpError>
 pub type Result<T> = std::result::Result<T, FtpError>;
 
-#[doc(hidden)]
 /// FTP Response Implementation
-#[allow(dead_code)]
-pub(crate) struct Response {
+#[derive(Debug, Clone)]
+pub struct Response {
     code: usize,
     message: String,
+    lines: Vec<String>,
+    line_codes: Vec<Option<usize>>,
+}
+
+impl Response {
+    /// Create a response, e.g. from a [`Middleware`] answering without the server.
+    pub fn new(code: usize, message: impl Into<String>) -> Self {
+        let message = message.into();
+        let (lines, line_codes) = reply::split_lines(code, &message);
+        Self {
+            code,
+            message,
+            lines,
+            line_codes,
+        }
+    }
+
+    /// Three digit reply code sent by the server.
+    pub fn code(&self) -> usize {
+        self.code
+    }
+
+    /// Reply text following the code, including continuation lines of multi-line replies.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Text of each reply line, without its code and trimmed.
+    ///
+    /// # Example
+    /// ```
+    /// use simpleftp::parse_reply;
+    ///
+    /// let reply = parse_reply("211-Features:\r\n MDTM\r\n SIZE\r\n211 End\r\n").unwrap();
+    /// assert_eq!(reply.lines(), ["Features:", "MDTM", "SIZE", "End"]);
+    /// assert_eq!(reply.line_codes(), [Some(211), None, None, Some(211)]);
+    /// ```
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Code starting each of [`Response::lines`]: the reply code for the first
+    /// and last lines, the code of intermediate lines carrying one, `None` for
+    /// plain continuation lines.
+    pub fn line_codes(&self) -> &[Option<usize>] {
+        &self.line_codes
+    }
+}
+
+/// Outcome of a successful login.
+///
+/// # Example
+/// ```no_run
+/// # use simpleftp::FtpClient;
+/// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+/// let login = client.login("user", "password").unwrap();
+/// for line in login.lines() {
+///     println!("{}", line);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginInfo {
+    lines: Vec<String>,
+    account_required: bool,
+}
+
+impl LoginInfo {
+    fn new(response: &Response, account_required: bool) -> Self {
+        Self {
+            lines: response.lines().to_vec(),
+            account_required,
+        }
+    }
+
+    /// Lines of the 230 reply, where servers put messages of the day, quota
+    /// notices or the home directory.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Whether the server asked for account information with `ACCT` before
+    /// letting the user in.
+    pub fn account_required(&self) -> bool {
+        self.account_required
+    }
+}
+
+/// Details of a completed file transfer, e.g. to find which passive port a
+/// firewall had to let through.
+///
+/// # Example
+/// ```no_run
+/// # use simpleftp::FtpClient;
+/// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+/// client.login("user", "password").unwrap();
+/// client.get("/pub/file.txt", &mut std::io::sink()).unwrap();
+/// let stats = client.last_transfer().unwrap();
+/// println!("{} bytes from {}", stats.bytes(), stats.peer_addr());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferStats {
+    bytes: u64,
+    duration: Duration,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+}
+
+impl TransferStats {
+    /// Bytes moved on the data connection.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Time spent moving the data.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Local address of the data connection.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Server address of the data connection, with the port given by `PASV`.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
 }
 
 /* Response codes definitions */
@@ -112,6 +409,10 @@ pub const CLOSING_DATA_CONNECTION: usize = 226;
 pub const CANNOT_OPEN_DATA_CONNECTION: usize = 425;
 pub const TRANSFER_ABORTED: usize = 426;
 pub const PASSIVE_MODE: usize = 227;
+pub const EXTENDED_PASSIVE_MODE: usize = 229;
+
+// Security
+pub const AUTH_OK: usize = 234;
 
 // Loging messages
 pub const LOGGED_IN: usize = 230;
@@ -135,6 +436,9 @@ pub const FILE_ACTION_ABORTED: usize = 552;
 pub const FILE_NAME_NOT_ALLOWED: usize = 553;
 pub const DIRECTORY_ALREADY_EXISTS: usize = 521;
 
+/// How long to wait for the second reply of an aborted transfer.
+const ABORT_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
 ///A simple FTP Client implementation.
 /// Offers FTP commands to:
 /// * Login
@@ -144,7 +448,41 @@ pub const DIRECTORY_ALREADY_EXISTS: usize = 521;
 /// * List files
 ///
 pub struct FtpClient {
-    reader: BufReader<TcpStream>,
+    reader: BufReader<Box<dyn Stream>>,
+    config: Config,
+    transcript: Option<Transcript>,
+    layers: Vec<Box<dyn Middleware>>,
+    session_bytes: u64,
+    history: History,
+    latency: LatencyWindow,
+    /// When the last command was sent.
+    last_activity: Instant,
+    /// Idle timeout of the session, once asked.
+    idle_limit: Option<Duration>,
+    cache: Cache,
+    /// Where the session is, to return there after logging in again.
+    working_dir: WorkingDir,
+    credentials: Option<Box<dyn CredentialProvider>>,
+    data_protected: bool,
+    /// Address the control connection was opened to, for reconnecting.
+    peer: SocketAddr,
+    /// Whether the server closed the control connection or announced it would.
+    disconnected: bool,
+    last_transfer: Option<TransferStats>,
+    /// Local and server addresses of the latest data connection, taken when
+    /// it opened since a reset connection no longer tells them.
+    data_addrs: Option<(SocketAddr, SocketAddr)>,
+    /// Whether a transfer holds the data connection until its final reply.
+    transferring: bool,
+    /// Listing layout told by `SYST`, once asked.
+    list_style: Option<Option<ListStyle>>,
+    /// End of the operation run by [`FtpClient::with_deadline`], if any.
+    deadline: Option<Instant>,
+    /// Workarounds waiting for a `SYST` reply the server only gives once
+    /// logged in.
+    pending_quirks: Option<QuirksRegistry>,
+    #[cfg(feature = "gssapi")]
+    security: Option<Box<dyn SecurityContext>>,
 }
 
 impl FtpClient {
@@ -167,22 +505,188 @@ impl FtpClient {
     ///}
     ///```
     pub fn connect(address: impl ToSocketAddrs) -> Result<Self> {
-        let reader = BufReader::new(TcpStream::connect(address)?);
-        let mut client = FtpClient { reader };
+        let config = Config::default();
+        let stream = config.open_any(address.to_socket_addrs()?.collect(), None)?;
+        Self::with_config(stream, config)
+    }
+
+    /// Open a FTP connection, giving up when the server doesn't accept it or
+    /// doesn't greet within `timeout`. Each resolved address gets the full timeout.
+    ///
+    /// # Arguments
+    /// `address`     Server address to connect
+    /// `timeout`     Limit for the connection and the greeting
+    ///
+    /// # Example
+    /// ```no_run
+    /// use simpleftp::FtpClient;
+    /// use std::time::Duration;
+    ///
+    /// let mut client = FtpClient::connect_timeout("127.0.0.1:21", Duration::from_secs(5)).unwrap();
+    /// client.login("user", "password").unwrap();
+    /// ```
+    pub fn connect_timeout(address: impl ToSocketAddrs, timeout: Duration) -> Result<Self> {
+        let config = Config {
+            connect_timeout: Some(timeout),
+            ..Config::default()
+        };
+        let stream = config.open_any(address.to_socket_addrs()?.collect(), None)?;
+        Self::with_config(stream, config)
+    }
+
+    /// Forget cached listings and metadata, see [`FtpClientBuilder::cache_ttl`].
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Create a builder to customize the connection before opening it.
+    pub fn builder() -> FtpClientBuilder {
+        FtpClientBuilder::new()
+    }
+
+    /// Wrap an already connected control stream and wait for the server greeting.
+    pub(crate) fn with_config(stream: TcpStream, config: Config) -> Result<Self> {
+        let mut stream = config.wrap(stream);
+        if let Some(tls) = config.tls.as_ref().filter(|tls| tls.implicit) {
+            stream = tls.wrap(&config.tls_domain, stream)?;
+        }
+        let peer = stream.socket().peer_addr()?;
+        let reader = BufReader::new(stream);
+        let mut client = FtpClient {
+            reader,
+            history: History::new(config.history_size.unwrap_or(DEFAULT_HISTORY_SIZE)),
+            latency: LatencyWindow::new(config.latency_window.unwrap_or(DEFAULT_LATENCY_WINDOW)),
+            last_activity: Instant::now(),
+            idle_limit: None,
+            cache: Cache::new(config.cache_ttl),
+            working_dir: WorkingDir::Login,
+            config,
+            transcript: None,
+            layers: Vec::new(),
+            session_bytes: 0,
+            credentials: None,
+            data_protected: false,
+            peer,
+            disconnected: false,
+            last_transfer: None,
+            data_addrs: None,
+            transferring: false,
+            list_style: None,
+            deadline: None,
+            pending_quirks: None,
+            #[cfg(feature = "gssapi")]
+            security: None,
+        };
 
-        if client.parse_response()?.code != SERVICE_READY {
+        let socket = client.reader.get_ref().socket();
+        socket.set_read_timeout(client.config.connect_timeout)?;
+        let greeting = client.parse_response()?;
+        if greeting.code != SERVICE_READY {
             return Err(FtpError::ConnectionError(
                 "Server not ready for conenctions".into(),
             ));
         }
+        client.check_banner(BannerStage::Greeting, &greeting)?;
+        client.reader.get_ref().socket().set_read_timeout(None)?;
+        if let Some(tls) = client.config.tls.clone() {
+            let domain = client.config.tls_domain.clone();
+            client.secure(&tls, &domain)?;
+        }
         Ok(client)
     }
 
+    /// Address of the server the control connection was established with.
+    /// Useful to know which address succeeded when a host resolves to several.
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.reader.get_ref().socket().peer_addr()?)
+    }
+
+    /// The latest commands and replies exchanged with the server, oldest first.
+    /// Handy to attach protocol context to error reports.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// if let Err(error) = client.login("user", "password") {
+    ///     eprintln!("{}", error);
+    ///     for entry in client.history() {
+    ///         eprintln!("    {}", entry);
+    ///     }
+    /// }
+    /// ```
+    pub fn history(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.history.entries()
+    }
+
+    /// Round-trip times of the latest commands, `None` before any reply.
+    /// Handy to pick timeouts suiting the server, or to back "the server is
+    /// slow" with numbers.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// # use std::time::Duration;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let latency = client.latency().unwrap();
+    /// println!("p50 {:?}, p95 {:?}", latency.p50, latency.p95);
+    /// let limit = latency.p95 * 20 + Duration::from_secs(1);
+    /// let names = client.with_deadline(limit, |client| client.name_list("/pub")).unwrap();
+    /// ```
+    pub fn latency(&self) -> Option<LatencyStats> {
+        self.latency.stats()
+    }
+
+    /// Total bytes moved on data connections during this session.
+    pub fn session_bytes(&self) -> u64 {
+        self.session_bytes
+    }
+
+    /// Details of the last completed file transfer, `None` before any or when
+    /// the addresses of its data connection couldn't be read. Listings aren't
+    /// counted.
+    pub fn last_transfer(&self) -> Option<&TransferStats> {
+        self.last_transfer.as_ref()
+    }
+
+    /// Start recording a sanitized transcript of the session to `writer`:
+    /// commands, replies with their round-trip time and transferred byte counts.
+    /// Passwords and account information are redacted.
+    /// Replaces any transcript already being recorded.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.record_transcript(std::fs::File::create("audit.log").unwrap());
+    /// client.login("user", "password").unwrap();
+    /// client.logout().unwrap();
+    /// ```
+    pub fn record_transcript(&mut self, writer: impl Write + Send + 'static) {
+        self.transcript = Some(Transcript::new(Box::new(writer)));
+    }
+
+    /// Pause or resume the transcript recording without dropping its writer.
+    pub fn set_transcript_enabled(&mut self, enabled: bool) {
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.set_enabled(enabled);
+        }
+    }
+
+    /// Stop recording the transcript and give back its writer.
+    pub fn stop_transcript(&mut self) -> Option<Box<dyn Write + Send>> {
+        self.transcript.take().map(Transcript::into_inner)
+    }
+
     /// Perform Login to server.
     /// # Arguments
     /// `username `   username for login
     /// `password`    password for given us er
     ///
+    /// # Returns
+    /// The text of the server's 230 reply, see [`LoginInfo`].
+    ///
     /// # Errors
     /// May return LoginError if login is not successful.
     ///
@@ -197,211 +701,1011 @@ impl FtpClient {
     ///     Ok(())
     /// }
     /// ```
-    pub fn login(&mut self, username: &str, password: &str) -> Result<()> {
+    pub fn login(&mut self, username: &str, password: &str) -> Result<LoginInfo> {
         // send username
-        let response = self.write_cmd(format!("USER {}", username))?;
-
-        if response.code != NEED_PASSWORD && response.code != LOGGED_IN {
-            return Err(FtpError::LoginError(format!(
-                "Could not authenticate: {}",
-                response.code
-            )));
+        let response = self.write_cmd(Command::User(username))?;
+        match response.code {
+            LOGGED_IN => return self.logged_in(&response, false),
+            NEED_PASSWORD => {}
+            code => {
+                return Err(self.refused(FtpError::LoginError(format!(
+                    "Could not authenticate: {}",
+                    code
+                ))))
+            }
         }
 
         // send password
-        let response = self.write_cmd(format!("PASS {}", password))?;
+        let response = self.write_cmd(Command::Pass(&Secret::from(password)))?;
         if response.code != LOGGED_IN {
-            return Err(FtpError::LoginError(format!(
+            return Err(self.refused(FtpError::LoginError(format!(
                 "Invalid username/password combination: {}",
                 response.code
-            )));
+            ))));
         }
 
-        Ok(())
+        self.logged_in(&response, false)
     }
 
-    /// Write a command to the server
-    /// # Arguments
-    /// `command`     text command to be sent to server
-    /// # Errors
-    /// Errors when failing to write to server or to parse a response.
-    fn write_cmd(&mut self, command: impl AsRef<str>) -> Result<Response> {
-        self.reader
-            .get_mut()
-            .write_all(format!("{}\r\n", command.as_ref()).as_bytes())?;
-        self.parse_response()
+    /// Install a [`Middleware`] layer. Commands go through layers in the order
+    /// they were added before reaching the server.
+    pub fn add_layer(&mut self, layer: impl Middleware + 'static) {
+        self.layers.push(Box::new(layer));
     }
 
-    /// Provide user account after login
-    /// # Arguments
-    /// account string representing the user account for login
+    /// Perform Login to a server issuing a one-time-password challenge.
     ///
-    /// # Errors
-    /// Errors when failing to write to server or to parse response.
-    pub fn account(&mut self, account: impl AsRef<str>) -> Result<()> {
-        match self.write_cmd(format!("ACCT {}", account.as_ref()))?.code {
-            LOGGED_IN => Ok(()),
-            _ => Err(FtpError::LoginError("Invalid account information".into())),
-        }
-    }
-
-    /// Retrieve a file from the server.
+    /// The text of the server's 331 reply to USER (e.g. `otp-md5 487 dog2`) is
+    /// given to `respond`, whose result is sent as the password.
     ///
     /// # Arguments
-    /// `file`    Name of the file (includes path) on the server to be retrieved
-    /// `dest`    Writer destination to dump data setn from server
+    /// `username`  username for login
+    /// `respond`   computes the response to the challenge
     ///
-    /// # Examples
+    /// # Example
     /// ```no_run
-    /// use simpleftp::*;
-    /// use simpleftp::Result;
-    /// fn main() -> Result<()> {
-    ///     let mut client = FtpClient::connect("127.0.0.1:21")?;
-    ///     client.login("user", "passowrd")?;
-    ///
-    ///     let mut destination = std::fs::File::open("log.txt").expect("Opening file");
-    ///     client.get("/home/will/code/log.txt", &mut destination)?;
-    ///     client.logout()?;
-    ///     Ok(())
-    /// }
-    ///
+    /// # use simpleftp::FtpClient;
+    /// # fn otp_response(challenge: &str) -> String { unimplemented!() }
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client
+    ///     .login_with_challenge("user", |challenge| Ok(otp_response(challenge)))
+    ///     .unwrap();
     /// ```
+    ///
     /// # Errors
-    /// Errors when failing to write to server or to parse response or due to connection problems.
-    pub fn get(&mut self, file: impl AsRef<str>, dest: &mut impl Write) -> Result<()> {
-        let mut stream = self.pasv()?;
-        let response = self.write_cmd(format!("RETR {}", file.as_ref()))?;
-        if response.code != FILE_OK && response.code != ALREADY_OPEN {
-            return Err(FtpError::CommandError(
-                "Could not process file retrieve".into(),
-            ));
+    /// When the callback fails or the server rejects the response.
+    pub fn login_with_challenge<F, S>(&mut self, username: &str, respond: F) -> Result<LoginInfo>
+    where
+        F: FnOnce(&str) -> Result<S>,
+        S: Into<Secret>,
+    {
+        let response = self.write_cmd(Command::User(username))?;
+        match response.code {
+            LOGGED_IN => return self.logged_in(&response, false),
+            NEED_PASSWORD => {}
+            code => {
+                return Err(self.refused(FtpError::LoginError(format!(
+                    "Could not authenticate: {}",
+                    code
+                ))))
+            }
         }
-        std::io::copy(&mut stream, dest)?;
-        #[cfg(feature = "debug")]
-        println!("Closing connection");
-        match self.parse_response()?.code {
-            CLOSING_DATA_CONNECTION => Ok(()),
-            _ => Err(FtpError::ConnectionError("Error closing connection".into())),
+        let answer = respond(response.message.trim())?.into();
+        let response = self.write_cmd(Command::Pass(&answer))?;
+        if response.code != LOGGED_IN {
+            return Err(self.refused(FtpError::LoginError(format!(
+                "Challenge response rejected: {}",
+                response.code
+            ))));
         }
+        self.logged_in(&response, false)
     }
 
-    /// Sends a file to the server.
-    ///
-    /// # Arguments
-    /// `file`    Name of the file (includes path) on the server to be retrieved
-    /// `source`  Reader stream containing data to send to server
-    ///
-    /// # Examples
-    /// ```no_run
-    ///  use simpleftp::FtpClient;
-    ///  use simpleftp::Result;
-    ///
-    /// fn main() -> Result<()> {
-    ///     let mut client = FtpClient::connect("127.0.0.1:21")?;
-    ///     client.login("user", "passowrd/")?;
-    ///
-    ///     let mut source = std::fs::File::create("log.txt").expect("Opening file");
-    ///     client.put("/home/will/code/log.txt", &mut source)?;
-    ///     client.logout()?;
-    ///     Ok(())
-    /// }
-    /// ```
-    /// # Errors
-    /// Errors when failing to write to server or to parse response or due to connection problems.
-    /// May also fail when reading from the source stream.
-    pub fn put(&mut self, file: impl AsRef<str>, source: &mut impl Read) -> Result<()> {
-        self.store_cmd(file, source, false)?;
-        Ok(())
-    }
-
-    /// Sends a file to the server and stories in a unique location under current directory.
+    /// Perform Login with credentials obtained from `provider`.
     ///
-    /// # Arguments
-    /// `file`    Name of the file (includes path) on the server to be retrieved
-    /// `source`  Reader stream containing data to send to server
+    /// The provider is also used to log in again on a new connection when the
+    /// server drops the session, e.g. with a 421 reply after an idle timeout.
+    /// The command that found the session closed is retried once if repeating
+    /// it is harmless, like `SIZE` or `PASV`, otherwise it fails with
+    /// [`FtpError::SessionExpired`]. The new session returns to the working
+    /// directory of the old one, known from `PWD` replies and the directory
+    /// changes since. After a relative change from a directory never asked
+    /// with `PWD` it stays in the login directory and the command fails with
+    /// [`FtpError::SessionExpired`].
     ///
-    ///  # Returns
-    ///  A result string with name of the file created
+    /// Credentials are only held while logging in and are wiped from memory
+    /// afterwards. The provider is kept to ask for them again when a new login
+    /// is needed, see [`FtpClient::relogin`].
     ///
-    /// # Examples
+    /// # Example
     /// ```no_run
-    ///  use simpleftp::FtpClient;
-    ///  use simpleftp::Result;
-    ///
-    /// fn main() -> Result<()> {
-    ///     let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
-    ///     client.login("user", "passowrd/").unwrap();
+    /// use simpleftp::{Credentials, FtpClient};
     ///
-    ///     let mut source = std::fs::File::create("log.txt").expect("Opening file");
-    ///     client.put_unique(&mut source).unwrap();
-    ///     client.logout().unwrap();
-    ///     Ok(())
-    /// }
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client
+    ///     .login_with(|| {
+    ///         let password = std::env::var("FTP_PASSWORD").unwrap_or_default();
+    ///         Ok(Credentials::new("user", password))
+    ///     })
+    ///     .unwrap();
     /// ```
+    ///
     /// # Errors
-    /// Errors when failing to write to server or to parse response or due to connection problems.
-    /// May also fail when reading from the source stream.
-    pub fn put_unique(&mut self, source: &mut impl Read) -> Result<String> {
-        self.store_cmd("", source, true)
+    /// When the provider fails or the server rejects the credentials.
+    pub fn login_with(&mut self, provider: impl CredentialProvider + 'static) -> Result<LoginInfo> {
+        self.credentials = Some(Box::new(provider));
+        self.relogin()
     }
 
-    /// Sends a file to the server. If file exists, append to it.
-    ///
-    /// # Arguments
-    /// `file`    Name of the file (includes path) on the server to be retrieved
-    /// `source`  Reader stream containing data to send to server
-    ///
-    /// # Examples
-    /// ```no_run
-    ///  use simpleftp::FtpClient;
-    ///  use simpleftp::Result;
-    ///
-    /// fn main() -> Result<()> {
-    ///     let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
-    ///     client.login("user", "passowrd/").unwrap();
+    /// Log in again with credentials freshly requested from the provider given
+    /// to [`FtpClient::login_with`].
     ///
-    ///     let mut source = std::fs::File::create("log.txt").expect("Opening file");
-    ///     client.append("/home/will/log.txt/", &mut source).unwrap();
-    ///     client.logout().unwrap();
-    ///     Ok(())
-    /// }
-    /// ```
     /// # Errors
-    /// Errors when failing to write to server or to parse response or due to connection problems.
-    /// May also fail when reading from the source stream.
-    pub fn append(&mut self, file: impl AsRef<str>, source: &mut impl Read) -> Result<()> {
-        self.store_cmd(file.as_ref(), source, false)?;
-        Ok(())
-    }
-    fn store_cmd(
-        &mut self,
-        file: impl AsRef<str>,
-        source: &mut impl Read,
-        unique: bool,
-    ) -> Result<String> {
-        let mut stream = self.pasv()?;
-        let response = if unique {
-            self.write_cmd(format!("STOU {}", file.as_ref()))?
-        } else {
-            self.write_cmd(format!("STOR {}", file.as_ref()))?
+    /// When no provider was given, the provider fails or the login is rejected.
+    pub fn relogin(&mut self) -> Result<LoginInfo> {
+        let credentials = match &self.credentials {
+            Some(provider) => provider.credentials()?,
+            None => {
+                return Err(FtpError::LoginError(
+                    "No credential provider to log in with".into(),
+                ))
+            }
         };
-
-        if response.code != FILE_OK {
-            return Err(FtpError::CommandError("Could not process file STOR".into()));
+        let response = self.write_cmd(Command::User(&credentials.username))?;
+        let response = match response.code {
+            LOGGED_IN => return self.logged_in(&response, false),
+            NEED_PASSWORD => self.write_cmd(Command::Pass(&credentials.password))?,
+            code => {
+                return Err(self.refused(FtpError::LoginError(format!(
+                    "Could not authenticate: {}",
+                    code
+                ))))
+            }
+        };
+        match (response.code, &credentials.account) {
+            (LOGGED_IN, _) => self.logged_in(&response, false),
+            (NEED_ACCOUNT, Some(account)) => match self.write_cmd(Command::Acct(account))? {
+                response if response.code == LOGGED_IN => self.logged_in(&response, true),
+                _ => Err(self.refused(FtpError::LoginError("Invalid account information".into()))),
+            },
+            (code, _) => Err(self.refused(FtpError::LoginError(format!(
+                "Invalid username/password combination: {}",
+                code
+            )))),
         }
-        #[cfg(feature = "debug")]
-        println!("Copying file:{}", file.as_ref());
+    }
 
-        std::io::copy(source, &mut stream)?;
+    /// Reconnect to the server and log in with the credential provider after
+    /// it dropped the session, then return to the working directory.
+    /// Recorders, layers and settings carry over.
+    fn resume_session(&mut self) -> Result<()> {
+        let working_dir = std::mem::take(&mut self.working_dir);
+        let stream = self.config.open_any(vec![self.peer], self.deadline)?;
+        let mut fresh = FtpClient::with_config(stream, self.config.clone())?;
+        std::mem::swap(&mut self.reader, &mut fresh.reader);
+        self.data_protected = fresh.data_protected;
+        #[cfg(feature = "gssapi")]
+        {
+            self.security = None;
+        }
+        self.disconnected = false;
+        self.cache.clear();
+        self.relogin()?;
+        let dir = match working_dir {
+            WorkingDir::Login => return Ok(()),
+            WorkingDir::Known(dir) => dir,
+            WorkingDir::Unknown => {
+                return Err(FtpError::SessionExpired(
+                    "Server closed the session, its working directory is unknown".into(),
+                ))
+            }
+        };
+        let command = Command::Cwd(dir.as_str());
+        let response = self.dispatch_cmd(command)?;
+        if !self.accepts(&command, &response) {
+            return Err(FtpError::SessionExpired(format!(
+                "Server closed the session, can't return to {}: {}",
+                dir,
+                response.message.trim()
+            )));
+        }
+        Ok(())
+    }
 
-        #[cfg(feature = "debug")]
-        println!("Closing connection");
+    /// Write a command to the server through the installed layers. Secret
+    /// commands bypass the layers so they never see the secret, and their wire
+    /// line is built in a [`Secret`] wiped after use.
+    /// # Arguments
+    /// `command`     command to be sent to server
+    /// # Errors
+    /// Errors when an argument can't be sent, when failing to write to server
+    /// or to parse a response.
+    fn write_cmd(&mut self, command: Command) -> Result<Response> {
+        let span = Span::command(&command);
+        self.measure(|metrics| metrics.command(command.name()));
+        let response = span.in_scope(|| self.dispatch_resumable(command));
+        match &response {
+            Ok(response) => span.record_code(response.code),
+            Err(error) => self.measure(|metrics| metrics.error(error)),
+        }
+        let logged_in = matches!(&response, Ok(response) if response.code == LOGGED_IN);
+        if logged_in
+            && matches!(
+                command,
+                Command::User(_) | Command::Pass(_) | Command::Acct(_)
+            )
+        {
+            if let Some(registry) = self.pending_quirks.take() {
+                // a failure shows on the next command, the login itself worked
+                let _ = self.apply_system_quirks(registry);
+            }
+        }
+        response
+    }
+
+    /// Whether the reply policy takes `response` as the success of `command`.
+    pub(crate) fn accepts(&self, command: &Command, response: &Response) -> bool {
+        self.config
+            .reply_policy
+            .accepts(command.name(), response.code)
+    }
+
+    /// Send `command`, turning a reply the reply policy rejects into the error
+    /// `refused` makes of its text, or [`FtpError::Unsupported`] when the
+    /// server doesn't implement the command.
+    pub(crate) fn expect(
+        &mut self,
+        command: Command,
+        refused: fn(String) -> FtpError,
+    ) -> Result<Response> {
+        let response = self.write_cmd(command)?;
+        if policy::is_unimplemented(response.code) {
+            return Err(self.refused(FtpError::Unsupported(command.name().into())));
+        }
+        if !self.accepts(&command, &response) {
+            let error = refused(format!("Invalid response {}", response.message));
+            return Err(self.refused(error));
+        }
+        Ok(response)
+    }
+
+    /// Report `error`, made of a negative reply, to the installed metrics and
+    /// return it. Failures of the connection itself are reported by `write_cmd`.
+    pub(crate) fn refused(&self, error: FtpError) -> FtpError {
+        self.measure(|metrics| metrics.error(&error));
+        error
+    }
+
+    /// Report to the installed metrics, if any.
+    fn measure(&self, record: impl FnOnce(&dyn Metrics)) {
+        if let Some(metrics) = &self.config.metrics {
+            record(metrics.as_ref());
+        }
+    }
+
+    /// Report the outcome of a data transfer started at `started`, recording the
+    /// byte count of a success with `bytes`.
+    fn measure_transfer<T>(
+        &self,
+        verb: &str,
+        started: Instant,
+        result: &Result<T>,
+        bytes: impl FnOnce(&dyn Metrics, &T),
+    ) {
+        self.measure(|metrics| match result {
+            Ok(value) => {
+                bytes(metrics, value);
+                metrics.transfer_duration(verb, started.elapsed());
+            }
+            Err(error) => metrics.error(error),
+        });
+    }
+
+    /// Dispatch `command` on a live session, reconnecting first when the
+    /// server dropped it since the last command.
+    fn dispatch_resumable(&mut self, command: Command) -> Result<Response> {
+        if self.disconnected && self.credentials.is_some() {
+            self.resume_session()?;
+        }
+        let response = self.dispatch_cmd(command);
+        if !self.disconnected || matches!(command, Command::Quit) {
+            return response;
+        }
+        if self.credentials.is_none() || !command.is_idempotent() {
+            let reason = match response {
+                Ok(response) => format!("{} {}", response.code, response.message.trim()),
+                Err(error) => error.to_string(),
+            };
+            return Err(FtpError::SessionExpired(format!(
+                "Server closed the session during {}: {}",
+                command.name(),
+                reason
+            )));
+        }
+        self.resume_session()?;
+        self.dispatch_cmd(command)
+    }
+
+    fn dispatch_cmd(&mut self, command: Command) -> Result<Response> {
+        if command.is_secret() {
+            let line = command.encode_secret()?;
+            return self.send_line(line.expose(), &command.logged());
+        }
+        let line = command.encode()?;
+        let line = line.trim_end_matches("\r\n");
+        if self.layers.is_empty() {
+            return self.send_cmd(line);
+        }
+        let mut layers = std::mem::take(&mut self.layers);
+        let response = Next::new(&mut layers, self).run(line);
+        layers.append(&mut self.layers);
+        self.layers = layers;
+        response
+    }
+
+    /// Write a command line to the server, bypassing the layers
+    pub(crate) fn send_cmd(&mut self, command: &str) -> Result<Response> {
+        self.cache.observe(command);
+        let response = self.send_line(&format!("{}\r\n", command), &events::redact(command))?;
+        self.working_dir.observe(command, &response);
+        Ok(response)
+    }
+
+    /// Write a raw command line, recording `logged` in place of it.
+    fn send_line(&mut self, line: &str, logged: &str) -> Result<Response> {
+        protocol::check_line(line, logged)?;
+        self.limit_control_timeouts()?;
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.command(logged)?;
+        }
+        #[cfg(feature = "gssapi")]
+        if let Some(context) = self.security.as_mut() {
+            let protected = gssapi::protect(context, line.trim_end_matches("\r\n"))?;
+            self.reader.get_mut().write_all(protected.as_bytes())?;
+        } else {
+            self.write_line(line)?;
+        }
+        #[cfg(not(feature = "gssapi"))]
+        self.write_line(line)?;
+        let sent = Instant::now();
+        self.last_activity = sent;
+        self.history.command(logged);
+        self.emit(ProtocolEvent::CommandSent(logged.to_string()));
+        let response = self.parse_response()?;
+        let round_trip = sent.elapsed();
+        self.latency.record(round_trip);
+        let verb = logged.split(' ').next().unwrap_or_default();
+        self.measure(|metrics| metrics.round_trip(verb, round_trip));
+        Ok(response)
+    }
+
+    /// Write a command line in the configured encoding. The encoded copy may
+    /// hold a password and is wiped after use.
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let written = match self.config.codec.encode(line)? {
+            Cow::Borrowed(bytes) => self.reader.get_mut().write_all(bytes),
+            Cow::Owned(mut bytes) => {
+                let written = self.reader.get_mut().write_all(&bytes);
+                credentials::wipe(&mut bytes);
+                written
+            }
+        };
+        self.disconnected |= written.is_err();
+        Ok(written?)
+    }
+
+    /// Notify the attached event sender, if any. A dropped receiver is not an error.
+    fn emit(&self, event: ProtocolEvent) {
+        if let Some(sender) = &self.config.events {
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Copy data between the data connection and the user stream,
+    /// reporting progress as it goes.
+    ///
+    /// # Arguments
+    /// `source`    stream to read from
+    /// `dest`      stream to write to
+    /// `socket`    data connection socket, to enforce the transfer deadline
+    ///
+    /// # Returns
+    /// The number of bytes copied.
+    fn transfer_data(
+        &mut self,
+        source: &mut impl Read,
+        dest: &mut impl Write,
+        socket: &TcpStream,
+    ) -> Result<u64> {
+        let (deadline, watched) = self.transfer_watch();
+        let started = Instant::now();
+        let mut last_progress = started;
+        let mut buffer = self.config.buffers.take();
+        let mut total = 0u64;
+        loop {
+            if watched {
+                self.limit_data_timeouts(socket, deadline)?;
+            }
+            let count = match source.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(count) => count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(self.data_error(error, deadline, last_progress)),
+            };
+            self.check_size_limits(total + count as u64)?;
+            dest.write_all(&buffer[..count])
+                .map_err(|error| self.data_error(error, deadline, last_progress))?;
+            last_progress = Instant::now();
+            total += count as u64;
+            self.session_bytes += count as u64;
+            self.emit(ProtocolEvent::TransferProgress { bytes: total });
+        }
+        dest.flush()?;
+        self.finish_transfer(total, started)
+    }
+
+    /// Write `buffers` to the data connection with as few system calls as the
+    /// stream allows, under the same limits as [`FtpClient::transfer_data`].
+    fn transfer_vectored(
+        &mut self,
+        buffers: &[&[u8]],
+        dest: &mut impl Write,
+        socket: &TcpStream,
+    ) -> Result<u64> {
+        let (deadline, watched) = self.transfer_watch();
+        let length = buffers.iter().map(|buffer| buffer.len() as u64).sum();
+        self.check_size_limits(length)?;
+        let mut slices: Vec<IoSlice> = buffers.iter().map(|buffer| IoSlice::new(buffer)).collect();
+        let mut remaining = &mut slices[..];
+        // drops leading empty buffers, which would look like a closed stream
+        IoSlice::advance_slices(&mut remaining, 0);
+        let started = Instant::now();
+        let mut last_progress = started;
+        let mut total = 0u64;
+        while !remaining.is_empty() {
+            if watched {
+                self.limit_data_timeouts(socket, deadline)?;
+            }
+            let count = match dest.write_vectored(remaining) {
+                Ok(0) => {
+                    return Err(self.data_error(
+                        ErrorKind::WriteZero.into(),
+                        deadline,
+                        last_progress,
+                    ))
+                }
+                Ok(count) => count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(self.data_error(error, deadline, last_progress)),
+            };
+            IoSlice::advance_slices(&mut remaining, count);
+            last_progress = Instant::now();
+            total += count as u64;
+            self.session_bytes += count as u64;
+            self.emit(ProtocolEvent::TransferProgress { bytes: total });
+        }
+        dest.flush()?;
+        self.finish_transfer(total, started)
+    }
+
+    /// Deadline of a transfer starting now, the earlier of the transfer
+    /// deadline and the one of the running operation, and whether the data
+    /// socket timeouts must be adjusted before each read or write.
+    fn transfer_watch(&self) -> (Option<Instant>, bool) {
+        let deadline = self
+            .config
+            .transfer_deadline
+            .map(|limit| Instant::now() + limit);
+        let deadline = match (deadline, self.deadline) {
+            (Some(transfer), Some(operation)) => Some(transfer.min(operation)),
+            (transfer, operation) => transfer.or(operation),
+        };
+        let watched = deadline.is_some() || self.config.stall_timeout.is_some();
+        (deadline, watched)
+    }
+
+    /// Claim the data connection for a transfer, released once the transfer
+    /// ends either way. Servers take one transfer at a time, so starting a
+    /// second one before the first got its final reply would mix up their
+    /// replies.
+    ///
+    /// # Errors
+    /// [`FtpError::TransferInProgress`] while another transfer is running.
+    fn begin_transfer(&mut self) -> Result<()> {
+        if self.transferring {
+            return Err(FtpError::TransferInProgress(
+                "the data connection is busy with another transfer".into(),
+            ));
+        }
+        self.transferring = true;
+        Ok(())
+    }
+
+    /// Send `command`, which opens a data transfer. Any positive preliminary
+    /// reply starts the transfer, as does a reply the reply policy accepts. A
+    /// 226 or 250 tells the server already finished, e.g. sending an empty file,
+    /// and no other reply follows the data.
+    ///
+    /// # Returns
+    /// The first reply, and whether the reply completing the transfer is still
+    /// to come.
+    ///
+    /// # Errors
+    /// The error `refused` makes of a reply refusing the transfer.
+    fn start_transfer(
+        &mut self,
+        command: Command,
+        refused: impl FnOnce(Response) -> FtpError,
+    ) -> Result<(Response, bool)> {
+        let response = self.write_cmd(command)?;
+        if ReplyPolicy::Strict.completes_transfer(response.code) {
+            return Ok((response, false));
+        }
+        if policy::is_preliminary(response.code) || self.accepts(&command, &response) {
+            return Ok((response, true));
+        }
+        if policy::is_unimplemented(response.code) {
+            return Err(FtpError::Unsupported(command.name().into()));
+        }
+        Err(refused(response))
+    }
+
+    /// Read the reply completing a data transfer, skipping further preliminary
+    /// replies such as 110 restart markers.
+    fn complete_transfer(&mut self, span: &Span) -> Result<()> {
+        let code = loop {
+            let code = self.parse_response()?.code;
+            if !policy::is_preliminary(code) {
+                break code;
+            }
+        };
+        span.record_code(code);
+        if !self.config.reply_policy.completes_transfer(code) {
+            return Err(FtpError::ConnectionError("Error closing connection".into()));
+        }
+        Ok(())
+    }
+
+    /// Report a completed transfer of `total` bytes on the data connection.
+    /// Its statistics are left out when its addresses couldn't be read.
+    fn finish_transfer(&mut self, total: u64, started: Instant) -> Result<u64> {
+        self.last_transfer = self
+            .data_addrs
+            .map(|(local_addr, peer_addr)| TransferStats {
+                bytes: total,
+                duration: started.elapsed(),
+                local_addr,
+                peer_addr,
+            });
+        trace::record_bytes(total);
+        self.emit(ProtocolEvent::TransferComplete { bytes: total });
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.transfer(total)?;
+        }
+        Ok(total)
+    }
+
+    /// Provide user account after login
+    /// # Arguments
+    /// account string representing the user account for login
+    ///
+    /// # Errors
+    /// Errors when failing to write to server or to parse response.
+    pub fn account(&mut self, account: impl AsRef<str>) -> Result<()> {
+        let command = Command::Acct(&Secret::from(account.as_ref()));
+        let response = self.write_cmd(command)?;
+        if !self.accepts(&command, &response) {
+            return Err(self.refused(FtpError::LoginError("Invalid account information".into())));
+        }
+        Ok(())
+    }
+
+    /// Retrieve a file from the server.
+    ///
+    /// # Arguments
+    /// `file`    Name of the file (includes path) on the server to be retrieved
+    /// `dest`    Writer destination to dump data setn from server
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use simpleftp::*;
+    /// use simpleftp::Result;
+    /// fn main() -> Result<()> {
+    ///     let mut client = FtpClient::connect("127.0.0.1:21")?;
+    ///     client.login("user", "passowrd")?;
+    ///
+    ///     let mut destination = std::fs::File::open("log.txt").expect("Opening file");
+    ///     client.get("/home/will/code/log.txt", &mut destination)?;
+    ///     client.logout()?;
+    ///     Ok(())
+    /// }
+    ///
+    /// ```
+    /// # Errors
+    /// Errors when failing to write to server or to parse response or due to connection problems.
+    pub fn get(&mut self, file: impl AsRef<str>, dest: &mut impl Write) -> Result<()> {
+        self.retrieve(file, dest)?;
+        Ok(())
+    }
+
+    /// Retrieve a file from the server, returning the number of bytes received.
+    pub(crate) fn retrieve(&mut self, file: impl AsRef<str>, dest: &mut impl Write) -> Result<u64> {
+        self.retrieve_with(file.as_ref(), |client, stream, socket| {
+            client.transfer_data(stream, dest, socket)
+        })
+    }
+
+    /// Retrieve a file, with `receive` reading the data from the data connection.
+    pub(crate) fn retrieve_with<F>(&mut self, file: &str, receive: F) -> Result<u64>
+    where
+        F: FnOnce(&mut Self, &mut Box<dyn Stream>, &TcpStream) -> Result<u64>,
+    {
+        self.retrieve_from(file, 0, receive)
+    }
+
+    /// Like [`FtpClient::retrieve_with`], the data read from `offset` on
+    /// after a `REST` when `offset` isn't 0.
+    pub(crate) fn retrieve_from<F>(&mut self, file: &str, offset: u64, receive: F) -> Result<u64>
+    where
+        F: FnOnce(&mut Self, &mut Box<dyn Stream>, &TcpStream) -> Result<u64>,
+    {
+        self.begin_transfer()?;
+        let span = Span::transfer("RETR", file);
+        let started = Instant::now();
+        let result = span.in_scope(|| {
+            let stream = self.data_connection()?;
+            if offset > 0 {
+                self.expect(Command::Rest(offset), FtpError::CommandError)?;
+            }
+            let (_, pending) = self.start_transfer(Command::Retr(file), |_| {
+                FtpError::CommandError("Could not process file retrieve".into())
+            })?;
+            let mut stream = self.secure_data(stream)?;
+            let socket = stream.socket().try_clone()?;
+            let received = match receive(self, &mut stream, &socket) {
+                Ok(received) => received,
+                Err(error) => {
+                    self.abort_transfer(stream);
+                    return Err(error);
+                }
+            };
+            // errors are irrelevant once all the data arrived
+            let _ = stream.close();
+            #[cfg(feature = "debug")]
+            println!("Closing connection");
+            if pending {
+                self.complete_transfer(&span)?;
+            }
+            Ok(received)
+        });
+        self.transferring = false;
+        self.measure_transfer("RETR", started, &result, |metrics, bytes| {
+            metrics.bytes_received(*bytes)
+        });
+        result
+    }
+
+    /// Sends a file to the server.
+    ///
+    /// # Arguments
+    /// `file`    Name of the file (includes path) on the server to be retrieved
+    /// `source`  Reader stream containing data to send to server
+    ///
+    /// # Examples
+    /// ```no_run
+    ///  use simpleftp::FtpClient;
+    ///  use simpleftp::Result;
+    ///
+    /// fn main() -> Result<()> {
+    ///     let mut client = FtpClient::connect("127.0.0.1:21")?;
+    ///     client.login("user", "passowrd/")?;
+    ///
+    ///     let mut source = std::fs::File::create("log.txt").expect("Opening file");
+    ///     client.put("/home/will/code/log.txt", &mut source)?;
+    ///     client.logout()?;
+    ///     Ok(())
+    /// }
+    /// ```
+    /// # Errors
+    /// Errors when failing to write to server or to parse response or due to connection problems.
+    /// May also fail when reading from the source stream.
+    pub fn put(&mut self, file: impl AsRef<str>, source: &mut impl Read) -> Result<()> {
+        self.store_cmd(file, source, |file| Command::Stor(file))?;
+        Ok(())
+    }
 
-        // close data connection
-        stream.shutdown(Shutdown::Both)?;
-        match self.parse_response()?.code {
-            CLOSING_DATA_CONNECTION => Ok(response.message),
-            _ => Err(FtpError::ConnectionError("Error closing connection".into())),
+    /// Sends a file made of several buffers to the server, writing them with
+    /// vectored writes instead of one system call per buffer.
+    ///
+    /// # Arguments
+    /// `file`      Name of the file (includes path) on the server
+    /// `buffers`   Content of the file, in order
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let header: &[u8] = b"id,name\n";
+    /// let rows: [&[u8]; 2] = [b"1,apple\n", b"2,pear\n"];
+    /// client
+    ///     .put_vectored("/data/fruits.csv", &[header, rows[0], rows[1]])
+    ///     .unwrap();
+    /// ```
+    /// # Errors
+    /// Errors when failing to write to server or to parse response or due to connection problems.
+    pub fn put_vectored(&mut self, file: impl AsRef<str>, buffers: &[&[u8]]) -> Result<()> {
+        if self.config.allocate_uploads {
+            self.announce_upload(buffers.iter().map(|buffer| buffer.len() as u64).sum())?;
+        }
+        self.store_with(
+            file.as_ref(),
+            |file| Command::Stor(file),
+            |client, stream, socket| client.transfer_vectored(buffers, stream, socket),
+        )?;
+        Ok(())
+    }
+
+    /// Sends a file to the server under a temporary name, `.<name>.part` in the
+    /// same directory, and renames it to `file` once the server confirmed the
+    /// transfer, so programs polling the directory never see a partial file.
+    /// An existing `file` is replaced. The temporary file is deleted when the
+    /// upload or the rename fails.
+    ///
+    /// # Arguments
+    /// `file`        Name of the file (includes path) on the server
+    /// `source`      Reader stream containing data to send to server
+    /// `check_size`  Compare the size of the temporary file with the bytes sent
+    ///               before renaming it. Sizes only match byte for byte, so
+    ///               this switches the session to binary mode first.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let mut source = std::fs::File::open("orders.csv").unwrap();
+    /// client.put_atomic("/inbox/orders.csv", &mut source, true).unwrap();
+    /// ```
+    /// # Errors
+    /// Errors when the transfer or the rename fails, when `file` has no file
+    /// name, or [`FtpError::IntegrityError`] when the sizes differ.
+    pub fn put_atomic(
+        &mut self,
+        file: impl AsRef<str>,
+        source: &mut impl Read,
+        check_size: bool,
+    ) -> Result<()> {
+        let file = RemotePath::new(file.as_ref());
+        let name = file.file_name().ok_or_else(|| {
+            FtpError::InvalidArgument(format!("no file name to upload to in {}", file))
+        })?;
+        let temporary = match file.parent() {
+            Some(parent) => parent.join(format!(".{}.part", name)),
+            None => RemotePath::new(format!(".{}.part", name)),
+        };
+        if check_size {
+            self.binary_mode()?;
+        }
+        let mut sent = 0;
+        let uploaded = self
+            .store_with(
+                temporary.as_str(),
+                |file| Command::Stor(file),
+                |client, stream, socket| {
+                    sent = client.transfer_data(source, stream, socket)?;
+                    Ok(sent)
+                },
+            )
+            .and_then(|_| {
+                // verified uploads were checked already
+                if !check_size || self.config.verify_uploads {
+                    return Ok(());
+                }
+                self.verify_upload(temporary.as_str(), sent)
+            });
+        let stored = uploaded.and_then(|_| self.rename_overwrite(&temporary, &file));
+        if stored.is_err() {
+            // the connection may be gone too, the first error tells more
+            let _ = self.delete_if_exists(&temporary);
+        }
+        stored
+    }
+
+    /// Sends a file to the server and stories in a unique location under current directory.
+    ///
+    /// # Arguments
+    /// `file`    Name of the file (includes path) on the server to be retrieved
+    /// `source`  Reader stream containing data to send to server
+    ///
+    ///  # Returns
+    ///  A result string with name of the file created
+    ///
+    /// # Examples
+    /// ```no_run
+    ///  use simpleftp::FtpClient;
+    ///  use simpleftp::Result;
+    ///
+    /// fn main() -> Result<()> {
+    ///     let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    ///     client.login("user", "passowrd/").unwrap();
+    ///
+    ///     let mut source = std::fs::File::create("log.txt").expect("Opening file");
+    ///     client.put_unique(&mut source).unwrap();
+    ///     client.logout().unwrap();
+    ///     Ok(())
+    /// }
+    /// ```
+    /// # Errors
+    /// Errors when failing to write to server or to parse response or due to connection problems.
+    /// May also fail when reading from the source stream.
+    pub fn put_unique(&mut self, source: &mut impl Read) -> Result<String> {
+        self.store_cmd("", source, |file| Command::Stou(file))
+    }
+
+    /// Sends a file to the server. If file exists, append to it.
+    ///
+    /// # Arguments
+    /// `file`    Name of the file (includes path) on the server to be retrieved
+    /// `source`  Reader stream containing data to send to server
+    ///
+    /// # Examples
+    /// ```no_run
+    ///  use simpleftp::FtpClient;
+    ///  use simpleftp::Result;
+    ///
+    /// fn main() -> Result<()> {
+    ///     let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    ///     client.login("user", "passowrd/").unwrap();
+    ///
+    ///     let mut source = std::fs::File::create("log.txt").expect("Opening file");
+    ///     client.append("/home/will/log.txt/", &mut source).unwrap();
+    ///     client.logout().unwrap();
+    ///     Ok(())
+    /// }
+    /// ```
+    /// # Errors
+    /// Errors when failing to write to server or to parse response or due to connection problems.
+    /// May also fail when reading from the source stream.
+    pub fn append(&mut self, file: impl AsRef<str>, source: &mut impl Read) -> Result<()> {
+        self.store_cmd(file.as_ref(), source, |file| Command::Appe(file))?;
+        Ok(())
+    }
+
+    /// Append to a file expected to hold `offset` bytes, e.g. where a previous
+    /// run stopped writing a log. The size is checked before appending, so a
+    /// file another writer changed meanwhile is left alone, and again after,
+    /// to detect a writer appending at the same time.
+    ///
+    /// # Arguments
+    /// `file`    Name of the file (includes path) on the server
+    /// `offset`  Size the file must have, 0 for a file that may not exist yet
+    /// `source`  Reader stream containing data to send to server
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// client.binary_mode().unwrap();
+    /// let written = client.size("/logs/app.log").unwrap();
+    /// client.append_at("/logs/app.log", written, &mut &b"started\n"[..]).unwrap();
+    /// ```
+    /// # Errors
+    /// [`FtpError::Conflict`] when the file doesn't hold `offset` bytes before
+    /// appending, or doesn't grow by exactly the bytes sent. Errors from the
+    /// transfer and from `SIZE`, which the server must support.
+    pub fn append_at(
+        &mut self,
+        file: impl AsRef<str>,
+        offset: u64,
+        source: &mut impl Read,
+    ) -> Result<u64> {
+        let file = file.as_ref();
+        self.cache.invalidate(file);
+        let size = match self.size(file) {
+            Err(FtpError::FileError(_)) if offset == 0 => 0,
+            size => size?,
+        };
+        if size != offset {
+            return Err(FtpError::Conflict(format!(
+                "{} holds {} bytes, expected {}",
+                file, size, offset
+            )));
+        }
+        let mut sent = 0;
+        self.store_with(
+            file,
+            |file| Command::Appe(file),
+            |client, stream, socket| {
+                sent = client.transfer_data(source, stream, socket)?;
+                Ok(sent)
+            },
+        )?;
+        match self.size(file)? {
+            size if size == offset + sent => Ok(size),
+            size => Err(FtpError::Conflict(format!(
+                "{} holds {} bytes after appending {} to {}",
+                file, size, sent, offset
+            ))),
+        }
+    }
+
+    fn store_cmd(
+        &mut self,
+        file: impl AsRef<str>,
+        source: &mut impl Read,
+        command: fn(&str) -> Command,
+    ) -> Result<String> {
+        self.store_with(file.as_ref(), command, |client, stream, socket| {
+            client.transfer_data(source, stream, socket)
+        })
+    }
+
+    /// Store a file with `command`: `Command::Stor`, `Command::Stou` or
+    /// `Command::Appe`, with `send` writing the data to the data connection.
+    pub(crate) fn store_with<F>(
+        &mut self,
+        file: &str,
+        command: fn(&str) -> Command,
+        send: F,
+    ) -> Result<String>
+    where
+        F: FnOnce(&mut Self, &mut Box<dyn Stream>, &TcpStream) -> Result<u64>,
+    {
+        self.store_from(file, command, 0, send)
+    }
+
+    /// Like [`FtpClient::store_with`], the data written from `offset` on
+    /// after a `REST` when `offset` isn't 0.
+    pub(crate) fn store_from<F>(
+        &mut self,
+        file: &str,
+        command: fn(&str) -> Command,
+        offset: u64,
+        send: F,
+    ) -> Result<String>
+    where
+        F: FnOnce(&mut Self, &mut Box<dyn Stream>, &TcpStream) -> Result<u64>,
+    {
+        let verb = command(file).verb();
+        self.begin_transfer()?;
+        let span = Span::transfer(verb, file);
+        let started = Instant::now();
+        let result = span.in_scope(|| {
+            let stream = self.data_connection()?;
+            if offset > 0 {
+                self.expect(Command::Rest(offset), FtpError::CommandError)?;
+            }
+            let (response, pending) = self.start_transfer(command(file), |_| {
+                FtpError::CommandError(format!("Could not process file {}", verb))
+            })?;
+            let mut stream = self.secure_data(stream)?;
+            #[cfg(feature = "debug")]
+            println!("Copying file:{}", file);
+
+            let socket = stream.socket().try_clone()?;
+            let sent = match send(self, &mut stream, &socket) {
+                Ok(sent) => sent,
+                Err(error) => {
+                    self.abort_transfer(stream);
+                    return Err(error);
+                }
+            };
+
+            #[cfg(feature = "debug")]
+            println!("Closing connection");
+
+            // close data connection
+            stream.close()?;
+            if pending {
+                self.complete_transfer(&span)?;
+            }
+            Ok((response.message, sent))
+        });
+        self.transferring = false;
+        self.measure_transfer(verb, started, &result, |metrics, (_, bytes)| {
+            metrics.bytes_sent(*bytes)
+        });
+        let (message, sent) = result?;
+        // the size of appended, patched or server named files is unknown
+        if self.config.verify_uploads && offset == 0 && matches!(command(file), Command::Stor(_)) {
+            self.verify_upload(file, sent)?;
+        }
+        Ok(message)
+    }
+
+    /// Compare the size of the uploaded `file` with the `sent` bytes.
+    ///
+    /// # Errors
+    /// [`FtpError::IntegrityError`] when they differ, or errors from `SIZE`.
+    fn verify_upload(&mut self, file: &str, sent: u64) -> Result<()> {
+        match self.size(file)? {
+            size if size == sent => Ok(()),
+            size => Err(FtpError::IntegrityError(format!(
+                "Uploaded {} bytes but {} holds {}",
+                sent, file, size
+            ))),
         }
     }
 
@@ -410,10 +1714,11 @@ impl FtpClient {
     /// # Errors
     /// When the connection to server fails or when the server provides invalid response.
     pub fn noop(&mut self) -> Result<()> {
-        match self.write_cmd("NOOP")?.code {
-            COMMAND_OK => Ok(()),
-            _ => Err(FtpError::CommandError("failed command".into())),
+        let response = self.write_cmd(Command::Noop)?;
+        if !self.accepts(&Command::Noop, &response) {
+            return Err(self.refused(FtpError::CommandError("failed command".into())));
         }
+        Ok(())
     }
 
     /// Rename a file on the server
@@ -439,23 +1744,83 @@ impl FtpClient {
     ///
     /// # Errors
     /// Due to connection errors with the server, incorrect filenames or server response.
+    /// [`FtpError::DestinationExists`] when the server won't replace an existing `to`.
     pub fn rename(&mut self, from: impl AsRef<str>, to: impl AsRef<str>) -> Result<()> {
-        let response = self.write_cmd(format!("RNFR {}", from.as_ref()))?;
-        if response.code != FILE_ACTION_PENDING {
-            return Err(FtpError::CommandError(format!(
+        let command = Command::Rnfr(from.as_ref());
+        let response = self.write_cmd(command)?;
+        if !self.accepts(&command, &response) {
+            return Err(self.refused(FtpError::CommandError(format!(
                 "Could not rename file: {}",
                 response.code
-            )));
+            ))));
         }
 
-        let response = self.write_cmd(format!("RNTO {}", to.as_ref()))?;
-        if response.code != FILE_ACTION_OK {
-            return Err(FtpError::CommandError(format!(
+        let command = Command::Rnto(to.as_ref());
+        let response = self.write_cmd(command)?;
+        match response.code {
+            _ if self.accepts(&command, &response) => Ok(()),
+            _ if Self::is_existing(&response) => {
+                Err(self.refused(FtpError::DestinationExists(format!(
+                    "Could not rename file to {}: {}",
+                    to.as_ref(),
+                    response.message.trim()
+                ))))
+            }
+            code => Err(self.refused(FtpError::CommandError(format!(
                 "Could not rename file: {}",
-                response.code
-            )));
+                code
+            )))),
+        }
+    }
+
+    /// Move a file into the directory `to_dir`, keeping its name. The directory
+    /// and its missing parents are created first.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let moved = client.move_to("/inbox/order.csv", "/inbox/done").unwrap();
+    /// assert_eq!(moved.as_str(), "/inbox/done/order.csv");
+    /// ```
+    ///
+    /// # Returns
+    /// The new path of the file.
+    ///
+    /// # Errors
+    /// When `from` has no file name, the directory can't be created or the
+    /// rename fails.
+    pub fn move_to(
+        &mut self,
+        from: impl AsRef<str>,
+        to_dir: impl AsRef<str>,
+    ) -> Result<RemotePath> {
+        let from = RemotePath::new(from.as_ref());
+        let name = from.file_name().ok_or_else(|| {
+            FtpError::InvalidArgument(format!("no file name to move in {}", from))
+        })?;
+        let to = RemotePath::new(to_dir.as_ref()).join(name);
+        self.create_dir_all(to_dir)?;
+        self.rename(&from, &to)?;
+        Ok(to)
+    }
+
+    /// Rename a file on the server, replacing the destination. Servers which
+    /// refuse to rename onto an existing file get the destination deleted
+    /// first, leaving a moment where neither name holds the new content.
+    ///
+    /// # Errors
+    /// Due to connection errors with the server, incorrect filenames, or when
+    /// the destination can't be deleted.
+    pub fn rename_overwrite(&mut self, from: impl AsRef<str>, to: impl AsRef<str>) -> Result<()> {
+        match self.rename(from.as_ref(), to.as_ref()) {
+            Err(FtpError::DestinationExists(_)) => {
+                self.delete(to.as_ref())?;
+                self.rename(from, to)
+            }
+            result => result,
         }
-        Ok(())
     }
 
     /// Deletes a file from the server
@@ -472,39 +1837,263 @@ impl FtpClient {
     /// client.logout().unwrap();
     /// ```
     pub fn delete(&mut self, file: impl AsRef<str>) -> Result<()> {
-        let response = self.write_cmd(format!("DELE {}", file.as_ref()))?;
-        if response.code != FILE_ACTION_OK {
-            return Err(FtpError::CommandError(format!(
+        let command = Command::Dele(file.as_ref());
+        let response = self.write_cmd(command)?;
+        if !self.accepts(&command, &response) {
+            return Err(self.refused(FtpError::CommandError(format!(
                 "Could not delete file: {}",
                 response.code
-            )));
+            ))));
         }
         Ok(())
     }
 
+    /// Deletes a file from the server, succeeding when it doesn't exist.
+    ///
+    /// # Arguments
+    /// `file`      Filename to be deleted on the server side
+    ///
+    /// # Returns
+    /// Whether the file existed and was deleted.
+    ///
+    /// # Errors
+    /// When the server refuses the deletion for any other reason than a missing
+    /// file, such as insufficient permissions.
+    pub fn delete_if_exists(&mut self, file: impl AsRef<str>) -> Result<bool> {
+        let command = Command::Dele(file.as_ref());
+        let response = self.write_cmd(command)?;
+        match response.code {
+            _ if self.accepts(&command, &response) => Ok(true),
+            _ if Self::is_not_found(&response) => Ok(false),
+            _other => Err(self.refused(FtpError::CommandError(format!(
+                "Could not delete file: {}",
+                response.code
+            )))),
+        }
+    }
+
+    /// Whether a negative reply means the target doesn't exist. Servers use 550
+    /// for missing files as well as for denied access, non-empty directories and
+    /// the like, so only a text saying the target is missing counts.
+    fn is_not_found(response: &Response) -> bool {
+        let message = response.message.to_lowercase();
+        response.code == FILE_NOT_AVAILABLE
+            && ["no such", "not found", "does not exist", "can't find"]
+                .iter()
+                .any(|reason| message.contains(reason))
+    }
+
+    /// Whether a negative reply means the target already exists, which servers
+    /// report with 550 or 553 and a text saying so.
+    fn is_existing(response: &Response) -> bool {
+        matches!(response.code, FILE_NOT_AVAILABLE | FILE_NAME_NOT_ALLOWED)
+            && response.message.to_lowercase().contains("exists")
+    }
+
     /// Retrieve data connection offered from the server
-    ///  in the form of a TCP stream.
+    ///  in the form of a TCP stream. The stream is never TLS protected.
+    /// The stream has the data read/write timeouts configured on the builder.
     ///
     /// # Errors
     /// If the connection cannot be established or if the server refuses.
     pub fn pasv(&mut self) -> Result<TcpStream> {
-        let response = self.write_cmd("PASV")?;
-        let code = response.code;
-        if code != PASSIVE_MODE && code != ALREADY_OPEN {
-            return Err(FtpError::ResponseError(format!(
+        let response = self.write_cmd(Command::Pasv)?;
+        if !self.accepts(&Command::Pasv, &response) {
+            return Err(self.refused(FtpError::ResponseError(format!(
                 "Invalid response code from server: {}",
-                code
-            )));
+                response.code
+            ))));
+        }
+        let (mut host, port) = Self::extract_pasv_address(&response.message)?;
+        if self.config.nat_safe_pasv {
+            host = self.peer.ip().to_string();
         }
-        let address = Self::extract_pasv_address(&response.message)?;
 
         #[cfg(feature = "debug")]
-        println!("{}", address);
+        println!("{}:{}", host, port);
 
-        let connection = TcpStream::connect(address)?;
+        let connection = self.config.open(&host, port, self.deadline)?;
+        connection.set_read_timeout(self.config.data_read_timeout)?;
+        connection.set_write_timeout(self.config.data_write_timeout)?;
+        self.emit(ProtocolEvent::DataOpened(connection.peer_addr()?));
         Ok(connection)
     }
 
+    /// Shorten the data socket timeouts so no single read or write outlives
+    /// `deadline` or waits longer than the stall timeout.
+    fn limit_data_timeouts(&self, socket: &TcpStream, deadline: Option<Instant>) -> Result<()> {
+        let mut limit = self.config.stall_timeout;
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Self::deadline_error());
+            }
+            limit = Some(limit.map_or(remaining, |stall| stall.min(remaining)));
+        }
+        let bound = |timeout: Option<Duration>| match (timeout, limit) {
+            (Some(timeout), Some(limit)) => Some(timeout.min(limit)),
+            (timeout, limit) => timeout.or(limit),
+        };
+        socket.set_read_timeout(bound(self.config.data_read_timeout))?;
+        socket.set_write_timeout(bound(self.config.data_write_timeout))?;
+        Ok(())
+    }
+
+    /// Convert a data connection failure, telling apart an exceeded transfer
+    /// deadline and a stalled transfer.
+    fn data_error(
+        &self,
+        error: std::io::Error,
+        deadline: Option<Instant>,
+        last_progress: Instant,
+    ) -> FtpError {
+        let timed_out = matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock);
+        match (deadline, self.config.stall_timeout) {
+            (Some(deadline), _) if Instant::now() >= deadline => Self::deadline_error(),
+            (_, Some(stall)) if timed_out && last_progress.elapsed() >= stall => {
+                FtpError::TransferStalled(format!("no data moved for {:?}", stall))
+            }
+            _ => error.into(),
+        }
+    }
+
+    /// Fail when moving the current transfer up to `transfer_bytes` would exceed
+    /// the per-transfer or per-session caps.
+    fn check_size_limits(&self, transfer_bytes: u64) -> Result<()> {
+        if let Some(limit) = self.config.max_transfer_size {
+            if transfer_bytes > limit {
+                return Err(FtpError::SizeLimitExceeded(format!(
+                    "transfer exceeds {} bytes",
+                    limit
+                )));
+            }
+        }
+        if let Some(limit) = self.config.max_session_bytes {
+            if self.session_bytes + transfer_bytes > limit {
+                return Err(FtpError::SizeLimitExceeded(format!(
+                    "session exceeds {} bytes",
+                    limit
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Abort the transfer in progress after a local failure, so the control
+    /// connection is left in a known state. Errors are ignored: the original
+    /// failure is what gets reported to the caller.
+    fn abort_transfer(&mut self, stream: Box<dyn Stream>) {
+        let _ = stream.socket().shutdown(Shutdown::Both);
+        drop(stream);
+        let first = match self.send_cmd("ABOR") {
+            Ok(response) => response,
+            Err(_) => return,
+        };
+        if first.code == DATA_CONNECTION_OPEN {
+            return;
+        }
+        // The transfer reply (426 when interrupted, 226 when it had completed) comes
+        // first and the ABOR reply follows, though some servers only send one of them.
+        let socket = self.reader.get_ref().socket();
+        let previous = socket.read_timeout().ok().flatten();
+        let _ = socket.set_read_timeout(Some(ABORT_REPLY_TIMEOUT));
+        let _ = self.parse_response();
+        let _ = self.reader.get_ref().socket().set_read_timeout(previous);
+    }
+
+    fn deadline_error() -> FtpError {
+        FtpError::ConnectionError("Transfer deadline exceeded".into())
+    }
+
+    /// Run `operation` within `limit`, including every command, reply, data
+    /// connection and transfer it makes, instead of bounding each socket
+    /// operation on its own. Nested deadlines can only shorten the outer one.
+    ///
+    /// A reply or transfer cut short leaves the control connection out of
+    /// step, so the session is dropped then: the next command connects again
+    /// when the client has credentials to log in with, or fails with
+    /// [`FtpError::SessionExpired`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// use simpleftp::FtpClient;
+    /// use std::time::Duration;
+    ///
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let mut report = Vec::new();
+    /// client
+    ///     .with_deadline(Duration::from_secs(30), |client| client.get("/daily.csv", &mut report))
+    ///     .unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Errors from `operation`, or [`FtpError::ConnectionError`] when it
+    /// didn't finish in time.
+    pub fn with_deadline<T, F>(&mut self, limit: Duration, operation: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let deadline = Instant::now() + limit;
+        let outer = self.deadline;
+        self.deadline = Some(outer.map_or(deadline, |outer| outer.min(deadline)));
+        let socket = self.reader.get_ref().socket();
+        let timeouts = (socket.read_timeout()?, socket.write_timeout()?);
+        let result = operation(self);
+        self.deadline = outer;
+        // the session may have been resumed on another socket meanwhile
+        let socket = self.reader.get_ref().socket();
+        let _ = socket.set_read_timeout(timeouts.0);
+        let _ = socket.set_write_timeout(timeouts.1);
+        match result {
+            Err(FtpError::ConnectionError(_)) if Instant::now() >= deadline => {
+                self.disconnected = true;
+                Err(FtpError::ConnectionError(format!(
+                    "Operation deadline of {:?} exceeded",
+                    limit
+                )))
+            }
+            result => result,
+        }
+    }
+
+    /// Shorten the control socket timeouts so no command or reply outlives the
+    /// deadline of the running operation.
+    fn limit_control_timeouts(&self) -> Result<()> {
+        let Some(deadline) = self.deadline else {
+            return Ok(());
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(FtpError::ConnectionError(
+                "Operation deadline exceeded".into(),
+            ));
+        }
+        let socket = self.reader.get_ref().socket();
+        let bound =
+            |timeout: Option<Duration>| Some(timeout.map_or(remaining, |t| t.min(remaining)));
+        socket.set_read_timeout(bound(socket.read_timeout()?))?;
+        socket.set_write_timeout(bound(socket.write_timeout()?))?;
+        Ok(())
+    }
+
+    /// Open a data connection through PASV, wrapped like the control connection.
+    fn data_connection(&mut self) -> Result<Box<dyn Stream>> {
+        let stream = self.pasv()?;
+        self.data_addrs = stream.local_addr().ok().zip(stream.peer_addr().ok());
+        Ok(self.config.wrap(stream))
+    }
+
+    /// Run the TLS handshake on a data connection when data protection is on.
+    /// Servers only start it once the transfer command was accepted, so this must
+    /// follow the preliminary reply.
+    fn secure_data(&self, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>> {
+        match &self.config.tls {
+            Some(tls) if self.data_protected => tls.wrap(&self.config.tls_domain, stream),
+            _ => Ok(stream),
+        }
+    }
+
     /// Get a list of files in the directory. Including file information.
     ///
     /// # Arguments
@@ -512,8 +2101,8 @@ impl FtpClient {
     ///
     /// # Errors
     /// Errors on connection failure or improper response from server
-    pub fn list(&mut self, dir: &str) -> Result<Vec<String>> {
-        self.list_cmd(dir, false)
+    pub fn list(&mut self, dir: impl AsRef<str>) -> Result<Vec<String>> {
+        self.list_cmd(|dir| Command::List(dir), dir.as_ref())
     }
 
     /// Get a list of files in the directory. Names only.
@@ -523,41 +2112,86 @@ impl FtpClient {
     ///
     /// # Errors
     /// Errors on connection failure or improper response from server
-    pub fn name_list(&mut self, dir: &str) -> Result<Vec<String>> {
-        self.list_cmd(dir, true)
+    pub fn name_list(&mut self, dir: impl AsRef<str>) -> Result<Vec<String>> {
+        self.list_cmd(|dir| Command::Nlst(dir), dir.as_ref())
     }
 
-    /// Get a list of files in the directory. N
+    /// Get the unmodified bytes the server sends for a `LIST` of `dir`, to run
+    /// another parser on them, e.g. for EBCDIC hosts, or to keep the listing
+    /// exactly as served. Not cached.
     ///
     /// # Arguments
-    /// `dir`   directory to git list udner
-    /// `named` if true get only names with no further information
+    /// `dir`   directory to list
+    ///
     /// # Errors
     /// Errors on connection failure or improper response from server
-    fn list_cmd(&mut self, dir: &str, named: bool) -> Result<Vec<String>> {
-        let datacon = self.pasv()?;
-        let response = if named {
-            self.write_cmd(format!("NLST {}", dir))?
-        } else {
-            self.write_cmd(format!("LIST {}", dir))?
-        };
-        if response.code != COMMAND_OK && response.code != ALREADY_OPEN && response.code != FILE_OK
-        {
-            return Err(FtpError::CommandError(response.message));
-        }
-        let line_reader = BufReader::new(datacon);
-        let file_list = line_reader
-            .lines()
-            .filter(|item| item.is_ok())
-            .map(|item| item.unwrap())
-            .collect();
+    pub fn list_raw(&mut self, dir: impl AsRef<str>) -> Result<Vec<u8>> {
+        self.list_data(|dir| Command::List(dir), dir.as_ref())
+    }
 
-        #[cfg(feature = "debug")]
-        println!("Closing connection");
-        match self.parse_response()?.code {
-            CLOSING_DATA_CONNECTION => Ok(file_list),
-            _ => Err(FtpError::ConnectionError("Error closing connection".into())),
+    /// Get the lines of a directory listing.
+    ///
+    /// # Arguments
+    /// `command`   listing command: `Command::List`, `Command::Nlst` or `Command::Mlsd`
+    /// `dir`       directory to git list udner
+    /// # Errors
+    /// Errors on connection failure or improper response from server
+    fn list_cmd(&mut self, command: fn(&str) -> Command, dir: &str) -> Result<Vec<String>> {
+        let verb = command(dir).verb();
+        if let Some(Cached::Lines(lines)) = self.cache.get(verb, dir) {
+            return Ok(lines);
         }
+        let data = self.list_data(command, dir)?;
+        let policy = self.config.list_decode;
+        let file_list: Vec<String> = data
+            .split_inclusive(|byte| *byte == b'\n')
+            .filter_map(|line| {
+                let line = line.strip_suffix(b"\n").unwrap_or(line);
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                let decoded = self.config.codec.decode_listing(line, policy);
+                decoded.map(|text| text.map(Cow::into_owned)).transpose()
+            })
+            .collect::<Result<_>>()?;
+        self.cache
+            .insert(verb, dir, Cached::Lines(file_list.clone()));
+        Ok(file_list)
+    }
+
+    /// Get the bytes of a directory listing, until the server closes the data
+    /// connection.
+    fn list_data(&mut self, command: fn(&str) -> Command, dir: &str) -> Result<Vec<u8>> {
+        let verb = command(dir).verb();
+        self.begin_transfer()?;
+        let span = Span::transfer(verb, dir);
+        let started = Instant::now();
+        let result = span.in_scope(|| {
+            let datacon = self.data_connection()?;
+            let (_, pending) = self.start_transfer(command(dir), |response| {
+                FtpError::CommandError(response.message)
+            })?;
+            let mut datacon = self.secure_data(datacon)?;
+            let mut data = Vec::new();
+            match datacon.read_to_end(&mut data) {
+                Ok(_) => {}
+                // servers closing TLS without notice end the listing this way
+                Err(error) if self.data_protected && error.kind() == ErrorKind::UnexpectedEof => {}
+                Err(error) => {
+                    self.abort_transfer(datacon);
+                    return Err(error.into());
+                }
+            }
+            let _ = datacon.close();
+
+            #[cfg(feature = "debug")]
+            println!("Closing connection");
+            if pending {
+                self.complete_transfer(&span)?;
+            }
+            Ok(data)
+        });
+        self.transferring = false;
+        self.measure_transfer(verb, started, &result, |_, _| {});
+        result
     }
 
     /// Change working directory on server side.
@@ -568,14 +2202,8 @@ impl FtpClient {
     /// # Errors
     /// Errors on connection failure or inexisting directory.
     pub fn change_dir(&mut self, dir: impl AsRef<str>) -> Result<()> {
-        let response = self.write_cmd(format!("CWD {}", dir.as_ref()))?;
-        match response.code {
-            COMMAND_OK | FILE_ACTION_OK => Ok(()),
-            _other => Err(FtpError::FileError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
-        }
+        self.expect(Command::Cwd(dir.as_ref()), FtpError::FileError)?;
+        Ok(())
     }
 
     /// Create directory on server side.
@@ -583,14 +2211,31 @@ impl FtpClient {
     /// # Arguments
     /// `dir`   directoryto created
     pub fn makedir(&mut self, dir: impl AsRef<str>) -> Result<()> {
-        let response = self.write_cmd(format!("MKD {}", dir.as_ref()))?;
-        match response.code {
-            DIRECTORY_ALREADY_EXISTS | PATH_CREATED => Ok(()),
-            _other => Err(FtpError::FileError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
+        self.expect(Command::Mkd(dir.as_ref()), FtpError::FileError)?;
+        Ok(())
+    }
+
+    /// Create a directory on the server along with its missing parents.
+    /// Directories which can't be created are taken as existing, the next
+    /// operation in them fails if they don't.
+    ///
+    /// # Errors
+    /// On connection failure.
+    pub fn create_dir_all(&mut self, dir: impl AsRef<str>) -> Result<()> {
+        let dir = RemotePath::new(dir.as_ref());
+        let mut path = if dir.is_absolute() {
+            RemotePath::root()
+        } else {
+            RemotePath::new("")
+        };
+        for component in dir.components() {
+            path = path.join(component);
+            match self.makedir(&path) {
+                Err(FtpError::FileError(_)) => {}
+                other => other?,
+            }
         }
+        Ok(())
     }
 
     /// Remove directory on server side.
@@ -598,50 +2243,57 @@ impl FtpClient {
     /// # Arguments
     /// `dir`   directoryto removed
     pub fn remove_dir(&mut self, dir: impl AsRef<str>) -> Result<()> {
-        let response = self.write_cmd(format!("RMD {}", dir.as_ref()))?;
+        self.expect(Command::Rmd(dir.as_ref()), FtpError::FileError)?;
+        Ok(())
+    }
+
+    /// Remove directory on server side, succeeding when it doesn't exist.
+    ///
+    /// # Arguments
+    /// `dir`   directory to remove
+    ///
+    /// # Returns
+    /// Whether the directory existed and was removed.
+    pub fn remove_dir_if_exists(&mut self, dir: impl AsRef<str>) -> Result<bool> {
+        let command = Command::Rmd(dir.as_ref());
+        let response = self.write_cmd(command)?;
         match response.code {
-            FILE_ACTION_OK => Ok(()),
-            _other => Err(FtpError::FileError(format!(
+            _ if self.accepts(&command, &response) => Ok(true),
+            _ if Self::is_not_found(&response) => Ok(false),
+            _other => Err(self.refused(FtpError::FileError(format!(
                 "Invalid response {}",
                 response.message
-            ))),
+            )))),
         }
     }
 
     /// Go to parent directory on server side.
     pub fn change_dir_up(&mut self) -> Result<()> {
-        let response = self.write_cmd("CDUP")?;
-        match response.code {
-            COMMAND_OK | FILE_ACTION_OK => Ok(()),
-            _other => Err(FtpError::CommandError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
-        }
+        self.expect(Command::Cdup, FtpError::CommandError)?;
+        Ok(())
     }
 
     /// Print  directory on server side.
     pub fn pwd(&mut self) -> Result<String> {
-        let response = self.write_cmd("PWD")?;
-        match response.code {
-            PATH_CREATED => Ok(response.message),
-            _other => Err(FtpError::CommandError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
-        }
+        let response = self.expect(Command::Pwd, FtpError::CommandError)?;
+        Ok(response.message)
+    }
+
+    /// Current directory on the server, parsed from the `PWD` reply.
+    ///
+    /// # Errors
+    /// When the server refuses PWD or its reply holds no quoted path.
+    pub fn current_dir(&mut self) -> Result<RemotePath> {
+        let message = self.pwd()?;
+        RemotePath::from_reply(&message).ok_or_else(|| {
+            FtpError::ResponseError(format!("No directory in PWD reply: {}", message))
+        })
     }
 
     /// Close current data connection
     pub fn abort(&mut self) -> Result<()> {
-        let response = self.write_cmd("ABOR")?;
-        match response.code {
-            CLOSING_DATA_CONNECTION => Ok(()),
-            _other => Err(FtpError::CommandError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
-        }
+        self.expect(Command::Abor, FtpError::CommandError)?;
+        Ok(())
     }
 
     /// Get status of directory
@@ -649,26 +2301,14 @@ impl FtpClient {
     /// # Arguments
     /// `path`  path of item to get status
     pub fn status(&mut self, path: impl AsRef<str>) -> Result<String> {
-        let response = self.write_cmd(format!("STAT {}", path.as_ref()))?;
-        match response.code {
-            SYSTEM | FILE | DIRECTORY => Ok(response.message),
-            _other => Err(FtpError::CommandError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
-        }
+        let response = self.expect(Command::Stat(path.as_ref()), FtpError::CommandError)?;
+        Ok(response.message)
     }
 
     /// Get server information
     pub fn system(&mut self) -> Result<String> {
-        let response = self.write_cmd("SYST")?;
-        match response.code {
-            SYSTEM | NAME_SYSTEM => Ok(response.message),
-            _other => Err(FtpError::CommandError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
-        }
+        let response = self.expect(Command::Syst, FtpError::CommandError)?;
+        Ok(response.message)
     }
 
     /// Get help for given item
@@ -685,28 +2325,153 @@ impl FtpClient {
     /// client.logout().unwrap();
     /// ```
     pub fn help(&mut self, item: impl AsRef<str>) -> Result<String> {
-        let response = self.write_cmd(format!("HELP {}", item.as_ref()))?;
-        match response.code {
-            HELP_MESSAGE | FILE => Ok(response.message),
-            _other => Err(FtpError::CommandError(format!(
+        let response = self.expect(Command::Help(item.as_ref()), FtpError::CommandError)?;
+        Ok(response.message)
+    }
+
+    /// Get the size of a file on the server (SIZE extension).
+    /// The size depends on the transfer type; use binary mode for the exact byte count.
+    ///
+    /// # Arguments
+    /// `file`  path of the file on the server
+    ///
+    /// # Errors
+    /// [`FtpError::Unsupported`] when the server lacks SIZE support. When the
+    /// file doesn't exist or the server replies with garbage.
+    pub fn size(&mut self, file: impl AsRef<str>) -> Result<u64> {
+        if let Some(Cached::Size(size)) = self.cache.get("SIZE", file.as_ref()) {
+            return Ok(size);
+        }
+        let response = self.expect(Command::Size(file.as_ref()), FtpError::FileError)?;
+        let size = response.message.trim().parse().map_err(|_| {
+            FtpError::ResponseError(format!("Invalid SIZE response {}", response.message))
+        })?;
+        self.cache.insert("SIZE", file.as_ref(), Cached::Size(size));
+        Ok(size)
+    }
+
+    /// Get the last modification time of a file on the server (MDTM extension).
+    ///
+    /// # Arguments
+    /// `file`  path of the file on the server
+    ///
+    /// # Errors
+    /// [`FtpError::Unsupported`] when the server lacks MDTM support. When the
+    /// file doesn't exist or the server replies with garbage.
+    pub fn modified_time(&mut self, file: impl AsRef<str>) -> Result<SystemTime> {
+        if let Some(Cached::Modified(time)) = self.cache.get("MDTM", file.as_ref()) {
+            return Ok(time);
+        }
+        let response = self.expect(Command::Mdtm(file.as_ref()), FtpError::FileError)?;
+        let time = time::parse_timestamp(&response.message).ok_or_else(|| {
+            FtpError::ResponseError(format!("Invalid MDTM response {}", response.message))
+        })?;
+        self.cache
+            .insert("MDTM", file.as_ref(), Cached::Modified(time));
+        Ok(time)
+    }
+
+    /// Get the space left for uploads to `path`, in bytes, with the AVBL extension.
+    /// Falls back to the ProFTPD `SITE QUOTA` report of the upload limit.
+    /// Returns `None` when the server reports no limit.
+    ///
+    /// # Arguments
+    /// `path`  directory on the server, the current one when empty
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// if let Some(space) = client.available_space("/backups").unwrap() {
+    ///     assert!(space > 4 << 30, "not enough room for the backup");
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// [`FtpError::Unsupported`] when the server supports neither AVBL nor
+    /// SITE QUOTA, [`FtpError::CommandError`] when it refuses them otherwise,
+    /// e.g. for a missing directory.
+    pub fn available_space(&mut self, path: impl AsRef<str>) -> Result<Option<u64>> {
+        let command = Command::Avbl(path.as_ref());
+        let response = self.write_cmd(command)?;
+        if self.accepts(&command, &response) {
+            return response.message.trim().parse().map(Some).map_err(|_| {
+                FtpError::ResponseError(format!("Invalid AVBL response {}", response.message))
+            });
+        }
+        // e.g. 550 for a missing directory, which SITE QUOTA wouldn't fix
+        if !policy::is_unimplemented(response.code) {
+            return Err(self.refused(FtpError::CommandError(format!(
                 "Invalid response {}",
                 response.message
-            ))),
+            ))));
+        }
+        let command = Command::Site("QUOTA");
+        let response = self.write_cmd(command)?;
+        if policy::is_unimplemented(response.code) {
+            return Err(self.refused(FtpError::Unsupported("AVBL".into())));
+        }
+        if !self.accepts(&command, &response) {
+            return Err(self.refused(FtpError::CommandError(format!(
+                "Invalid response {}",
+                response.message
+            ))));
+        }
+        // e.g. "Uploaded bytes:     1024.00/1048576.00" or "Uploaded bytes: unlimited"
+        let uploads = response
+            .message
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Uploaded bytes:"))
+            .map(str::trim)
+            .ok_or_else(|| {
+                FtpError::ResponseError(format!("No upload quota in {}", response.message))
+            })?;
+        match uploads.split_once('/') {
+            Some((used, limit)) if limit.trim() != "unlimited" => {
+                let bytes = |value: &str| value.trim().parse::<f64>().ok();
+                match (bytes(used), bytes(limit)) {
+                    (Some(used), Some(limit)) => Ok(Some((limit - used).max(0.0) as u64)),
+                    _ => Err(FtpError::ResponseError(format!(
+                        "Invalid upload quota {}",
+                        uploads
+                    ))),
+                }
+            }
+            _ => Ok(None),
         }
     }
 
     /// Allocate space for a file transfer
     ///
     /// # Arguments
-    /// `size`  byute size to be allocated
-    pub fn allocate(&mut self, size: usize) -> Result<()> {
-        let response = self.write_cmd(format!("ALLO {}", size))?;
-        match response.code {
-            COMMAND_OK => Ok(()),
-            _other => Err(FtpError::CommandError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
+    /// `size`          byute size to be allocated
+    /// `record_size`   maximum record or page size, for servers storing
+    ///                 record-structured files
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// // ALLO 80000 R 80
+    /// client.allocate(80_000, Some(80)).unwrap();
+    /// ```
+    pub fn allocate(&mut self, size: usize, record_size: Option<usize>) -> Result<()> {
+        let command = Command::Allo(size as u64, record_size.map(|size| size as u64));
+        self.expect(command, FtpError::CommandError)?;
+        Ok(())
+    }
+
+    /// Announce an upload of `size` bytes with `ALLO`, using the configured
+    /// record size. Servers without ALLO support are tolerated.
+    pub(crate) fn announce_upload(&mut self, size: u64) -> Result<()> {
+        let Ok(size) = usize::try_from(size) else {
+            return Ok(());
+        };
+        match self.allocate(size, self.config.record_size) {
+            Ok(()) | Err(FtpError::CommandError(_) | FtpError::Unsupported(_)) => Ok(()),
+            Err(error) => Err(error),
         }
     }
 
@@ -718,14 +2483,8 @@ impl FtpClient {
     /// # Errors
     /// Errors on connection failure or inexisting pathname.
     pub fn mount(&mut self, pathname: impl AsRef<str>) -> Result<()> {
-        let response = self.write_cmd(format!("SMNT {}", pathname.as_ref()))?;
-        match response.code {
-            COMMAND_OK | FILE_ACTION_OK => Ok(()),
-            _other => Err(FtpError::FileError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
-        }
+        self.expect(Command::Smnt(pathname.as_ref()), FtpError::FileError)?;
+        Ok(())
     }
 
     /// Disconnect from the server
@@ -734,110 +2493,909 @@ impl FtpClient {
     /// On the strange circumstances the server refuses the logout operation
     /// or does not recognize the command.
     pub fn logout(&mut self) -> Result<()> {
-        let response = self.write_cmd("QUIT")?;
-        match response.code {
-            SERVICE_CLOSING => Ok(()),
-            _other => Err(FtpError::FileError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
-        }
+        self.expect(Command::Quit, FtpError::FileError)?;
+        Ok(())
     }
 
     /// Set transfer mode to binary.
     /// # Errors
     /// On connection failure or when type not suported by server
     pub fn binary_mode(&mut self) -> Result<()> {
-        let response = self.write_cmd("TYPE I")?;
-        match response.code {
-            COMMAND_OK => Ok(()),
-            _other => Err(FtpError::FileError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
-        }
+        self.expect(Command::Type(DataType::Image), FtpError::FileError)?;
+        Ok(())
     }
 
     /// Set transfer mode to ASCII.
     /// # Errors
     /// On connection failure or when type not suported by server
     pub fn ascii_mode(&mut self) -> Result<()> {
-        let response = self.write_cmd("TYPE A")?;
-        match response.code {
-            COMMAND_OK => Ok(()),
-            _other => Err(FtpError::FileError(format!(
-                "Invalid response {}",
-                response.message
-            ))),
-        }
+        self.expect(Command::Type(DataType::Ascii), FtpError::FileError)?;
+        Ok(())
     }
 
     /// Reads a response and returns the server's response
     fn parse_response(&mut self) -> Result<Response> {
-        let mut response = String::new();
-        self.reader
-            .read_line(&mut response)
-            .map_err(|_| FtpError::ResponseError("Could not read server response".into()))?;
-        #[cfg(feature = "debug")]
-        print!("Parsing: {}", response);
+        self.limit_control_timeouts()?;
+        let mut parser = ReplyParser::new();
+        let mut line = Vec::new();
+        while !parser.is_complete() {
+            line.clear();
+            let read =
+                self.reader
+                    .read_until(b'\n', &mut line)
+                    .map_err(|error| match error.kind() {
+                        ErrorKind::TimedOut | ErrorKind::WouldBlock => FtpError::from(error),
+                        _ => {
+                            self.disconnected = true;
+                            FtpError::ResponseError("Could not read server response".into())
+                        }
+                    })?;
+            if read == 0 {
+                self.disconnected = true;
+                return Err(FtpError::ResponseError(format!(
+                    "Connection closed during response: {}",
+                    parser.text().escape_debug()
+                )));
+            }
+            let line = self.config.codec.decode(&line)?;
+            #[cfg(feature = "debug")]
+            print!("Parsing: {}", line);
+            parser.push_line(&line)?;
+        }
+        let response = parser.text().to_string();
 
-        if response.len() < 5 {
-            return Err(FtpError::ResponseError(format!(
-                "Invalid response code form server: {}",
-                response
-            )));
+        #[cfg(feature = "gssapi")]
+        let response = match self.security.as_mut() {
+            Some(context) => gssapi::unprotect(context, response)?,
+            None => response,
+        };
+        let response = reply::parse_text(&response)?;
+        // servers close the connection after a 421 reply
+        self.disconnected |= response.code == NOT_AVAILABLE;
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.reply(response.code, &response.message)?;
         }
-        let code: usize = response[0..3].parse().map_err(|_| {
-            FtpError::ResponseError(format!("Invalid response code form server: {}", response))
-        })?;
+        self.history.reply(response.code, &response.message);
+        self.emit(ProtocolEvent::ReplyReceived {
+            code: response.code,
+            message: response.message.clone(),
+        });
+        Ok(response)
+    }
 
-        // multiline response
-        if response[0..4].contains('-') {
-            let mut new_line = String::new();
-            while !new_line.starts_with(&response[0..3]) {
-                new_line.clear();
-                self.reader.read_line(&mut new_line)?;
-                response.push_str(&new_line[..]);
-                #[cfg(feature = "debug")]
-                println!("multi-line  {}", new_line);
+    // Helper method to extract the TCP connection address common on PASV and PORT responses.
+    // Servers disagree on the surrounding text, so the address is the first run of six
+    // comma separated numbers in the message, with or without parentheses.
+    fn extract_pasv_address(response: &str) -> Result<(String, u16)> {
+        let bytes = response.as_bytes();
+        for (start, byte) in bytes.iter().enumerate() {
+            // only consider whole numbers which don't continue a list
+            let begins_list = start == 0
+                || !bytes[start - 1].is_ascii_digit()
+                    && !response[..start].trim_end().ends_with(',');
+            if !byte.is_ascii_digit() || !begins_list {
+                continue;
+            }
+            if let Some(numbers) = Self::address_numbers(&response[start..]) {
+                return Ok((
+                    format!(
+                        "{}.{}.{}.{}",
+                        numbers[0], numbers[1], numbers[2], numbers[3]
+                    ),
+                    u16::from(numbers[4]) * 256 + u16::from(numbers[5]),
+                ));
             }
         }
+        Err(FtpError::ResponseError(format!(
+            "Invalid PASV response from server: {}",
+            response
+        )))
+    }
 
-        Ok(Response {
-            code,
-            message: response[3..].to_string(),
-        })
+    // Parse `h1,h2,h3,h4,p1,p2` at the start of `text`, allowing spaces around commas.
+    fn address_numbers(text: &str) -> Option<[u8; 6]> {
+        let mut numbers = [0u8; 6];
+        let mut rest = text;
+        for (index, number) in numbers.iter_mut().enumerate() {
+            if index > 0 {
+                rest = rest.trim_start().strip_prefix(',')?.trim_start();
+            }
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            if digits == 0 || digits > 3 {
+                return None;
+            }
+            *number = rest[..digits].parse().ok()?;
+            rest = &rest[digits..];
+        }
+        // `1,2,3,4,5,6,7` is not an address either
+        if rest.trim_start().starts_with(',') {
+            return None;
+        }
+        Some(numbers)
     }
+}
 
-    // Helper method to extract the TCP connection address common on PASV and PORT responses
-    fn extract_pasv_address(response: &str) -> Result<String> {
-        let ipinfo = response
-            .chars()
-            .filter(|c| *c == ',' || c.is_numeric())
-            .collect::<String>();
-        let tokens = ipinfo
-            .split(',')
-            .map(|tok| tok.trim().to_string())
-            .collect::<Vec<String>>();
-        let numbers = tokens
-            .iter()
-            .filter_map(|tok| tok.parse::<u32>().ok())
-            .collect::<Vec<u32>>();
-
-        let format_error =
-            FtpError::ResponseError(format!("Invalid PASV response from server: {}", response));
-
-        if numbers.len() < 6 {
-            Err(format_error.clone())
-        } else {
-            let port_lower: u32 = numbers[5];
-            let port_upper: u32 = numbers[4];
+#[cfg(test)]
+mod tests {
+    use super::testing::scripted_server;
+    #[cfg(feature = "server")]
+    use super::testing::serve;
+    #[cfg(feature = "server")]
+    use super::testing::served_client;
+    #[cfg(feature = "server")]
+    use super::testing::start_server;
+    #[cfg(feature = "server")]
+    use super::testing::TempRoot;
+    use super::FtpClient;
+    use super::FtpError;
+
+    fn pasv(message: &str) -> Option<(String, u16)> {
+        FtpClient::extract_pasv_address(message).ok()
+    }
+
+    fn address(host: &str, port: u16) -> Option<(String, u16)> {
+        Some((host.to_string(), port))
+    }
 
-            let full_port = port_upper * 256 + port_lower;
-            Ok(format!(
-                "{}.{}.{}.{}:{}",
-                &numbers[0], &numbers[1], &numbers[2], &numbers[3], full_port
-            ))
+    #[test]
+    fn pasv_standard_replies() {
+        // vsftpd
+        assert_eq!(
+            pasv(" Entering Passive Mode (192,168,1,10,195,149)."),
+            address("192.168.1.10", 50069)
+        );
+        // FileZilla Server
+        assert_eq!(
+            pasv(" Entering Passive Mode (10,0,0,5,19,137)"),
+            address("10.0.0.5", 5001)
+        );
+        // ProFTPD
+        assert_eq!(
+            pasv(" Entering Passive Mode (127,0,0,1,117,48)."),
+            address("127.0.0.1", 30000)
+        );
+    }
+
+    #[test]
+    fn pasv_without_parentheses() {
+        // IIS and several mainframe servers
+        assert_eq!(
+            pasv(" Entering Passive Mode 10,1,2,3,4,1"),
+            address("10.1.2.3", 1025)
+        );
+        // z/OS
+        assert_eq!(
+            pasv(" Entering Passive Mode 9,20,101,8,4,30."),
+            address("9.20.101.8", 1054)
+        );
+        assert_eq!(pasv(" =172,16,0,1,200,10"), address("172.16.0.1", 51210));
+    }
+
+    #[test]
+    fn pasv_with_extra_prose() {
+        // numbers in the prose before the address are skipped
+        assert_eq!(
+            pasv(" Entering Passive Mode for 1 file (192,168,0,2,7,208) ok"),
+            address("192.168.0.2", 2000)
+        );
+        assert_eq!(
+            pasv(" Passive mode 0K, data port is 192,168,0,2,7,208 (transfer 3 of 10)"),
+            address("192.168.0.2", 2000)
+        );
+        // Serv-U style
+        assert_eq!(
+            pasv(" Entering Passive Mode (203,0,113,7,78,52)\r\n"),
+            address("203.0.113.7", 20020)
+        );
+    }
+
+    #[test]
+    fn pasv_with_spaces_around_commas() {
+        assert_eq!(
+            pasv(" Entering Passive Mode (10, 0, 0, 1, 4, 0)"),
+            address("10.0.0.1", 1024)
+        );
+        assert_eq!(
+            pasv(" Entering Passive Mode (10 ,0 ,0 ,1 ,4 ,0)"),
+            address("10.0.0.1", 1024)
+        );
+    }
+
+    #[test]
+    fn pasv_invalid_replies() {
+        assert_eq!(pasv(" Entering Passive Mode"), None);
+        assert_eq!(pasv(" Entering Passive Mode (10,0,0,1,4)"), None);
+        assert_eq!(pasv(" Entering Passive Mode (10,0,0,1,256,0)"), None);
+        assert_eq!(pasv(" Entering Passive Mode (10,0,0,1,4,0,7)"), None);
+        assert_eq!(pasv(" Entering Passive Mode (1000,0,0,1,4,0)"), None);
+    }
+
+    #[test]
+    fn transfer_reply_sequences() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        let control = TcpListener::bind("127.0.0.1:0").unwrap();
+        let data = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = control.local_addr().unwrap();
+        let port = data.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut socket, _) = control.accept().unwrap();
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+            socket.write_all(b"220 Ready\r\n").unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let reply = match line.trim_end() {
+                    "PASV" => format!(
+                        "227 Entering Passive Mode (127,0,0,1,{},{})\r\n",
+                        port >> 8,
+                        port & 0xFF
+                    ),
+                    "RETR marked.txt" => {
+                        data.accept().unwrap().0.write_all(b"hello").unwrap();
+                        "125 Starting\r\n110 MARK 5 = 5\r\n226 Done\r\n".to_string()
+                    }
+                    // completed before any preliminary reply
+                    "RETR sent.txt" => {
+                        data.accept().unwrap().0.write_all(b"hello").unwrap();
+                        "226 Done\r\n".to_string()
+                    }
+                    _ => "200 OK\r\n".to_string(),
+                };
+                socket.write_all(reply.as_bytes()).unwrap();
+                line.clear();
+            }
+        });
+        let mut client = FtpClient::connect(address).unwrap();
+        assert!(client.last_transfer().is_none());
+        for file in ["marked.txt", "sent.txt"] {
+            let mut received = Vec::new();
+            client.get(file, &mut received).unwrap();
+            assert_eq!(received, b"hello");
         }
+        client.noop().unwrap();
+        let stats = client.last_transfer().unwrap();
+        assert_eq!(stats.bytes(), 5);
+        assert_eq!(stats.peer_addr().port(), port);
+        assert_eq!(stats.local_addr().ip(), address.ip());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn broken_listings_fail() {
+        use std::io::Write;
+        use std::os::unix::io::AsRawFd;
+
+        let address = scripted_server(|command, data| match command {
+            "LIST /pub" => {
+                let (mut socket, _) = data.accept().unwrap();
+                socket.write_all(b"-rw-r--r-- 1 ftp ftp 5 Jan").unwrap();
+                // close with a reset rather than the end of the stream
+                let linger = libc::linger {
+                    l_onoff: 1,
+                    l_linger: 0,
+                };
+                // SAFETY: the socket is open and `linger` outlives the call.
+                unsafe {
+                    libc::setsockopt(
+                        socket.as_raw_fd(),
+                        libc::SOL_SOCKET,
+                        libc::SO_LINGER,
+                        std::ptr::addr_of!(linger).cast(),
+                        std::mem::size_of::<libc::linger>() as libc::socklen_t,
+                    )
+                };
+                "150 Opening\r\n226 Done\r\n".to_string()
+            }
+            _ => "200 OK\r\n".to_string(),
+        });
+        let mut client = FtpClient::connect(address).unwrap();
+        assert!(client.list("/pub").is_err());
+    }
+
+    #[test]
+    fn available_space_falls_back_on_quotas() {
+        use super::HistoryEntry;
+
+        let server = |quotas: bool| {
+            scripted_server(move |command, _| {
+                match command {
+                    "AVBL /big" => "213 1048576\r\n",
+                    "AVBL /missing" => "550 No such directory\r\n",
+                    "SITE QUOTA" if quotas => {
+                        "200-The current quota for this session are [current/limit]:\r\n\
+                         Name: alice\r\n\
+                         Uploaded bytes:\t1024.00/1048576.00\r\n\
+                         Downloaded bytes:\tunlimited\r\n\
+                         200 Please contact root if these entries are inaccurate\r\n"
+                    }
+                    command if command.starts_with("AVBL") => "502 Command not implemented\r\n",
+                    _ => "500 Unknown command\r\n",
+                }
+                .to_string()
+            })
+        };
+        let mut client = FtpClient::connect(server(true)).unwrap();
+        assert_eq!(client.available_space("/big").unwrap(), Some(1_048_576));
+        assert_eq!(client.available_space("/quota").unwrap(), Some(1_047_552));
+        // a refusal other than a missing command is passed on as it is
+        assert!(matches!(
+            client.available_space("/missing"),
+            Err(FtpError::CommandError(_))
+        ));
+        let quotas = client
+            .history()
+            .filter(|entry| **entry == HistoryEntry::Command("SITE QUOTA".into()))
+            .count();
+        assert_eq!(quotas, 1);
+
+        let mut client = FtpClient::connect(server(false)).unwrap();
+        assert!(matches!(
+            client.available_space("/quota"),
+            Err(FtpError::Unsupported(feature)) if feature == "AVBL"
+        ));
+    }
+
+    #[test]
+    fn operation_deadlines() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::time::{Duration, Instant};
+
+        let control = TcpListener::bind("127.0.0.1:0").unwrap();
+        let data = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = control.local_addr().unwrap();
+        let port = data.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut socket, _) = control.accept().unwrap();
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+            socket.write_all(b"220 Ready\r\n").unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                let reply = match line.trim_end() {
+                    "PASV" => format!(
+                        "227 Entering Passive Mode (127,0,0,1,{},{})\r\n",
+                        port >> 8,
+                        port & 0xFF
+                    ),
+                    // every read is quick, the whole transfer is slow
+                    "RETR slow.txt" => {
+                        let mut stream = data.accept().unwrap().0;
+                        socket.write_all(b"150 Sending\r\n").unwrap();
+                        for _ in 0..20 {
+                            if stream.write_all(b"x").is_err() {
+                                break;
+                            }
+                            std::thread::sleep(Duration::from_millis(100));
+                        }
+                        "226 Done\r\n".to_string()
+                    }
+                    _ => "200 OK\r\n".to_string(),
+                };
+                let _ = socket.write_all(reply.as_bytes());
+                line.clear();
+            }
+        });
+        let mut client = FtpClient::connect(address).unwrap();
+        client
+            .with_deadline(Duration::from_secs(5), |client| client.noop())
+            .unwrap();
+        let started = Instant::now();
+        let result = client.with_deadline(Duration::from_millis(500), |client| {
+            client.noop()?;
+            client.get("slow.txt", &mut Vec::new())
+        });
+        assert!(matches!(result, Err(FtpError::ConnectionError(_))));
+        assert!(started.elapsed() < Duration::from_millis(1500));
+        // the late replies would be taken for those of the next commands
+        assert!(matches!(client.noop(), Err(FtpError::SessionExpired(_))));
+    }
+
+    /// A server sending `bytes` bytes of every file one at a time, `pause`
+    /// apart, then closing the data connection.
+    fn trickling_server(bytes: usize, pause: std::time::Duration) -> std::net::SocketAddr {
+        use std::io::Write;
+
+        scripted_server(move |command, data| match command {
+            command if command.starts_with("RETR") => {
+                let (mut socket, _) = data.accept().unwrap();
+                std::thread::spawn(move || {
+                    for _ in 0..bytes {
+                        if socket.write_all(b"x").is_err() {
+                            return;
+                        }
+                        std::thread::sleep(pause);
+                    }
+                });
+                "150 Opening\r\n226 Done\r\n".to_string()
+            }
+            _ => "200 OK\r\n".to_string(),
+        })
+    }
+
+    #[test]
+    fn connections_time_out() {
+        use std::net::TcpListener;
+        use std::time::{Duration, Instant};
+
+        // accepted by the kernel, never greeted
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let started = Instant::now();
+        let result =
+            FtpClient::connect_timeout(silent.local_addr().unwrap(), Duration::from_millis(300));
+        assert!(matches!(result, Err(FtpError::ConnectionError(_))));
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(started.elapsed() < Duration::from_millis(1500));
+
+        // unroutable, dropped or refused depending on the network
+        let started = Instant::now();
+        assert!(FtpClient::connect_timeout("10.255.255.1:21", Duration::from_millis(300)).is_err());
+        assert!(started.elapsed() < Duration::from_millis(1500));
+
+        // only the greeting is bounded, not slow replies afterwards
+        let address = scripted_server(|_, _| {
+            std::thread::sleep(Duration::from_millis(500));
+            "200 OK\r\n".to_string()
+        });
+        let mut client = FtpClient::connect_timeout(address, Duration::from_millis(300)).unwrap();
+        client.noop().unwrap();
+    }
+
+    #[test]
+    fn challenges_are_answered() {
+        let address = scripted_server(|command, _| {
+            match command {
+                "USER otp" => "331 otp-md5 487 dog2\r\n",
+                "USER guest" => "230 No password needed\r\n",
+                "PASS answer to otp-md5 487 dog2" => "230 Welcome\r\n",
+                command if command.starts_with("PASS") => "530 Login incorrect\r\n",
+                _ => "200 OK\r\n",
+            }
+            .to_string()
+        });
+        let mut client = FtpClient::connect(address).unwrap();
+        let info = client
+            .login_with_challenge("otp", |challenge| Ok(format!("answer to {}", challenge)))
+            .unwrap();
+        assert_eq!(info.lines(), ["Welcome"]);
+
+        let denied = client.login_with_challenge("otp", |_| Ok("guess"));
+        assert!(matches!(denied, Err(FtpError::LoginError(_))));
+        let failed = client.login_with_challenge("otp", |_| -> super::Result<&str> {
+            Err(FtpError::LoginError("no key for the challenge".into()))
+        });
+        assert!(matches!(failed, Err(FtpError::LoginError(error)) if error.contains("no key")));
+        // no challenge, no callback
+        client
+            .login_with_challenge("guest", |_| -> super::Result<&str> { unreachable!() })
+            .unwrap();
+    }
+
+    #[test]
+    fn data_timeouts_and_deadlines() {
+        use std::time::{Duration, Instant};
+
+        // one byte, then nothing until long after the read timeout
+        let address = trickling_server(1, Duration::from_secs(3));
+        let mut client = FtpClient::builder()
+            .data_read_timeout(Duration::from_millis(300))
+            .connect(address.to_string())
+            .unwrap();
+        let started = Instant::now();
+        let result = client.get("/stalled.txt", &mut Vec::new());
+        assert!(matches!(result, Err(FtpError::ConnectionError(_))));
+        assert!(started.elapsed() < Duration::from_millis(1500));
+
+        // every read is well within the timeout, the whole transfer is not
+        let address = trickling_server(20, Duration::from_millis(100));
+        let mut client = FtpClient::builder()
+            .data_read_timeout(Duration::from_millis(300))
+            .transfer_deadline(Duration::from_millis(500))
+            .connect(address.to_string())
+            .unwrap();
+        let started = Instant::now();
+        let result = client.get("/slow.txt", &mut Vec::new());
+        assert!(
+            matches!(&result, Err(FtpError::ConnectionError(error)) if error.contains("deadline")),
+            "{:?}",
+            result
+        );
+        assert!(started.elapsed() < Duration::from_millis(1500));
+
+        // without a deadline the same transfer completes
+        let address = trickling_server(5, Duration::from_millis(100));
+        let mut client = FtpClient::builder()
+            .data_read_timeout(Duration::from_millis(300))
+            .connect(address.to_string())
+            .unwrap();
+        let mut content = Vec::new();
+        client.get("/slow.txt", &mut content).unwrap();
+        assert_eq!(content, b"xxxxx");
+
+        // an operation deadline bounds a stalled transfer without any timeout
+        let address = trickling_server(1, Duration::from_secs(3));
+        let mut client = FtpClient::connect(address).unwrap();
+        let started = Instant::now();
+        let result = client.with_deadline(Duration::from_millis(500), |client| {
+            client.get("/stalled.txt", &mut Vec::new())
+        });
+        assert!(matches!(result, Err(FtpError::ConnectionError(_))));
+        assert!(started.elapsed() < Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn stalled_transfers_are_detected() {
+        use std::time::{Duration, Instant};
+
+        let address = trickling_server(1, Duration::from_secs(3));
+        let mut client = FtpClient::builder()
+            .stall_timeout(Duration::from_millis(300))
+            .connect(address.to_string())
+            .unwrap();
+        let started = Instant::now();
+        let result = client.get("/stalled.txt", &mut Vec::new());
+        assert!(
+            matches!(result, Err(FtpError::TransferStalled(_))),
+            "{:?}",
+            result
+        );
+        assert!(started.elapsed() < Duration::from_millis(1500));
+
+        // slow data keeps moving, the transfer is not stalled
+        let address = trickling_server(5, Duration::from_millis(100));
+        let mut client = FtpClient::builder()
+            .stall_timeout(Duration::from_millis(300))
+            .connect(address.to_string())
+            .unwrap();
+        let mut content = Vec::new();
+        client.get("/slow.txt", &mut content).unwrap();
+        assert_eq!(content, b"xxxxx");
+    }
+
+    #[test]
+    fn transfers_are_capped() {
+        use std::io::Read;
+        use std::time::Duration;
+
+        let capped = |builder: super::FtpClientBuilder| {
+            builder
+                .connect(trickling_server(10, Duration::ZERO).to_string())
+                .unwrap()
+        };
+        let mut client = capped(FtpClient::builder().max_transfer_size(9));
+        let mut content = Vec::new();
+        let result = client.get("/big.txt", &mut content);
+        assert!(matches!(result, Err(FtpError::SizeLimitExceeded(_))));
+        assert!(content.len() <= 9);
+
+        let mut client = capped(FtpClient::builder().max_session_bytes(15));
+        client.get("/a.txt", &mut Vec::new()).unwrap();
+        let result = client.get("/b.txt", &mut Vec::new());
+        assert!(matches!(result, Err(FtpError::SizeLimitExceeded(_))));
+
+        // uploads are capped as well
+        let address = scripted_server(|command, data| match command {
+            command if command.starts_with("STOR") => {
+                let (mut socket, _) = data.accept().unwrap();
+                std::thread::spawn(move || socket.read_to_end(&mut Vec::new()));
+                "150 Opening\r\n226 Done\r\n".to_string()
+            }
+            _ => "200 OK\r\n".to_string(),
+        });
+        let mut client = FtpClient::builder()
+            .max_transfer_size(4)
+            .connect(address.to_string())
+            .unwrap();
+        let result = client.put("/up.txt", &mut &b"hello"[..]);
+        assert!(matches!(result, Err(FtpError::SizeLimitExceeded(_))));
+        let result = client.put_vectored("/up.txt", &[b"he", b"llo"]);
+        assert!(matches!(result, Err(FtpError::SizeLimitExceeded(_))));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn missing_targets_are_not_errors() {
+        let files: &[(&str, &[u8])] = &[("docs/a.txt", b"a"), ("empty/", b"")];
+        let (mut client, root) = served_client("if-exists", files);
+        assert!(!client.delete_if_exists("/missing.txt").unwrap());
+        assert!(!client.remove_dir_if_exists("/missing").unwrap());
+        // 550 Directory not empty and 550 Is a directory are real failures
+        assert!(matches!(
+            client.remove_dir_if_exists("/docs"),
+            Err(FtpError::FileError(_))
+        ));
+        assert!(client.delete_if_exists("/docs").is_err());
+        assert!(root.join("docs/a.txt").exists());
+        assert!(client.delete_if_exists("/docs/a.txt").unwrap());
+        assert!(client.remove_dir_if_exists("/empty").unwrap());
+        assert!(client.remove_dir_if_exists("/docs").unwrap());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn rename_onto_existing_files() {
+        use super::{Next, Response};
+
+        let files: &[(&str, &[u8])] = &[("a.txt", b"new"), ("b.txt", b"old")];
+        let (mut client, root) = served_client("rename", files);
+        // like servers refusing to replace files on rename
+        let existing = root.join("b.txt");
+        client.add_layer(move |command: &str, next: &mut Next| {
+            if command.starts_with("RNTO") && existing.exists() {
+                return Ok(Response::new(553, " File exists\r\n"));
+            }
+            next.run(command)
+        });
+        assert!(matches!(
+            client.rename("/a.txt", "/b.txt"),
+            Err(FtpError::DestinationExists(_))
+        ));
+        client.rename_overwrite("/a.txt", "/b.txt").unwrap();
+        assert!(!root.join("a.txt").exists());
+        assert_eq!(std::fs::read(root.join("b.txt")).unwrap(), b"new");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn move_files_into_new_directories() {
+        let (mut client, root) = served_client("move", &[("inbox/a.txt", b"hello")]);
+        let moved = client.move_to("/inbox/a.txt", "/inbox/done/2024").unwrap();
+        assert_eq!(moved.as_str(), "/inbox/done/2024/a.txt");
+        assert!(root.join("inbox/done/2024/a.txt").exists());
+        assert!(client.move_to("/inbox/done/2024/..", "/inbox").is_err());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn uploads_announce_their_size() {
+        use super::Next;
+        use std::sync::{Arc, Mutex};
+
+        let root = TempRoot::new("allo", &[]);
+        let mut client = FtpClient::builder()
+            .allocate_uploads(true)
+            .record_size(80)
+            .connect(serve(&root).to_string())
+            .unwrap();
+        client.login("anonymous", "").unwrap();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&sent);
+        client.add_layer(move |command: &str, next: &mut Next| {
+            recorded.lock().unwrap().push(command.to_string());
+            next.run(command)
+        });
+        client
+            .put_vectored("/a.txt", &[b"hello ", b"world"])
+            .unwrap();
+        assert_eq!(std::fs::read(root.join("a.txt")).unwrap(), b"hello world");
+        assert!(sent.lock().unwrap().contains(&"ALLO 11 R 80".to_string()));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn nested_transfers_are_refused() {
+        let (mut client, _root) = served_client("busy", &[("a.txt", b"first")]);
+        let received = client
+            .retrieve_with("/a.txt", |client, stream, socket| {
+                let nested = client.list("/");
+                assert!(matches!(nested, Err(FtpError::TransferInProgress(_))));
+                let nested = client.put("/b.txt", &mut &b"second"[..]);
+                assert!(matches!(nested, Err(FtpError::TransferInProgress(_))));
+                client.transfer_data(stream, &mut Vec::new(), socket)
+            })
+            .unwrap();
+        assert_eq!(received, 5);
+        // the guard is released once the transfer is over
+        client.put("/b.txt", &mut &b"second"[..]).unwrap();
+        assert_eq!(client.name_list("/").unwrap().len(), 2);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn raw_listings() {
+        let files: &[(&str, &[u8])] = &[("a.txt", b"a"), ("b.txt", b"bb")];
+        let (mut client, _root) = served_client("raw", files);
+        let raw = client.list_raw("/").unwrap();
+        assert!(raw.ends_with(b"\r\n"));
+        let lines: Vec<String> = String::from_utf8(raw)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(lines, client.list("/").unwrap());
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn uploads_are_verified() {
+        use super::{FtpError, Next, Response};
+
+        let root = TempRoot::new("verify", &[]);
+        let mut client = FtpClient::builder()
+            .verify_uploads(true)
+            .connect(serve(&root).to_string())
+            .unwrap();
+        client.login("anonymous", "").unwrap();
+        client.binary_mode().unwrap();
+        client.put("/a.txt", &mut &b"line\n"[..]).unwrap();
+        // a server storing fewer bytes than sent
+        client.add_layer(|command: &str, next: &mut Next| match command {
+            "SIZE /b.txt" => Ok(Response::new(213, " 3\r\n")),
+            _ => next.run(command),
+        });
+        let result = client.put("/b.txt", &mut &b"line\n"[..]);
+        assert!(matches!(result, Err(FtpError::IntegrityError(_))));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn appends_check_the_offset() {
+        use super::FtpError;
+
+        let (mut client, root) = served_client("append", &[]);
+        client.binary_mode().unwrap();
+        client.append("/app.log", &mut &b"one\n"[..]).unwrap();
+        client.append("/app.log", &mut &b"two\n"[..]).unwrap();
+        assert_eq!(
+            client
+                .append_at("/app.log", 8, &mut &b"three\n"[..])
+                .unwrap(),
+            14
+        );
+        // another writer appended meanwhile
+        std::fs::write(root.join("app.log"), b"one\ntwo\nthree\nfour\n").unwrap();
+        let result = client.append_at("/app.log", 14, &mut &b"five\n"[..]);
+        assert!(matches!(result, Err(FtpError::Conflict(_))));
+        assert_eq!(
+            std::fs::read(root.join("app.log")).unwrap(),
+            b"one\ntwo\nthree\nfour\n"
+        );
+        assert_eq!(
+            client
+                .append_at("/new.log", 0, &mut &b"first\n"[..])
+                .unwrap(),
+            6
+        );
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn missing_extensions_are_unsupported() {
+        use super::{FtpError, Next, Response, SymlinkPolicy, WalkOptions};
+
+        let (mut client, _root) = served_client("unsupported", &[("pub/a.txt", b"a")]);
+        client.add_layer(
+            |command: &str, next: &mut Next| match command.split(' ').next() {
+                Some("MLSD") => Ok(Response::new(502, " Command not implemented\r\n")),
+                Some("MDTM") => Ok(Response::new(500, " Unknown command\r\n")),
+                _ => next.run(command),
+            },
+        );
+        let result = client.modified_time("/pub/a.txt");
+        assert!(matches!(result, Err(FtpError::Unsupported(ref feature)) if feature == "MDTM"));
+        let result = client.mlsd("/pub");
+        assert!(matches!(result, Err(FtpError::Unsupported(ref feature)) if feature == "MLSD"));
+        assert_eq!(result.unwrap_err().class(), "unsupported");
+        // walking falls back on LIST
+        let walk = WalkOptions::new().symlinks(SymlinkPolicy::Skip);
+        assert_eq!(client.walk("/pub", &walk).unwrap().len(), 1);
+        assert!(matches!(
+            client.size("/pub/missing.txt"),
+            Err(FtpError::FileError(_))
+        ));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn atomic_uploads_rename_complete_files() {
+        let (mut client, root) = served_client(
+            "atomic",
+            &[
+                ("inbox/a.csv", b"old"),
+                ("inbox/taken/", b""),
+                ("inbox/taken/b", b""),
+            ],
+        );
+        client.ascii_mode().unwrap();
+        client
+            .put_atomic("/inbox/a.csv", &mut &b"id,name\r\n1,a"[..], true)
+            .unwrap();
+        assert_eq!(
+            std::fs::read(root.join("inbox/a.csv")).unwrap(),
+            b"id,name\r\n1,a"
+        );
+        assert!(!root.join("inbox/.a.csv.part").exists());
+        // sizes are only compared in binary mode
+        assert!(client
+            .history()
+            .any(|entry| *entry == super::HistoryEntry::Command("TYPE I".into())));
+        assert!(client
+            .put_atomic("/missing/a.csv", &mut &b"id"[..], false)
+            .is_err());
+        // a directory can't be replaced
+        assert!(client
+            .put_atomic("/inbox/taken", &mut &b"id"[..], false)
+            .is_err());
+        assert!(!root.join("inbox/.taken.part").exists());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn expired_sessions_log_in_again() {
+        use super::{Credentials, FtpServer, StaticUsers};
+        use std::time::Duration;
+
+        let server = FtpServer::new(StaticUsers::new().user("alice", "wonderland"))
+            .idle_timeout(Duration::from_millis(200));
+        let address = start_server(server);
+        let idle = || std::thread::sleep(Duration::from_millis(400));
+
+        let mut client = FtpClient::connect(address).unwrap();
+        client.login("alice", "wonderland").unwrap();
+        idle();
+        assert!(matches!(client.noop(), Err(FtpError::SessionExpired(_))));
+
+        let mut client = FtpClient::connect(address).unwrap();
+        client
+            .login_with(|| Ok(Credentials::new("alice", "wonderland")))
+            .unwrap();
+        idle();
+        assert!(client.system().is_ok());
+        idle();
+        assert!(matches!(
+            client.makedir("/docs"),
+            Err(FtpError::SessionExpired(_))
+        ));
+        assert!(client.system().is_ok());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn expired_sessions_return_to_the_working_directory() {
+        use super::{Credentials, FtpServer, LocalStorage, StaticUsers};
+        use std::time::Duration;
+
+        let root = TempRoot::new(
+            "resume",
+            &[("docs/guide/", b""), ("docs/guide/a.txt", b"abc")],
+        );
+        let server = FtpServer::new(StaticUsers::new().user("alice", "wonderland"))
+            .storage(LocalStorage::new(root.to_path_buf()))
+            .idle_timeout(Duration::from_millis(200));
+        let address = start_server(server);
+        let idle = || std::thread::sleep(Duration::from_millis(400));
+        let login = || {
+            let mut client = FtpClient::connect(address).unwrap();
+            client
+                .login_with(|| Ok(Credentials::new("alice", "wonderland")))
+                .unwrap();
+            client
+        };
+
+        let mut client = login();
+        client.change_dir("/docs").unwrap();
+        client.change_dir("guide").unwrap();
+        idle();
+        assert_eq!(client.size("a.txt").unwrap(), 3);
+        assert_eq!(client.current_dir().unwrap().as_str(), "/docs/guide");
+
+        let mut client = login();
+        client.current_dir().unwrap();
+        client.change_dir("docs").unwrap();
+        client.change_dir_up().unwrap();
+        client.change_dir("docs/guide").unwrap();
+        idle();
+        assert_eq!(client.size("a.txt").unwrap(), 3);
+
+        // moved from the login directory without asking where it is
+        let mut client = login();
+        client.change_dir("docs/guide").unwrap();
+        idle();
+        assert!(matches!(
+            client.size("a.txt"),
+            Err(FtpError::SessionExpired(_))
+        ));
+        assert_eq!(client.current_dir().unwrap().as_str(), "/");
     }
 }
@@ -0,0 +1,272 @@
+//! Ordering and filtering of directory listings.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::Command;
+use crate::EntryKind;
+use crate::FtpClient;
+use crate::FtpEntry;
+use crate::FtpError;
+use crate::RemotePath;
+use crate::Result;
+
+type EntryFilter = Arc<dyn Fn(&FtpEntry) -> bool + Send + Sync>;
+
+/// Key to sort listed entries by. Entries the key is unknown for come first,
+/// ties are broken by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListOrder {
+    Name,
+    /// Last modification time.
+    Modified,
+    Size,
+}
+
+impl ListOrder {
+    pub(crate) fn compare(&self, a: &FtpEntry, b: &FtpEntry) -> Ordering {
+        match self {
+            ListOrder::Name => Ordering::Equal,
+            ListOrder::Modified => a.modified().cmp(&b.modified()),
+            ListOrder::Size => a.size().cmp(&b.size()),
+        }
+        .then_with(|| a.name().cmp(b.name()))
+    }
+}
+
+/// Settings of [`FtpClient::list_entries_with`]. Filters are applied before
+/// sorting, so only the kept entries are sorted.
+#[derive(Clone, Default)]
+pub struct ListOptions {
+    order: Option<ListOrder>,
+    descending: bool,
+    pattern: Option<String>,
+    kind: Option<EntryKind>,
+    filter: Option<EntryFilter>,
+}
+
+impl ListOptions {
+    /// Keep every entry in the order the server listed them.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sort the entries by `order`, ascending.
+    pub fn sort_by(mut self, order: ListOrder) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    /// Sort in descending order instead, e.g. newest first.
+    pub fn descending(mut self, descending: bool) -> Self {
+        self.descending = descending;
+        self
+    }
+
+    /// Keep entries whose name matches `pattern`, where `*` stands for any
+    /// run of characters and `?` for any single one.
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+
+    /// Keep entries of `kind` only.
+    pub fn kind(mut self, kind: EntryKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Keep entries `filter` returns true for.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&FtpEntry) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Filter and sort `entries` in place.
+    pub fn apply(&self, entries: &mut Vec<FtpEntry>) {
+        entries.retain(|entry| {
+            self.pattern
+                .as_ref()
+                .is_none_or(|pattern| matches(pattern, entry.name()))
+                && self.kind.is_none_or(|kind| entry.kind() == kind)
+                && self.filter.as_ref().is_none_or(|filter| filter(entry))
+        });
+        if let Some(order) = self.order {
+            if self.descending {
+                entries.sort_by(|a, b| order.compare(b, a));
+            } else {
+                entries.sort_by(|a, b| order.compare(a, b));
+            }
+        }
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of
+/// characters and `?` for any single character.
+pub(crate) fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (
+        pattern.chars().collect::<Vec<_>>(),
+        name.chars().collect::<Vec<_>>(),
+    );
+    // positions to resume from when the last `*` has to take one more character
+    let (mut p, mut n, mut star) = (0, 0, None);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&expected) if expected == '?' || expected == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    (p, n) = (star_p + 1, star_n + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&rest| rest == '*')
+}
+
+impl FtpClient {
+    /// List a directory like [`FtpClient::list_entries`], then filter and
+    /// sort the entries with `options`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{EntryKind, FtpClient, ListOptions, ListOrder};
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let options = ListOptions::new()
+    ///     .pattern("*.log")
+    ///     .kind(EntryKind::File)
+    ///     .sort_by(ListOrder::Modified)
+    ///     .descending(true);
+    /// let newest = client.list_entries_with("/var/log", &options).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Errors on connection failure or improper response from server
+    pub fn list_entries_with(
+        &mut self,
+        dir: impl AsRef<str>,
+        options: &ListOptions,
+    ) -> Result<Vec<FtpEntry>> {
+        let mut entries = self.list_entries(dir)?;
+        options.apply(&mut entries);
+        Ok(entries)
+    }
+
+    /// Names of the entries of `dir` matching the wildcards of `pattern`.
+    /// The server is asked to match them with `NLST <dir>/<pattern>`, which
+    /// many support; the names are matched again locally, and the whole
+    /// directory is listed when the server refuses the pattern.
+    ///
+    /// # Errors
+    /// Errors on connection failure or when `dir` can't be listed.
+    pub fn name_list_matching(
+        &mut self,
+        dir: impl AsRef<str>,
+        pattern: &str,
+    ) -> Result<Vec<String>> {
+        let dir = dir.as_ref();
+        let globbed = RemotePath::new(dir).join(pattern);
+        let names = match self.list_cmd(|path| Command::Nlst(path), globbed.as_str()) {
+            Err(FtpError::FileError(_) | FtpError::CommandError(_) | FtpError::Unsupported(_)) => {
+                self.name_list(dir)?
+            }
+            names => names?,
+        };
+        Ok(names
+            .into_iter()
+            // some servers list full paths
+            .map(|name| name.rsplit('/').next().unwrap_or(&name).to_string())
+            .filter(|name| matches(pattern, name))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "server")]
+    use crate::testing::served_client;
+    #[cfg(feature = "server")]
+    use crate::FtpEntry;
+
+    #[test]
+    fn wildcards_match_names() {
+        use super::matches;
+
+        assert!(matches("*.log", "app.log"));
+        assert!(matches("*.log", ".log"));
+        assert!(!matches("*.log", "app.log.1"));
+        assert!(matches("a?p*.l*g", "app-2024.log"));
+        assert!(matches("*", ""));
+        assert!(!matches("?", ""));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(matches("données_*", "données_été"));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn listings_are_sorted_and_filtered() {
+        use super::{ListOptions, ListOrder};
+        use crate::{EntryKind, SymlinkPolicy, WalkOptions};
+
+        let files: &[(&str, &[u8])] = &[
+            ("logs/b.log", &[b'x'; 30]),
+            ("logs/a.log", &[b'x'; 10]),
+            ("logs/c.txt", &[b'x'; 20]),
+            ("logs/old.d/z.log", b"z"),
+        ];
+        let (mut client, _root) = served_client("sort", files);
+        let names = |entries: Vec<FtpEntry>| {
+            entries
+                .iter()
+                .map(|entry| entry.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        let options = ListOptions::new()
+            .kind(EntryKind::File)
+            .sort_by(ListOrder::Size)
+            .descending(true);
+        let listed = client.list_entries_with("/logs", &options).unwrap();
+        assert_eq!(names(listed), ["b.log", "c.txt", "a.log"]);
+        let options = ListOptions::new().pattern("*.log").sort_by(ListOrder::Name);
+        let listed = client.list_entries_with("/logs", &options).unwrap();
+        assert_eq!(names(listed), ["a.log", "b.log"]);
+        let options = ListOptions::new().filter(|entry| entry.size() == Some(20));
+        let listed = client.list_entries_with("/logs", &options).unwrap();
+        assert_eq!(names(listed), ["c.txt"]);
+
+        let mut matching = client.name_list_matching("/logs", "*.log").unwrap();
+        matching.sort();
+        assert_eq!(matching, ["a.log", "b.log"]);
+
+        let walk = WalkOptions::new()
+            .symlinks(SymlinkPolicy::Skip)
+            .sort_by(ListOrder::Name)
+            .pattern("*.log");
+        let walked = client.walk("/logs", &walk).unwrap();
+        let paths = walked
+            .iter()
+            .map(|found| found.path.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "/logs/a.log",
+                "/logs/b.log",
+                "/logs/old.d",
+                "/logs/old.d/z.log"
+            ]
+        );
+    }
+}
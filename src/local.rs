@@ -0,0 +1,662 @@
+//! Helpers moving files between the server and the local filesystem.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::Command;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::Result;
+
+/// What [`FtpClient::put_if_changed`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadOutcome {
+    Uploaded,
+    /// The remote file already matched the local one.
+    Skipped,
+}
+
+impl FtpClient {
+    /// Download a remote file into a local file, replacing it atomically.
+    ///
+    /// Switches to binary mode, so the file arrives as stored and its size can
+    /// be compared. Data is written to `<local>.part` which is renamed to `local` only once the
+    /// server confirmed the transfer and the received size matches the remote SIZE
+    /// (when the server supports it), so readers never see a truncated file.
+    /// The partial file is removed on failure.
+    ///
+    /// # Arguments
+    /// `remote`    path of the file on the server
+    /// `local`     path of the local file to write
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::builder()
+    ///     .check_free_space(true)
+    ///     .connect("127.0.0.1:21")
+    ///     .unwrap();
+    /// client.login("user", "password").unwrap();
+    /// client.get_to_path("/pub/image.iso", "image.iso").unwrap();
+    /// client.logout().unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When the transfer fails, the local file cannot be written, the sizes don't match
+    /// or, with the free space check enabled, when the local disk is too small.
+    pub fn get_to_path(&mut self, remote: impl AsRef<str>, local: impl AsRef<Path>) -> Result<()> {
+        let (remote, local) = (remote.as_ref(), local.as_ref());
+        if self.config.check_free_space {
+            self.check_free_space(remote, local)?;
+        }
+        let part = part_path(local);
+        match self.download_part(remote, &part) {
+            Ok(()) => Ok(std::fs::rename(&part, local)?),
+            Err(error) => {
+                let _ = std::fs::remove_file(&part);
+                Err(error)
+            }
+        }
+    }
+
+    /// Download `remote` into `part` and check its size against the server's.
+    fn download_part(&mut self, remote: &str, part: &Path) -> Result<()> {
+        // SIZE depends on the transfer type
+        self.binary_mode()?;
+        let mut file = File::create(part)?;
+        let received = self.retrieve_file(remote, &mut file)?;
+        file.sync_all()?;
+        match self.size(remote) {
+            Ok(size) if size != received => Err(FtpError::FileError(format!(
+                "Size mismatch for {}: {} bytes received, {} expected",
+                remote, received, size
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Download a remote file only when it is newer than the local copy, or when
+    /// their sizes differ if the server supports SIZE. The local file gets the
+    /// remote modification time so later comparisons are exact.
+    /// Returns whether the file was downloaded.
+    ///
+    /// # Arguments
+    /// `remote`    path of the file on the server
+    /// `local`     path of the local file to refresh
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// if client.get_if_newer("/data/prices.csv", "prices.csv").unwrap() {
+    ///     println!("prices updated");
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// When the server lacks MDTM support or the transfer fails.
+    pub fn get_if_newer(
+        &mut self,
+        remote: impl AsRef<str>,
+        local: impl AsRef<Path>,
+    ) -> Result<bool> {
+        let (remote, local) = (remote.as_ref(), local.as_ref());
+        let remote_time = self.modified_time(remote)?;
+        if self.is_current(remote, remote_time, local)? {
+            return Ok(false);
+        }
+        self.get_to_path(remote, local)?;
+        File::options()
+            .write(true)
+            .open(local)?
+            .set_modified(remote_time)?;
+        Ok(true)
+    }
+
+    /// Complete a local file holding the start of a remote one, e.g. left by
+    /// an interrupted download, retrieving only the missing bytes with `REST`.
+    /// A local file larger than the remote one is downloaded again, a missing
+    /// one downloaded whole. Returns whether anything was downloaded.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// client.resume_to_path("/pub/image.iso", "image.iso").unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When the server lacks `SIZE` or `REST`, the transfer fails or the local
+    /// file doesn't end up with the remote size.
+    pub fn resume_to_path(
+        &mut self,
+        remote: impl AsRef<str>,
+        local: impl AsRef<Path>,
+    ) -> Result<bool> {
+        let (remote, local) = (remote.as_ref(), local.as_ref());
+        let Ok(metadata) = std::fs::metadata(local) else {
+            self.get_to_path(remote, local)?;
+            return Ok(true);
+        };
+        // offsets count the bytes of the binary form
+        self.binary_mode()?;
+        let size = self.size(remote)?;
+        let held = metadata.len();
+        if held == size {
+            return Ok(false);
+        }
+        if held > size {
+            self.get_to_path(remote, local)?;
+            return Ok(true);
+        }
+        let mut file = File::options().append(true).open(local)?;
+        let received = self.retrieve_from(remote, held, |client, stream, socket| {
+            client.transfer_data(stream, &mut file, socket)
+        })?;
+        file.sync_all()?;
+        if held + received != size {
+            return Err(FtpError::FileError(format!(
+                "Size mismatch for {}: {} bytes after resuming at {}, {} expected",
+                remote,
+                held + received,
+                held,
+                size
+            )));
+        }
+        Ok(true)
+    }
+
+    /// Whether `local` exists, is not older than `remote` and, when the server
+    /// tells, has the same size.
+    pub(crate) fn is_current(
+        &mut self,
+        remote: &str,
+        remote_time: SystemTime,
+        local: &Path,
+    ) -> Result<bool> {
+        let Ok(metadata) = std::fs::metadata(local) else {
+            return Ok(false);
+        };
+        // SIZE depends on the transfer type
+        self.binary_mode()?;
+        Ok(metadata.modified()? >= remote_time
+            && self
+                .size(remote)
+                .map_or(true, |size| size == metadata.len()))
+    }
+
+    /// Upload a local file to the server.
+    ///
+    /// Switches to binary mode, announces the file size with ALLO so the server can
+    /// reserve space (servers without ALLO support are tolerated) and streams the file.
+    ///
+    /// # Arguments
+    /// `local`     path of the local file to send
+    /// `remote`    path of the file to create on the server
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// client.put_from_path("backup.tar", "/backups/backup.tar").unwrap();
+    /// client.logout().unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When the local file cannot be read or the transfer fails.
+    pub fn put_from_path(
+        &mut self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<str>,
+    ) -> Result<()> {
+        let mut file = File::open(local)?;
+        let size = file.metadata()?.len();
+        self.binary_mode()?;
+        self.announce_upload(size)?;
+        self.store_file(remote.as_ref(), &mut file)
+    }
+
+    /// Download into a local file, copying in the kernel when possible.
+    fn retrieve_file(&mut self, remote: &str, file: &mut File) -> Result<u64> {
+        self.retrieve_with(remote, |client, stream, socket| {
+            #[cfg(all(feature = "zero-copy", target_os = "linux"))]
+            if stream.is_plain() {
+                return client.splice_to_file(socket, file);
+            }
+            client.transfer_data(stream, file, socket)
+        })
+    }
+
+    /// Upload a local file, copying in the kernel when possible.
+    fn store_file(&mut self, remote: &str, file: &mut File) -> Result<()> {
+        self.store_with(
+            remote,
+            |file| Command::Stor(file),
+            |client, stream, socket| {
+                #[cfg(all(feature = "zero-copy", target_os = "linux"))]
+                if stream.is_plain() {
+                    return client.send_file(file, socket);
+                }
+                client.transfer_data(file, stream, socket)
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Upload a local file unless the remote copy is unchanged: same size and,
+    /// when the server supports `XCRC`, same CRC-32, otherwise a remote
+    /// modification time not older than the local one.
+    ///
+    /// # Arguments
+    /// `local`     path of the local file to send
+    /// `remote`    path of the file on the server
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, UploadOutcome};
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let outcome = client.put_if_changed("site/index.html", "/www/index.html").unwrap();
+    /// if outcome == UploadOutcome::Skipped {
+    ///     println!("already up to date");
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// When the local file cannot be read or the transfer fails.
+    pub fn put_if_changed(
+        &mut self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<str>,
+    ) -> Result<UploadOutcome> {
+        let (local, remote) = (local.as_ref(), remote.as_ref());
+        if self.is_unchanged(local, remote)? {
+            return Ok(UploadOutcome::Skipped);
+        }
+        self.put_from_path(local, remote)?;
+        Ok(UploadOutcome::Uploaded)
+    }
+
+    /// Compare a local file with its remote copy, anything unknown counts as changed.
+    pub(crate) fn is_unchanged(&mut self, local: &Path, remote: &str) -> Result<bool> {
+        let metadata = std::fs::metadata(local)?;
+        // SIZE depends on the transfer type
+        self.binary_mode()?;
+        match self.size(remote) {
+            Ok(size) if size == metadata.len() => {}
+            _ => return Ok(false),
+        }
+        if let Some(checksum) = self.remote_crc32(remote)? {
+            return Ok(checksum == crc32(&mut File::open(local)?)?);
+        }
+        match self.modified_time(remote) {
+            Ok(time) => Ok(time >= metadata.modified()?),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// CRC-32 of a remote file computed by the server with the `XCRC` extension,
+    /// `None` when unsupported or when the reply doesn't hold a checksum.
+    pub(crate) fn remote_crc32(&mut self, remote: &str) -> Result<Option<u32>> {
+        let command = Command::Xcrc(remote);
+        let response = self.write_cmd(command)?;
+        if !self.accepts(&command, &response) {
+            return Ok(None);
+        }
+        Ok(parse_crc32(&response.message))
+    }
+
+    /// Fail when the remote file is larger than the space left where `local`
+    /// goes. Passes when the server doesn't tell the size.
+    fn check_free_space(&mut self, remote: &str, local: &Path) -> Result<()> {
+        let directory = match local.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let available = match available_space(directory)? {
+            Some(available) => available,
+            None => return Ok(()),
+        };
+        // SIZE depends on the transfer type
+        self.binary_mode()?;
+        // without a size there is nothing to check
+        let size = match self.size(remote) {
+            Ok(size) => size,
+            Err(error @ (FtpError::ConnectionError(_) | FtpError::SessionExpired(_))) => {
+                return Err(error)
+            }
+            Err(_) => return Ok(()),
+        };
+        if size > available {
+            return Err(FtpError::FileError(format!(
+                "Not enough space for {}: {} bytes needed, {} available",
+                local.display(),
+                size,
+                available
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Temporary name used while downloading to `local`.
+fn part_path(local: &Path) -> PathBuf {
+    let mut part = local.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// The checksum of an `XCRC` reply, which servers send as the first word of
+/// the text: exactly eight hex digits, as in `250 1A2B3C4D`.
+pub(crate) fn parse_crc32(message: &str) -> Option<u32> {
+    let word = message.split_whitespace().next()?;
+    if word.len() != 8 || !word.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(word, 16).ok()
+}
+
+/// Lookup table of the reflected CRC-32 (IEEE) polynomial.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut value = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                0xEDB8_8320 ^ (value >> 1)
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        table[index] = value;
+        index += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE) of the whole content of `reader`.
+pub(crate) fn crc32(reader: &mut impl Read) -> Result<u32> {
+    let mut reader = Crc32Reader::new(reader);
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+    Ok(reader.crc32())
+}
+
+/// A reader computing the CRC-32 (IEEE) and the length of what is read
+/// through it.
+pub(crate) struct Crc32Reader<R> {
+    inner: R,
+    crc: u32,
+    length: u64,
+}
+
+impl<R: Read> Crc32Reader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            crc: !0,
+            length: 0,
+        }
+    }
+
+    /// Checksum of the bytes read so far.
+    pub(crate) fn crc32(&self) -> u32 {
+        !self.crc
+    }
+
+    /// Number of bytes read so far.
+    pub(crate) fn length(&self) -> u64 {
+        self.length
+    }
+}
+
+impl<R: Read> Read for Crc32Reader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buffer)?;
+        for byte in &buffer[..count] {
+            self.crc =
+                CRC32_TABLE[((self.crc ^ u32::from(*byte)) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+        self.length += count as u64;
+        Ok(count)
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+#[cfg(unix)]
+pub(crate) fn available_space(path: &Path) -> Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| FtpError::FileError(format!("Invalid path {}", path.display())))?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a valid NUL terminated string and `stats` is only read on success.
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // SAFETY: statvfs returned 0 so the structure was filled in.
+    let stats = unsafe { stats.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stats.f_bavail as u64 * stats.f_frsize as u64))
+}
+
+/// Free space can't be queried without platform bindings: skip the check.
+#[cfg(not(unix))]
+pub(crate) fn available_space(_path: &Path) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "server")]
+    use crate::testing::served_client;
+    #[cfg(feature = "server")]
+    use crate::FtpClient;
+
+    /// Make the client's server answer `SIZE` like servers converting line
+    /// endings in ASCII mode: one byte more, for a file with one line.
+    #[cfg(feature = "server")]
+    fn ascii_sizes(client: &mut FtpClient) {
+        use crate::{Next, Response};
+
+        let mut ascii = false;
+        client.add_layer(move |command: &str, next: &mut Next| {
+            match command {
+                "TYPE A" => ascii = true,
+                "TYPE I" => ascii = false,
+                _ if ascii && command.starts_with("SIZE ") => {
+                    let response = next.run(command)?;
+                    let size: u64 = response.message().trim().parse().unwrap();
+                    return Ok(Response::new(213, format!(" {}\r\n", size + 1)));
+                }
+                _ => {}
+            }
+            next.run(command)
+        });
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn downloads_compare_binary_sizes() {
+        let (mut client, root) = served_client("get-ascii", &[("a.txt", b"line\n")]);
+        ascii_sizes(&mut client);
+        client.ascii_mode().unwrap();
+        let local = root.join("copy.txt");
+        client.get_to_path("/a.txt", &local).unwrap();
+        assert_eq!(std::fs::read(&local).unwrap(), b"line\n");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn downloads_check_free_space() {
+        use crate::testing::{serve, TempRoot};
+        use crate::{FtpError, Next, Response};
+
+        let root = TempRoot::new("free-space", &[("a.txt", b"line\n")]);
+        let address = serve(&root);
+        let connect = || {
+            let mut client = FtpClient::builder()
+                .check_free_space(true)
+                .connect(address.to_string())
+                .unwrap();
+            client.login("anonymous", "").unwrap();
+            client
+        };
+        let local = root.join("copy.txt");
+
+        let mut client = connect();
+        ascii_sizes(&mut client);
+        client.ascii_mode().unwrap();
+        client.get_to_path("/a.txt", &local).unwrap();
+        assert_eq!(std::fs::read(&local).unwrap(), b"line\n");
+
+        // a file larger than any disk
+        let mut client = connect();
+        client.add_layer(|command: &str, next: &mut Next| match command {
+            "SIZE /a.txt" => Ok(Response::new(213, format!(" {}\r\n", u64::MAX))),
+            _ => next.run(command),
+        });
+        let result = client.get_to_path("/a.txt", root.join("huge.txt"));
+        assert!(matches!(result, Err(FtpError::FileError(message)) if message.contains("space")));
+        assert!(!root.join("huge.txt").exists());
+
+        // a server without SIZE
+        let mut client = connect();
+        client.add_layer(|command: &str, next: &mut Next| {
+            if command.starts_with("SIZE") {
+                return Ok(Response::new(502, " Command not implemented\r\n"));
+            }
+            next.run(command)
+        });
+        client
+            .get_to_path("/a.txt", root.join("unsized.txt"))
+            .unwrap();
+        assert_eq!(std::fs::read(root.join("unsized.txt")).unwrap(), b"line\n");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn current_copies_are_kept() {
+        let (mut client, root) = served_client("newer-ascii", &[("a.txt", b"line\n")]);
+        ascii_sizes(&mut client);
+        let local = root.join("copy.txt");
+        client.ascii_mode().unwrap();
+        assert!(client.get_if_newer("/a.txt", &local).unwrap());
+        client.ascii_mode().unwrap();
+        assert!(!client.get_if_newer("/a.txt", &local).unwrap());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn files_are_uploaded_from_paths() {
+        use crate::{Next, Response};
+        use std::sync::{Arc, Mutex};
+
+        let content = [7; 300];
+        let (mut client, root) = served_client("put-path", &[("local/a.bin", &content)]);
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let log = sent.clone();
+        client.add_layer(move |command: &str, next: &mut Next| {
+            log.lock().unwrap().push(command.to_string());
+            match command.starts_with("ALLO") {
+                true => Ok(Response::new(200, " Space reserved\r\n")),
+                false => next.run(command),
+            }
+        });
+        client.ascii_mode().unwrap();
+        client
+            .put_from_path(root.join("local/a.bin"), "/a.bin")
+            .unwrap();
+        assert_eq!(std::fs::read(root.join("a.bin")).unwrap(), content);
+        let sent = sent.lock().unwrap();
+        let position = |prefix| sent.iter().rposition(|command| command.starts_with(prefix));
+        assert_eq!(sent[position("ALLO").unwrap()], "ALLO 300");
+        assert!(position("TYPE I") < position("ALLO") && position("ALLO") < position("STOR"));
+
+        // the embedded server has no ALLO
+        let (mut client, root) = served_client("put-path-no-allo", &[("local/a.bin", &content)]);
+        client
+            .put_from_path(root.join("local/a.bin"), "/a.bin")
+            .unwrap();
+        assert_eq!(std::fs::read(root.join("a.bin")).unwrap(), content);
+        assert!(client
+            .put_from_path(root.join("missing"), "/b.bin")
+            .is_err());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn uploads_skip_unchanged_files() {
+        use crate::{Next, Response, UploadOutcome};
+        use std::fs::File;
+        use std::time::{Duration, SystemTime};
+
+        let files: &[(&str, &[u8])] = &[("a.txt", b"hello"), ("local/a.txt", b"hello")];
+        let (mut client, root) = served_client("put-changed", files);
+        let local = root.join("local/a.txt");
+        let touch = |content: &[u8], age: i64| {
+            std::fs::write(&local, content).unwrap();
+            let now = SystemTime::now();
+            let offset = Duration::from_secs(age.unsigned_abs());
+            let time = if age < 0 { now + offset } else { now - offset };
+            File::options()
+                .write(true)
+                .open(&local)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+
+        // the embedded server has no XCRC, the modification times decide
+        touch(b"hello", 3600);
+        let outcome = client.put_if_changed(&local, "/a.txt").unwrap();
+        assert_eq!(outcome, UploadOutcome::Skipped);
+        touch(b"hello", -3600);
+        let outcome = client.put_if_changed(&local, "/a.txt").unwrap();
+        assert_eq!(outcome, UploadOutcome::Uploaded);
+        touch(b"hello!", 3600);
+        let outcome = client.put_if_changed(&local, "/a.txt").unwrap();
+        assert_eq!(outcome, UploadOutcome::Uploaded);
+        assert_eq!(std::fs::read(root.join("a.txt")).unwrap(), b"hello!");
+
+        // with XCRC the content decides, whatever the times
+        let remote = root.join("a.txt");
+        client.add_layer(move |command: &str, next: &mut Next| {
+            if command == "XCRC /a.txt" {
+                let checksum = super::crc32(&mut File::open(&remote)?)?;
+                return Ok(Response::new(250, format!(" {:08X}\r\n", checksum)));
+            }
+            next.run(command)
+        });
+        touch(b"hello!", -3600);
+        let outcome = client.put_if_changed(&local, "/a.txt").unwrap();
+        assert_eq!(outcome, UploadOutcome::Skipped);
+        touch(b"jello!", 3600);
+        let outcome = client.put_if_changed(&local, "/a.txt").unwrap();
+        assert_eq!(outcome, UploadOutcome::Uploaded);
+        assert_eq!(std::fs::read(root.join("a.txt")).unwrap(), b"jello!");
+    }
+
+    #[test]
+    fn checksum_replies() {
+        use super::parse_crc32;
+
+        assert_eq!(parse_crc32(" 3610A686\r\n"), Some(0x3610_a686));
+        assert_eq!(parse_crc32(" 0000beef"), Some(0xbeef));
+        // words in the text are not checksums
+        assert_eq!(parse_crc32(" Ok 3610A686\r\n"), None);
+        assert_eq!(parse_crc32(" cafe"), None);
+        assert_eq!(parse_crc32(" 3610A686F"), None);
+        assert_eq!(parse_crc32(" +3610A68"), None);
+        assert_eq!(parse_crc32(""), None);
+    }
+}
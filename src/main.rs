@@ -1,12 +1,14 @@
-use simpleftp::FtpClient;
-use simpleftp::Result;
+mod cli;
 
-fn main() -> Result<()> {
-    let mut client = FtpClient::connect("test.rebex.net:21")?;
-    client.login("demo", "password")?;
+use std::process::ExitCode;
 
-    client.get("/readme.txt", &mut std::fs::File::create("readme.txt")?)?;
-
-    client.logout()?;
-    Ok(())
+fn main() -> ExitCode {
+    match cli::run(std::env::args().skip(1)) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("simpleftp: {}", error);
+            ExitCode::from(2)
+        }
+    }
 }
@@ -0,0 +1,470 @@
+//! Records of uploaded files, to check later that the server still holds them.
+
+use std::fmt::Write;
+use std::fs::File;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::json;
+use crate::local::Crc32Reader;
+use crate::time::format_timestamp;
+use crate::time::parse_timestamp;
+use crate::Command;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::RemotePath;
+use crate::Result;
+
+/// A file stored on the server, as recorded in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManifestEntry {
+    pub remote: RemotePath,
+    /// Size in bytes.
+    pub size: u64,
+    /// CRC-32 (IEEE) of the content, as `XCRC` computes it.
+    #[cfg_attr(feature = "serde", serde(with = "serde_form::crc32"))]
+    pub crc32: u32,
+    /// When the upload completed.
+    #[cfg_attr(feature = "serde", serde(with = "serde_form::stored"))]
+    pub stored: SystemTime,
+}
+
+/// The files stored by a batch upload, see
+/// [`MirrorOptions::manifest`](crate::MirrorOptions::manifest), in CSV or
+/// JSON form:
+///
+/// ```text
+/// remote,size,crc32,stored
+/// "/www/index.html",1024,8a9136aa,20240501120000
+///
+/// [{"remote":"/www/index.html","size":1024,"crc32":"8a9136aa","stored":"20240501120000"}]
+/// ```
+///
+/// With the `serde` feature, a manifest serializes to the same JSON.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+const CSV_HEADER: &str = "remote,size,crc32,stored";
+
+impl Manifest {
+    /// A manifest without entries.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The manifest as CSV, one line per file after a header line.
+    /// Timestamps are UTC, `YYYYMMDDHHMMSS`.
+    pub fn to_csv(&self) -> String {
+        let mut csv = format!("{}\n", CSV_HEADER);
+        for entry in &self.entries {
+            let _ = writeln!(
+                csv,
+                "\"{}\",{},{:08x},{}",
+                entry.remote.as_str().replace('"', "\"\""),
+                entry.size,
+                entry.crc32,
+                format_timestamp(entry.stored)
+            );
+        }
+        csv
+    }
+
+    /// Read a manifest written by [`Manifest::to_csv`].
+    ///
+    /// # Errors
+    /// [`FtpError::InvalidArgument`] naming the first line that can't be read.
+    pub fn from_csv(csv: &str) -> Result<Self> {
+        let mut lines = csv.lines().enumerate();
+        if lines.next().map(|(_, header)| header.trim()) != Some(CSV_HEADER) {
+            return Err(FtpError::InvalidArgument(
+                "Manifest doesn't start with a CSV header".into(),
+            ));
+        }
+        let mut manifest = Self::new();
+        for (index, line) in lines.filter(|(_, line)| !line.trim().is_empty()) {
+            let entry = parse_csv_line(line).ok_or_else(|| {
+                FtpError::InvalidArgument(format!("Invalid manifest line {}: {}", index + 1, line))
+            })?;
+            manifest.entries.push(entry);
+        }
+        Ok(manifest)
+    }
+
+    /// The manifest as a JSON array of objects with the fields of the CSV
+    /// form, `crc32` as hexadecimal text.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (index, entry) in self.entries.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"remote\":{},\"size\":{},\"crc32\":\"{:08x}\",\"stored\":\"{}\"}}",
+                json::string(entry.remote.as_str()),
+                entry.size,
+                entry.crc32,
+                format_timestamp(entry.stored)
+            );
+        }
+        json.push(']');
+        json
+    }
+
+    /// Read a manifest written by [`Manifest::to_json`].
+    ///
+    /// # Errors
+    /// [`FtpError::InvalidArgument`] naming the first entry that can't be read.
+    pub fn from_json(text: &str) -> Result<Self> {
+        let mut reader = json::Reader::new(text);
+        if !reader.consume('[') {
+            return Err(FtpError::InvalidArgument(
+                "Manifest isn't a JSON array".into(),
+            ));
+        }
+        let mut manifest = Self::new();
+        let mut closed = reader.consume(']');
+        while !closed {
+            let entry = parse_json_entry(&mut reader).ok_or_else(|| {
+                FtpError::InvalidArgument(format!(
+                    "Invalid manifest entry {}",
+                    manifest.entries.len() + 1
+                ))
+            })?;
+            manifest.entries.push(entry);
+            closed = reader.consume(']');
+            if !closed && !reader.consume(',') {
+                return Err(FtpError::InvalidArgument(format!(
+                    "Invalid JSON after manifest entry {}",
+                    manifest.entries.len()
+                )));
+            }
+        }
+        if !reader.at_end() {
+            return Err(FtpError::InvalidArgument(
+                "Invalid JSON after the manifest".into(),
+            ));
+        }
+        Ok(manifest)
+    }
+}
+
+/// One object of the JSON form, its fields in any order.
+fn parse_json_entry(reader: &mut json::Reader) -> Option<ManifestEntry> {
+    let (mut remote, mut size, mut crc32, mut stored) = (None, None, None, None);
+    if !reader.consume('{') {
+        return None;
+    }
+    let mut closed = reader.consume('}');
+    while !closed {
+        let key = reader.string()?;
+        if !reader.consume(':') {
+            return None;
+        }
+        match key.as_str() {
+            "remote" => remote = Some(RemotePath::new(reader.string()?)),
+            "size" => size = Some(reader.integer()?),
+            "crc32" => crc32 = Some(parse_crc32_field(&reader.string()?)?),
+            "stored" => stored = Some(parse_timestamp(&reader.string()?)?),
+            _ => return None,
+        }
+        closed = reader.consume('}');
+        if !closed && !reader.consume(',') {
+            return None;
+        }
+    }
+    Some(ManifestEntry {
+        remote: remote?,
+        size: size?,
+        crc32: crc32?,
+        stored: stored?,
+    })
+}
+
+/// A checksum written as 8 hexadecimal digits.
+fn parse_crc32_field(text: &str) -> Option<u32> {
+    if text.len() != 8 || !text.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// One CSV line: the quoted remote path, then size, checksum and timestamp.
+fn parse_csv_line(line: &str) -> Option<ManifestEntry> {
+    let rest = line.trim_end().strip_prefix('"')?;
+    let mut remote = String::new();
+    let mut characters = rest.char_indices();
+    let fields = loop {
+        match characters.next()? {
+            (index, '"') if rest[index + 1..].starts_with('"') => {
+                remote.push('"');
+                characters.next();
+            }
+            (index, '"') => break rest[index + 1..].strip_prefix(',')?,
+            (_, character) => remote.push(character),
+        }
+    };
+    let mut fields = fields.split(',');
+    let entry = ManifestEntry {
+        remote: RemotePath::new(remote),
+        size: fields.next()?.parse().ok()?,
+        crc32: parse_crc32_field(fields.next()?)?,
+        stored: parse_timestamp(fields.next()?)?,
+    };
+    fields.next().is_none().then_some(entry)
+}
+
+/// Serde encoding of the fields whose JSON form isn't serde's default, so
+/// serializing a manifest gives [`Manifest::to_json`].
+#[cfg(feature = "serde")]
+mod serde_form {
+    pub(super) mod crc32 {
+        use serde::de::Error;
+        use serde::Deserialize;
+        use serde::Deserializer;
+        use serde::Serializer;
+
+        use crate::manifest::parse_crc32_field;
+
+        pub(crate) fn serialize<S: Serializer>(
+            crc32: &u32,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(&format_args!("{:08x}", crc32))
+        }
+
+        pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<u32, D::Error> {
+            let text = String::deserialize(deserializer)?;
+            parse_crc32_field(&text)
+                .ok_or_else(|| D::Error::custom(format!("invalid CRC-32 {:?}", text)))
+        }
+    }
+
+    pub(super) mod stored {
+        use std::time::SystemTime;
+
+        use serde::de::Error;
+        use serde::Deserialize;
+        use serde::Deserializer;
+        use serde::Serializer;
+
+        use crate::time::format_timestamp;
+        use crate::time::parse_timestamp;
+
+        pub(crate) fn serialize<S: Serializer>(
+            stored: &SystemTime,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&format_timestamp(*stored))
+        }
+
+        pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<SystemTime, D::Error> {
+            let text = String::deserialize(deserializer)?;
+            parse_timestamp(&text)
+                .ok_or_else(|| D::Error::custom(format!("invalid timestamp {:?}", text)))
+        }
+    }
+}
+
+impl FtpClient {
+    /// Upload the local file `local` to `remote` like
+    /// [`FtpClient::put_from_path`] and record it for a [`Manifest`]. The
+    /// size and checksum are those of the bytes sent, computed along the way,
+    /// so the data goes through user space even with the `zero-copy` feature.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, Manifest, RemotePath};
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let mut manifest = Manifest::new();
+    /// let entry = client
+    ///     .put_recorded("report.pdf", &RemotePath::new("/inbox/report.pdf"))
+    ///     .unwrap();
+    /// manifest.entries.push(entry);
+    /// std::fs::write("upload.json", manifest.to_json()).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When the local file cannot be read or the transfer fails.
+    pub fn put_recorded(
+        &mut self,
+        local: impl AsRef<Path>,
+        remote: &RemotePath,
+    ) -> Result<ManifestEntry> {
+        let file = File::open(local)?;
+        let size = file.metadata()?.len();
+        self.binary_mode()?;
+        self.announce_upload(size)?;
+        let mut source = Crc32Reader::new(file);
+        self.store_with(
+            remote.as_str(),
+            |file| Command::Stor(file),
+            |client, stream, socket| client.transfer_data(&mut source, stream, socket),
+        )?;
+        Ok(ManifestEntry {
+            remote: remote.clone(),
+            size: source.length(),
+            crc32: source.crc32(),
+            stored: SystemTime::now(),
+        })
+    }
+
+    /// Check that the server still holds each file of `manifest`: the size
+    /// must match and, when the server supports `XCRC`, the checksum too.
+    /// Returns the files that don't, with [`FtpError::IntegrityError`] for
+    /// changed content or the error `SIZE` gave, e.g. for a missing file.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, Manifest};
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let json = std::fs::read_to_string("upload.json").unwrap();
+    /// let manifest = Manifest::from_json(&json).unwrap();
+    /// for (remote, problem) in client.verify_manifest(&manifest).unwrap() {
+    ///     println!("{}: {}", remote, problem);
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// On connection failure.
+    pub fn verify_manifest(&mut self, manifest: &Manifest) -> Result<Vec<(RemotePath, FtpError)>> {
+        // SIZE depends on the transfer type
+        self.binary_mode()?;
+        let mut mismatches = Vec::new();
+        for entry in &manifest.entries {
+            let remote = entry.remote.as_str();
+            let problem = match self.size(remote) {
+                Err(error @ (FtpError::ConnectionError(_) | FtpError::SessionExpired(_))) => {
+                    return Err(error)
+                }
+                Err(error) => Some(error),
+                Ok(size) if size != entry.size => Some(FtpError::IntegrityError(format!(
+                    "{} holds {} bytes, {} were stored",
+                    remote, size, entry.size
+                ))),
+                Ok(_) => match self.remote_crc32(remote)? {
+                    Some(crc32) if crc32 != entry.crc32 => Some(FtpError::IntegrityError(format!(
+                        "{} has CRC-32 {:08x}, {:08x} was stored",
+                        remote, crc32, entry.crc32
+                    ))),
+                    _ => None,
+                },
+            };
+            if let Some(problem) = problem {
+                mismatches.push((entry.remote.clone(), problem));
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "server")]
+    use crate::testing::anonymous_client;
+    #[cfg(feature = "server")]
+    use crate::testing::serve;
+    #[cfg(feature = "server")]
+    use crate::testing::TempRoot;
+    #[cfg(feature = "server")]
+    use crate::RemotePath;
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn upload_manifests_are_verified() {
+        use super::Manifest;
+        use crate::{FtpError, MirrorOptions};
+
+        let files: &[(&str, &[u8])] = &[
+            ("local/docs/a.txt", b"hello"),
+            ("local/say \"hi\", b.txt", b"hi"),
+            ("remote/", b""),
+        ];
+        let base = TempRoot::new("manifest", files);
+        let (root, local) = (base.join("remote"), base.join("local"));
+        let mut client = anonymous_client(serve(&root));
+        let options = MirrorOptions::new().manifest(true);
+        let report = client.put_dir(&local, "/site", &options).unwrap();
+        let manifest = report.manifest.unwrap();
+        assert_eq!(manifest.entries.len(), 2);
+        assert_eq!(
+            manifest.entries[0].remote,
+            RemotePath::new("/site/docs/a.txt")
+        );
+        assert_eq!(manifest.entries[0].size, 5);
+        assert_eq!(manifest.entries[0].crc32, 0x3610_a686);
+        assert!(manifest
+            .to_json()
+            .contains(r#""remote":"/site/say \"hi\", b.txt""#));
+
+        let reloaded = Manifest::from_csv(&manifest.to_csv()).unwrap();
+        assert_eq!(reloaded.entries.len(), 2);
+        assert_eq!(reloaded.entries[1].remote, manifest.entries[1].remote);
+        assert_eq!(Manifest::from_json(&manifest.to_json()).unwrap(), reloaded);
+        #[cfg(feature = "serde")]
+        {
+            assert_eq!(
+                serde_json::to_string(&reloaded).unwrap(),
+                reloaded.to_json()
+            );
+            let parsed: Manifest = serde_json::from_str(&manifest.to_json()).unwrap();
+            assert_eq!(parsed, reloaded);
+        }
+        assert!(client.verify_manifest(&reloaded).unwrap().is_empty());
+        std::fs::write(root.join("site/docs/a.txt"), b"hello world").unwrap();
+        std::fs::remove_file(root.join("site/say \"hi\", b.txt")).unwrap();
+        let mut client = anonymous_client(serve(&root));
+        let mismatches = client.verify_manifest(&reloaded).unwrap();
+        assert_eq!(mismatches.len(), 2);
+        assert!(matches!(mismatches[0].1, FtpError::IntegrityError(_)));
+        assert!(matches!(mismatches[1].1, FtpError::FileError(_)));
+        assert!(Manifest::from_csv("remote,size\n").is_err());
+    }
+
+    #[test]
+    fn manifests_read_json() {
+        use super::Manifest;
+        use std::time::{Duration, SystemTime};
+
+        let manifest = Manifest::from_json(
+            r#" [ {"size": 5, "stored": "20240501120000", "crc32": "3610a686",
+                "remote": "/a \"\u00e9\ud83d\ude00\\\n.txt"} ] "#,
+        )
+        .unwrap();
+        let entry = &manifest.entries[0];
+        assert_eq!(entry.remote.as_str(), "/a \"é😀\\\n.txt");
+        assert_eq!((entry.size, entry.crc32), (5, 0x3610_a686));
+        assert_eq!(
+            entry.stored,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_564_800)
+        );
+        assert_eq!(Manifest::from_json(&manifest.to_json()).unwrap(), manifest);
+        assert!(Manifest::from_json("[]").unwrap().entries.is_empty());
+        for invalid in [
+            "",
+            "{}",
+            "[{}]",
+            "[1]",
+            r#"[{"remote":"/a","size":5,"crc32":"3610a686"}]"#,
+            r#"[{"remote":"/a","size":5,"crc32":3610,"stored":"20240501120000"}]"#,
+            r#"[{"remote":"/a","size":-5,"crc32":"3610a686","stored":"20240501120000"}]"#,
+            r#"[{"remote":"/a","size":5,"crc32":"3610a686","stored":"20240501120000","x":1}]"#,
+            r#"[{"remote":"/a","size":5,"crc32":"3610a686","stored":"20240501120000"},]"#,
+            r#"[{"remote":"/a","size":5,"crc32":"3610a686","stored":"20240501120000"}] x"#,
+            r#"[{"remote":"\ud83d","size":5,"crc32":"3610a686","stored":"20240501120000"}]"#,
+        ] {
+            assert!(Manifest::from_json(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Hooks reporting client activity to a monitoring system.
+
+use std::time::Duration;
+
+use crate::FtpError;
+
+/// Receiver of client measurements, e.g. an adapter updating Prometheus counters
+/// and histograms. Every method does nothing by default, so implementations only
+/// provide the measurements they export.
+///
+/// Install it with [`crate::FtpClientBuilder::metrics`]. Clients created from the
+/// same builder share it, so it must tolerate calls from several threads.
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::{FtpClientBuilder, FtpError, Metrics};
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+///
+/// #[derive(Default)]
+/// struct Counters {
+///     commands: AtomicU64,
+///     errors: AtomicU64,
+///     received: AtomicU64,
+/// }
+///
+/// impl Metrics for Counters {
+///     fn command(&self, _verb: &str) {
+///         self.commands.fetch_add(1, Ordering::Relaxed);
+///     }
+///     fn error(&self, _error: &FtpError) {
+///         self.errors.fetch_add(1, Ordering::Relaxed);
+///     }
+///     fn bytes_received(&self, bytes: u64) {
+///         self.received.fetch_add(bytes, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let counters = Arc::new(Counters::default());
+/// let mut client = FtpClientBuilder::new()
+///     .metrics(counters.clone())
+///     .connect("127.0.0.1:21")
+///     .unwrap();
+/// client.login("user", "password").unwrap();
+/// println!("{} commands", counters.commands.load(Ordering::Relaxed));
+/// ```
+pub trait Metrics: Send + Sync {
+    /// A command was sent, named by its verb, e.g. `RETR`.
+    fn command(&self, verb: &str) {
+        let _ = verb;
+    }
+
+    /// A command or data transfer failed, see [`FtpError::class`] for a label.
+    /// Commands the server refuses count as failures when the refusal becomes
+    /// an error, e.g. a 550 reply to `CWD`, but not when an operation takes
+    /// it as an answer, like a missing file for `delete_if_exists`.
+    /// A transfer failing on its command reports both failures.
+    fn error(&self, error: &FtpError) {
+        let _ = error;
+    }
+
+    /// Bytes downloaded by a completed transfer.
+    fn bytes_received(&self, bytes: u64) {
+        let _ = bytes;
+    }
+
+    /// Bytes uploaded by a completed transfer.
+    fn bytes_sent(&self, bytes: u64) {
+        let _ = bytes;
+    }
+
+    /// Time from sending a command to reading its first reply, named by the
+    /// command verb, e.g. `CWD`.
+    fn round_trip(&self, verb: &str, duration: Duration) {
+        let _ = (verb, duration);
+    }
+
+    /// Time taken by a completed transfer, from opening the data connection to
+    /// the final reply, named by the command verb, e.g. `STOR`.
+    fn transfer_duration(&self, verb: &str, duration: Duration) {
+        let _ = (verb, duration);
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::testing::start_server;
+    use crate::testing::TempRoot;
+    use crate::FtpClient;
+    use crate::FtpError;
+
+    #[test]
+    fn round_trips_are_tracked() {
+        use super::Metrics;
+        use crate::{AnonymousAccess, FtpServer};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        #[derive(Default)]
+        struct Verbs(Mutex<Vec<String>>);
+        impl Metrics for Verbs {
+            fn round_trip(&self, verb: &str, _duration: Duration) {
+                self.0.lock().unwrap().push(verb.to_string());
+            }
+        }
+        let verbs = Arc::new(Verbs::default());
+        let address = start_server(FtpServer::new(AnonymousAccess));
+        let mut client = FtpClient::builder()
+            .latency_window(2)
+            .metrics(verbs.clone())
+            .connect(address.to_string())
+            .unwrap();
+        client.login("anonymous", "").unwrap();
+        client.noop().unwrap();
+        let stats = client.latency().unwrap();
+        assert_eq!(stats.samples, 2);
+        assert!(stats.p50 <= stats.p95 && stats.p95 <= stats.max);
+        assert_eq!(*verbs.0.lock().unwrap(), ["USER", "PASS", "NOOP"]);
+    }
+
+    #[test]
+    fn refused_commands_are_counted() {
+        use super::Metrics;
+        use crate::{FtpServer, LocalStorage};
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Errors(Mutex<Vec<&'static str>>);
+        impl Metrics for Errors {
+            fn error(&self, error: &FtpError) {
+                self.0.lock().unwrap().push(error.class());
+            }
+        }
+        let root = TempRoot::new("metrics", &[("a.txt", b"a")]);
+        let errors = Arc::new(Errors::default());
+        let server =
+            FtpServer::new(crate::AnonymousAccess).storage(LocalStorage::new(root.to_path_buf()));
+        let mut client = FtpClient::builder()
+            .metrics(errors.clone())
+            .connect(start_server(server).to_string())
+            .unwrap();
+        client.login("anonymous", "").unwrap();
+        assert!(client.change_dir("/missing").is_err());
+        assert!(client.delete("/missing.txt").is_err());
+        assert!(client.size("/missing.txt").is_err());
+        assert!(client.site_help().is_err());
+        assert!(client.size("/a.txt").is_ok());
+        let classes = errors.0.lock().unwrap().clone();
+        assert_eq!(classes.len(), 4);
+        assert_eq!(classes[3], "unsupported");
+    }
+}
@@ -0,0 +1,59 @@
+//! User-installable layers wrapped around every control command.
+
+use crate::FtpClient;
+use crate::Response;
+use crate::Result;
+
+/// A layer around command execution. Each layer receives the command line and
+/// decides whether and how to pass it to the rest of the chain through `next`,
+/// which makes it possible to log, retry, measure or throttle commands.
+///
+/// Closures with the same signature implement this trait.
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::FtpClient;
+/// use std::time::Instant;
+///
+/// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+/// client.add_layer(|command: &str, next: &mut simpleftp::Next| {
+///     let start = Instant::now();
+///     let response = next.run(command);
+///     println!("{} took {:?}", command, start.elapsed());
+///     response
+/// });
+/// client.login("user", "password").unwrap();
+/// ```
+pub trait Middleware: Send {
+    /// Handle `command`, usually by calling `next.run` one or more times.
+    fn handle(&mut self, command: &str, next: &mut Next) -> Result<Response>;
+}
+
+impl<F> Middleware for F
+where
+    F: FnMut(&str, &mut Next) -> Result<Response> + Send,
+{
+    fn handle(&mut self, command: &str, next: &mut Next) -> Result<Response> {
+        self(command, next)
+    }
+}
+
+/// The remaining layers of the chain, ending with the server itself.
+pub struct Next<'a> {
+    layers: &'a mut [Box<dyn Middleware>],
+    client: &'a mut FtpClient,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(layers: &'a mut [Box<dyn Middleware>], client: &'a mut FtpClient) -> Self {
+        Self { layers, client }
+    }
+
+    /// Pass `command` to the next layer, or send it to the server when no layers are left.
+    pub fn run(&mut self, command: &str) -> Result<Response> {
+        match self.layers.split_first_mut() {
+            Some((layer, rest)) => layer.handle(command, &mut Next::new(rest, self.client)),
+            None => self.client.send_cmd(command),
+        }
+    }
+}
@@ -0,0 +1,1155 @@
+//! Recursive transfers of directory trees, optionally over several connections.
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::EntryKind;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::ListOptions;
+use crate::Manifest;
+use crate::ManifestEntry;
+use crate::RemotePath;
+use crate::Result;
+use crate::SymlinkPolicy;
+use crate::UploadOutcome;
+use crate::WalkOptions;
+
+type Connector = Arc<dyn Fn() -> Result<FtpClient> + Send + Sync>;
+type ProgressHandler = Arc<dyn Fn(&MirrorProgress) + Send + Sync>;
+type FileHook = Arc<dyn Fn(&MirrorFile) + Send + Sync>;
+type CompleteHook = Arc<dyn Fn(&MirrorFile, bool) + Send + Sync>;
+type ErrorHook = Arc<dyn Fn(&MirrorFile, &FtpError) + Send + Sync>;
+
+/// Settings of [`FtpClient::get_dir`] and [`FtpClient::put_dir`].
+#[derive(Clone, Default)]
+pub struct MirrorOptions {
+    parallelism: usize,
+    connect: Option<Connector>,
+    changed_only: bool,
+    symlinks: SymlinkPolicy,
+    progress: Option<ProgressHandler>,
+    on_file_start: Option<FileHook>,
+    on_file_complete: Option<CompleteHook>,
+    on_file_error: Option<ErrorHook>,
+    dry_run: bool,
+    file_retries: usize,
+    session_retries: usize,
+    manifest: bool,
+    collision: Collision,
+}
+
+/// What a download does with a file that already exists locally, see
+/// [`MirrorOptions::on_collision`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collision {
+    /// Replace the local file.
+    #[default]
+    Overwrite,
+    /// Keep the local file, counting the remote one as skipped.
+    Skip,
+    /// Take the local file for the start of the remote one and download the
+    /// rest, with [`FtpClient::resume_to_path`].
+    Resume,
+    /// Keep the local file and save the remote one next to it, as
+    /// `name (1).ext` or the first such name not taken.
+    RenameWithSuffix,
+}
+
+impl MirrorOptions {
+    /// Transfer every file, one at a time over the client's connection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open the connections of [`Self::parallel`] and [`Self::session_retries`]
+    /// with `connect`, which must return a logged in client.
+    pub fn connector<F>(mut self, connect: F) -> Self
+    where
+        F: Fn() -> Result<FtpClient> + Send + Sync + 'static,
+    {
+        self.connect = Some(Arc::new(connect));
+        self
+    }
+
+    /// Transfer up to `connections` files at once, each over its own connection
+    /// opened with the [`Self::connector`], without which files are transferred
+    /// one at a time. The client the operation is called on still lists and
+    /// creates directories.
+    pub fn parallel(mut self, connections: usize) -> Self {
+        self.parallelism = connections;
+        self
+    }
+
+    /// Only transfer files that changed, with [`FtpClient::get_if_newer`] and
+    /// [`FtpClient::put_if_changed`], which turns the transfer into a sync.
+    pub fn changed_only(mut self, changed_only: bool) -> Self {
+        self.changed_only = changed_only;
+        self
+    }
+
+    /// How to treat remote symbolic links when downloading. Unless skipped,
+    /// links to files are downloaded as regular files, links to directories
+    /// are mirrored as directories when followed, and broken links are left out.
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Try a file again, up to `retries` times on the same connection, when its
+    /// transfer fails while the session is fine, e.g. a refused `RETR` or a
+    /// stalled data connection.
+    pub fn file_retries(mut self, retries: usize) -> Self {
+        self.file_retries = retries;
+        self
+    }
+
+    /// When the connection drops or the server ends the session, open a new
+    /// one with the [`Self::connector`] and resume with the file that failed,
+    /// up to `retries` times per connection. Without parallel transfers, the
+    /// client the operation is called on is replaced. Without a connector, the
+    /// session is not retried.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, MirrorOptions};
+    /// let connect = || {
+    ///     let mut client = FtpClient::connect("127.0.0.1:21")?;
+    ///     client.login("user", "password")?;
+    ///     Ok(client)
+    /// };
+    /// let mut client = connect().unwrap();
+    /// let options = MirrorOptions::new()
+    ///     .connector(connect)
+    ///     .file_retries(2)
+    ///     .session_retries(3);
+    /// client.get_dir("/pub/photos", "photos", &options).unwrap();
+    /// ```
+    pub fn session_retries(mut self, retries: usize) -> Self {
+        self.session_retries = retries;
+        self
+    }
+
+    /// Only plan the transfer: list the directories to create and the files to
+    /// transfer in [`MirrorReport::planned`] without changing anything. The
+    /// remote tree is still listed and, with [`Self::changed_only`], files are
+    /// still compared.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Record each file [`FtpClient::put_dir`] uploads, with its size and
+    /// checksum, in [`MirrorReport::manifest`]. Files skipped as unchanged
+    /// aren't recorded. Uploads go through [`FtpClient::put_recorded`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, MirrorOptions};
+    /// # let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// let options = MirrorOptions::new().manifest(true);
+    /// let report = client.put_dir("site", "/www", &options).unwrap();
+    /// std::fs::write("upload.csv", report.manifest.unwrap().to_csv()).unwrap();
+    /// ```
+    pub fn manifest(mut self, enabled: bool) -> Self {
+        self.manifest = enabled;
+        self
+    }
+
+    /// How to download files that already exist locally, replacing them by
+    /// default. Each file found is listed in [`MirrorReport::collisions`].
+    /// Ignored with [`Self::changed_only`], which compares the files instead.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{Collision, FtpClient, MirrorOptions};
+    /// # let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// let options = MirrorOptions::new().on_collision(Collision::RenameWithSuffix);
+    /// let report = client.get_dir("/inbox", "inbox", &options).unwrap();
+    /// for collision in &report.collisions {
+    ///     println!("{} kept, new copy in {:?}", collision.local.display(), collision.saved_as);
+    /// }
+    /// ```
+    pub fn on_collision(mut self, collision: Collision) -> Self {
+        self.collision = collision;
+        self
+    }
+
+    /// Call `handler` once per file, in the order the files were planned even
+    /// when parallel transfers complete out of order.
+    pub fn progress<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&MirrorProgress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(handler));
+        self
+    }
+
+    /// Call `hook` before each file transfer, from the thread running it.
+    pub fn on_file_start<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&MirrorFile) + Send + Sync + 'static,
+    {
+        self.on_file_start = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` as soon as a file is done, from the thread that transferred
+    /// it, with whether it was transferred or skipped as unchanged. Unlike
+    /// [`Self::progress`], files are reported in the order they complete.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, MirrorOptions};
+    /// # let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// let options = MirrorOptions::new().on_file_complete(|file, transferred| {
+    ///     if transferred {
+    ///         println!("{} is ready", file.local.display());
+    ///     }
+    /// });
+    /// client.get_dir("/reports", "inbox", &options).unwrap();
+    /// ```
+    pub fn on_file_complete<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&MirrorFile, bool) + Send + Sync + 'static,
+    {
+        self.on_file_complete = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` when a file transfer fails for good, after its retries,
+    /// before the error ends the directory transfer.
+    pub fn on_file_error<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&MirrorFile, &FtpError) + Send + Sync + 'static,
+    {
+        self.on_file_error = Some(Arc::new(hook));
+        self
+    }
+}
+
+/// A file handled by a directory transfer.
+#[derive(Debug, Clone)]
+pub struct MirrorProgress {
+    pub remote: RemotePath,
+    pub local: PathBuf,
+    /// False when the file was skipped as unchanged.
+    pub transferred: bool,
+    /// Position of the file in the plan, starting at 1.
+    pub index: usize,
+    /// Number of files planned.
+    pub total: usize,
+}
+
+/// Outcome of a directory transfer. After a dry run, the counts are those the
+/// transfer would have.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MirrorReport {
+    pub transferred: usize,
+    pub skipped: usize,
+    /// The changes a dry run would make, in order. Empty otherwise.
+    pub planned: Vec<MirrorAction>,
+    /// The files uploaded, in plan order, with [`MirrorOptions::manifest`].
+    pub manifest: Option<Manifest>,
+    /// The downloaded files that already existed locally, in plan order, and
+    /// what [`MirrorOptions::on_collision`] did with them.
+    pub collisions: Vec<MirrorCollision>,
+}
+
+/// A download onto an existing local file, see [`MirrorOptions::on_collision`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorCollision {
+    pub remote: RemotePath,
+    /// The local file that existed.
+    pub local: PathBuf,
+    pub resolution: Collision,
+    /// Where the remote file was saved, `None` when it was skipped or the
+    /// local file was already complete.
+    pub saved_as: Option<PathBuf>,
+}
+
+/// The outcome of a file of a directory transfer.
+struct JobOutcome {
+    /// False when the file was skipped.
+    transferred: bool,
+    collision: Option<MirrorCollision>,
+    /// The upload, with [`MirrorOptions::manifest`].
+    recorded: Option<ManifestEntry>,
+}
+
+/// A change planned by a dry run, see [`MirrorOptions::dry_run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MirrorAction {
+    CreateLocalDir(PathBuf),
+    /// A remote directory to create unless it exists, which a dry run doesn't
+    /// check.
+    CreateRemoteDir(RemotePath),
+    Download {
+        remote: RemotePath,
+        local: PathBuf,
+    },
+    Upload {
+        local: PathBuf,
+        remote: RemotePath,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Download,
+    Upload,
+}
+
+/// A file of a directory transfer, as passed to the lifecycle hooks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorFile {
+    pub remote: RemotePath,
+    pub local: PathBuf,
+}
+
+impl FtpClient {
+    /// Download the tree below `remote` into the local directory `local`,
+    /// creating the directories as needed.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, MirrorOptions};
+    /// let connect = || {
+    ///     let mut client = FtpClient::connect("127.0.0.1:21")?;
+    ///     client.login("user", "password")?;
+    ///     Ok(client)
+    /// };
+    /// let mut client = connect().unwrap();
+    /// let options = MirrorOptions::new()
+    ///     .connector(connect)
+    ///     .parallel(4)
+    ///     .changed_only(true)
+    ///     .progress(|file| println!("{}/{} {}", file.index, file.total, file.remote));
+    /// let report = client.get_dir("/pub/photos", "photos", &options).unwrap();
+    /// println!("{} downloaded, {} unchanged", report.transferred, report.skipped);
+    /// ```
+    ///
+    /// # Errors
+    /// On the first failed transfer, once the transfers in progress are over.
+    pub fn get_dir(
+        &mut self,
+        remote: impl AsRef<str>,
+        local: impl AsRef<Path>,
+        options: &MirrorOptions,
+    ) -> Result<MirrorReport> {
+        let root = RemotePath::new(remote.as_ref());
+        let local = local.as_ref();
+        let mut planned = Vec::new();
+        let mut create_dir = |dir: &Path| {
+            if !options.dry_run {
+                return std::fs::create_dir_all(dir);
+            }
+            if !dir.is_dir() {
+                planned.push(MirrorAction::CreateLocalDir(dir.into()));
+            }
+            Ok(())
+        };
+        create_dir(local)?;
+        let walk = WalkOptions::new().symlinks(options.symlinks);
+        let mut jobs = Vec::new();
+        for found in self.walk(&root, &walk)? {
+            let relative = found.path.strip_prefix(&root).ok_or_else(|| {
+                FtpError::FileError(format!("{} is outside of {}", found.path, root))
+            })?;
+            let target = relative.to_local(local)?;
+            // links to directories are entered when followed and skipped otherwise
+            let linked_dir = found.link_kind == Some(EntryKind::Directory)
+                && options.symlinks == SymlinkPolicy::Follow;
+            if found.entry.is_dir() || linked_dir {
+                create_dir(&target)?;
+            } else if found.entry.is_file() || found.link_kind == Some(EntryKind::File) {
+                jobs.push(MirrorFile {
+                    remote: found.path,
+                    local: target,
+                });
+            }
+        }
+        if options.dry_run {
+            return self.plan_jobs(jobs, Direction::Download, options, planned);
+        }
+        self.run_jobs(jobs, Direction::Download, options)
+    }
+
+    /// Download the files of the remote directory `dir` whose name matches
+    /// `pattern` into the local directory `local`, where `*` stands for any
+    /// run of characters and `?` for any single one. Subdirectories aren't
+    /// entered. Takes the same options as [`FtpClient::get_dir`], e.g. to
+    /// keep existing local files with [`MirrorOptions::on_collision`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{Collision, FtpClient, MirrorOptions};
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let options = MirrorOptions::new().on_collision(Collision::Skip);
+    /// let report = client.get_matching("/logs", "*.log", "logs", &options).unwrap();
+    /// println!("{} new, {} already there", report.transferred, report.skipped);
+    /// ```
+    ///
+    /// # Errors
+    /// When `dir` can't be listed, or on the first failed transfer once the
+    /// transfers in progress are over.
+    pub fn get_matching(
+        &mut self,
+        dir: impl AsRef<str>,
+        pattern: &str,
+        local: impl AsRef<Path>,
+        options: &MirrorOptions,
+    ) -> Result<MirrorReport> {
+        let dir = RemotePath::new(dir.as_ref());
+        let local = local.as_ref();
+        let mut planned = Vec::new();
+        if !options.dry_run {
+            std::fs::create_dir_all(local)?;
+        } else if !local.is_dir() {
+            planned.push(MirrorAction::CreateLocalDir(local.into()));
+        }
+        let files = ListOptions::new().pattern(pattern).kind(EntryKind::File);
+        let mut jobs = Vec::new();
+        for entry in self.list_entries_with(&dir, &files)? {
+            jobs.push(MirrorFile {
+                remote: dir.join(entry.name()),
+                local: RemotePath::new(entry.name()).to_local(local)?,
+            });
+        }
+        if options.dry_run {
+            return self.plan_jobs(jobs, Direction::Download, options, planned);
+        }
+        self.run_jobs(jobs, Direction::Download, options)
+    }
+
+    /// Upload the local directory `local` into `remote`, creating the remote
+    /// directories as needed.
+    ///
+    /// # Errors
+    /// When the local tree can't be read, or on the first failed transfer once
+    /// the transfers in progress are over.
+    pub fn put_dir(
+        &mut self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<str>,
+        options: &MirrorOptions,
+    ) -> Result<MirrorReport> {
+        let root = RemotePath::new(remote.as_ref());
+        let local = local.as_ref();
+        let mut jobs = Vec::new();
+        let mut pending = vec![local.to_path_buf()];
+        let mut planned = Vec::new();
+        let mut create_dir = |client: &mut Self, dir: &RemotePath| {
+            if !options.dry_run {
+                return client.ensure_dir(dir);
+            }
+            planned.push(MirrorAction::CreateRemoteDir(dir.clone()));
+            Ok(())
+        };
+        create_dir(self, &root)?;
+        while let Some(dir) = pending.pop() {
+            let mut entries = std::fs::read_dir(&dir)?.collect::<std::io::Result<Vec<_>>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+            for entry in entries.into_iter().rev() {
+                let path = entry.path();
+                let relative = path.strip_prefix(local).unwrap_or(&path);
+                let target = root.join_local(relative)?;
+                if entry.file_type()?.is_dir() {
+                    create_dir(self, &target)?;
+                    pending.push(path);
+                } else {
+                    jobs.push(MirrorFile {
+                        remote: target,
+                        local: path,
+                    });
+                }
+            }
+        }
+        jobs.reverse();
+        if options.dry_run {
+            return self.plan_jobs(jobs, Direction::Upload, options, planned);
+        }
+        self.run_jobs(jobs, Direction::Upload, options)
+    }
+
+    /// Create a remote directory unless it exists.
+    fn ensure_dir(&mut self, dir: &RemotePath) -> Result<()> {
+        match self.makedir(dir) {
+            Err(FtpError::FileError(_)) => Ok(()),
+            other => other,
+        }
+    }
+
+    /// Report the transfers `run_jobs` would make, after the directories to
+    /// create in `planned`.
+    fn plan_jobs(
+        &mut self,
+        jobs: Vec<MirrorFile>,
+        direction: Direction,
+        options: &MirrorOptions,
+        planned: Vec<MirrorAction>,
+    ) -> Result<MirrorReport> {
+        let mut report = MirrorReport {
+            planned,
+            ..MirrorReport::default()
+        };
+        for job in jobs {
+            let unchanged = match direction {
+                Direction::Download if !options.changed_only => {
+                    options.collision == Collision::Skip && job.local.exists()
+                }
+                _ if !options.changed_only => false,
+                Direction::Download => {
+                    let remote_time = self.modified_time(&job.remote)?;
+                    self.is_current(job.remote.as_str(), remote_time, &job.local)?
+                }
+                Direction::Upload => self.is_unchanged(&job.local, job.remote.as_str())?,
+            };
+            if unchanged {
+                report.skipped += 1;
+                continue;
+            }
+            report.transferred += 1;
+            report.planned.push(match direction {
+                Direction::Download => MirrorAction::Download {
+                    remote: job.remote,
+                    local: job.local,
+                },
+                Direction::Upload => MirrorAction::Upload {
+                    local: job.local,
+                    remote: job.remote,
+                },
+            });
+        }
+        Ok(report)
+    }
+
+    fn run_jobs(
+        &mut self,
+        jobs: Vec<MirrorFile>,
+        direction: Direction,
+        options: &MirrorOptions,
+    ) -> Result<MirrorReport> {
+        let total = jobs.len();
+        let mut report = MirrorReport::default();
+        let recording = matches!(direction, Direction::Upload) && options.manifest;
+        if recording {
+            report.manifest = Some(Manifest::new());
+        }
+        let mut record = |index: usize, job: &MirrorFile, outcome: JobOutcome| -> Result<()> {
+            let transferred = outcome.transferred;
+            report.collisions.extend(outcome.collision);
+            if transferred {
+                report.transferred += 1;
+            } else {
+                report.skipped += 1;
+            }
+            if let (Some(manifest), Some(entry)) = (&mut report.manifest, outcome.recorded) {
+                manifest.entries.push(entry);
+            }
+            if let Some(progress) = &options.progress {
+                progress(&MirrorProgress {
+                    remote: job.remote.clone(),
+                    local: job.local.clone(),
+                    transferred,
+                    index: index + 1,
+                    total,
+                });
+            }
+            Ok(())
+        };
+
+        let connect = match &options.connect {
+            Some(connect) if options.parallelism > 1 && total > 1 => connect.clone(),
+            _ => {
+                let mut reconnects = 0;
+                for (index, job) in jobs.iter().enumerate() {
+                    let outcome = self.transfer_job(job, direction, options, &mut reconnects)?;
+                    record(index, job, outcome)?;
+                }
+                return Ok(report);
+            }
+        };
+
+        let queue = Mutex::new(jobs.into_iter().enumerate().collect::<VecDeque<_>>());
+        let stop = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel();
+        let workers = options.parallelism.min(total);
+        let mut failure = None;
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                let sender = sender.clone();
+                let (queue, stop, connect) = (&queue, &stop, &connect);
+                scope.spawn(move || {
+                    let mut client = match connect() {
+                        Ok(client) => client,
+                        Err(error) => {
+                            stop.store(true, Ordering::Relaxed);
+                            let _ = sender.send(Err(error));
+                            return;
+                        }
+                    };
+                    let mut reconnects = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        let Some((index, job)) = queue.lock().ok().and_then(|mut q| q.pop_front())
+                        else {
+                            break;
+                        };
+                        match client.transfer_job(&job, direction, options, &mut reconnects) {
+                            Ok(outcome) => {
+                                let _ = sender.send(Ok((index, job, outcome)));
+                            }
+                            Err(error) => {
+                                stop.store(true, Ordering::Relaxed);
+                                let _ = sender.send(Err(error));
+                            }
+                        }
+                    }
+                    let _ = client.logout();
+                });
+            }
+            drop(sender);
+
+            // completions arrive in any order, report them in plan order
+            let mut done = BTreeMap::new();
+            let mut next = 0;
+            for message in receiver {
+                match message {
+                    Ok((index, job, outcome)) => {
+                        done.insert(index, (job, outcome));
+                    }
+                    Err(error) => {
+                        failure.get_or_insert(error);
+                    }
+                }
+                while let Some((job, outcome)) = done.remove(&next) {
+                    if let Err(error) = record(next, &job, outcome) {
+                        failure.get_or_insert(error);
+                    }
+                    next += 1;
+                }
+            }
+        });
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(report),
+        }
+    }
+
+    /// Transfer one file between the lifecycle hooks, returning whether it was
+    /// transferred or skipped. `reconnects` counts the sessions opened again
+    /// on this connection.
+    fn transfer_job(
+        &mut self,
+        job: &MirrorFile,
+        direction: Direction,
+        options: &MirrorOptions,
+        reconnects: &mut usize,
+    ) -> Result<JobOutcome> {
+        if let Some(hook) = &options.on_file_start {
+            hook(job);
+        }
+        let mut failures = 0;
+        let result = loop {
+            let error = match self.transfer_file(job, direction, options) {
+                Err(error) => error,
+                result => break result,
+            };
+            // a garbled or cut reply leaves the control connection out of step
+            let session_lost = self.disconnected
+                || matches!(
+                    error,
+                    FtpError::ConnectionError(_)
+                        | FtpError::SessionExpired(_)
+                        | FtpError::ResponseError(_)
+                );
+            match (&options.connect, &error) {
+                (Some(connect), _) if session_lost && *reconnects < options.session_retries => {
+                    *reconnects += 1;
+                    match connect() {
+                        Ok(client) => *self = client,
+                        Err(error) => break Err(error),
+                    }
+                }
+                (_, error)
+                    if !session_lost
+                        && is_file_failure(error)
+                        && failures < options.file_retries =>
+                {
+                    failures += 1;
+                }
+                _ => break Err(error),
+            }
+        };
+        match (&result, &options.on_file_complete, &options.on_file_error) {
+            (Ok(outcome), Some(hook), _) => hook(job, outcome.transferred),
+            (Err(error), _, Some(hook)) => hook(job, error),
+            _ => {}
+        }
+        result
+    }
+
+    fn transfer_file(
+        &mut self,
+        job: &MirrorFile,
+        direction: Direction,
+        options: &MirrorOptions,
+    ) -> Result<JobOutcome> {
+        if let (Direction::Download, false) = (direction, options.changed_only) {
+            if job.local.exists() {
+                return self.download_onto(job, options.collision);
+            }
+        }
+        if let (Direction::Upload, true) = (direction, options.manifest) {
+            return self.upload_recorded(job, options.changed_only);
+        }
+        let transferred = match (direction, options.changed_only) {
+            (Direction::Download, false) => self.get_to_path(&job.remote, &job.local).map(|_| true),
+            (Direction::Download, true) => self.get_if_newer(&job.remote, &job.local),
+            (Direction::Upload, false) => self.put_from_path(&job.local, &job.remote).map(|_| true),
+            (Direction::Upload, true) => self
+                .put_if_changed(&job.local, &job.remote)
+                .map(|outcome| outcome == UploadOutcome::Uploaded),
+        }?;
+        Ok(JobOutcome {
+            transferred,
+            collision: None,
+            recorded: None,
+        })
+    }
+
+    /// Upload the file of `job` for a manifest, unless `changed_only` and the
+    /// remote copy is unchanged.
+    fn upload_recorded(&mut self, job: &MirrorFile, changed_only: bool) -> Result<JobOutcome> {
+        let recorded = if changed_only && self.is_unchanged(&job.local, job.remote.as_str())? {
+            None
+        } else {
+            Some(self.put_recorded(&job.local, &job.remote)?)
+        };
+        Ok(JobOutcome {
+            transferred: recorded.is_some(),
+            collision: None,
+            recorded,
+        })
+    }
+
+    /// Download the file of `job` onto the existing local one as `resolution`
+    /// says.
+    fn download_onto(&mut self, job: &MirrorFile, resolution: Collision) -> Result<JobOutcome> {
+        let saved_as = match resolution {
+            Collision::Overwrite => {
+                self.get_to_path(&job.remote, &job.local)?;
+                Some(job.local.clone())
+            }
+            Collision::Skip => None,
+            Collision::Resume => self
+                .resume_to_path(&job.remote, &job.local)?
+                .then(|| job.local.clone()),
+            Collision::RenameWithSuffix => {
+                let free = free_name(&job.local)?;
+                if let Err(error) = self.get_to_path(&job.remote, &free) {
+                    let _ = std::fs::remove_file(&free);
+                    return Err(error);
+                }
+                Some(free)
+            }
+        };
+        Ok(JobOutcome {
+            transferred: saved_as.is_some(),
+            collision: Some(MirrorCollision {
+                remote: job.remote.clone(),
+                local: job.local.clone(),
+                resolution,
+                saved_as,
+            }),
+            recorded: None,
+        })
+    }
+}
+
+/// Create the first of `name (1).ext`, `name (2).ext`... not taken next to
+/// `local`, empty, so no other worker or program takes the same name.
+fn free_name(local: &Path) -> Result<PathBuf> {
+    let stem = local.file_stem().unwrap_or_default();
+    let mut number = 1u64;
+    loop {
+        let mut name = stem.to_os_string();
+        name.push(format!(" ({})", number));
+        if let Some(extension) = local.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        let candidate = local.with_file_name(name);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(_) => return Ok(candidate),
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => number += 1,
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// Whether `error` failed one file transfer while leaving the session usable.
+/// Malformed and cut replies aren't, the control connection can't be trusted
+/// after them.
+fn is_file_failure(error: &FtpError) -> bool {
+    matches!(
+        error,
+        FtpError::FileError(_)
+            | FtpError::CommandError(_)
+            | FtpError::TransferStalled(_)
+            | FtpError::IntegrityError(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "server")]
+    use crate::testing::anonymous_client;
+    use crate::testing::scripted_server;
+    #[cfg(feature = "server")]
+    use crate::testing::serve;
+    #[cfg(feature = "server")]
+    use crate::testing::TempRoot;
+    use crate::FtpClient;
+    #[cfg(feature = "server")]
+    use crate::FtpError;
+    #[cfg(feature = "server")]
+    use crate::RemotePath;
+
+    #[test]
+    fn mirror_follows_links_by_target() {
+        use super::MirrorOptions;
+        use crate::SymlinkPolicy;
+        use std::io::Write;
+
+        let send = |data: &std::net::TcpListener, content: &str| {
+            data.accept()
+                .unwrap()
+                .0
+                .write_all(content.as_bytes())
+                .unwrap();
+            "150 Opening\r\n226 Done\r\n".to_string()
+        };
+        let address = scripted_server(move |command, data| match command {
+            "LIST /pub" => send(
+                data,
+                "lrwxrwxrwx 1 ftp ftp 4 Jan 10 2024 docs -> real\r\n\
+                 lrwxrwxrwx 1 ftp ftp 5 Jan 10 2024 latest.txt -> a.txt\r\n\
+                 lrwxrwxrwx 1 ftp ftp 7 Jan 10 2024 broken -> missing\r\n\
+                 -rw-r--r-- 1 ftp ftp 5 Jan 10 2024 a.txt\r\n",
+            ),
+            "LIST /pub/docs" => send(data, "-rw-r--r-- 1 ftp ftp 5 Jan 10 2024 b.txt\r\n"),
+            "RETR /pub/latest.txt" | "RETR /pub/a.txt" | "RETR /pub/docs/b.txt" => {
+                send(data, "hello")
+            }
+            "PWD" => "257 \"/\"\r\n".to_string(),
+            "CWD /pub/docs" | "CWD /" => "250 OK\r\n".to_string(),
+            "SIZE /pub/latest.txt" | "SIZE /pub/a.txt" | "SIZE /pub/docs/b.txt" => {
+                "213 5\r\n".to_string()
+            }
+            // the client connected for the listing already
+            command if command.starts_with("MLSD") => {
+                drop(data.accept().unwrap());
+                "500 Unknown command\r\n".to_string()
+            }
+            command if command.starts_with("CWD") || command.starts_with("SIZE") => {
+                "550 No such file or directory\r\n".to_string()
+            }
+            _ => "200 OK\r\n".to_string(),
+        });
+        let local = std::env::temp_dir().join(format!("simpleftp-links-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&local);
+        let mut client = FtpClient::connect(address).unwrap();
+
+        let options = MirrorOptions::new().symlinks(SymlinkPolicy::Report);
+        let report = client.get_dir("/pub", &local, &options).unwrap();
+        assert_eq!(report.transferred, 2);
+        assert_eq!(std::fs::read(local.join("latest.txt")).unwrap(), b"hello");
+        assert!(!local.join("docs").exists() && !local.join("broken").exists());
+
+        let options = MirrorOptions::new().symlinks(SymlinkPolicy::Follow);
+        let report = client.get_dir("/pub", &local, &options).unwrap();
+        assert_eq!(report.transferred, 3);
+        assert_eq!(std::fs::read(local.join("docs/b.txt")).unwrap(), b"hello");
+        std::fs::remove_dir_all(local).unwrap();
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn download_collisions_follow_the_policy() {
+        use super::{Collision, MirrorOptions};
+
+        let root = TempRoot::new(
+            "collide",
+            &[
+                ("remote/logs/a.log", b"0123456789"),
+                ("remote/logs/b.log", b"fresh"),
+                ("remote/logs/c.txt", b"other"),
+                ("local/", b""),
+            ],
+        );
+        let local = root.join("local");
+        let mut client = anonymous_client(serve(&root.join("remote")));
+
+        let reset = || {
+            std::fs::write(local.join("a.log"), b"01234").unwrap();
+            for stale in ["b.log", "a (1).log", "b (1).log"] {
+                let _ = std::fs::remove_file(local.join(stale));
+            }
+        };
+        let read = |name: &str| std::fs::read(local.join(name)).unwrap();
+
+        reset();
+        let options = MirrorOptions::new().on_collision(Collision::Skip);
+        let report = client
+            .get_matching("/logs", "*.log", &local, &options)
+            .unwrap();
+        assert_eq!((report.transferred, report.skipped), (1, 1));
+        assert_eq!(read("a.log"), b"01234");
+        assert_eq!(read("b.log"), b"fresh");
+        assert!(!local.join("c.txt").exists());
+        assert_eq!(report.collisions.len(), 1);
+        assert_eq!(report.collisions[0].local, local.join("a.log"));
+        assert_eq!(report.collisions[0].saved_as, None);
+
+        reset();
+        let options = MirrorOptions::new().on_collision(Collision::Resume);
+        let report = client
+            .get_matching("/logs", "a.log", &local, &options)
+            .unwrap();
+        assert_eq!(read("a.log"), b"0123456789");
+        assert_eq!(report.collisions[0].saved_as, Some(local.join("a.log")));
+        // already complete
+        let report = client
+            .get_matching("/logs", "a.log", &local, &options)
+            .unwrap();
+        assert_eq!((report.transferred, report.skipped), (0, 1));
+
+        reset();
+        std::fs::write(local.join("a (1).log"), b"taken").unwrap();
+        let options = MirrorOptions::new().on_collision(Collision::RenameWithSuffix);
+        let report = client.get_dir("/logs", &local, &options).unwrap();
+        assert_eq!(read("a.log"), b"01234");
+        assert_eq!(read("a (2).log"), b"0123456789");
+        assert_eq!(report.collisions[0].resolution, Collision::RenameWithSuffix);
+        assert_eq!(report.collisions[0].saved_as, Some(local.join("a (2).log")));
+
+        reset();
+        let report = client
+            .get_dir("/logs", &local, &MirrorOptions::new())
+            .unwrap();
+        assert_eq!(read("a.log"), b"0123456789");
+        assert_eq!(report.transferred, 3);
+        assert_eq!(report.collisions[0].resolution, Collision::Overwrite);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn mirror_dry_run_changes_nothing() {
+        use super::{MirrorAction, MirrorOptions};
+
+        let base = TempRoot::new(
+            "dry-run",
+            &[("local/docs/a.txt", b"hello"), ("remote/", b"")],
+        );
+        let (root, local) = (base.join("remote"), base.join("local"));
+        let mut client = anonymous_client(serve(&root));
+        let options = MirrorOptions::new().dry_run(true);
+        let report = client.put_dir(&local, "/site", &options).unwrap();
+        assert_eq!(report.transferred, 1);
+        assert_eq!(
+            report.planned,
+            [
+                MirrorAction::CreateRemoteDir(RemotePath::new("/site")),
+                MirrorAction::CreateRemoteDir(RemotePath::new("/site/docs")),
+                MirrorAction::Upload {
+                    local: local.join("docs/a.txt"),
+                    remote: RemotePath::new("/site/docs/a.txt"),
+                },
+            ]
+        );
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn mirror_calls_lifecycle_hooks() {
+        use super::MirrorOptions;
+        use std::sync::{Arc, Mutex};
+
+        let base = TempRoot::new("hooks", &[("remote/a.txt", b"a"), ("remote/b.txt", b"b")]);
+        let local = base.join("local");
+        let mut client = anonymous_client(serve(&base.join("remote")));
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let (started, completed) = (calls.clone(), calls.clone());
+        let options = MirrorOptions::new()
+            .on_file_start(move |file| {
+                started
+                    .lock()
+                    .unwrap()
+                    .push(format!("start {}", file.remote))
+            })
+            .on_file_complete(move |file, transferred| {
+                // the file is in place when the hook runs
+                assert!(file.local.exists() && transferred);
+                completed
+                    .lock()
+                    .unwrap()
+                    .push(format!("done {}", file.remote));
+            });
+        client.get_dir("/", &local, &options).unwrap();
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["start /a.txt", "done /a.txt", "start /b.txt", "done /b.txt"]
+        );
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn mirror_retries_files_and_sessions() {
+        use super::MirrorOptions;
+        use crate::{Next, Response};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let base = TempRoot::new("retries", &[("remote/a.txt", b"a"), ("remote/b.txt", b"b")]);
+        let local = base.join("local");
+        let address = serve(&base.join("remote"));
+        let mut client = anonymous_client(address);
+        // a.txt is refused once, then the connection drops during b.txt
+        let mut failures = vec!["RETR /b.txt", "RETR /a.txt"];
+        client.add_layer(
+            move |command: &str, next: &mut Next| match failures.last() {
+                Some(&"RETR /a.txt") if command == "RETR /a.txt" => {
+                    failures.pop();
+                    Ok(Response::new(450, " File busy\r\n"))
+                }
+                Some(&"RETR /b.txt") if command == "RETR /b.txt" => {
+                    failures.pop();
+                    Err(FtpError::ConnectionError("connection reset".into()))
+                }
+                _ => next.run(command),
+            },
+        );
+        let connections = Arc::new(AtomicUsize::new(0));
+        let opened = connections.clone();
+        let options = MirrorOptions::new()
+            .connector(move || {
+                opened.fetch_add(1, Ordering::Relaxed);
+                let mut client = FtpClient::connect(address)?;
+                client.login("anonymous", "")?;
+                Ok(client)
+            })
+            .file_retries(1)
+            .session_retries(1);
+        let report = client.get_dir("/", &local, &options).unwrap();
+        assert_eq!(report.transferred, 2);
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+        assert_eq!(std::fs::read(local.join("b.txt")).unwrap(), b"b");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn parallel_transfers_use_the_connector() {
+        use super::MirrorOptions;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let files = [
+            ("remote/a.txt", &b"a"[..]),
+            ("remote/b.txt", b"b"),
+            ("remote/c.txt", b"c"),
+        ];
+        let base = TempRoot::new("parallel", &files);
+        let address = serve(&base.join("remote"));
+        let mut client = anonymous_client(address);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let opened = connections.clone();
+        let connect = move || {
+            opened.fetch_add(1, Ordering::Relaxed);
+            let mut client = FtpClient::connect(address)?;
+            client.login("anonymous", "")?;
+            Ok(client)
+        };
+
+        // without a connector, files go one at a time over the client
+        let options = MirrorOptions::new().parallel(2);
+        let report = client.get_dir("/", base.join("serial"), &options).unwrap();
+        assert_eq!(report.transferred, 3);
+
+        // the connector serves both settings, whatever the order
+        let options = MirrorOptions::new()
+            .parallel(2)
+            .session_retries(1)
+            .connector(connect);
+        let report = client.get_dir("/", base.join("local"), &options).unwrap();
+        assert_eq!(report.transferred, 3);
+        assert_eq!(connections.load(Ordering::Relaxed), 2);
+        assert_eq!(std::fs::read(base.join("local/c.txt")).unwrap(), b"c");
+    }
+
+    #[test]
+    fn mirror_reconnects_when_the_server_breaks_off() {
+        use super::MirrorOptions;
+        use std::io::Write;
+
+        // the first server breaks off halfway through b.txt, by hanging up or
+        // with a garbled reply
+        let server = |failure: Option<&'static str>| {
+            scripted_server(move |command, data| match command {
+                "LIST /" | "RETR /a.txt" | "RETR /b.txt" => {
+                    let content = match command {
+                        "LIST /" => {
+                            "-rw-r--r-- 1 ftp ftp 2 Jan 10 2024 a.txt\r\n\
+                             -rw-r--r-- 1 ftp ftp 2 Jan 10 2024 b.txt\r\n"
+                        }
+                        "RETR /b.txt" if failure.is_some() => "x",
+                        _ => "xy",
+                    };
+                    let (mut socket, _) = data.accept().unwrap();
+                    socket.write_all(content.as_bytes()).unwrap();
+                    match command {
+                        "RETR /b.txt" if failure.is_some() => failure.unwrap().to_string(),
+                        _ => "150 Opening\r\n226 Done\r\n".to_string(),
+                    }
+                }
+                "SIZE /a.txt" | "SIZE /b.txt" => "213 2\r\n".to_string(),
+                command if command.starts_with("MLSD") => {
+                    drop(data.accept().unwrap());
+                    "500 Unknown command\r\n".to_string()
+                }
+                _ => "200 OK\r\n".to_string(),
+            })
+        };
+        for failure in ["", "150 Opening\r\nhello world\r\n"] {
+            let local =
+                std::env::temp_dir().join(format!("simpleftp-break-off-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&local);
+            let mut client = FtpClient::connect(server(Some(failure))).unwrap();
+            let second = server(None);
+            // the file retry would be spent on the broken connection
+            let options = MirrorOptions::new()
+                .connector(move || FtpClient::connect(second))
+                .file_retries(1)
+                .session_retries(1);
+            let report = client.get_dir("/", &local, &options).unwrap();
+            assert_eq!(report.transferred, 2);
+            assert_eq!(std::fs::read(local.join("b.txt")).unwrap(), b"xy");
+            std::fs::remove_dir_all(local).unwrap();
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Remote path manipulation.
+//!
+//! FTP paths always use `/` as separator whatever the local platform, so
+//! [`std::path::Path`] can't be used for them.
+
+use std::fmt;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::FtpError;
+use crate::Result;
+
+/// A path on the server.
+///
+/// Every method taking a remote path accepts a `RemotePath` as well as a plain
+/// string, since it implements `AsRef<str>`.
+///
+/// # Example
+/// ```
+/// use simpleftp::RemotePath;
+///
+/// let dir = RemotePath::new("/pub/releases/");
+/// let file = dir.join("../docs/./manual.pdf").normalize();
+/// assert_eq!(file.as_str(), "/pub/docs/manual.pdf");
+/// assert_eq!(file.file_name(), Some("manual.pdf"));
+/// assert_eq!(file.parent().unwrap().as_str(), "/pub/docs");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct RemotePath(String);
+
+impl RemotePath {
+    /// Wrap `path` as is. Use [`RemotePath::normalize`] to clean it up.
+    pub fn new(path: impl Into<String>) -> Self {
+        Self(path.into())
+    }
+
+    /// The root directory, `/`.
+    pub fn root() -> Self {
+        Self("/".into())
+    }
+
+    /// Parse the quoted path of a `257` reply (`PWD`, `MKD`), where quotes in the
+    /// path are doubled. Returns `None` when the reply carries no quoted path.
+    pub fn from_reply(message: &str) -> Option<Self> {
+        let start = message.find('"')? + 1;
+        let mut path = String::new();
+        let mut chars = message[start..].chars();
+        while let Some(c) = chars.next() {
+            if c == '"' {
+                if chars.as_str().starts_with('"') {
+                    chars.next();
+                } else {
+                    return Some(Self(path));
+                }
+            }
+            path.push(c);
+        }
+        None
+    }
+
+    /// The path as sent to the server.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the path starts at the root directory.
+    pub fn is_absolute(&self) -> bool {
+        self.0.starts_with('/')
+    }
+
+    /// Append `path` to this one. An absolute `path` replaces it.
+    pub fn join(&self, path: impl AsRef<str>) -> Self {
+        let path = path.as_ref();
+        if path.starts_with('/') || self.0.is_empty() {
+            Self(path.to_string())
+        } else if self.0.ends_with('/') {
+            Self(format!("{}{}", self.0, path))
+        } else {
+            Self(format!("{}/{}", self.0, path))
+        }
+    }
+
+    /// The last component, if it names a file or directory (not `..`).
+    pub fn file_name(&self) -> Option<&str> {
+        match self.components().last() {
+            Some("..") | None => None,
+            name => name,
+        }
+    }
+
+    /// The path without its last component, `None` for the root or a single
+    /// relative component.
+    pub fn parent(&self) -> Option<Self> {
+        let trimmed = self.0.trim_end_matches('/');
+        let index = trimmed.rfind('/')?;
+        let parent = trimmed[..index].trim_end_matches('/');
+        if parent.is_empty() {
+            Some(Self::root())
+        } else {
+            Some(Self(parent.to_string()))
+        }
+    }
+
+    /// Non-empty components other than `.`.
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .split('/')
+            .filter(|component| !component.is_empty() && *component != ".")
+    }
+
+    /// Collapse repeated separators, `.` and `..` components and drop any
+    /// trailing separator. `..` at the root stays at the root, leading `..` of a
+    /// relative path are kept.
+    pub fn normalize(&self) -> Self {
+        let mut components: Vec<&str> = Vec::new();
+        for component in self.components() {
+            match component {
+                ".." if components.last().is_some_and(|last| *last != "..") => {
+                    components.pop();
+                }
+                ".." if self.is_absolute() => {}
+                component => components.push(component),
+            }
+        }
+        let joined = components.join("/");
+        if self.is_absolute() {
+            Self(format!("/{}", joined))
+        } else if joined.is_empty() {
+            Self(".".into())
+        } else {
+            Self(joined)
+        }
+    }
+
+    /// Append a relative local path, converting platform separators to `/`.
+    ///
+    /// # Errors
+    /// When `local` is absolute, goes up with `..` or isn't valid UTF-8.
+    pub fn join_local(&self, local: impl AsRef<Path>) -> Result<Self> {
+        let local = local.as_ref();
+        let mut path = self.clone();
+        for component in local.components() {
+            match component {
+                Component::Normal(name) => {
+                    let name = name.to_str().ok_or_else(|| {
+                        FtpError::InvalidArgument(format!(
+                            "Local path is not valid UTF-8: {}",
+                            local.display()
+                        ))
+                    })?;
+                    path = path.join(name);
+                }
+                Component::CurDir => {}
+                _ => {
+                    return Err(FtpError::InvalidArgument(format!(
+                        "Local path must stay relative: {}",
+                        local.display()
+                    )))
+                }
+            }
+        }
+        Ok(path)
+    }
+
+    /// The part of this path below `base`, once both are normalized.
+    pub fn strip_prefix(&self, base: &RemotePath) -> Option<Self> {
+        let (path, base) = (self.normalize(), base.normalize());
+        if path.is_absolute() != base.is_absolute() {
+            return None;
+        }
+        let mut components = path.components();
+        for expected in base.components() {
+            if components.next() != Some(expected) {
+                return None;
+            }
+        }
+        let rest = components.collect::<Vec<_>>().join("/");
+        Some(if rest.is_empty() {
+            Self(".".into())
+        } else {
+            Self(rest)
+        })
+    }
+
+    /// Map this path, relative to some remote directory, below the local
+    /// directory `base`. Server supplied names can't escape `base`.
+    ///
+    /// # Errors
+    /// When a component is `..` or holds a local path separator.
+    pub fn to_local(&self, base: impl AsRef<Path>) -> Result<PathBuf> {
+        let mut local = base.as_ref().to_path_buf();
+        for component in self.components() {
+            let mut parts = Path::new(component).components();
+            match (parts.next(), parts.next()) {
+                (Some(Component::Normal(name)), None) => local.push(name),
+                _ => {
+                    return Err(FtpError::InvalidArgument(format!(
+                        "Remote name can't be stored locally: {}",
+                        component
+                    )))
+                }
+            }
+        }
+        Ok(local)
+    }
+
+    /// The path in double quotes with inner quotes doubled, as servers write
+    /// it in `257` replies.
+    pub fn quoted(&self) -> String {
+        format!("\"{}\"", self.0.replace('"', "\"\""))
+    }
+}
+
+impl AsRef<str> for RemotePath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RemotePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for RemotePath {
+    fn from(path: &str) -> Self {
+        Self(path.to_string())
+    }
+}
+
+impl From<String> for RemotePath {
+    fn from(path: String) -> Self {
+        Self(path)
+    }
+}
+
+impl From<RemotePath> for String {
+    fn from(path: RemotePath) -> Self {
+        path.0
+    }
+}
+
+/// The working directory of a session, followed through the commands sent so
+/// a new session can return to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) enum WorkingDir {
+    /// Where the login left the session.
+    #[default]
+    Login,
+    /// A path leading back to the directory, absolute unless the login
+    /// directory is relative.
+    Known(RemotePath),
+    /// Moved relatively from a place never asked with `PWD`.
+    Unknown,
+}
+
+impl WorkingDir {
+    /// Follow `command`, a raw command line the server answered with `response`.
+    pub(crate) fn observe(&mut self, command: &str, response: &crate::Response) {
+        let (verb, argument) = command.split_once(' ').unwrap_or((command, ""));
+        let verb = verb.to_ascii_uppercase();
+        if verb == "USER" || verb == "REIN" {
+            *self = WorkingDir::Login;
+            return;
+        }
+        if !(200..300).contains(&response.code) {
+            return;
+        }
+        let moved = match verb.as_str() {
+            "PWD" | "XPWD" => {
+                if let Some(dir) = RemotePath::from_reply(&response.message) {
+                    *self = WorkingDir::Known(dir);
+                }
+                return;
+            }
+            "CWD" | "XCWD" => argument,
+            "CDUP" | "XCUP" => "..",
+            _ => return,
+        };
+        *self = match self {
+            _ if moved.starts_with('/') => WorkingDir::Known(RemotePath::new(moved)),
+            WorkingDir::Known(dir) => WorkingDir::Known(dir.join(moved)),
+            _ => WorkingDir::Unknown,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RemotePath;
+
+    #[test]
+    fn remote_path_normalization() {
+        let normalize = |path: &str| RemotePath::new(path).normalize().to_string();
+        assert_eq!(normalize("/a//b/./c/"), "/a/b/c");
+        assert_eq!(normalize("/a/../../b"), "/b");
+        assert_eq!(normalize("a/../../b"), "../b");
+        assert_eq!(normalize("./"), ".");
+        assert_eq!(normalize("/"), "/");
+    }
+
+    #[test]
+    fn remote_path_parts() {
+        let path = RemotePath::new("/pub/file.txt");
+        assert_eq!(path.file_name(), Some("file.txt"));
+        assert_eq!(path.parent(), Some(RemotePath::new("/pub")));
+        assert_eq!(RemotePath::new("/pub").parent(), Some(RemotePath::root()));
+        assert_eq!(RemotePath::root().parent(), None);
+        assert_eq!(RemotePath::new("file.txt").parent(), None);
+        assert_eq!(RemotePath::new("/pub").join("/etc").as_str(), "/etc");
+        assert_eq!(RemotePath::new("/pub/").join("a").as_str(), "/pub/a");
+    }
+
+    #[test]
+    fn remote_path_quoting() {
+        let path = RemotePath::new("/odd \"name\"");
+        assert_eq!(path.quoted(), "\"/odd \"\"name\"\"\"");
+        let reply = format!(" {} is current directory.", path.quoted());
+        assert_eq!(RemotePath::from_reply(&reply), Some(path));
+        assert_eq!(RemotePath::from_reply(" no path"), None);
+    }
+
+    #[test]
+    fn remote_path_local_mapping() {
+        let base = RemotePath::new("/pub");
+        let local = std::path::Path::new("docs").join("manual.pdf");
+        let remote = base.join_local(&local).unwrap();
+        assert_eq!(remote.as_str(), "/pub/docs/manual.pdf");
+        let relative = remote.strip_prefix(&base).unwrap();
+        assert_eq!(relative.as_str(), "docs/manual.pdf");
+        assert_eq!(
+            relative.to_local("mirror").unwrap(),
+            std::path::Path::new("mirror").join(&local)
+        );
+        assert!(RemotePath::new("../etc/passwd").to_local("mirror").is_err());
+        assert!(base.join_local("../secret").is_err());
+        assert_eq!(remote.strip_prefix(&RemotePath::new("/other")), None);
+    }
+}
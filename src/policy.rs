@@ -0,0 +1,152 @@
+//! Reply codes taken as the success of each command.
+
+use crate::ALREADY_OPEN;
+use crate::AUTH_OK;
+use crate::BAD_PARAMETER_FOR_COMMAND;
+use crate::CLOSING_DATA_CONNECTION;
+use crate::COMMAND_NOT_IMPLEMENTED;
+use crate::COMMAND_OK;
+use crate::COMMAND_UNIMPLEMENTED;
+use crate::DATA_CONNECTION_OPEN;
+use crate::DIRECTORY;
+use crate::DIRECTORY_ALREADY_EXISTS;
+use crate::EXTENDED_PASSIVE_MODE;
+use crate::FILE;
+use crate::FILE_ACTION_OK;
+use crate::FILE_ACTION_PENDING;
+use crate::FILE_OK;
+use crate::HELP_MESSAGE;
+use crate::LOGGED_IN;
+use crate::NAME_SYSTEM;
+use crate::NEED_ACCOUNT;
+use crate::NEED_PASSWORD;
+use crate::PASSIVE_MODE;
+use crate::PATH_CREATED;
+use crate::SERVICE_CLOSING;
+use crate::SYSTEM;
+use crate::UNKNOWN_COMMAND;
+
+/// How strictly replies are checked against the codes the standards specify
+/// for each command.
+///
+/// # Example
+/// ```
+/// use simpleftp::ReplyPolicy;
+///
+/// assert!(ReplyPolicy::Strict.accepts("PWD", 257));
+/// assert!(!ReplyPolicy::Strict.accepts("CWD", 200));
+/// assert!(ReplyPolicy::Lenient.accepts("CWD", 200));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplyPolicy {
+    /// Only the codes of RFC 959 and the extensions defining the command.
+    Strict,
+    /// Also the codes quirky servers send instead, e.g. 200 for `CWD` or 250
+    /// for `MKD`.
+    #[default]
+    Lenient,
+}
+
+impl ReplyPolicy {
+    /// Whether `code` tells that `verb` succeeded, or for commands opening a
+    /// data transfer, that the transfer starts. Any 2xx code is taken for
+    /// commands this crate doesn't know.
+    pub fn accepts(&self, verb: &str, code: usize) -> bool {
+        let Some((strict, lenient)) = expected(&verb.to_ascii_uppercase()) else {
+            return (200..300).contains(&code);
+        };
+        strict.contains(&code) || *self == ReplyPolicy::Lenient && lenient.contains(&code)
+    }
+
+    /// Whether `code` tells that a data transfer completed, which RFC 959 does
+    /// with 226 or 250. Any positive completion reply is taken leniently.
+    pub(crate) fn completes_transfer(&self, code: usize) -> bool {
+        matches!(code, CLOSING_DATA_CONNECTION | FILE_ACTION_OK)
+            || *self == ReplyPolicy::Lenient && (200..300).contains(&code)
+    }
+}
+
+/// Whether `code` is a positive preliminary reply, after which RFC 959 has
+/// the server send another reply to the same command.
+pub(crate) fn is_preliminary(code: usize) -> bool {
+    (100..200).contains(&code)
+}
+
+/// Whether `code` tells that the server doesn't know or implement a command,
+/// or not with the parameter given, e.g. `MODE Z`.
+pub(crate) fn is_unimplemented(code: usize) -> bool {
+    matches!(
+        code,
+        UNKNOWN_COMMAND | COMMAND_UNIMPLEMENTED | BAD_PARAMETER_FOR_COMMAND
+    )
+}
+
+/// Success codes of `verb` in the standards, and those quirky servers send.
+fn expected(verb: &str) -> Option<(&'static [usize], &'static [usize])> {
+    let codes: (&[usize], &[usize]) = match verb {
+        "USER" => (&[LOGGED_IN, NEED_PASSWORD, NEED_ACCOUNT], &[]),
+        "PASS" => (&[LOGGED_IN, COMMAND_NOT_IMPLEMENTED, NEED_ACCOUNT], &[]),
+        "ACCT" => (&[LOGGED_IN, COMMAND_NOT_IMPLEMENTED], &[]),
+        "CWD" => (&[FILE_ACTION_OK], &[COMMAND_OK]),
+        "CDUP" => (&[COMMAND_OK, FILE_ACTION_OK], &[]),
+        "PWD" => (&[PATH_CREATED], &[COMMAND_OK, FILE_ACTION_OK]),
+        "MKD" => (&[PATH_CREATED], &[FILE_ACTION_OK, DIRECTORY_ALREADY_EXISTS]),
+        "RMD" | "DELE" | "RNTO" => (&[FILE_ACTION_OK], &[COMMAND_OK]),
+        "RNFR" | "REST" => (&[FILE_ACTION_PENDING], &[]),
+        "SMNT" => (&[COMMAND_NOT_IMPLEMENTED, FILE_ACTION_OK], &[COMMAND_OK]),
+        "RETR" | "STOR" | "STOU" | "APPE" => (&[ALREADY_OPEN, FILE_OK], &[]),
+        "LIST" | "NLST" | "MLSD" => (&[ALREADY_OPEN, FILE_OK], &[COMMAND_OK]),
+        "PASV" => (&[PASSIVE_MODE], &[ALREADY_OPEN]),
+        "EPSV" => (&[EXTENDED_PASSIVE_MODE], &[]),
+        "TYPE" | "NOOP" | "PORT" | "PBSZ" | "PROT" | "SITE" => (&[COMMAND_OK], &[]),
+        "ALLO" => (&[COMMAND_OK, COMMAND_NOT_IMPLEMENTED], &[]),
+        "ABOR" => (&[DATA_CONNECTION_OPEN, CLOSING_DATA_CONNECTION], &[]),
+        "STAT" => (&[SYSTEM, DIRECTORY, FILE], &[]),
+        "SYST" => (&[NAME_SYSTEM], &[SYSTEM]),
+        "HELP" => (&[SYSTEM, HELP_MESSAGE], &[FILE]),
+        "SIZE" | "MDTM" | "AVBL" => (&[FILE], &[]),
+        "XCRC" => (&[FILE_ACTION_OK], &[]),
+        "AUTH" => (&[AUTH_OK], &[]),
+        "QUIT" => (&[SERVICE_CLOSING], &[]),
+        _ => return None,
+    };
+    Some(codes)
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::testing::start_server;
+    use crate::FtpClient;
+    use crate::FtpError;
+
+    #[test]
+    fn reply_policies() {
+        use super::ReplyPolicy;
+        use crate::{AnonymousAccess, FtpServer, Next, Response};
+
+        let address = start_server(FtpServer::new(AnonymousAccess)).to_string();
+        for policy in [ReplyPolicy::Strict, ReplyPolicy::Lenient] {
+            let mut client = FtpClient::builder()
+                .reply_policy(policy)
+                .connect(&address)
+                .unwrap();
+            client.login("anonymous", "").unwrap();
+            // like servers answering CWD with 200 rather than 250
+            client.add_layer(|command: &str, next: &mut Next| {
+                if command.starts_with("CWD") {
+                    return Ok(Response::new(200, " Directory changed\r\n"));
+                }
+                next.run(command)
+            });
+            let changed = client.change_dir("/");
+            assert_eq!(changed.is_ok(), policy == ReplyPolicy::Lenient);
+            assert!(matches!(changed, Ok(()) | Err(FtpError::FileError(_))));
+            assert!(client.pwd().is_ok());
+        }
+        assert!(ReplyPolicy::Strict.accepts("mkd", 257));
+        assert!(!ReplyPolicy::Strict.accepts("MKD", 250));
+        assert!(ReplyPolicy::Lenient.accepts("MKD", 250));
+        assert!(!ReplyPolicy::Lenient.accepts("PWD", 550));
+        assert!(ReplyPolicy::Strict.accepts("XMD5", 251));
+    }
+}
@@ -0,0 +1,252 @@
+//! Scheduling of periodic polls of many remote paths over one connection.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Decides when each of a set of targets, e.g. watched directories, is polled
+/// next. Polls are spread over the interval with random jitter so targets
+/// added together don't hit the server in lockstep, no two polls start closer
+/// than the minimum spacing, and a target whose poll failed backs off
+/// exponentially up to a limit.
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::{FtpClient, PollScheduler};
+/// use std::time::Duration;
+///
+/// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+/// client.login("user", "password").unwrap();
+/// let mut scheduler = PollScheduler::new(Duration::from_secs(60))
+///     .jitter(0.2)
+///     .min_spacing(Duration::from_millis(500));
+/// for dir in ["/inbox/a", "/inbox/b", "/inbox/c"] {
+///     scheduler.add(dir);
+/// }
+/// while let Some(dir) = scheduler.wait() {
+///     match client.name_list(dir) {
+///         Ok(names) => {
+///             println!("{}: {} files", dir, names.len());
+///             scheduler.succeeded(&dir);
+///         }
+///         Err(_) => scheduler.failed(&dir),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PollScheduler<K> {
+    interval: Duration,
+    jitter: f64,
+    max_backoff: Duration,
+    spacing: Duration,
+    targets: Vec<Target<K>>,
+    last_poll: Option<Instant>,
+    state: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Target<K> {
+    key: K,
+    due: Instant,
+    failures: u32,
+}
+
+impl<K: Clone + PartialEq> PollScheduler<K> {
+    /// Poll each target every `interval`, with 10% jitter, no minimum spacing
+    /// and backoff up to 32 intervals.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            jitter: 0.1,
+            max_backoff: interval * 32,
+            spacing: Duration::ZERO,
+            targets: Vec::new(),
+            last_poll: None,
+            state: RandomState::new().hash_one(Instant::now()) | 1,
+        }
+    }
+
+    /// Vary each delay randomly by up to `fraction` of it either way, `0.0`
+    /// for exact intervals. Clamped to `0.0..=1.0`.
+    pub fn jitter(mut self, fraction: f64) -> Self {
+        self.jitter = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Longest delay before polling a target again after failures.
+    pub fn max_backoff(mut self, limit: Duration) -> Self {
+        self.max_backoff = limit;
+        self
+    }
+
+    /// Shortest time between the start of two polls, of any targets, to bound
+    /// the rate of commands sent to the server.
+    pub fn min_spacing(mut self, spacing: Duration) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Seed the jitter generator, to replay a schedule in tests.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.state = seed | 1;
+        self
+    }
+
+    /// Start polling `key`, first at a random point of the next interval.
+    pub fn add(&mut self, key: K) {
+        let offset = self.interval.mul_f64(self.random());
+        self.targets.push(Target {
+            key,
+            due: Instant::now() + offset,
+            failures: 0,
+        });
+    }
+
+    /// Stop polling `key`.
+    pub fn remove(&mut self, key: &K) {
+        self.targets.retain(|target| target.key != *key);
+    }
+
+    /// Number of targets polled.
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    /// Whether no targets are polled.
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// The target to poll next and when, `None` without targets. The target is
+    /// rescheduled one jittered interval, or its backoff, after that time.
+    pub fn next_poll(&mut self) -> Option<(K, Instant)> {
+        let index = (0..self.targets.len()).min_by_key(|&index| self.targets[index].due)?;
+        let mut at = self.targets[index].due;
+        if let Some(last) = self.last_poll {
+            at = at.max(last + self.spacing);
+        }
+        self.last_poll = Some(at);
+        let delay = self.delay(self.targets[index].failures);
+        let target = &mut self.targets[index];
+        target.due = at + delay;
+        Some((target.key.clone(), at))
+    }
+
+    /// Sleep until the next poll is due and return its target, `None` without
+    /// targets.
+    pub fn wait(&mut self) -> Option<K> {
+        let (key, at) = self.next_poll()?;
+        std::thread::sleep(at.saturating_duration_since(Instant::now()));
+        Some(key)
+    }
+
+    /// Record that polling `key` failed, doubling its delay up to the backoff
+    /// limit.
+    pub fn failed(&mut self, key: &K) {
+        let Some(index) = self.targets.iter().position(|target| target.key == *key) else {
+            return;
+        };
+        self.targets[index].failures = self.targets[index].failures.saturating_add(1);
+        let delay = self.delay(self.targets[index].failures);
+        self.targets[index].due = Instant::now() + delay;
+    }
+
+    /// Record that polling `key` worked, ending its backoff.
+    pub fn succeeded(&mut self, key: &K) {
+        let Some(index) = self.targets.iter().position(|target| target.key == *key) else {
+            return;
+        };
+        if self.targets[index].failures > 0 {
+            self.targets[index].failures = 0;
+            let delay = self.delay(0);
+            self.targets[index].due = Instant::now() + delay;
+        }
+    }
+
+    /// Jittered delay after `failures` failed polls in a row, never longer
+    /// than the backoff limit.
+    fn delay(&mut self, failures: u32) -> Duration {
+        let base = match failures {
+            0 => self.interval,
+            _ => self
+                .interval
+                .checked_mul(1 << failures.min(16))
+                .map_or(self.max_backoff, |delay| delay.min(self.max_backoff)),
+        };
+        let factor = 1.0 + self.jitter * (2.0 * self.random() - 1.0);
+        base.mul_f64(factor).min(self.max_backoff)
+    }
+
+    /// xorshift64*, returns a value in `0.0..1.0`.
+    fn random(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn poll_schedules() {
+        use super::PollScheduler;
+        use std::time::{Duration, Instant};
+
+        let start = Instant::now();
+        let mut scheduler = PollScheduler::new(Duration::from_secs(10))
+            .jitter(0.0)
+            .min_spacing(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(25))
+            .seed(7);
+        for dir in ["/a", "/b", "/c"] {
+            scheduler.add(dir);
+        }
+        let polls: Vec<_> = (0..6).map(|_| scheduler.next_poll().unwrap()).collect();
+        for pair in polls.windows(2) {
+            assert!(pair[1].1 >= pair[0].1 + Duration::from_secs(1));
+        }
+        // each target comes back once per interval
+        for (key, at) in &polls[..3] {
+            let again = polls[3..].iter().find(|(other, _)| other == key).unwrap();
+            assert!(again.1 >= *at + Duration::from_secs(10));
+        }
+        assert!(polls[0].1 < start + Duration::from_secs(10));
+
+        let mut failing = PollScheduler::new(Duration::from_secs(10))
+            .jitter(0.0)
+            .max_backoff(Duration::from_secs(25));
+        failing.add("/a");
+        for expected in [20, 25, 25] {
+            failing.failed(&"/a");
+            let (_, at) = failing.next_poll().unwrap();
+            let delay = at.duration_since(Instant::now()).as_secs_f64();
+            assert!(
+                (delay - expected as f64).abs() < 1.0,
+                "{} {}",
+                delay,
+                expected
+            );
+        }
+        failing.succeeded(&"/a");
+        let (_, first) = failing.next_poll().unwrap();
+        let (_, second) = failing.next_poll().unwrap();
+        assert_eq!(second - first, Duration::from_secs(10));
+        failing.remove(&"/a");
+        assert!(failing.next_poll().is_none());
+
+        // jitter doesn't stretch the delay past the limit
+        let mut jittered = PollScheduler::new(Duration::from_secs(10))
+            .jitter(1.0)
+            .max_backoff(Duration::from_secs(25))
+            .seed(3);
+        jittered.add("/a");
+        for _ in 0..20 {
+            jittered.failed(&"/a");
+            let (_, at) = jittered.next_poll().unwrap();
+            assert!(at <= Instant::now() + Duration::from_secs(25));
+        }
+    }
+}
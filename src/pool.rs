@@ -0,0 +1,119 @@
+//! Reusable buffers for the data transfer loops.
+
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// Default size of a transfer buffer.
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+/// Default number of idle buffers kept for reuse.
+pub const DEFAULT_BUFFER_COUNT: usize = 4;
+
+/// Idle buffers shared by the clients created from the same configuration.
+#[derive(Clone)]
+pub(crate) struct BufferPool {
+    size: usize,
+    count: usize,
+    idle: Arc<Mutex<Vec<Box<[u8]>>>>,
+}
+
+impl BufferPool {
+    /// Keep up to `count` idle buffers of `size` bytes.
+    pub(crate) fn new(count: usize, size: usize) -> Self {
+        Self {
+            size: size.max(1),
+            count,
+            idle: Arc::new(Mutex::new(Vec::with_capacity(count))),
+        }
+    }
+
+    /// Take an idle buffer or allocate one. It returns to the pool when dropped.
+    pub(crate) fn take(&self) -> PooledBuffer {
+        let buffer = self
+            .idle
+            .lock()
+            .ok()
+            .and_then(|mut idle| idle.pop())
+            .unwrap_or_else(|| vec![0; self.size].into_boxed_slice());
+        PooledBuffer {
+            buffer: Some(buffer),
+            pool: self.clone(),
+        }
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUFFER_COUNT, DEFAULT_BUFFER_SIZE)
+    }
+}
+
+pub(crate) struct PooledBuffer {
+    buffer: Option<Box<[u8]>>,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buffer.as_deref().unwrap_or_default()
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buffer.as_deref_mut().unwrap_or_default()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let Some(buffer) = self.buffer.take() else {
+            return;
+        };
+        if let Ok(mut idle) = self.pool.idle.lock() {
+            if idle.len() < self.pool.count {
+                idle.push(buffer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BufferPool;
+
+    #[test]
+    fn buffers_are_reused() {
+        let pool = BufferPool::new(2, 16);
+        let mut buffer = pool.take();
+        assert_eq!(buffer.len(), 16);
+        buffer[0] = 1;
+        let address = buffer.as_ptr();
+        drop(buffer);
+        // the same allocation comes back, as left
+        let buffer = pool.take();
+        assert_eq!(buffer.as_ptr(), address);
+        assert_eq!(buffer[0], 1);
+        drop(buffer);
+        // clones share the idle buffers
+        assert_eq!(pool.clone().take().as_ptr(), address);
+    }
+
+    #[test]
+    fn idle_buffers_are_bounded() {
+        let pool = BufferPool::new(2, 16);
+        let taken: Vec<_> = (0..5).map(|_| pool.take()).collect();
+        assert_eq!(pool.idle.lock().unwrap().len(), 0);
+        drop(taken);
+        assert_eq!(pool.idle.lock().unwrap().len(), 2);
+
+        let pool = BufferPool::new(0, 0);
+        let buffer = pool.take();
+        assert_eq!(buffer.len(), 1);
+        drop(buffer);
+        assert!(pool.idle.lock().unwrap().is_empty());
+    }
+}
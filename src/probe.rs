@@ -0,0 +1,190 @@
+//! Preflight checks describing what a server supports.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::status::reply_lines;
+use crate::Command;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::ListStyle;
+use crate::Result;
+
+/// How long the trial `PASV` connection may take, so an unreachable address
+/// doesn't hang the probe.
+const PASV_TRIAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What [`FtpClient::probe`] learned about a server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerReport {
+    /// Reply to `SYST`, `None` when refused.
+    pub system: Option<String>,
+    /// Listing layout told by `SYST`.
+    pub list_style: Option<ListStyle>,
+    /// Extensions listed by `FEAT`, e.g. `MLST type*;size*;modify*;` or
+    /// `UTF8`. Empty when the server doesn't support `FEAT`.
+    pub features: Vec<String>,
+    /// Reply to `HELP`, usually the commands the server knows.
+    pub help: Option<String>,
+    /// Whether `FEAT` offers `AUTH TLS` or `AUTH SSL`, so FTPS can be used.
+    pub tls: bool,
+    /// Host announced by a trial `PASV`, `None` when refused or unreadable.
+    pub pasv_host: Option<String>,
+    /// Whether a data connection to the address announced by `PASV` opened.
+    pub pasv_reachable: bool,
+    /// Host of the control connection, to compare with [`Self::pasv_host`].
+    pub peer_host: String,
+}
+
+impl ServerReport {
+    /// Whether `FEAT` lists `feature`, e.g. `MLST` or `REST STREAM`, ignoring
+    /// case and parameters.
+    pub fn supports(&self, feature: &str) -> bool {
+        lists_feature(&self.features, feature)
+    }
+
+    /// Whether `PASV` announces another host than the one connected to, and no
+    /// data connection to it opened: a server behind NAT announcing its
+    /// private address, which [`crate::Quirks::nat_safe_pasv`] works around.
+    pub fn pasv_misconfigured(&self) -> bool {
+        self.pasv_host
+            .as_ref()
+            .is_some_and(|host| *host != self.peer_host && !self.pasv_reachable)
+    }
+}
+
+impl FtpClient {
+    /// Ask `SYST`, `FEAT` and `HELP` and try a `PASV` data connection, to
+    /// describe a new server before writing code against it. Refused commands
+    /// leave their part of the report empty. The trial data connection is
+    /// closed without a transfer, and counts as unreachable when it takes
+    /// more than 5 seconds.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let report = client.probe().unwrap();
+    /// println!("{:?}, MLSD: {}", report.system, report.supports("MLST"));
+    /// if report.pasv_misconfigured() {
+    ///     println!("PASV announces {:?}, use nat_safe_pasv", report.pasv_host);
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// On connection failure.
+    pub fn probe(&mut self) -> Result<ServerReport> {
+        let mut report = ServerReport {
+            peer_host: self.peer.ip().to_string(),
+            ..ServerReport::default()
+        };
+        report.system = refused_as_none(self.system())?;
+        report.list_style = report.system.as_deref().and_then(ListStyle::from_system);
+        report.features = self.features()?;
+        // e.g. `AUTH TLS` or `AUTH SSL;TLS-C;TLS`
+        report.tls = report.features.iter().any(|feature| {
+            let feature = feature.to_ascii_uppercase();
+            feature.strip_prefix("AUTH ").is_some_and(|methods| {
+                methods
+                    .split(';')
+                    .any(|method| matches!(method.trim(), "TLS" | "SSL"))
+            })
+        });
+        report.help = refused_as_none(self.help(""))?;
+        let response = self.write_cmd(Command::Pasv)?;
+        if self.accepts(&Command::Pasv, &response) {
+            if let Ok((host, port)) = Self::extract_pasv_address(&response.message) {
+                let trial = Instant::now() + PASV_TRIAL_TIMEOUT;
+                let deadline = self.deadline.map_or(trial, |deadline| deadline.min(trial));
+                report.pasv_reachable = self.config.open(&host, port, Some(deadline)).is_ok();
+                report.pasv_host = Some(host);
+            }
+        }
+        Ok(report)
+    }
+
+    /// The extensions listed by `FEAT`, empty when the server refuses it.
+    pub(crate) fn features(&mut self) -> Result<Vec<String>> {
+        let command = Command::Raw("FEAT");
+        let response = self.write_cmd(command)?;
+        if !self.accepts(&command, &response) {
+            return Ok(Vec::new());
+        }
+        Ok(reply_lines(&response)
+            .iter()
+            .skip(1)
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+}
+
+/// Whether `features` lists `feature`, see [`ServerReport::supports`].
+pub(crate) fn lists_feature(features: &[String], feature: &str) -> bool {
+    let feature = feature.to_ascii_uppercase();
+    features.iter().any(|listed| {
+        let listed = listed.to_ascii_uppercase();
+        listed == feature
+            || listed
+                .strip_prefix(&feature)
+                .is_some_and(|rest| rest.starts_with(' '))
+    })
+}
+
+/// `None` for a command the server refused, errors when the connection failed.
+fn refused_as_none<T>(result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(
+            error @ (FtpError::ConnectionError(_)
+            | FtpError::ResponseError(_)
+            | FtpError::SessionExpired(_)),
+        ) => Err(error),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::testing::start_server;
+    use crate::FtpClient;
+
+    #[test]
+    fn servers_are_probed() {
+        use crate::{AnonymousAccess, FtpServer, ListStyle, Next, Response};
+
+        let mut client = FtpClient::connect(start_server(FtpServer::new(AnonymousAccess))).unwrap();
+        client.login("anonymous", "").unwrap();
+        let report = client.probe().unwrap();
+        assert_eq!(report.list_style, Some(ListStyle::Unix));
+        assert!(report.supports("mlsd"));
+        assert!(report.supports("EPSV"));
+        assert!(!report.supports("EPS"));
+        assert!(!report.tls);
+        assert_eq!(report.pasv_host.as_deref(), Some("127.0.0.1"));
+        assert!(report.pasv_reachable);
+        assert!(!report.pasv_misconfigured());
+        // the session is still usable
+        client.noop().unwrap();
+
+        // a server behind NAT, announcing an address nobody listens on
+        client.add_layer(|command: &str, next: &mut Next| match command {
+            "PASV" => Ok(Response::new(
+                227,
+                " Entering Passive Mode (127,0,0,2,0,1)\r\n",
+            )),
+            "FEAT" => Ok(Response::new(
+                211,
+                "-Features:\r\n AUTH SSL;TLS\r\n SIZE\r\n211 End\r\n",
+            )),
+            "HELP" => Ok(Response::new(502, " Not implemented\r\n")),
+            _ => next.run(command),
+        });
+        let report = client.probe().unwrap();
+        assert_eq!(report.features, ["AUTH SSL;TLS", "SIZE"]);
+        assert!(report.tls);
+        assert_eq!(report.help, None);
+        assert!(report.pasv_misconfigured());
+    }
+}
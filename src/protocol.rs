@@ -0,0 +1,351 @@
+//! Control connection commands and their wire form.
+
+use std::net::SocketAddrV4;
+
+use crate::FtpError;
+use crate::Result;
+use crate::Secret;
+
+/// Representation type selected with `TYPE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Ascii,
+    Image,
+}
+
+/// A command of the control connection.
+///
+/// Arguments are borrowed, an empty argument is left out of the command line,
+/// so `Stat("")` is sent as a bare `STAT`.
+///
+/// # Example
+/// ```
+/// use simpleftp::Command;
+///
+/// assert_eq!(Command::Retr("/pub/file.txt").encode().unwrap(), "RETR /pub/file.txt\r\n");
+/// assert_eq!(Command::List("").encode().unwrap(), "LIST\r\n");
+/// assert!(Command::Dele("a\r\nRMD /").encode().is_err());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub enum Command<'a> {
+    User(&'a str),
+    Pass(&'a Secret),
+    Acct(&'a Secret),
+    Auth(&'a str),
+    Adat(&'a str),
+    Pbsz(u32),
+    Prot(&'a str),
+    Cwd(&'a str),
+    Cdup,
+    Pwd,
+    Mkd(&'a str),
+    Rmd(&'a str),
+    Smnt(&'a str),
+    Quit,
+    Port(SocketAddrV4),
+    Pasv,
+    Type(DataType),
+    Retr(&'a str),
+    /// `REST` with the byte offset the next transfer starts at.
+    Rest(u64),
+    Stor(&'a str),
+    Stou(&'a str),
+    Appe(&'a str),
+    /// `ALLO` with the byte count and, for record-structured files, the
+    /// maximum record or page size.
+    Allo(u64, Option<u64>),
+    Rnfr(&'a str),
+    Rnto(&'a str),
+    Abor,
+    Dele(&'a str),
+    List(&'a str),
+    Nlst(&'a str),
+    Mlsd(&'a str),
+    Size(&'a str),
+    Mdtm(&'a str),
+    Avbl(&'a str),
+    Xcrc(&'a str),
+    Stat(&'a str),
+    Syst,
+    Help(&'a str),
+    Noop,
+    /// `SITE` with its parameters, e.g. `CHMOD 644 file`.
+    Site(&'a str),
+    /// Any other command line, sent as is.
+    Raw(&'a str),
+}
+
+impl Command<'_> {
+    /// The command name, empty for [`Command::Raw`].
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Command::User(_) => "USER",
+            Command::Pass(_) => "PASS",
+            Command::Acct(_) => "ACCT",
+            Command::Auth(_) => "AUTH",
+            Command::Adat(_) => "ADAT",
+            Command::Pbsz(_) => "PBSZ",
+            Command::Prot(_) => "PROT",
+            Command::Cwd(_) => "CWD",
+            Command::Cdup => "CDUP",
+            Command::Pwd => "PWD",
+            Command::Mkd(_) => "MKD",
+            Command::Rmd(_) => "RMD",
+            Command::Smnt(_) => "SMNT",
+            Command::Quit => "QUIT",
+            Command::Port(_) => "PORT",
+            Command::Pasv => "PASV",
+            Command::Type(_) => "TYPE",
+            Command::Retr(_) => "RETR",
+            Command::Rest(_) => "REST",
+            Command::Stor(_) => "STOR",
+            Command::Stou(_) => "STOU",
+            Command::Appe(_) => "APPE",
+            Command::Allo(..) => "ALLO",
+            Command::Rnfr(_) => "RNFR",
+            Command::Rnto(_) => "RNTO",
+            Command::Abor => "ABOR",
+            Command::Dele(_) => "DELE",
+            Command::List(_) => "LIST",
+            Command::Nlst(_) => "NLST",
+            Command::Mlsd(_) => "MLSD",
+            Command::Size(_) => "SIZE",
+            Command::Mdtm(_) => "MDTM",
+            Command::Avbl(_) => "AVBL",
+            Command::Xcrc(_) => "XCRC",
+            Command::Stat(_) => "STAT",
+            Command::Syst => "SYST",
+            Command::Help(_) => "HELP",
+            Command::Noop => "NOOP",
+            Command::Site(_) => "SITE",
+            Command::Raw(_) => "",
+        }
+    }
+
+    /// The command name, or the first word of a [`Command::Raw`] line.
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            Command::Raw(line) => line.split(' ').next().unwrap_or_default(),
+            _ => self.verb(),
+        }
+    }
+
+    /// Whether the argument must be kept out of logs and layers.
+    pub fn is_secret(&self) -> bool {
+        matches!(self, Command::Pass(_) | Command::Acct(_))
+    }
+
+    /// Whether sending the command again changes nothing on the server, so it
+    /// may be retried on a new session.
+    pub(crate) fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            Command::Cwd(_)
+                | Command::Pwd
+                | Command::Type(_)
+                | Command::Pasv
+                | Command::Size(_)
+                | Command::Mdtm(_)
+                | Command::Avbl(_)
+                | Command::Stat(_)
+                | Command::Syst
+                | Command::Help(_)
+                | Command::Noop
+        )
+    }
+
+    /// The remote path the command acts on, if any.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Command::Cwd(path)
+            | Command::Mkd(path)
+            | Command::Rmd(path)
+            | Command::Smnt(path)
+            | Command::Retr(path)
+            | Command::Stor(path)
+            | Command::Stou(path)
+            | Command::Appe(path)
+            | Command::Rnfr(path)
+            | Command::Rnto(path)
+            | Command::Dele(path)
+            | Command::List(path)
+            | Command::Nlst(path)
+            | Command::Mlsd(path)
+            | Command::Size(path)
+            | Command::Mdtm(path)
+            | Command::Avbl(path)
+            | Command::Xcrc(path)
+            | Command::Stat(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The command line without its terminator, secrets included.
+    fn line(&self) -> String {
+        let argument = match self {
+            Command::Pass(secret) | Command::Acct(secret) => secret.expose().to_string(),
+            Command::Pbsz(size) => size.to_string(),
+            Command::Rest(offset) => offset.to_string(),
+            Command::Allo(size, None) => size.to_string(),
+            Command::Allo(size, Some(record_size)) => format!("{} R {}", size, record_size),
+            Command::Type(DataType::Ascii) => "A".to_string(),
+            Command::Type(DataType::Image) => "I".to_string(),
+            Command::Port(address) => {
+                let [a, b, c, d] = address.ip().octets();
+                let port = address.port();
+                format!("{},{},{},{},{},{}", a, b, c, d, port >> 8, port & 0xff)
+            }
+            Command::Raw(line) => return line.to_string(),
+            Command::User(argument)
+            | Command::Auth(argument)
+            | Command::Adat(argument)
+            | Command::Prot(argument)
+            | Command::Cwd(argument)
+            | Command::Mkd(argument)
+            | Command::Rmd(argument)
+            | Command::Smnt(argument)
+            | Command::Retr(argument)
+            | Command::Stor(argument)
+            | Command::Stou(argument)
+            | Command::Appe(argument)
+            | Command::Rnfr(argument)
+            | Command::Rnto(argument)
+            | Command::Dele(argument)
+            | Command::List(argument)
+            | Command::Nlst(argument)
+            | Command::Mlsd(argument)
+            | Command::Size(argument)
+            | Command::Mdtm(argument)
+            | Command::Avbl(argument)
+            | Command::Xcrc(argument)
+            | Command::Stat(argument)
+            | Command::Help(argument)
+            | Command::Site(argument) => argument.to_string(),
+            Command::Cdup
+            | Command::Pwd
+            | Command::Quit
+            | Command::Pasv
+            | Command::Abor
+            | Command::Syst
+            | Command::Noop => String::new(),
+        };
+        if argument.is_empty() {
+            self.verb().to_string()
+        } else {
+            format!("{} {}", self.verb(), argument)
+        }
+    }
+
+    /// The command line as recorded in transcripts, history and events, with
+    /// secrets masked.
+    pub fn logged(&self) -> String {
+        if self.is_secret() {
+            format!("{} ****", self.verb())
+        } else {
+            self.line()
+        }
+    }
+
+    /// The command line as written to the server, terminated by CRLF. The line
+    /// of a secret command holds the secret in a plain string.
+    ///
+    /// # Errors
+    /// When an argument holds a line break or a NUL byte.
+    pub fn encode(&self) -> Result<String> {
+        let line = format!("{}\r\n", self.line());
+        check_line(&line, &self.logged())?;
+        Ok(line)
+    }
+
+    /// Like [`Command::encode`], building the line of a secret command in a
+    /// [`Secret`] wiped after use.
+    pub(crate) fn encode_secret(&self) -> Result<Secret> {
+        let (Command::Pass(secret) | Command::Acct(secret)) = self else {
+            return self.encode().map(Secret::new);
+        };
+        let verb = self.verb();
+        let mut line = Secret::with_capacity(verb.len() + secret.expose().len() + 3);
+        line.push_str(verb);
+        line.push_str(" ");
+        line.push_str(secret.expose());
+        line.push_str("\r\n");
+        check_line(line.expose(), &self.logged())?;
+        Ok(line)
+    }
+}
+
+/// Refuse command lines carrying line breaks or NUL bytes before their terminator,
+/// which would let a hostile file name smuggle extra commands to the server.
+/// Telnet IAC (0xFF) can't occur in a UTF-8 string and needs no escaping.
+pub(crate) fn check_line(line: &str, logged: &str) -> Result<()> {
+    let command = line.strip_suffix("\r\n").unwrap_or(line);
+    if command.contains(['\r', '\n', '\0']) {
+        return Err(FtpError::InvalidArgument(format!(
+            "control characters in command: {}",
+            logged.escape_debug()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+    use super::DataType;
+    use crate::FtpError;
+    use crate::Secret;
+
+    #[test]
+    fn injected_commands_are_refused() {
+        assert!(Command::Retr("a.txt").encode().is_ok());
+        for name in [
+            "a.txt\r\nDELE b.txt",
+            "a.txt\nDELE b.txt",
+            "a.txt\rx",
+            "a\0.txt",
+        ] {
+            assert!(matches!(
+                Command::Retr(name).encode(),
+                Err(FtpError::InvalidArgument(_))
+            ));
+        }
+        let secret = Secret::from("pass\r\nDELE b.txt");
+        assert!(Command::Pass(&secret).encode_secret().is_err());
+    }
+
+    #[test]
+    fn commands_are_encoded() {
+        let secret = Secret::from("hunter2");
+        assert_eq!(
+            Command::User("anonymous").encode().unwrap(),
+            "USER anonymous\r\n"
+        );
+        assert_eq!(
+            Command::Pass(&secret).encode_secret().unwrap().expose(),
+            "PASS hunter2\r\n"
+        );
+        assert_eq!(Command::Pass(&secret).logged(), "PASS ****");
+        assert_eq!(Command::Pwd.encode().unwrap(), "PWD\r\n");
+        assert_eq!(Command::Stat("").encode().unwrap(), "STAT\r\n");
+        assert_eq!(
+            Command::Type(DataType::Image).encode().unwrap(),
+            "TYPE I\r\n"
+        );
+        assert_eq!(Command::Allo(4096, None).encode().unwrap(), "ALLO 4096\r\n");
+        assert_eq!(
+            Command::Allo(4096, Some(512)).encode().unwrap(),
+            "ALLO 4096 R 512\r\n"
+        );
+        assert_eq!(
+            Command::Port("192.168.1.2:1025".parse().unwrap())
+                .encode()
+                .unwrap(),
+            "PORT 192,168,1,2,4,1\r\n"
+        );
+        assert_eq!(
+            Command::Site("CHMOD 644 a").encode().unwrap(),
+            "SITE CHMOD 644 a\r\n"
+        );
+        assert_eq!(Command::Raw("FEAT").encode().unwrap(), "FEAT\r\n");
+    }
+}
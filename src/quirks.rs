@@ -0,0 +1,231 @@
+//! Workarounds for misbehaving servers, picked by host name or `SYST` reply.
+
+use crate::builder::Config;
+use crate::encoding::Codec;
+use crate::Encoding;
+use crate::EncodingMode;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::ListStyle;
+use crate::Result;
+
+/// Workarounds for one server, see [`QuirksRegistry`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    nat_safe_pasv: bool,
+    disable_mlsd: bool,
+    list_via_stat: bool,
+    encoding: Option<(Encoding, EncodingMode)>,
+}
+
+impl Quirks {
+    /// No workarounds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open data connections to the address of the control connection,
+    /// ignoring the one in PASV replies, for servers behind NAT announcing
+    /// their private address.
+    pub fn nat_safe_pasv(mut self, enabled: bool) -> Self {
+        self.nat_safe_pasv = enabled;
+        self
+    }
+
+    /// Don't try MLSD when walking directories, for servers sending broken
+    /// MLSD listings rather than refusing the command.
+    pub fn disable_mlsd(mut self, disabled: bool) -> Self {
+        self.disable_mlsd = disabled;
+        self
+    }
+
+    /// List directories with `STAT <dir>` in
+    /// [`FtpClient::list_entries`](crate::FtpClient::list_entries), for
+    /// servers whose data connections are unusable for listings.
+    pub fn list_via_stat(mut self, enabled: bool) -> Self {
+        self.list_via_stat = enabled;
+        self
+    }
+
+    /// Exchange names in `encoding`, as
+    /// [`FtpClientBuilder::encoding`](crate::FtpClientBuilder::encoding) does.
+    pub fn encoding(mut self, encoding: Encoding, mode: EncodingMode) -> Self {
+        self.encoding = Some((encoding, mode));
+        self
+    }
+
+    /// Turn on the workarounds in `config`, leaving the others as they are.
+    pub(crate) fn apply(&self, config: &mut Config) {
+        config.nat_safe_pasv |= self.nat_safe_pasv;
+        config.disable_mlsd |= self.disable_mlsd;
+        config.list_via_stat |= self.list_via_stat;
+        if let Some((encoding, mode)) = self.encoding {
+            config.codec = Codec { encoding, mode };
+        }
+    }
+}
+
+/// Workarounds keyed by host name or by `SYST` reply, applied by
+/// [`FtpClientBuilder::quirks`](crate::FtpClientBuilder::quirks) when
+/// connecting. Entries added later take precedence over earlier ones.
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::{Encoding, EncodingMode, FtpClient, Quirks, QuirksRegistry};
+///
+/// let registry = QuirksRegistry::new()
+///     .host("*.legacy.example.com", Quirks::new().nat_safe_pasv(true))
+///     .system("Windows_NT", Quirks::new().encoding(Encoding::Latin1, EncodingMode::Lossy));
+/// let mut client = FtpClient::builder()
+///     .quirks(registry)
+///     .connect("ftp.legacy.example.com:21")
+///     .unwrap();
+/// client.login("user", "password").unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct QuirksRegistry {
+    hosts: Vec<(String, Quirks)>,
+    systems: Vec<(String, Quirks)>,
+}
+
+impl QuirksRegistry {
+    /// A registry without entries.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply `quirks` to the host `pattern`: a name matched ignoring case, or
+    /// `*.example.com` for any subdomain of `example.com`.
+    pub fn host(mut self, pattern: impl Into<String>, quirks: Quirks) -> Self {
+        self.hosts
+            .push((pattern.into().to_ascii_lowercase(), quirks));
+        self
+    }
+
+    /// Apply `quirks` to servers whose `SYST` reply starts with `prefix`,
+    /// ignoring case. The client then asks `SYST` right after connecting.
+    pub fn system(mut self, prefix: impl Into<String>, quirks: Quirks) -> Self {
+        self.systems
+            .push((prefix.into().to_ascii_lowercase(), quirks));
+        self
+    }
+
+    /// Workarounds for `host`, if any.
+    pub fn for_host(&self, host: &str) -> Option<&Quirks> {
+        let host = host.to_ascii_lowercase();
+        self.hosts
+            .iter()
+            .rev()
+            .find(|(pattern, _)| match pattern.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|subdomain| subdomain.ends_with('.')),
+                None => *pattern == host,
+            })
+            .map(|(_, quirks)| quirks)
+    }
+
+    /// Workarounds for a server replying `system` to `SYST`, if any.
+    pub fn for_system(&self, system: &str) -> Option<&Quirks> {
+        let system = system.trim_start().to_ascii_lowercase();
+        self.systems
+            .iter()
+            .rev()
+            .find(|(prefix, _)| system.starts_with(prefix.as_str()))
+            .map(|(_, quirks)| quirks)
+    }
+
+    /// Whether any entry depends on the `SYST` reply.
+    pub(crate) fn has_systems(&self) -> bool {
+        !self.systems.is_empty()
+    }
+}
+
+impl FtpClient {
+    /// Ask `SYST` and turn on the workarounds `registry` has for the reply.
+    /// Servers refusing `SYST` before login are asked again once logged in.
+    ///
+    /// # Errors
+    /// On connection failure.
+    pub(crate) fn apply_system_quirks(&mut self, registry: QuirksRegistry) -> Result<()> {
+        let system = match self.system() {
+            Ok(system) => system,
+            Err(FtpError::CommandError(_) | FtpError::Unsupported(_)) => {
+                self.pending_quirks = Some(registry);
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        };
+        // saves asking again for the listing style
+        self.list_style = Some(ListStyle::from_system(&system));
+        if let Some(quirks) = registry.for_system(&system) {
+            quirks.apply(&mut self.config);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "server")]
+    use crate::testing::serve;
+    #[cfg(feature = "server")]
+    use crate::testing::TempRoot;
+    #[cfg(feature = "server")]
+    use crate::FtpClient;
+
+    #[test]
+    fn quirks_registry_lookup() {
+        use super::{Quirks, QuirksRegistry};
+
+        let nat = Quirks::new().nat_safe_pasv(true);
+        let stat = Quirks::new().list_via_stat(true);
+        let registry = QuirksRegistry::new()
+            .host("*.example.com", nat)
+            .host("ftp.Example.com", stat)
+            .system("windows_nt", Quirks::new().disable_mlsd(true));
+        assert_eq!(registry.for_host("FTP.example.com"), Some(&stat));
+        assert_eq!(registry.for_host("a.b.example.com"), Some(&nat));
+        assert_eq!(registry.for_host("example.com"), None);
+        assert_eq!(registry.for_host("badexample.com"), None);
+        assert!(registry.for_system(" Windows_NT version 10").is_some());
+        assert!(registry.for_system("UNIX Type: L8").is_none());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn quirks_apply_when_connecting() {
+        use super::{Quirks, QuirksRegistry};
+        use crate::{Next, Response, WalkOptions};
+        use std::sync::{Arc, Mutex};
+
+        let root = TempRoot::new("quirks", &[("docs/a.txt", b"quirky")]);
+        let registry = QuirksRegistry::new()
+            .host("127.0.0.1", Quirks::new().nat_safe_pasv(true))
+            .system("unix", Quirks::new().disable_mlsd(true));
+        let mut client = FtpClient::builder()
+            .quirks(registry)
+            .connect(serve(&root).to_string())
+            .unwrap();
+        client.login("anonymous", "").unwrap();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&sent);
+        client.add_layer(move |command: &str, next: &mut Next| {
+            recorded.lock().unwrap().push(command.to_string());
+            let response = next.run(command)?;
+            // a server behind NAT announcing an unroutable address
+            let message = response.message().replace("127,0,0,1", "192,0,2,1");
+            Ok(Response::new(response.code(), message))
+        });
+        let mut received = Vec::new();
+        client.get("/docs/a.txt", &mut received).unwrap();
+        assert_eq!(received, b"quirky");
+        let found = client.walk("/docs", &WalkOptions::new()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(!sent
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|command| command.starts_with("MLSD")));
+    }
+}
@@ -0,0 +1,291 @@
+//! Parsing of control connection replies, independent of any socket.
+//!
+//! A reply is a line starting with a three digit code from 100 to 599. A hyphen after the code
+//! opens a multi-line reply, closed by a line starting with the same code followed
+//! by a space. Lines in between may hold anything, including other codes.
+
+use crate::FtpError;
+use crate::Response;
+use crate::Result;
+
+/// Assembles a reply from the lines read off the control connection.
+///
+/// # Example
+/// ```
+/// use simpleftp::ReplyParser;
+///
+/// let mut parser = ReplyParser::new();
+/// assert!(!parser.push_line("211-Features:\r\n").unwrap());
+/// assert!(!parser.push_line(" MDTM\r\n").unwrap());
+/// assert!(parser.push_line("211 End\r\n").unwrap());
+/// let reply = parser.finish().unwrap();
+/// assert_eq!(reply.code(), 211);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ReplyParser {
+    text: String,
+    code: Option<[u8; 3]>,
+    complete: bool,
+}
+
+impl ReplyParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next line, with its line terminator. Returns whether the reply
+    /// is complete.
+    ///
+    /// # Errors
+    /// When the first line doesn't start with a reply code, or a line is added
+    /// to a complete reply.
+    pub fn push_line(&mut self, line: &str) -> Result<bool> {
+        if self.complete {
+            return Err(FtpError::ResponseError(format!(
+                "Line after the end of the reply: {}",
+                line.escape_debug()
+            )));
+        }
+        let bytes = line.as_bytes();
+        match self.code {
+            None => {
+                let code = reply_code(bytes).ok_or_else(|| {
+                    FtpError::ResponseError(format!(
+                        "Invalid response code form server: {}",
+                        line.escape_debug()
+                    ))
+                })?;
+                self.code = Some(code);
+                self.complete = bytes.get(3) != Some(&b'-');
+            }
+            Some(code) => {
+                self.complete = bytes.starts_with(&code)
+                    && matches!(bytes.get(3), None | Some(b' ' | b'\r' | b'\n'));
+            }
+        }
+        self.text.push_str(line);
+        Ok(self.complete)
+    }
+
+    /// Whether the last line of the reply was added.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// The reply lines added so far.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The reply, once complete.
+    ///
+    /// # Errors
+    /// When the reply is incomplete.
+    pub fn finish(self) -> Result<Response> {
+        if !self.complete {
+            return Err(FtpError::ResponseError(format!(
+                "Incomplete response from server: {}",
+                self.text.escape_debug()
+            )));
+        }
+        parse_text(&self.text)
+    }
+}
+
+/// Parse one complete reply, such as `"200 Command okay.\r\n"`. The message of
+/// the result is everything after the code, including continuation lines and
+/// line terminators.
+///
+/// # Example
+/// ```
+/// use simpleftp::parse_reply;
+///
+/// let reply = parse_reply("257 \"/home\" created\r\n").unwrap();
+/// assert_eq!(reply.code(), 257);
+/// assert_eq!(reply.message(), " \"/home\" created\r\n");
+/// assert!(parse_reply("hello\r\n").is_err());
+/// ```
+///
+/// # Errors
+/// When `input` holds no reply, an incomplete one, or data after the reply.
+pub fn parse_reply(input: &str) -> Result<Response> {
+    let mut parser = ReplyParser::new();
+    for line in input.split_inclusive('\n') {
+        parser.push_line(line)?;
+    }
+    parser.finish()
+}
+
+/// Like [`parse_reply`], for bytes which must be valid UTF-8.
+///
+/// # Errors
+/// When `input` isn't UTF-8 or [`parse_reply`] fails.
+pub fn parse_reply_bytes(input: &[u8]) -> Result<Response> {
+    let input = std::str::from_utf8(input)
+        .map_err(|_| FtpError::ResponseError("Response is not valid UTF-8".into()))?;
+    parse_reply(input)
+}
+
+/// Code and message of assembled reply text, which may have been rewritten after
+/// framing, e.g. by unwrapping protected replies.
+pub(crate) fn parse_text(text: &str) -> Result<Response> {
+    let code = reply_code(text.as_bytes()).ok_or_else(|| {
+        FtpError::ResponseError(format!(
+            "Invalid response code form server: {}",
+            text.escape_debug()
+        ))
+    })?;
+    Ok(Response::new(number(code), &text[3..]))
+}
+
+/// Text and code of each line of a reply message, whose first line lost its
+/// code when the message was parsed. An empty message still makes one line.
+pub(crate) fn split_lines(code: usize, message: &str) -> (Vec<String>, Vec<Option<usize>>) {
+    if message.lines().next().is_none() {
+        return (vec![String::new()], vec![Some(code)]);
+    }
+    message
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let (text, code) = match reply_code(line.as_bytes()) {
+                _ if index == 0 => (line, code),
+                Some(digits) => (&line[3..], number(digits)),
+                None => return (line.trim().to_string(), None),
+            };
+            let text = text.strip_prefix(['-', ' ']).unwrap_or(text);
+            (text.trim().to_string(), Some(code))
+        })
+        .unzip()
+}
+
+fn number(code: [u8; 3]) -> usize {
+    code.iter()
+        .fold(0, |code, digit| code * 10 + usize::from(digit - b'0'))
+}
+
+/// The three digits of a line starting with a reply code, whose first digit
+/// is 1 to 5.
+fn reply_code(line: &[u8]) -> Option<[u8; 3]> {
+    let code: [u8; 3] = line.get(..3)?.try_into().ok()?;
+    let separated = matches!(line.get(3), None | Some(b' ' | b'-' | b'\r' | b'\n'));
+    let valid = (b'1'..=b'5').contains(&code[0]) && code.iter().all(u8::is_ascii_digit);
+    (valid && separated).then_some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_reply;
+    use super::parse_reply_bytes;
+    use super::ReplyParser;
+    use crate::FtpError;
+
+    #[test]
+    fn single_line_replies() {
+        let reply = parse_reply("200 Command okay.\r\n").unwrap();
+        assert_eq!((reply.code(), reply.message()), (200, " Command okay.\r\n"));
+        let reply = parse_reply("226 Done\n").unwrap();
+        assert_eq!((reply.code(), reply.message()), (226, " Done\n"));
+        let reply = parse_reply("220\r\n").unwrap();
+        assert_eq!((reply.code(), reply.message()), (220, "\r\n"));
+        let reply = parse_reply_bytes(b"550 No such file").unwrap();
+        assert_eq!((reply.code(), reply.message()), (550, " No such file"));
+    }
+
+    #[test]
+    fn multi_line_replies() {
+        let text = "211-Features:\r\n MDTM\r\n211-ish text\r\n 211 indented\r\n211 End\r\n";
+        let reply = parse_reply(text).unwrap();
+        assert_eq!(reply.code(), 211);
+        assert_eq!(reply.message(), &text[3..]);
+        assert_eq!(
+            reply.lines(),
+            ["Features:", "MDTM", "ish text", "211 indented", "End"]
+        );
+        assert_eq!(
+            reply.line_codes(),
+            [Some(211), None, Some(211), None, Some(211)]
+        );
+
+        // a line starting with the code but no space doesn't end the reply
+        let mut parser = ReplyParser::new();
+        assert!(!parser.push_line("230-Welcome\r\n").unwrap());
+        assert!(!parser.push_line("2300 files available\r\n").unwrap());
+        assert!(!parser.push_line("230-still going\r\n").unwrap());
+        assert!(!parser.push_line("200 other code\r\n").unwrap());
+        assert!(parser.push_line("230 Logged in\r\n").unwrap());
+        assert!(parser.push_line("230 again\r\n").is_err());
+        assert_eq!(parser.finish().unwrap().code(), 230);
+
+        let mut parser = ReplyParser::new();
+        assert!(!parser.push_line("220-Hello\r\n").unwrap());
+        assert!(parser.push_line("220\r\n").unwrap());
+        assert_eq!(parser.finish().unwrap().lines(), ["Hello", ""]);
+    }
+
+    #[test]
+    fn malformed_replies_are_refused() {
+        for text in [
+            "",
+            "\r\n",
+            "20\r\n",
+            "2x0 Broken\r\n",
+            "200Oops\r\n",
+            "hello world\r\n",
+            "é00 Unicode\r\n",
+            "20é Unicode\r\n",
+            "211-Features:\r\n MDTM\r\n",
+            "211-Features:\r\n212 End\r\n",
+            "200 One\r\n200 Two\r\n",
+            "000 Zero\r\n",
+            "600 Out of range\r\n",
+        ] {
+            assert!(
+                matches!(parse_reply(text), Err(FtpError::ResponseError(_))),
+                "{:?}",
+                text
+            );
+        }
+        assert!(parse_reply_bytes(b"200 \xff\xfe\r\n").is_err());
+        assert!(ReplyParser::new().finish().is_err());
+    }
+
+    #[test]
+    fn reply_continuations_and_truncation() {
+        // continuation lines with other codes, out of range codes or none
+        let text = "150-Opening\r\n226 not the end\r\n000 nor this\r\n\r\n150 Here\r\n";
+        let reply = parse_reply(text).unwrap();
+        assert_eq!(reply.code(), 150);
+        assert_eq!(
+            reply.line_codes(),
+            [Some(150), Some(226), None, None, Some(150)]
+        );
+        assert_eq!(reply.lines().last().unwrap(), "Here");
+
+        // the closing line needs the opening code
+        let mut parser = ReplyParser::new();
+        assert!(!parser.push_line("211-Features:\r\n").unwrap());
+        assert!(!parser.push_line("212 End\r\n").unwrap());
+        assert!(!parser.is_complete());
+        assert_eq!(parser.text(), "211-Features:\r\n212 End\r\n");
+        assert!(parser.finish().is_err());
+
+        // cut off in the middle of a line
+        let mut parser = ReplyParser::new();
+        assert!(!parser.push_line("211-Feat").unwrap());
+        assert!(parser.finish().is_err());
+        assert!(ReplyParser::new().push_line("21").is_err());
+        let reply = parse_reply("250 Do").unwrap();
+        assert_eq!(
+            (reply.code(), reply.lines()),
+            (250, &["Do".to_string()][..])
+        );
+
+        // a bare code still makes one line
+        let reply = parse_reply("220").unwrap();
+        assert_eq!(
+            (reply.lines(), reply.line_codes()),
+            (&[String::new()][..], &[Some(220)][..])
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Audit trail of the embedded server.
+
+use std::net::SocketAddr;
+
+use crate::RemotePath;
+
+/// Something a client did, reported to the callback installed with
+/// [`crate::FtpServer::audit`] and, with the `tracing` feature, as an event of
+/// the `simpleftp::audit` target.
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::{AnonymousAccess, AuditAction, FtpServer};
+///
+/// FtpServer::new(AnonymousAccess)
+///     .audit(|event| {
+///         if let AuditAction::Written { path, bytes, .. } = &event.action {
+///             println!("{} {} uploaded {} ({} bytes)", event.peer, event.user, path, bytes);
+///         }
+///     })
+///     .listen("0.0.0.0:21")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    /// Address of the client.
+    pub peer: SocketAddr,
+    /// User logged in, or trying to.
+    pub user: String,
+    pub action: AuditAction,
+}
+
+/// What an [`AuditEvent`] reports. Paths are those of the storage backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditAction {
+    LoginSucceeded,
+    LoginFailed,
+    /// A file was downloaded, entirely if `complete`.
+    Read {
+        path: RemotePath,
+        bytes: u64,
+        complete: bool,
+    },
+    /// A file was uploaded or appended to, entirely if `complete`.
+    Written {
+        path: RemotePath,
+        bytes: u64,
+        append: bool,
+        complete: bool,
+    },
+    Deleted {
+        path: RemotePath,
+    },
+    DirectoryCreated {
+        path: RemotePath,
+    },
+    Renamed {
+        from: RemotePath,
+        to: RemotePath,
+    },
+}
+
+/// Receiver of the audit events of a server.
+pub(crate) type AuditHook = Box<dyn Fn(&AuditEvent) + Send + Sync>;
+
+/// Report `event` to `hook` and to the tracing subscriber.
+pub(crate) fn emit(hook: Option<&AuditHook>, event: AuditEvent) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        target: "simpleftp::audit",
+        peer = %event.peer,
+        user = %event.user,
+        action = ?event.action,
+    );
+    if let Some(hook) = hook {
+        hook(&event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::start_server;
+    use crate::testing::TempRoot;
+    use crate::FtpClient;
+    use crate::RemotePath;
+
+    #[test]
+    fn server_audits_sessions() {
+        use super::AuditAction;
+        use crate::{FtpServer, LocalStorage, StaticUsers};
+        use std::sync::{Arc, Mutex};
+
+        let root = TempRoot::new("audit", &[]);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let server = FtpServer::new(StaticUsers::new().user("alice", "wonderland"))
+            .storage(LocalStorage::new(root.to_path_buf()))
+            .audit(move |event| recorded.lock().unwrap().push(event.clone()));
+        let mut client = FtpClient::connect(start_server(server)).unwrap();
+        assert!(client.login("alice", "rabbit").is_err());
+        client.login("alice", "wonderland").unwrap();
+        client.put("a.txt", &mut &b"hello"[..]).unwrap();
+        client.get("a.txt", &mut Vec::new()).unwrap();
+        client.delete("a.txt").unwrap();
+        client.logout().unwrap();
+        let path = RemotePath::new("/a.txt");
+        let actions: Vec<_> = events
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|event| event.action)
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                AuditAction::LoginFailed,
+                AuditAction::LoginSucceeded,
+                AuditAction::Written {
+                    path: path.clone(),
+                    bytes: 5,
+                    append: false,
+                    complete: true
+                },
+                AuditAction::Read {
+                    path: path.clone(),
+                    bytes: 5,
+                    complete: true
+                },
+                AuditAction::Deleted { path },
+            ]
+        );
+    }
+}
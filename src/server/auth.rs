@@ -0,0 +1,142 @@
+//! User authentication of the embedded server.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::Secret;
+
+/// Outcome of a login attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthResult {
+    Granted,
+    Denied,
+}
+
+/// Decides who may log in to the server, e.g. by looking users up in a database
+/// or a directory service. Called once per `PASS` command.
+///
+/// Closures with the same signature as [`Authenticator::check`] implement this trait.
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::{AuthResult, FtpServer};
+/// use std::net::SocketAddr;
+///
+/// let server = FtpServer::new(|user: &str, password: &str, peer: SocketAddr| {
+///     if peer.ip().is_loopback() && user == "admin" && password == "secret" {
+///         AuthResult::Granted
+///     } else {
+///         AuthResult::Denied
+///     }
+/// });
+/// server.listen("127.0.0.1:2121").unwrap();
+/// ```
+pub trait Authenticator: Send + Sync {
+    /// Check the credentials of `user` connecting from `peer`.
+    fn check(&self, user: &str, password: &str, peer: SocketAddr) -> AuthResult;
+}
+
+impl<F> Authenticator for F
+where
+    F: Fn(&str, &str, SocketAddr) -> AuthResult + Send + Sync,
+{
+    fn check(&self, user: &str, password: &str, peer: SocketAddr) -> AuthResult {
+        self(user, password, peer)
+    }
+}
+
+/// A fixed set of users and passwords.
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::{FtpServer, StaticUsers};
+///
+/// let users = StaticUsers::new().user("alice", "wonderland").user("bob", "builder");
+/// FtpServer::new(users).listen("0.0.0.0:21").unwrap();
+/// ```
+#[derive(Default)]
+pub struct StaticUsers {
+    users: HashMap<String, Secret>,
+}
+
+impl StaticUsers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `name`, logging in with `password`.
+    pub fn user(mut self, name: impl Into<String>, password: impl Into<Secret>) -> Self {
+        self.users.insert(name.into(), password.into());
+        self
+    }
+}
+
+impl Authenticator for StaticUsers {
+    fn check(&self, user: &str, password: &str, _peer: SocketAddr) -> AuthResult {
+        match self.users.get(user) {
+            Some(expected) if same_secret(expected.expose(), password) => AuthResult::Granted,
+            _ => AuthResult::Denied,
+        }
+    }
+}
+
+/// Anonymous access: the `anonymous` and `ftp` users log in with any password,
+/// conventionally their e-mail address. Everybody else is refused.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnonymousAccess;
+
+impl Authenticator for AnonymousAccess {
+    fn check(&self, user: &str, _password: &str, _peer: SocketAddr) -> AuthResult {
+        if user.eq_ignore_ascii_case("anonymous") || user.eq_ignore_ascii_case("ftp") {
+            AuthResult::Granted
+        } else {
+            AuthResult::Denied
+        }
+    }
+}
+
+/// Compare passwords in a time independent of where they differ.
+fn same_secret(expected: &str, given: &str) -> bool {
+    let (expected, given) = (expected.as_bytes(), given.as_bytes());
+    expected.len() == given.len()
+        && expected
+            .iter()
+            .zip(given)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::start_server;
+    use crate::FtpClient;
+    use crate::FtpError;
+
+    #[test]
+    fn server_authenticates_users() {
+        use super::{AnonymousAccess, StaticUsers};
+        use crate::FtpServer;
+
+        let users = StaticUsers::new().user("alice", "wonderland");
+        let address = start_server(FtpServer::new(users));
+        let mut client = FtpClient::connect(address).unwrap();
+        assert!(matches!(
+            client.login("alice", "wonder"),
+            Err(FtpError::LoginError(_))
+        ));
+        assert!(matches!(
+            client.login("anonymous", "me@example.com"),
+            Err(FtpError::LoginError(_))
+        ));
+        let login = client.login("alice", "wonderland").unwrap();
+        assert_eq!(login.lines(), ["Logged in"]);
+        assert!(!login.account_required());
+        client.logout().unwrap();
+
+        let address = start_server(FtpServer::new(AnonymousAccess));
+        let mut client = FtpClient::connect(address).unwrap();
+        assert!(client.system().is_err());
+        client.login("ftp", "me@example.com").unwrap();
+        assert!(client.system().is_ok());
+    }
+}
@@ -0,0 +1,120 @@
+//! Connection and bandwidth limits of the embedded server.
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
+use std::time::Instant;
+
+use super::ServerConfig;
+
+/// Open control connections, by client address.
+#[derive(Default)]
+pub(crate) struct Connections {
+    by_ip: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// A connection counted against the limits until dropped.
+pub(crate) struct ConnectionSlot {
+    config: Arc<ServerConfig>,
+    ip: IpAddr,
+}
+
+impl ConnectionSlot {
+    /// Count a new connection from `ip`, unless it exceeds a limit.
+    pub(crate) fn admit(config: &Arc<ServerConfig>, ip: IpAddr) -> Option<Self> {
+        // the counts stay consistent even if a thread panicked holding the lock
+        let mut by_ip = config
+            .connections
+            .by_ip
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let total: usize = by_ip.values().sum();
+        let from_ip = by_ip.get(&ip).copied().unwrap_or(0);
+        if config.max_connections.is_some_and(|max| total >= max)
+            || config
+                .max_connections_per_ip
+                .is_some_and(|max| from_ip >= max)
+        {
+            return None;
+        }
+        by_ip.insert(ip, from_ip + 1);
+        Some(Self {
+            config: config.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut by_ip = self
+            .config
+            .connections
+            .by_ip
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = by_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                by_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Copy `reader` to `writer`, no faster than `limit` bytes per second if set.
+pub(crate) fn copy(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    limit: Option<u64>,
+) -> std::io::Result<u64> {
+    let Some(limit) = limit.filter(|limit| *limit > 0) else {
+        return std::io::copy(reader, writer);
+    };
+    // chunks of about a tenth of a second keep the rate smooth
+    let mut buffer = vec![0; (limit / 10).clamp(512, 64 * 1024) as usize];
+    let started = Instant::now();
+    let mut total = 0u64;
+    loop {
+        let count = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(total),
+            Ok(count) => count,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        writer.write_all(&buffer[..count])?;
+        total += count as u64;
+        let due = Duration::from_secs_f64(total as f64 / limit as f64);
+        if let Some(ahead) = due.checked_sub(started.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::start_server;
+    use crate::FtpClient;
+
+    #[test]
+    fn server_enforces_connection_limits() {
+        use crate::{AnonymousAccess, FtpServer};
+        use std::time::Duration;
+
+        let server = FtpServer::new(AnonymousAccess)
+            .max_connections_per_ip(1)
+            .idle_timeout(Duration::from_millis(200));
+        let address = start_server(server).to_string();
+        let mut client = FtpClient::connect(&address).unwrap();
+        assert!(FtpClient::connect(&address).is_err());
+        client.noop().unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(client.noop().is_err());
+    }
+}
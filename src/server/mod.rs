@@ -0,0 +1,243 @@
+//! An embeddable FTP server (`server` feature), serving each control connection
+//! on its own thread.
+
+mod audit;
+mod auth;
+mod limits;
+mod session;
+mod storage;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::Ipv4Addr;
+use std::net::TcpListener;
+use std::net::ToSocketAddrs;
+use std::ops::RangeInclusive;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use audit::{AuditAction, AuditEvent};
+pub use auth::{AnonymousAccess, AuthResult, Authenticator, StaticUsers};
+pub use storage::{FileInfo, LocalStorage, StorageBackend};
+
+use crate::RemotePath;
+use crate::Result;
+use crate::ServerTlsConfig;
+use crate::NOT_AVAILABLE;
+use audit::AuditHook;
+use limits::ConnectionSlot;
+use limits::Connections;
+use session::Session;
+use storage::EmptyStorage;
+
+/// Settings shared by the sessions of a server.
+pub(crate) struct ServerConfig {
+    pub(crate) authenticator: Box<dyn Authenticator>,
+    pub(crate) storage: Box<dyn StorageBackend>,
+    pub(crate) greeting: String,
+    pub(crate) passive_ports: Option<RangeInclusive<u16>>,
+    /// Offset in `passive_ports` of the next port to try.
+    pub(crate) next_port: AtomicUsize,
+    pub(crate) external_ip: Option<Ipv4Addr>,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) max_connections_per_ip: Option<usize>,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) bandwidth_limit: Option<u64>,
+    pub(crate) connections: Connections,
+    pub(crate) read_only: bool,
+    /// Directory of the storage each user is confined to, normalized.
+    pub(crate) user_roots: HashMap<String, RemotePath>,
+    pub(crate) tls: Option<ServerTlsConfig>,
+    pub(crate) audit: Option<AuditHook>,
+}
+
+/// An FTP server, e.g. to expose files to legacy devices or to test clients.
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::{FtpServer, LocalStorage, StaticUsers};
+///
+/// let server = FtpServer::new(StaticUsers::new().user("user", "password"))
+///     .storage(LocalStorage::new("/srv/ftp"))
+///     .greeting("Welcome to the test server");
+/// std::thread::spawn(move || server.listen("127.0.0.1:2121"));
+/// ```
+pub struct FtpServer {
+    config: ServerConfig,
+}
+
+impl FtpServer {
+    /// A server letting in the users accepted by `authenticator`, serving an
+    /// empty directory until given a [`StorageBackend`].
+    pub fn new(authenticator: impl Authenticator + 'static) -> Self {
+        Self {
+            config: ServerConfig {
+                authenticator: Box::new(authenticator),
+                storage: Box::new(EmptyStorage),
+                greeting: "Service ready".into(),
+                passive_ports: None,
+                next_port: AtomicUsize::new(0),
+                external_ip: None,
+                max_connections: None,
+                max_connections_per_ip: None,
+                idle_timeout: None,
+                bandwidth_limit: None,
+                connections: Connections::default(),
+                read_only: false,
+                user_roots: HashMap::new(),
+                tls: None,
+                audit: None,
+            },
+        }
+    }
+
+    /// Serve the files of `storage`.
+    pub fn storage(mut self, storage: impl StorageBackend + 'static) -> Self {
+        self.config.storage = Box::new(storage);
+        self
+    }
+
+    /// Text of the 220 reply greeting new connections.
+    pub fn greeting(mut self, text: impl Into<String>) -> Self {
+        self.config.greeting = text.into();
+        self
+    }
+
+    /// Open passive data connections on ports of `ports` only, e.g. the range
+    /// forwarded by a firewall or published by a container. By default the
+    /// system picks any free port.
+    pub fn passive_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.config.passive_ports = Some(ports);
+        self
+    }
+
+    /// Address advertised in `PASV` replies instead of the local address of the
+    /// control connection, for servers behind NAT or port mapping. `EPSV`
+    /// replies carry no address and need no setting.
+    pub fn external_ip(mut self, ip: Ipv4Addr) -> Self {
+        self.config.external_ip = Some(ip);
+        self
+    }
+
+    /// Refuse connections with a 421 reply while `max` are open.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.config.max_connections = Some(max);
+        self
+    }
+
+    /// Refuse connections with a 421 reply while `max` are open from the same
+    /// address.
+    pub fn max_connections_per_ip(mut self, max: usize) -> Self {
+        self.config.max_connections_per_ip = Some(max);
+        self
+    }
+
+    /// Close control connections left without commands for `timeout`, with a
+    /// 421 reply. Transfers in progress don't count as idle.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Cap the data transfers of each connection to `bytes_per_second`.
+    pub fn bandwidth_limit(mut self, bytes_per_second: u64) -> Self {
+        self.config.bandwidth_limit = Some(bytes_per_second);
+        self
+    }
+
+    /// Refuse every command changing the storage (`STOR`, `APPE`, `DELE`,
+    /// `RMD`, `MKD`, `RNFR`…) with a 550 reply.
+    pub fn read_only(mut self) -> Self {
+        self.config.read_only = true;
+        self
+    }
+
+    /// Confine the sessions of `user` to the storage directory `root`, which
+    /// they see as `/`. Other users see the whole storage.
+    pub fn user_root(mut self, user: impl Into<String>, root: impl Into<RemotePath>) -> Self {
+        let root = RemotePath::root().join(root.into()).normalize();
+        self.config.user_roots.insert(user.into(), root);
+        self
+    }
+
+    /// Offer FTPS with `AUTH TLS`, or start TLS on every connection when
+    /// `tls` is implicit. Requires the `rustls` or `native-tls` feature to
+    /// create the [`ServerTlsConfig`].
+    pub fn tls(mut self, tls: ServerTlsConfig) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    /// Report logins, transfers and changes to the storage to `hook`, called
+    /// from the session threads.
+    pub fn audit(mut self, hook: impl Fn(&AuditEvent) + Send + Sync + 'static) -> Self {
+        self.config.audit = Some(Box::new(hook));
+        self
+    }
+
+    /// Listen on `address` and serve connections until accepting fails.
+    ///
+    /// # Errors
+    /// When the address can't be bound or accepting connections fails.
+    pub fn listen(self, address: impl ToSocketAddrs) -> Result<()> {
+        self.serve(TcpListener::bind(address)?)
+    }
+
+    /// Serve the connections arriving on `listener` until accepting fails.
+    ///
+    /// # Errors
+    /// When accepting connections fails.
+    pub fn serve(self, listener: TcpListener) -> Result<()> {
+        let config = Arc::new(self.config);
+        loop {
+            let (mut stream, peer) = listener.accept()?;
+            let Some(slot) = ConnectionSlot::admit(&config, peer.ip()) else {
+                let _ = write!(stream, "{} Too many connections\r\n", NOT_AVAILABLE);
+                continue;
+            };
+            let config = config.clone();
+            std::thread::spawn(move || {
+                // the client went away, nobody is left to tell
+                let _ = Session::new(config, stream, peer).run();
+                drop(slot);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::start_server;
+
+    #[test]
+    fn server_uses_passive_port_range() {
+        use super::FtpServer;
+        use crate::{AnonymousAccess, FtpClientBuilder, ProtocolEvent};
+
+        let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = free.local_addr().unwrap().port();
+        drop(free);
+        let server = FtpServer::new(AnonymousAccess)
+            .passive_ports(port..=port)
+            .external_ip([127, 0, 0, 1].into());
+        let (sender, events) = std::sync::mpsc::channel();
+        let mut client = FtpClientBuilder::new()
+            .events(sender)
+            .connect(start_server(server).to_string())
+            .unwrap();
+        client.login("anonymous", "").unwrap();
+        for _ in 0..2 {
+            assert!(client.list("/").unwrap().is_empty());
+        }
+        let advertised = format!("(127,0,0,1,{},{})", port >> 8, port & 0xff);
+        let passive = events
+            .try_iter()
+            .filter(|event| {
+                matches!(event, ProtocolEvent::ReplyReceived { code: 227, message }
+                    if message.contains(&advertised))
+            })
+            .count();
+        assert_eq!(passive, 2);
+    }
+}
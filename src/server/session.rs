@@ -0,0 +1,870 @@
+//! A control connection of the embedded server.
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use super::audit;
+use super::limits;
+use super::AuditAction;
+use super::AuditEvent;
+use super::AuthResult;
+use super::FileInfo;
+use super::ServerConfig;
+use crate::stream::Stream;
+use crate::time;
+use crate::RemotePath;
+use crate::AUTH_OK;
+use crate::BAD_COMMAND_SEQUENCE;
+use crate::BAD_PARAMETER_FOR_COMMAND;
+use crate::CANNOT_OPEN_DATA_CONNECTION;
+use crate::CLOSING_DATA_CONNECTION;
+use crate::COMMAND_OK;
+use crate::COMMAND_UNIMPLEMENTED;
+use crate::DATA_CONNECTION_OPEN;
+use crate::EXTENDED_PASSIVE_MODE;
+use crate::FILE;
+use crate::FILE_ACTION_OK;
+use crate::FILE_ACTION_PENDING;
+use crate::FILE_NOT_AVAILABLE;
+use crate::FILE_OK;
+use crate::LOCAL_ERROR;
+use crate::LOGGED_IN;
+use crate::NAME_SYSTEM;
+use crate::NEED_PASSWORD;
+use crate::NOT_AVAILABLE;
+use crate::NOT_LOGGED_IN;
+use crate::PARAMETER_ERROR;
+use crate::PASSIVE_MODE;
+use crate::PATH_CREATED;
+use crate::SERVICE_CLOSING;
+use crate::SERVICE_READY;
+use crate::SYSTEM;
+use crate::TRANSFER_ABORTED;
+use crate::UNKNOWN_COMMAND;
+
+/// Longest command line accepted, longer lines end the session.
+const MAX_LINE: u64 = 4096;
+/// Reply to an `EPSV` for another network protocol.
+const NETWORK_PROTOCOL_NOT_SUPPORTED: usize = 522;
+/// Reply to a transfer needing `PROT P` first.
+const PROTECTION_REQUIRED: usize = 521;
+/// Reply to a request the server's policy forbids, e.g. `PROT C` when TLS is
+/// required.
+const POLICY_DENIED: usize = 534;
+/// How long a client may take to open a data connection after a transfer command.
+const DATA_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a client may take to complete a TLS handshake on the control
+/// connection, shortened to the idle timeout if that is shorter.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Commands changing the storage, refused by read-only servers.
+const MUTATING: [&str; 10] = [
+    "STOR", "APPE", "STOU", "DELE", "RMD", "XRMD", "MKD", "XMKD", "RNFR", "RNTO",
+];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Whether the session goes on after a command.
+enum Flow {
+    Continue,
+    Close,
+}
+
+pub(crate) struct Session {
+    config: Arc<ServerConfig>,
+    control: BufReader<Box<dyn Stream>>,
+    peer: SocketAddr,
+    user: Option<String>,
+    logged_in: bool,
+    /// Storage directory the session is confined to.
+    root: RemotePath,
+    /// Current directory, as seen by the client below `root`.
+    cwd: RemotePath,
+    rename_from: Option<RemotePath>,
+    /// Offset given by `REST` for the next transfer.
+    restart: u64,
+    passive: Option<TcpListener>,
+    /// Whether the control connection runs over TLS.
+    secured: bool,
+    /// Whether data connections run over TLS, after `PROT P`.
+    data_protected: bool,
+}
+
+impl Session {
+    pub(crate) fn new(config: Arc<ServerConfig>, stream: TcpStream, peer: SocketAddr) -> Self {
+        Self {
+            config,
+            control: BufReader::new(Box::new(stream)),
+            peer,
+            user: None,
+            logged_in: false,
+            root: RemotePath::root(),
+            cwd: RemotePath::root(),
+            rename_from: None,
+            restart: 0,
+            passive: None,
+            secured: false,
+            data_protected: false,
+        }
+    }
+
+    /// Serve commands until the client quits or goes away.
+    pub(crate) fn run(mut self) -> std::io::Result<()> {
+        self.control
+            .get_ref()
+            .socket()
+            .set_read_timeout(self.config.idle_timeout)?;
+        if self.config.tls.as_ref().is_some_and(|tls| tls.implicit) {
+            self.secure_control()?;
+            self.data_protected = true;
+        }
+        let greeting = self.config.greeting.clone();
+        self.reply(SERVICE_READY, &greeting)?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = match self.control.by_ref().take(MAX_LINE).read_line(&mut line) {
+                Err(error)
+                    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    return self.reply(NOT_AVAILABLE, "Idle timeout, closing connection");
+                }
+                read => read?,
+            };
+            if read == 0 {
+                return Ok(());
+            }
+            if !line.ends_with('\n') {
+                return self.reply(UNKNOWN_COMMAND, "Command line too long");
+            }
+            let command = line.trim_end_matches(['\r', '\n']);
+            let (verb, argument) = command.split_once(' ').unwrap_or((command, ""));
+            if let Flow::Close = self.handle(&verb.to_ascii_uppercase(), argument)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn handle(&mut self, verb: &str, argument: &str) -> std::io::Result<Flow> {
+        if verb != "RNTO" {
+            self.rename_from = None;
+        }
+        let restart = std::mem::take(&mut self.restart);
+        match verb {
+            "USER" => self.user(argument)?,
+            "PASS" => self.pass(argument)?,
+            "QUIT" => {
+                self.reply(SERVICE_CLOSING, "Goodbye")?;
+                return Ok(Flow::Close);
+            }
+            "NOOP" => self.reply(COMMAND_OK, "OK")?,
+            "FEAT" => self.feat()?,
+            "AUTH" => self.auth(argument)?,
+            "PBSZ" if self.secured => self.reply(COMMAND_OK, "PBSZ=0")?,
+            "PROT" if self.secured => self.prot(argument)?,
+            "PBSZ" | "PROT" => self.reply(BAD_COMMAND_SEQUENCE, "Use AUTH TLS first")?,
+            _ if !self.logged_in => self.reply(NOT_LOGGED_IN, "Please login with USER and PASS")?,
+            _ if self.config.read_only && MUTATING.contains(&verb) => self.reply(
+                FILE_NOT_AVAILABLE,
+                "Permission denied, the server is read-only",
+            )?,
+            "SYST" => self.reply(NAME_SYSTEM, "UNIX Type: L8")?,
+            "TYPE" => match argument.to_ascii_uppercase().as_str() {
+                "A" | "A N" | "I" | "L 8" => self.reply(COMMAND_OK, "Type set")?,
+                _ => self.reply(BAD_PARAMETER_FOR_COMMAND, "Type not supported")?,
+            },
+            "OPTS" if argument.eq_ignore_ascii_case("UTF8 ON") => {
+                self.reply(COMMAND_OK, "Always in UTF-8 mode")?
+            }
+            "PWD" | "XPWD" => {
+                let text = format!("{} is the current directory", self.cwd.quoted());
+                self.reply(PATH_CREATED, &text)?
+            }
+            "CWD" | "XCWD" => self.cwd(argument)?,
+            "CDUP" | "XCUP" => self.cwd("..")?,
+            "PASV" => self.pasv()?,
+            "EPSV" => self.epsv(argument)?,
+            "LIST" => self.list(argument, Listing::Long)?,
+            "NLST" => self.list(argument, Listing::Names)?,
+            "MLSD" => self.list(argument, Listing::Facts)?,
+            "REST" => self.rest(argument)?,
+            "RETR" => self.retr(argument, restart)?,
+            "STOR" => self.stor(argument, false, restart)?,
+            "APPE" => self.stor(argument, true, 0)?,
+            "DELE" => self.remove(argument, false)?,
+            "RMD" | "XRMD" => self.remove(argument, true)?,
+            "MKD" | "XMKD" => self.mkd(argument)?,
+            "RNFR" => self.rnfr(argument)?,
+            "RNTO" => self.rnto(argument)?,
+            "SIZE" => self.size(argument)?,
+            "MDTM" => self.mdtm(argument)?,
+            "ABOR" => self.reply(DATA_CONNECTION_OPEN, "No transfer to abort")?,
+            _ => self.reply(COMMAND_UNIMPLEMENTED, "Command not implemented")?,
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn feat(&mut self) -> std::io::Result<()> {
+        let mut lines = vec!["Features:"];
+        if self.config.tls.is_some() {
+            lines.extend([" AUTH TLS", " PBSZ", " PROT"]);
+        }
+        lines.extend([
+            " EPSV",
+            " SIZE",
+            " MDTM",
+            " MLSD",
+            " REST STREAM",
+            " UTF8",
+            "End",
+        ]);
+        self.reply_lines(SYSTEM, &lines)
+    }
+
+    fn auth(&mut self, mechanism: &str) -> std::io::Result<()> {
+        if self.config.tls.is_none() {
+            return self.reply(COMMAND_UNIMPLEMENTED, "TLS not configured");
+        }
+        if !["TLS", "TLS-C", "SSL"].contains(&mechanism.to_ascii_uppercase().as_str()) {
+            return self.reply(BAD_PARAMETER_FOR_COMMAND, "Mechanism not supported");
+        }
+        if self.secured {
+            return self.reply(BAD_COMMAND_SEQUENCE, "Already using TLS");
+        }
+        self.reply(AUTH_OK, "Starting TLS")?;
+        // a failed handshake leaves the connection unusable and ends the session
+        self.secure_control()
+    }
+
+    /// Run the TLS handshake on the control connection.
+    fn secure_control(&mut self) -> std::io::Result<()> {
+        let Some(tls) = self.config.tls.clone() else {
+            return Ok(());
+        };
+        let socket = self.control.get_ref().socket().try_clone()?;
+        // a client stalling the handshake must not hold the session forever
+        let limit = self
+            .config
+            .idle_timeout
+            .map_or(HANDSHAKE_TIMEOUT, |idle| idle.min(HANDSHAKE_TIMEOUT));
+        socket.set_read_timeout(Some(limit))?;
+        socket.set_write_timeout(Some(limit))?;
+        let placeholder: Box<dyn Stream> = Box::new(socket.try_clone()?);
+        let control = std::mem::replace(&mut self.control, BufReader::new(placeholder));
+        self.control = BufReader::new(tls.accept(control.into_inner())?);
+        socket.set_read_timeout(self.config.idle_timeout)?;
+        socket.set_write_timeout(None)?;
+        self.secured = true;
+        Ok(())
+    }
+
+    fn prot(&mut self, level: &str) -> std::io::Result<()> {
+        let required = self.config.tls.as_ref().is_some_and(|tls| tls.required);
+        match level.to_ascii_uppercase().as_str() {
+            "P" => {
+                self.data_protected = true;
+                self.reply(COMMAND_OK, "Data connections protected")
+            }
+            "C" if required => self.reply(POLICY_DENIED, "Data connections must be protected"),
+            "C" => {
+                self.data_protected = false;
+                self.reply(COMMAND_OK, "Data connections in clear")
+            }
+            _ => self.reply(BAD_PARAMETER_FOR_COMMAND, "Protection level not supported"),
+        }
+    }
+
+    fn user(&mut self, name: &str) -> std::io::Result<()> {
+        if self.config.tls.as_ref().is_some_and(|tls| tls.required) && !self.secured {
+            return self.reply(NOT_LOGGED_IN, "TLS required, use AUTH TLS first");
+        }
+        if name.is_empty() {
+            return self.reply(PARAMETER_ERROR, "User name required");
+        }
+        self.user = Some(name.to_string());
+        self.logged_in = false;
+        self.reply(NEED_PASSWORD, "Password required")
+    }
+
+    fn pass(&mut self, password: &str) -> std::io::Result<()> {
+        let Some(user) = self.user.as_deref() else {
+            return self.reply(BAD_COMMAND_SEQUENCE, "Login with USER first");
+        };
+        match self.config.authenticator.check(user, password, self.peer) {
+            AuthResult::Granted => {
+                self.logged_in = true;
+                let root = self.config.user_roots.get(user).cloned();
+                self.root = root.unwrap_or_else(RemotePath::root);
+                self.cwd = RemotePath::root();
+                self.audit(AuditAction::LoginSucceeded);
+                self.reply(LOGGED_IN, "Logged in")
+            }
+            AuthResult::Denied => {
+                self.audit(AuditAction::LoginFailed);
+                self.user = None;
+                self.reply(NOT_LOGGED_IN, "Login incorrect")
+            }
+        }
+    }
+
+    /// The absolute path named by a command argument, as seen by the client.
+    fn client_path(&self, argument: &str) -> RemotePath {
+        self.cwd.join(argument).normalize()
+    }
+
+    /// The storage path named by a command argument, below the session root.
+    fn resolve(&self, argument: &str) -> RemotePath {
+        self.storage_path(&self.client_path(argument))
+    }
+
+    fn storage_path(&self, client_path: &RemotePath) -> RemotePath {
+        let relative = client_path.as_str().trim_start_matches('/');
+        self.root.join(relative).normalize()
+    }
+
+    fn cwd(&mut self, argument: &str) -> std::io::Result<()> {
+        let path = self.client_path(argument);
+        match self.config.storage.metadata(&self.storage_path(&path)) {
+            Ok(info) if info.is_dir => {
+                self.cwd = path;
+                self.reply(FILE_ACTION_OK, "Directory changed")
+            }
+            Ok(_) => self.reply(FILE_NOT_AVAILABLE, "Not a directory"),
+            Err(error) => self.reply_error(error),
+        }
+    }
+
+    fn pasv(&mut self) -> std::io::Result<()> {
+        let local = self.control.get_ref().socket().local_addr()?.ip();
+        let advertised = match (self.config.external_ip, local) {
+            (Some(ip), _) => ip,
+            (None, IpAddr::V4(ip)) => ip,
+            (None, IpAddr::V6(_)) => {
+                return self.reply(COMMAND_UNIMPLEMENTED, "Use EPSV over IPv6");
+            }
+        };
+        let Some(port) = self.listen_passive(local)? else {
+            return Ok(());
+        };
+        let [a, b, c, d] = advertised.octets();
+        let text = format!(
+            "Entering Passive Mode ({},{},{},{},{},{})",
+            a,
+            b,
+            c,
+            d,
+            port >> 8,
+            port & 0xff
+        );
+        self.reply(PASSIVE_MODE, &text)
+    }
+
+    fn epsv(&mut self, argument: &str) -> std::io::Result<()> {
+        if argument.eq_ignore_ascii_case("ALL") {
+            return self.reply(COMMAND_OK, "EPSV ALL accepted");
+        }
+        let local = self.control.get_ref().socket().local_addr()?.ip();
+        let family = if local.is_ipv4() { "1" } else { "2" };
+        if !argument.is_empty() && argument != family {
+            return self.reply(NETWORK_PROTOCOL_NOT_SUPPORTED, &format!("Use ({})", family));
+        }
+        let Some(port) = self.listen_passive(local)? else {
+            return Ok(());
+        };
+        let text = format!("Entering Extended Passive Mode (|||{}|)", port);
+        self.reply(EXTENDED_PASSIVE_MODE, &text)
+    }
+
+    /// Listen for the next data connection on `ip`, within the configured port
+    /// range if any. Replies with an error when no port is free.
+    fn listen_passive(&mut self, ip: IpAddr) -> std::io::Result<Option<u16>> {
+        self.passive = None;
+        let listener = match &self.config.passive_ports {
+            None => TcpListener::bind((ip, 0)).ok(),
+            Some(ports) => {
+                // start after the port handed out last, sessions rarely collide
+                let count = ports.len();
+                let offset = self.config.next_port.fetch_add(1, Ordering::Relaxed);
+                (0..count)
+                    .map(|attempt| *ports.start() as usize + (offset + attempt) % count)
+                    .find_map(|port| TcpListener::bind((ip, port as u16)).ok())
+            }
+        };
+        let Some(listener) = listener else {
+            self.reply(CANNOT_OPEN_DATA_CONNECTION, "No passive port available")?;
+            return Ok(None);
+        };
+        let port = listener.local_addr()?.port();
+        self.passive = Some(listener);
+        Ok(Some(port))
+    }
+
+    /// Announce a transfer and accept its data connection, replying with an
+    /// error when it can't be opened.
+    fn open_data(&mut self) -> std::io::Result<Option<Box<dyn Stream>>> {
+        let Some(listener) = self.passive.take() else {
+            self.reply(CANNOT_OPEN_DATA_CONNECTION, "Use PASV first")?;
+            return Ok(None);
+        };
+        let required = self.config.tls.as_ref().is_some_and(|tls| tls.required);
+        if required && !self.data_protected {
+            self.reply(PROTECTION_REQUIRED, "Use PROT P first")?;
+            return Ok(None);
+        }
+        self.reply(FILE_OK, "Opening data connection")?;
+        let accepted = accept_data(&listener, self.peer.ip()).and_then(|stream| {
+            let stream: Box<dyn Stream> = Box::new(stream);
+            match &self.config.tls {
+                Some(tls) if self.data_protected => tls.accept(stream),
+                _ => Ok(stream),
+            }
+        });
+        match accepted {
+            Ok(stream) => Ok(Some(stream)),
+            Err(_) => {
+                self.reply(CANNOT_OPEN_DATA_CONNECTION, "Can't open data connection")?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn list(&mut self, argument: &str, listing: Listing) -> std::io::Result<()> {
+        // options like `-la` are accepted and ignored
+        let argument = argument
+            .split(' ')
+            .skip_while(|word| word.starts_with('-'))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let path = self.resolve(&argument);
+        let storage = &self.config.storage;
+        let entries = match storage.metadata(&path) {
+            Ok(info) if info.is_dir => storage.list(&path),
+            Ok(info) => Ok(vec![info]),
+            Err(error) => Err(error),
+        };
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(error) => return self.reply_error(error),
+        };
+        let now = SystemTime::now();
+        let text: String = entries
+            .iter()
+            .map(|info| match listing {
+                Listing::Long => format!("{}\r\n", long_line(info, now)),
+                Listing::Names => format!("{}\r\n", info.name),
+                Listing::Facts => format!("{}\r\n", facts_line(info)),
+            })
+            .collect();
+        let Some(mut data) = self.open_data()? else {
+            return Ok(());
+        };
+        let sent = limits::copy(&mut text.as_bytes(), &mut data, self.config.bandwidth_limit);
+        let _ = data.close();
+        match sent {
+            Ok(_) => self.reply(CLOSING_DATA_CONNECTION, "Transfer complete"),
+            Err(_) => self.reply(TRANSFER_ABORTED, "Transfer aborted"),
+        }
+    }
+
+    fn rest(&mut self, argument: &str) -> std::io::Result<()> {
+        match argument.parse() {
+            Ok(offset) => {
+                self.restart = offset;
+                self.reply(FILE_ACTION_PENDING, &format!("Restarting at {}", offset))
+            }
+            Err(_) => self.reply(PARAMETER_ERROR, "Invalid offset"),
+        }
+    }
+
+    /// Send a file, from the offset `restart` on.
+    fn retr(&mut self, argument: &str, restart: u64) -> std::io::Result<()> {
+        let path = self.resolve(argument);
+        let skipped = self.config.storage.open_read(&path).and_then(|mut file| {
+            std::io::copy(&mut file.by_ref().take(restart), &mut std::io::sink())?;
+            Ok(file)
+        });
+        let mut file = match skipped {
+            Ok(file) => file,
+            Err(error) => return self.reply_error(error),
+        };
+        let Some(mut data) = self.open_data()? else {
+            return Ok(());
+        };
+        let mut counted = Counted::new(&mut data);
+        let copied = limits::copy(&mut file, &mut counted, self.config.bandwidth_limit);
+        let bytes = counted.bytes;
+        let _ = data.close();
+        self.audit(AuditAction::Read {
+            path,
+            bytes,
+            complete: copied.is_ok(),
+        });
+        match copied {
+            Ok(_) => self.reply(CLOSING_DATA_CONNECTION, "Transfer complete"),
+            Err(_) => self.reply(TRANSFER_ABORTED, "Transfer aborted"),
+        }
+    }
+
+    /// Receive a file, written from the offset `restart` on when not 0.
+    fn stor(&mut self, argument: &str, append: bool, restart: u64) -> std::io::Result<()> {
+        let path = self.resolve(argument);
+        // opening for writing truncates, so not before the transfer can happen
+        let Some(mut data) = self.open_data()? else {
+            return Ok(());
+        };
+        let storage = &self.config.storage;
+        let opened = if restart > 0 {
+            storage.open_write_at(&path, restart)
+        } else {
+            storage.open_write(&path, append)
+        };
+        let mut file = match opened {
+            Ok(file) => file,
+            Err(error) => {
+                let _ = data.close();
+                if error.kind() == ErrorKind::Unsupported {
+                    return self.reply(BAD_PARAMETER_FOR_COMMAND, "Restart not supported");
+                }
+                return self.reply_error(error);
+            }
+        };
+        let mut counted = Counted::new(&mut file);
+        let copied = limits::copy(&mut data, &mut counted, self.config.bandwidth_limit)
+            .and_then(|_| counted.flush());
+        let bytes = counted.bytes;
+        let _ = data.close();
+        self.audit(AuditAction::Written {
+            path,
+            bytes,
+            append,
+            complete: copied.is_ok(),
+        });
+        match copied {
+            Ok(()) => self.reply(CLOSING_DATA_CONNECTION, "Transfer complete"),
+            Err(_) => self.reply(TRANSFER_ABORTED, "Transfer aborted"),
+        }
+    }
+
+    /// Remove a file (`DELE`) or a directory (`RMD`).
+    fn remove(&mut self, argument: &str, directory: bool) -> std::io::Result<()> {
+        let path = self.resolve(argument);
+        let storage = &self.config.storage;
+        let removed = match storage.metadata(&path) {
+            Ok(info) if info.is_dir == directory => storage.delete(&path),
+            Ok(_) if directory => Err(ErrorKind::NotADirectory.into()),
+            Ok(_) => Err(ErrorKind::IsADirectory.into()),
+            Err(error) => Err(error),
+        };
+        match removed {
+            Ok(()) => {
+                self.audit(AuditAction::Deleted { path });
+                self.reply(FILE_ACTION_OK, "Removed")
+            }
+            Err(error) => self.reply_error(error),
+        }
+    }
+
+    fn mkd(&mut self, argument: &str) -> std::io::Result<()> {
+        let path = self.client_path(argument);
+        let created = self.storage_path(&path);
+        match self.config.storage.mkdir(&created) {
+            Ok(()) => {
+                self.audit(AuditAction::DirectoryCreated { path: created });
+                self.reply(PATH_CREATED, &format!("{} created", path.quoted()))
+            }
+            Err(error) => self.reply_error(error),
+        }
+    }
+
+    fn rnfr(&mut self, argument: &str) -> std::io::Result<()> {
+        let path = self.resolve(argument);
+        match self.config.storage.metadata(&path) {
+            Ok(_) => {
+                self.rename_from = Some(path);
+                self.reply(FILE_ACTION_PENDING, "Ready for RNTO")
+            }
+            Err(error) => self.reply_error(error),
+        }
+    }
+
+    fn rnto(&mut self, argument: &str) -> std::io::Result<()> {
+        let Some(from) = self.rename_from.take() else {
+            return self.reply(BAD_COMMAND_SEQUENCE, "Send RNFR first");
+        };
+        let to = self.resolve(argument);
+        match self.config.storage.rename(&from, &to) {
+            Ok(()) => {
+                self.audit(AuditAction::Renamed { from, to });
+                self.reply(FILE_ACTION_OK, "Renamed")
+            }
+            Err(error) => self.reply_error(error),
+        }
+    }
+
+    fn size(&mut self, argument: &str) -> std::io::Result<()> {
+        match self.config.storage.metadata(&self.resolve(argument)) {
+            Ok(info) if !info.is_dir => self.reply(FILE, &info.size.to_string()),
+            Ok(_) => self.reply(FILE_NOT_AVAILABLE, "Not a regular file"),
+            Err(error) => self.reply_error(error),
+        }
+    }
+
+    fn mdtm(&mut self, argument: &str) -> std::io::Result<()> {
+        match self.config.storage.metadata(&self.resolve(argument)) {
+            Ok(FileInfo {
+                modified: Some(modified),
+                ..
+            }) => self.reply(FILE, &time::format_timestamp(modified)),
+            Ok(_) => self.reply(FILE_NOT_AVAILABLE, "Modification time unknown"),
+            Err(error) => self.reply_error(error),
+        }
+    }
+
+    fn audit(&self, action: AuditAction) {
+        let event = AuditEvent {
+            peer: self.peer,
+            user: self.user.clone().unwrap_or_default(),
+            action,
+        };
+        audit::emit(self.config.audit.as_ref(), event);
+    }
+
+    /// Report a failed storage operation.
+    fn reply_error(&mut self, error: std::io::Error) -> std::io::Result<()> {
+        let text = match error.kind() {
+            ErrorKind::NotFound => "No such file or directory",
+            ErrorKind::PermissionDenied => "Permission denied",
+            ErrorKind::AlreadyExists => "File exists",
+            ErrorKind::IsADirectory => "Is a directory",
+            ErrorKind::NotADirectory => "Not a directory",
+            ErrorKind::DirectoryNotEmpty => "Directory not empty",
+            _ => return self.reply(LOCAL_ERROR, "Local error"),
+        };
+        self.reply(FILE_NOT_AVAILABLE, text)
+    }
+
+    fn reply(&mut self, code: usize, text: &str) -> std::io::Result<()> {
+        write!(self.control.get_mut(), "{} {}\r\n", code, text)
+    }
+
+    /// Send a multi-line reply, the last line closing it.
+    fn reply_lines(&mut self, code: usize, lines: &[&str]) -> std::io::Result<()> {
+        let Some((last, first)) = lines.split_last() else {
+            return Ok(());
+        };
+        let mut text = String::new();
+        for (index, line) in first.iter().enumerate() {
+            if index == 0 {
+                text.push_str(&format!("{}-{}\r\n", code, line));
+            } else {
+                text.push_str(&format!("{}\r\n", line));
+            }
+        }
+        text.push_str(&format!("{} {}\r\n", code, last));
+        self.control.get_mut().write_all(text.as_bytes())
+    }
+}
+
+/// A writer counting the bytes going through it.
+struct Counted<W> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W> Counted<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, bytes: 0 }
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Format of a directory listing.
+#[derive(Clone, Copy)]
+enum Listing {
+    /// `LIST`, like `ls -l`.
+    Long,
+    /// `NLST`, names only.
+    Names,
+    /// `MLSD`, machine readable facts.
+    Facts,
+}
+
+/// Wait for the client to connect to `listener` from `peer`, connections from
+/// other hosts are refused.
+fn accept_data(listener: &TcpListener, peer: IpAddr) -> std::io::Result<TcpStream> {
+    listener.set_nonblocking(true)?;
+    let started = Instant::now();
+    loop {
+        match listener.accept() {
+            Ok((stream, address)) if address.ip() == peer => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Ok(_) => {}
+            Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                if started.elapsed() > DATA_CONNECT_TIMEOUT {
+                    return Err(ErrorKind::TimedOut.into());
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// A `LIST` line: the year replaces the time for files older than six months.
+fn long_line(info: &FileInfo, now: SystemTime) -> String {
+    let modified = info.modified.unwrap_or(SystemTime::UNIX_EPOCH);
+    let (year, month, day, hour, minute, _) = time::to_utc(modified);
+    let recent = now
+        .duration_since(modified)
+        .is_ok_and(|age| age < Duration::from_secs(183 * 86_400));
+    let when = if recent {
+        format!("{:02}:{:02}", hour, minute)
+    } else {
+        format!(" {}", year)
+    };
+    format!(
+        "{} 1 ftp ftp {:>12} {} {:>2} {} {}",
+        if info.is_dir {
+            "drwxr-xr-x"
+        } else {
+            "-rw-r--r--"
+        },
+        info.size,
+        MONTHS[month as usize - 1],
+        day,
+        when,
+        info.name
+    )
+}
+
+/// A `MLSD` line.
+fn facts_line(info: &FileInfo) -> String {
+    let mut line = format!(
+        "type={};size={};",
+        if info.is_dir { "dir" } else { "file" },
+        info.size
+    );
+    if let Some(modified) = info.modified {
+        line.push_str(&format!("modify={};", time::format_timestamp(modified)));
+    }
+    format!("{} {}", line, info.name)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "rustls")]
+    use crate::testing::loopback_certificate;
+    use crate::testing::start_server;
+    use crate::testing::TempRoot;
+    use crate::FtpClient;
+
+    #[test]
+    fn refused_uploads_keep_the_file() {
+        use crate::{AnonymousAccess, FtpServer, LocalStorage};
+        use std::io::{BufRead, BufReader, Write};
+
+        let root = TempRoot::new("stor-first", &[("a.txt", b"keep me")]);
+        let server = FtpServer::new(AnonymousAccess).storage(LocalStorage::new(root.to_path_buf()));
+        let mut control = std::net::TcpStream::connect(start_server(server)).unwrap();
+        let mut replies = BufReader::new(control.try_clone().unwrap());
+        let mut reply = || {
+            let mut line = String::new();
+            replies.read_line(&mut line).unwrap();
+            line
+        };
+        assert!(reply().starts_with("220"));
+        for (command, code) in [
+            ("USER anonymous", "331"),
+            ("PASS guest", "230"),
+            ("STOR a.txt", "425"),
+            ("APPE a.txt", "425"),
+        ] {
+            write!(control, "{}\r\n", command).unwrap();
+            assert!(reply().starts_with(code), "{}", command);
+        }
+        assert_eq!(std::fs::read(root.join("a.txt")).unwrap(), b"keep me");
+    }
+
+    #[cfg(feature = "rustls")]
+    #[test]
+    fn server_drops_stalled_tls_handshakes() {
+        use crate::{AnonymousAccess, FtpServer, ServerTlsConfig};
+        use rustls::pki_types::PrivateKeyDer;
+        use std::io::Read;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let certified = loopback_certificate();
+        let key = PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into());
+        let config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![certified.cert.der().clone()], key)
+            .unwrap();
+        let server = FtpServer::new(AnonymousAccess)
+            .tls(ServerTlsConfig::rustls(Arc::new(config)).implicit(true))
+            .max_connections_per_ip(1)
+            .idle_timeout(Duration::from_millis(200));
+        let address = start_server(server);
+        // connect and never start the handshake
+        let mut stalled = std::net::TcpStream::connect(address).unwrap();
+        stalled
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(stalled.read(&mut [0; 16]).unwrap(), 0);
+        // the slot is given back right after the connection closes
+        std::thread::sleep(Duration::from_millis(100));
+        let mut next = std::net::TcpStream::connect(address).unwrap();
+        next.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(next.read(&mut [0; 16]).is_err());
+    }
+
+    #[test]
+    fn server_confines_read_only_users() {
+        use crate::{FtpServer, LocalStorage, StaticUsers};
+
+        let files: &[(&str, &[u8])] = &[("secret.txt", b"secret"), ("alice/a.txt", b"hello")];
+        let root = TempRoot::new("chroot", files);
+        let server = FtpServer::new(StaticUsers::new().user("alice", "wonderland"))
+            .storage(LocalStorage::new(root.to_path_buf()))
+            .user_root("alice", "/alice")
+            .read_only();
+        let mut client = FtpClient::connect(start_server(server)).unwrap();
+        client.login("alice", "wonderland").unwrap();
+        assert_eq!(client.size("/a.txt").unwrap(), 5);
+        assert!(client.size("/../secret.txt").is_err());
+        client.change_dir("..").unwrap();
+        assert_eq!(client.current_dir().unwrap().as_str(), "/");
+        assert!(client.put("/b.txt", &mut &b"new"[..]).is_err());
+        assert!(client.delete("/a.txt").is_err());
+        assert!(client.makedir("/docs").is_err());
+        assert!(root.join("alice/a.txt").exists());
+    }
+}
@@ -0,0 +1,244 @@
+//! Files served by the embedded server.
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::RemotePath;
+
+/// Description of a file or directory of a [`StorageBackend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// The files behind the embedded server, e.g. a local directory, data kept in
+/// memory, an object store or a read-only view of another backend.
+///
+/// Paths are absolute and normalized, without `.` or `..` components, so a
+/// backend never sees a path outside of its root. Errors are reported to
+/// clients, `NotFound` and `PermissionDenied` as such and others as local errors.
+pub trait StorageBackend: Send + Sync {
+    /// Open a file for reading.
+    fn open_read(&self, path: &RemotePath) -> std::io::Result<Box<dyn Read + Send>>;
+
+    /// Create or truncate a file, or append to it, for writing.
+    fn open_write(&self, path: &RemotePath, append: bool)
+        -> std::io::Result<Box<dyn Write + Send>>;
+
+    /// Open an existing file for writing from `offset` on, keeping the rest
+    /// of its content, for a `STOR` after `REST`. Backends unable to do so
+    /// refuse with `Unsupported`.
+    fn open_write_at(
+        &self,
+        path: &RemotePath,
+        offset: u64,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
+        let _ = (path, offset);
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    /// The content of a directory.
+    fn list(&self, path: &RemotePath) -> std::io::Result<Vec<FileInfo>>;
+
+    /// Create a directory.
+    fn mkdir(&self, path: &RemotePath) -> std::io::Result<()>;
+
+    /// Remove a file or an empty directory.
+    fn delete(&self, path: &RemotePath) -> std::io::Result<()>;
+
+    /// Move a file or directory.
+    fn rename(&self, from: &RemotePath, to: &RemotePath) -> std::io::Result<()>;
+
+    /// Describe a file or directory.
+    fn metadata(&self, path: &RemotePath) -> std::io::Result<FileInfo>;
+}
+
+/// Serve the content of a local directory.
+///
+/// Symbolic links inside the directory are followed, even when they point
+/// outside of it.
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::{AnonymousAccess, FtpServer, LocalStorage};
+///
+/// FtpServer::new(AnonymousAccess)
+///     .storage(LocalStorage::new("/srv/ftp"))
+///     .listen("0.0.0.0:21")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn local(&self, path: &RemotePath) -> std::io::Result<PathBuf> {
+        path.strip_prefix(&RemotePath::root())
+            .and_then(|relative| relative.to_local(&self.root).ok())
+            .ok_or_else(|| std::io::Error::from(ErrorKind::PermissionDenied))
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn open_read(&self, path: &RemotePath) -> std::io::Result<Box<dyn Read + Send>> {
+        let file = File::open(self.local(path)?)?;
+        if file.metadata()?.is_dir() {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        Ok(Box::new(file))
+    }
+
+    fn open_write(
+        &self,
+        path: &RemotePath,
+        append: bool,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(self.local(path)?)?;
+        Ok(Box::new(file))
+    }
+
+    fn open_write_at(
+        &self,
+        path: &RemotePath,
+        offset: u64,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
+        let mut file = OpenOptions::new().write(true).open(self.local(path)?)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Box::new(file))
+    }
+
+    fn list(&self, path: &RemotePath) -> std::io::Result<Vec<FileInfo>> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(self.local(path)?)? {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            // follow links like the other operations do
+            let metadata = std::fs::metadata(entry.path())?;
+            entries.push(FileInfo {
+                name,
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn mkdir(&self, path: &RemotePath) -> std::io::Result<()> {
+        std::fs::create_dir(self.local(path)?)
+    }
+
+    fn delete(&self, path: &RemotePath) -> std::io::Result<()> {
+        let local = self.local(path)?;
+        if std::fs::symlink_metadata(&local)?.is_dir() {
+            std::fs::remove_dir(local)
+        } else {
+            std::fs::remove_file(local)
+        }
+    }
+
+    fn rename(&self, from: &RemotePath, to: &RemotePath) -> std::io::Result<()> {
+        std::fs::rename(self.local(from)?, self.local(to)?)
+    }
+
+    fn metadata(&self, path: &RemotePath) -> std::io::Result<FileInfo> {
+        let metadata = std::fs::metadata(self.local(path)?)?;
+        Ok(FileInfo {
+            name: path.file_name().unwrap_or("/").to_string(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// Storage of a server configured without any, holding an empty root.
+pub(crate) struct EmptyStorage;
+
+impl StorageBackend for EmptyStorage {
+    fn open_read(&self, _path: &RemotePath) -> std::io::Result<Box<dyn Read + Send>> {
+        Err(ErrorKind::NotFound.into())
+    }
+
+    fn open_write(
+        &self,
+        _path: &RemotePath,
+        _append: bool,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
+        Err(ErrorKind::PermissionDenied.into())
+    }
+
+    fn list(&self, path: &RemotePath) -> std::io::Result<Vec<FileInfo>> {
+        self.metadata(path).map(|_| Vec::new())
+    }
+
+    fn mkdir(&self, _path: &RemotePath) -> std::io::Result<()> {
+        Err(ErrorKind::PermissionDenied.into())
+    }
+
+    fn delete(&self, _path: &RemotePath) -> std::io::Result<()> {
+        Err(ErrorKind::PermissionDenied.into())
+    }
+
+    fn rename(&self, _from: &RemotePath, _to: &RemotePath) -> std::io::Result<()> {
+        Err(ErrorKind::PermissionDenied.into())
+    }
+
+    fn metadata(&self, path: &RemotePath) -> std::io::Result<FileInfo> {
+        if path.components().next().is_some() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok(FileInfo {
+            name: "/".into(),
+            is_dir: true,
+            size: 0,
+            modified: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::served_client;
+
+    #[test]
+    fn server_stores_files() {
+        let (mut client, root) = served_client("server", &[]);
+        client.makedir("/docs").unwrap();
+        client.put("/docs/a.txt", &mut &b"hello"[..]).unwrap();
+        assert_eq!(std::fs::read(root.join("docs/a.txt")).unwrap(), b"hello");
+        let entries = client.list_entries("/docs").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].name(), entries[0].size()), ("a.txt", Some(5)));
+        let mut content = Vec::new();
+        client.get("/docs/a.txt", &mut content).unwrap();
+        assert_eq!(content, b"hello");
+        assert!(client.size("/../docs/a.txt").is_ok());
+        client.delete("/docs/a.txt").unwrap();
+        client.remove_dir("/docs").unwrap();
+        client.logout().unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+//! A client shared between threads.
+
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use crate::FtpClient;
+use crate::FtpError;
+use crate::Result;
+
+type Connector = Box<dyn Fn() -> Result<FtpClient> + Send + Sync>;
+
+struct Shared {
+    client: Mutex<Option<FtpClient>>,
+    connect: Connector,
+}
+
+/// One FTP connection shared by several threads, e.g. the handlers of a web server.
+///
+/// Commands of different threads are serialized. The session connects again
+/// with its connect function whenever the connection was lost or a thread
+/// panicked while using it, since the state of the connection is unknown then.
+/// Operations are only repeated on a new connection when asked for with
+/// [`FtpSession::run_idempotent`]. Clones share the same connection.
+///
+/// The session has no copy of the client's methods: they are called on the
+/// [`SessionGuard`] returned by [`FtpSession::lock`], which dereferences to
+/// the [`FtpClient`], or in the closures given to [`FtpSession::run`].
+///
+/// # Example
+/// ```no_run
+/// use simpleftp::{FtpClient, FtpSession};
+///
+/// let session = FtpSession::new(|| {
+///     let mut client = FtpClient::connect("127.0.0.1:21")?;
+///     client.login("user", "password")?;
+///     Ok(client)
+/// })
+/// .unwrap();
+///
+/// let handler = session.clone();
+/// std::thread::spawn(move || {
+///     let size = handler
+///         .run_idempotent(|client| client.size("/pub/file.txt"))
+///         .unwrap();
+///     println!("{} bytes", size);
+/// });
+/// session.lock().unwrap().noop().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct FtpSession {
+    shared: Arc<Shared>,
+}
+
+impl FtpSession {
+    /// Connect with `connect`, which must return a logged in client. It is
+    /// called again to recover the connection.
+    ///
+    /// # Errors
+    /// When the first connection fails.
+    pub fn new<F>(connect: F) -> Result<Self>
+    where
+        F: Fn() -> Result<FtpClient> + Send + Sync + 'static,
+    {
+        let client = connect()?;
+        Ok(Self {
+            shared: Arc::new(Shared {
+                client: Mutex::new(Some(client)),
+                connect: Box::new(connect),
+            }),
+        })
+    }
+
+    /// Get exclusive use of the client, waiting for other threads to release it.
+    /// The client is connected again first when the previous user panicked or the
+    /// server closed the connection.
+    ///
+    /// # Errors
+    /// When connecting again fails.
+    pub fn lock(&self) -> Result<SessionGuard<'_>> {
+        let mut guard = self.shared.client.lock().unwrap_or_else(|poisoned| {
+            self.shared.client.clear_poison();
+            let mut guard = poisoned.into_inner();
+            *guard = None;
+            guard
+        });
+        if guard.as_ref().is_none_or(|client| client.disconnected) {
+            *guard = None;
+            *guard = Some((self.shared.connect)()?);
+        }
+        Ok(SessionGuard {
+            guard,
+            connect: &self.shared.connect,
+        })
+    }
+
+    /// Run `operation` on the client. When it fails on a connection error or
+    /// an expired session the connection is dropped, so the next use of the
+    /// session connects again, and the error is returned: `operation` may have
+    /// partly taken effect, so it isn't run again. See
+    /// [`FtpSession::run_idempotent`] for operations that are safe to repeat.
+    ///
+    /// # Errors
+    /// Errors from `operation`.
+    pub fn run<T, F>(&self, operation: F) -> Result<T>
+    where
+        F: FnOnce(&mut FtpClient) -> Result<T>,
+    {
+        let mut guard = self.lock()?;
+        let result = operation(&mut guard);
+        if let Err(FtpError::ConnectionError(_) | FtpError::SessionExpired(_)) = result {
+            *guard.guard = None;
+        }
+        result
+    }
+
+    /// Run `operation` on the client, and when it fails on a connection error
+    /// or an expired session connect again and run it once more.
+    ///
+    /// `operation` must be idempotent: running it twice must have the same
+    /// effect as running it once, as listings, downloads, `SIZE` or
+    /// overwriting uploads do. Appends, renames and deletions are not, the
+    /// first attempt may have reached the server before the connection broke.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, FtpSession};
+    /// # let session = FtpSession::new(|| FtpClient::connect("127.0.0.1:21")).unwrap();
+    /// let files = session.run_idempotent(|client| client.list("/pub")).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Errors from `operation` or from connecting again.
+    pub fn run_idempotent<T, F>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut(&mut FtpClient) -> Result<T>,
+    {
+        let mut guard = self.lock()?;
+        match operation(&mut guard) {
+            Err(FtpError::ConnectionError(_) | FtpError::SessionExpired(_)) => {
+                guard.reconnect()?;
+                operation(&mut guard)
+            }
+            result => result,
+        }
+    }
+
+    /// Drop the current connection and connect again.
+    ///
+    /// # Errors
+    /// When connecting fails, the next use of the session tries again.
+    pub fn reconnect(&self) -> Result<()> {
+        self.lock()?.reconnect()
+    }
+}
+
+/// Exclusive access to the client of a [`FtpSession`], released when dropped.
+/// Dereferences to the [`FtpClient`].
+pub struct SessionGuard<'a> {
+    guard: MutexGuard<'a, Option<FtpClient>>,
+    connect: &'a Connector,
+}
+
+impl SessionGuard<'_> {
+    fn reconnect(&mut self) -> Result<()> {
+        // a failed attempt leaves no client, the next lock tries again
+        *self.guard = None;
+        *self.guard = Some((self.connect)()?);
+        Ok(())
+    }
+}
+
+impl Deref for SessionGuard<'_> {
+    type Target = FtpClient;
+
+    fn deref(&self) -> &FtpClient {
+        self.guard.as_ref().expect("session guard without client")
+    }
+}
+
+impl DerefMut for SessionGuard<'_> {
+    fn deref_mut(&mut self) -> &mut FtpClient {
+        self.guard.as_mut().expect("session guard without client")
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::testing::start_server;
+    use crate::testing::TempRoot;
+    use crate::FtpClient;
+    use crate::FtpError;
+
+    #[test]
+    fn sessions_repeat_only_idempotent_operations() {
+        use super::FtpSession;
+        use crate::{AnonymousAccess, FtpServer, LocalStorage};
+        use std::time::Duration;
+
+        let root = TempRoot::new("session", &[]);
+        let server = FtpServer::new(AnonymousAccess)
+            .storage(LocalStorage::new(root.to_path_buf()))
+            .idle_timeout(Duration::from_millis(200));
+        let address = start_server(server);
+        let session = FtpSession::new(move || {
+            let mut client = FtpClient::connect(address)?;
+            client.login("anonymous", "")?;
+            Ok(client)
+        })
+        .unwrap();
+        let idle = || std::thread::sleep(Duration::from_millis(400));
+
+        idle();
+        let mut attempts = 0;
+        let result = session.run(|client| {
+            attempts += 1;
+            client.append("/app.log", &mut &b"one\n"[..])
+        });
+        assert!(matches!(result, Err(FtpError::SessionExpired(_))));
+        assert_eq!(attempts, 1);
+        // the next use connects again
+        session
+            .run(|client| client.append("/app.log", &mut &b"two\n"[..]))
+            .unwrap();
+        assert_eq!(std::fs::read(root.join("app.log")).unwrap(), b"two\n");
+
+        idle();
+        let mut attempts = 0;
+        let size = session.run_idempotent(|client| {
+            attempts += 1;
+            client.size("/app.log")
+        });
+        assert_eq!((size.unwrap(), attempts), (4, 2));
+
+        // a connection lost while locked is replaced on the next lock
+        idle();
+        assert!(session.lock().unwrap().noop().is_err());
+        session.lock().unwrap().noop().unwrap();
+    }
+
+    #[test]
+    fn sessions_are_shared_and_recovered() {
+        use super::FtpSession;
+        use crate::{AnonymousAccess, FtpServer, Next};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let address = start_server(FtpServer::new(AnonymousAccess));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let opened = connections.clone();
+        let session = FtpSession::new(move || {
+            opened.fetch_add(1, Ordering::Relaxed);
+            let mut client = FtpClient::connect(address)?;
+            client.login("anonymous", "")?;
+            Ok(client)
+        })
+        .unwrap();
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let session = session.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..5 {
+                        session.run(|client| client.noop()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+
+        // the state of the connection is unknown after a panic
+        let panicking = session.clone();
+        let panicked = std::thread::spawn(move || {
+            let _guard = panicking.lock().unwrap();
+            panic!("handler failed");
+        })
+        .join();
+        assert!(panicked.is_err());
+        session.lock().unwrap().noop().unwrap();
+        assert_eq!(connections.load(Ordering::Relaxed), 2);
+
+        // the connection breaks during the first attempt only
+        session
+            .lock()
+            .unwrap()
+            .add_layer(|command: &str, next: &mut Next| {
+                if command.starts_with("NOOP") {
+                    return Err(FtpError::ConnectionError("connection reset".into()));
+                }
+                next.run(command)
+            });
+        let mut attempts = 0;
+        session
+            .run_idempotent(|client| {
+                attempts += 1;
+                client.noop()
+            })
+            .unwrap();
+        assert_eq!((attempts, connections.load(Ordering::Relaxed)), (2, 3));
+        // and is retried once
+        let mut attempts = 0;
+        let result: crate::Result<()> = session.run_idempotent(|_| {
+            attempts += 1;
+            Err(FtpError::ConnectionError("connection reset".into()))
+        });
+        assert!(matches!(result, Err(FtpError::ConnectionError(_))));
+        assert_eq!((attempts, connections.load(Ordering::Relaxed)), (2, 4));
+    }
+}
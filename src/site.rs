@@ -0,0 +1,121 @@
+//! Discovery of the `SITE` subcommands a server implements.
+
+use std::collections::BTreeSet;
+
+use crate::status::reply_lines;
+use crate::Command;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::Response;
+use crate::Result;
+
+impl FtpClient {
+    /// Names of the `SITE` subcommands the server implements, e.g. `CHMOD`,
+    /// `UTIME` or `CPFR`, in upper case, to use helpers such as
+    /// [`FtpClient::site_chmod`] only where they work. Asked with
+    /// `SITE HELP`, or `HELP SITE` when the server refuses it. Subcommands
+    /// listed as unimplemented, marked with `*`, are left out.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, FtpPermissions};
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// if client.site_help().unwrap().contains("CHMOD") {
+    ///     client.site_chmod("/www/index.html", FtpPermissions::from_mode(0o644)).unwrap();
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// Errors on connection failure, or [`FtpError::Unsupported`] when the
+    /// server answers neither.
+    pub fn site_help(&mut self) -> Result<BTreeSet<String>> {
+        let response = self.write_cmd(Command::Site("HELP"))?;
+        // help comes with 214 rather than the 200 of other SITE commands
+        let response = if (200..300).contains(&response.code) {
+            response
+        } else {
+            self.expect(Command::Help("SITE"), FtpError::CommandError)?
+        };
+        Ok(site_commands(&response))
+    }
+}
+
+/// Subcommands listed by a help reply, such as
+///
+/// ```text
+/// 214-The following SITE commands are recognized (* =>'s unimplemented).
+/// 214-CHMOD <sp> mode <sp> filename
+///    UMASK           IDLE            HELP            EXEC*
+/// 214 Direct comments to root@localhost
+/// ```
+///
+/// or `214 CHMOD UMASK HELP`: the words in upper case, but those of the
+/// heading of a multi-line reply and `SITE` itself, as in
+/// `214 Syntax: SITE <sp> string`.
+pub(crate) fn site_commands(response: &Response) -> BTreeSet<String> {
+    let lines = reply_lines(response);
+    let listed = if lines.len() > 1 { &lines[1..] } else { lines };
+    listed
+        .iter()
+        .flat_map(|line| line.split_whitespace())
+        .filter(|word| word.len() > 1 && word.bytes().all(|byte| byte.is_ascii_uppercase()))
+        .filter(|word| *word != "SITE")
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "server")]
+    use crate::testing::start_server;
+    #[cfg(feature = "server")]
+    use crate::FtpClient;
+
+    #[test]
+    fn site_help_lists_subcommands() {
+        use super::site_commands;
+        use crate::parse_reply;
+
+        let reply = parse_reply(
+            "214-The following SITE commands are recognized (* =>'s unimplemented).\r\n\
+             214-CHMOD <sp> mode <sp> filename\r\n   \
+             UMASK           IDLE            HELP            EXEC*\r\n\
+             214 Direct comments to root@localhost\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            site_commands(&reply).into_iter().collect::<Vec<_>>(),
+            ["CHMOD", "HELP", "IDLE", "UMASK"]
+        );
+        let reply = parse_reply("214 CHMOD UMASK HELP\r\n").unwrap();
+        assert_eq!(site_commands(&reply).len(), 3);
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn site_help_falls_back_on_help_site() {
+        use crate::{AnonymousAccess, FtpError, FtpServer, Next, Response};
+
+        let address = start_server(FtpServer::new(AnonymousAccess));
+        let mut client = FtpClient::connect(address).unwrap();
+        client.login("anonymous", "").unwrap();
+        // the embedded server knows neither
+        assert!(matches!(client.site_help(), Err(FtpError::Unsupported(_))));
+        client.add_layer(|command: &str, next: &mut Next| match command {
+            "SITE HELP" => Ok(Response::new(500, " Unknown SITE command\r\n")),
+            "HELP SITE" => Ok(Response::new(214, " Syntax: SITE <sp> string\r\n")),
+            _ => next.run(command),
+        });
+        assert!(client.site_help().unwrap().is_empty());
+
+        let mut client = FtpClient::connect(address).unwrap();
+        client.login("anonymous", "").unwrap();
+        client.add_layer(|command: &str, next: &mut Next| match command {
+            "SITE HELP" => Ok(Response::new(214, " CHMOD UTIME CPFR CPTO\r\n")),
+            _ => next.run(command),
+        });
+        let commands = client.site_help().unwrap();
+        assert!(commands.contains("UTIME") && commands.contains("CPFR"));
+    }
+}
@@ -0,0 +1,437 @@
+//! Point in time views of remote trees and the changes between them.
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::SystemTime;
+
+use crate::EntryKind;
+use crate::FtpClient;
+use crate::FtpEntry;
+use crate::FtpError;
+use crate::RemotePath;
+use crate::Result;
+use crate::WalkOptions;
+
+/// What a snapshot records of each entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotEntry {
+    pub kind: EntryKind,
+    /// Size in bytes, when the listing tells it.
+    pub size: Option<u64>,
+    /// Last modification, when the listing tells it.
+    pub modified: Option<SystemTime>,
+}
+
+impl SnapshotEntry {
+    /// Whether `other` holds other content, judged by the facts both know.
+    /// The times of directories are ignored: they change with their content,
+    /// which is compared entry by entry.
+    pub fn differs_from(&self, other: &SnapshotEntry) -> bool {
+        if self.kind != other.kind {
+            return true;
+        }
+        self.kind != EntryKind::Directory
+            && (known_differ(self.size, other.size) || known_differ(self.modified, other.modified))
+    }
+}
+
+/// Whether both facts are known and differ.
+fn known_differ<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+    a.zip(b).is_some_and(|(a, b)| a != b)
+}
+
+impl From<&FtpEntry> for SnapshotEntry {
+    fn from(entry: &FtpEntry) -> Self {
+        Self {
+            kind: entry.kind(),
+            size: entry.size(),
+            modified: entry.modified(),
+        }
+    }
+}
+
+/// The entries of a remote tree, by path relative to its root, as listed by
+/// [`FtpClient::snapshot`].
+///
+/// With the `serde` feature, snapshots can be saved between runs and diffed
+/// against a fresh one to find what changed meanwhile. That is only as
+/// reliable as the sizes and times the server lists: coarse `LIST` dates
+/// hide changes within the same minute, where `MLSD` facts don't.
+///
+/// # Example
+/// ```no_run
+/// # #[cfg(feature = "serde")] {
+/// use simpleftp::{FtpClient, RemoteSnapshot};
+///
+/// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+/// client.login("user", "password").unwrap();
+/// let now = client.snapshot("/inbox").unwrap();
+/// if let Ok(saved) = std::fs::read("inbox.json") {
+///     let before: RemoteSnapshot = serde_json::from_slice(&saved).unwrap();
+///     println!("{:?}", before.diff(&now));
+/// }
+/// std::fs::write("inbox.json", serde_json::to_vec(&now).unwrap()).unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RemoteSnapshot {
+    root: RemotePath,
+    entries: BTreeMap<RemotePath, SnapshotEntry>,
+}
+
+impl RemoteSnapshot {
+    /// A snapshot of `root` holding no entries.
+    pub fn new(root: impl Into<String>) -> Self {
+        Self {
+            root: RemotePath::new(root),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// The directory the snapshot was taken of.
+    pub fn root(&self) -> &RemotePath {
+        &self.root
+    }
+
+    /// Record `entry` at `path`, relative to the root, replacing any entry
+    /// already there.
+    pub fn insert(&mut self, path: RemotePath, entry: SnapshotEntry) {
+        self.entries.insert(path, entry);
+    }
+
+    /// The entry at `path`, relative to the root.
+    pub fn get(&self, path: &RemotePath) -> Option<&SnapshotEntry> {
+        self.entries.get(path)
+    }
+
+    /// The entries, by relative path in sorted order.
+    pub fn iter(&self) -> impl Iterator<Item = (&RemotePath, &SnapshotEntry)> {
+        self.entries.iter()
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the tree was empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The changes turning this snapshot into `newer`. Entries whose kind,
+    /// size or modification time differ are modified, see
+    /// [`SnapshotEntry::differs_from`]; facts missing from either snapshot
+    /// aren't compared.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let before = client.snapshot("/inbox").unwrap();
+    /// std::thread::sleep(std::time::Duration::from_secs(60));
+    /// let changes = before.diff(&client.snapshot("/inbox").unwrap());
+    /// for path in &changes.added {
+    ///     println!("new file: {}", path);
+    /// }
+    /// ```
+    pub fn diff(&self, newer: &RemoteSnapshot) -> ChangeSet {
+        let mut changes = ChangeSet::default();
+        for (path, entry) in &self.entries {
+            match newer.entries.get(path) {
+                None => changes.removed.push(path.clone()),
+                Some(now) if entry.differs_from(now) => changes.modified.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+        changes.added = newer
+            .entries
+            .keys()
+            .filter(|path| !self.entries.contains_key(*path))
+            .cloned()
+            .collect();
+        changes
+    }
+}
+
+/// Differences between two snapshots, see [`RemoteSnapshot::diff`]. Paths are
+/// relative to the snapshot root, in sorted order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChangeSet {
+    pub added: Vec<RemotePath>,
+    pub removed: Vec<RemotePath>,
+    pub modified: Vec<RemotePath>,
+}
+
+impl ChangeSet {
+    /// Whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl FtpClient {
+    /// Walk the tree below `dir` and record the kind, size and modification
+    /// time of each entry, to be compared with a later snapshot.
+    ///
+    /// # Errors
+    /// Errors on connection failure or when a directory can't be listed.
+    pub fn snapshot(&mut self, dir: impl AsRef<str>) -> Result<RemoteSnapshot> {
+        self.snapshot_with(dir, &WalkOptions::new())
+    }
+
+    /// Take a snapshot like [`FtpClient::snapshot`], walking with `options`.
+    ///
+    /// # Errors
+    /// Errors on connection failure or when a directory can't be listed.
+    pub fn snapshot_with(
+        &mut self,
+        dir: impl AsRef<str>,
+        options: &WalkOptions,
+    ) -> Result<RemoteSnapshot> {
+        let mut snapshot = RemoteSnapshot::new(dir.as_ref());
+        for found in self.walk(dir.as_ref(), options)? {
+            if let Some(path) = found.path.strip_prefix(&snapshot.root) {
+                snapshot.insert(path, SnapshotEntry::from(&found.entry));
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Take a snapshot of `dir` listing up to `connections` directories at
+    /// once: this client and the others opened with `connect`, which must
+    /// return a logged in client. Symbolic links are reported, not followed.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let connect = || {
+    ///     let mut client = FtpClient::connect("127.0.0.1:21")?;
+    ///     client.login("user", "password")?;
+    ///     Ok(client)
+    /// };
+    /// let mut client = connect().unwrap();
+    /// let snapshot = client.scan("/archive", 8, connect).unwrap();
+    /// println!("{} entries", snapshot.len());
+    /// ```
+    ///
+    /// # Errors
+    /// On the first connection failure, failed `connect` or directory that
+    /// can't be listed, once the listings in progress are over.
+    pub fn scan<F>(
+        &mut self,
+        dir: impl AsRef<str>,
+        connections: usize,
+        connect: F,
+    ) -> Result<RemoteSnapshot>
+    where
+        F: Fn() -> Result<FtpClient> + Sync,
+    {
+        let root = RemotePath::new(dir.as_ref());
+        let scan = Scan {
+            state: Mutex::new(ScanState {
+                pending: VecDeque::from([root.clone()]),
+                busy: 0,
+                failure: None,
+                snapshot: RemoteSnapshot::new(dir.as_ref()),
+            }),
+            ready: Condvar::new(),
+        };
+        std::thread::scope(|scope| {
+            for _ in 1..connections {
+                let (scan, connect) = (&scan, &connect);
+                scope.spawn(move || match connect() {
+                    Ok(mut client) => {
+                        client.scan_dirs(scan);
+                        let _ = client.logout();
+                    }
+                    Err(error) => scan.fail(error),
+                });
+            }
+            self.scan_dirs(&scan);
+        });
+        let state = scan
+            .state
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        match state.failure {
+            Some(error) => Err(error),
+            None => Ok(state.snapshot),
+        }
+    }
+
+    /// List directories of `scan` until none are left or one failed.
+    fn scan_dirs(&mut self, scan: &Scan) {
+        let mut use_mlsd = !self.config.disable_mlsd;
+        while let Some(dir) = scan.take() {
+            let listing = self.walk_list(&dir, &mut use_mlsd);
+            let mut state = scan.lock();
+            state.busy -= 1;
+            match listing {
+                Ok(entries) => {
+                    for entry in entries {
+                        if entry.name() == "." || entry.name() == ".." {
+                            continue;
+                        }
+                        let path = dir.join(entry.name());
+                        if entry.is_dir() {
+                            state.pending.push_back(path.clone());
+                        }
+                        if let Some(relative) = path.strip_prefix(&state.snapshot.root) {
+                            state.snapshot.insert(relative, SnapshotEntry::from(&entry));
+                        }
+                    }
+                }
+                Err(error) => {
+                    state.failure.get_or_insert(error);
+                }
+            }
+            scan.ready.notify_all();
+        }
+    }
+}
+
+/// Directories shared by the connections of [`FtpClient::scan`].
+struct Scan {
+    state: Mutex<ScanState>,
+    /// Signalled when directories are queued or the scan ends.
+    ready: Condvar,
+}
+
+struct ScanState {
+    pending: VecDeque<RemotePath>,
+    /// Directories being listed, which may queue more.
+    busy: usize,
+    failure: Option<FtpError>,
+    snapshot: RemoteSnapshot,
+}
+
+impl Scan {
+    fn lock(&self) -> std::sync::MutexGuard<'_, ScanState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The next directory to list, waiting while others may still queue
+    /// some. `None` once the scan is over.
+    fn take(&self) -> Option<RemotePath> {
+        let mut state = self.lock();
+        loop {
+            if state.failure.is_some() {
+                return None;
+            }
+            if let Some(dir) = state.pending.pop_front() {
+                state.busy += 1;
+                return Some(dir);
+            }
+            if state.busy == 0 {
+                return None;
+            }
+            state = self
+                .ready
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// End the scan with `error`.
+    fn fail(&self, error: FtpError) {
+        self.lock().failure.get_or_insert(error);
+        self.ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "server")]
+    use crate::testing::serve;
+    #[cfg(feature = "server")]
+    use crate::testing::served_client;
+    #[cfg(feature = "server")]
+    use crate::testing::TempRoot;
+    #[cfg(feature = "server")]
+    use crate::FtpClient;
+    #[cfg(feature = "server")]
+    use crate::RemotePath;
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn scans_list_over_several_connections() {
+        let root = TempRoot::new("scan", &[]);
+        for dir in 0..6 {
+            for sub in 0..3 {
+                let path = root.join(format!("tree/d{}/s{}", dir, sub));
+                std::fs::create_dir_all(&path).unwrap();
+                std::fs::write(path.join("f.txt"), vec![b'x'; dir * 10 + sub]).unwrap();
+            }
+        }
+        let address = serve(&root);
+        let connect = || {
+            let mut client = FtpClient::connect(address)?;
+            client.login("anonymous", "")?;
+            Ok(client)
+        };
+        let mut client = connect().unwrap();
+        let scanned = client.scan("/tree", 4, connect).unwrap();
+        assert_eq!(scanned.len(), 6 + 6 * 3 * 2);
+        assert_eq!(scanned, client.snapshot("/tree").unwrap());
+        assert!(client.scan("/missing", 4, connect).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshots_survive_serialization() {
+        use super::{RemoteSnapshot, SnapshotEntry};
+        use crate::{EntryKind, RemotePath};
+        use std::time::{Duration, SystemTime};
+
+        let mut snapshot = RemoteSnapshot::new("/inbox");
+        let entry = SnapshotEntry {
+            kind: EntryKind::File,
+            size: Some(42),
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+        };
+        snapshot.insert(RemotePath::new("a.txt"), entry);
+        snapshot.insert(
+            RemotePath::new("old"),
+            SnapshotEntry {
+                kind: EntryKind::Directory,
+                size: None,
+                modified: None,
+            },
+        );
+        let saved = serde_json::to_string(&snapshot).unwrap();
+        let loaded: RemoteSnapshot = serde_json::from_str(&saved).unwrap();
+        assert_eq!(loaded, snapshot);
+        assert_eq!(loaded.root(), &RemotePath::new("/inbox"));
+        assert!(loaded.diff(&snapshot).is_empty());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn snapshots_are_diffed() {
+        let files: &[(&str, &[u8])] = &[
+            ("inbox/a.txt", b"a"),
+            ("inbox/old/b.txt", b"b"),
+            ("inbox/c.txt", b"c"),
+        ];
+        let (mut client, root) = served_client("snapshot", files);
+        let before = client.snapshot("/inbox").unwrap();
+        assert_eq!(before.len(), 4);
+        assert!(before.diff(&before).is_empty());
+
+        std::fs::write(root.join("inbox/a.txt"), b"longer").unwrap();
+        std::fs::remove_file(root.join("inbox/old/b.txt")).unwrap();
+        std::fs::write(root.join("inbox/d.txt"), b"d").unwrap();
+        let changes = before.diff(&client.snapshot("/inbox").unwrap());
+        assert_eq!(changes.added, vec![RemotePath::new("d.txt")]);
+        assert_eq!(changes.removed, vec![RemotePath::new("old/b.txt")]);
+        assert_eq!(changes.modified, vec![RemotePath::new("a.txt")]);
+    }
+}
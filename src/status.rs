@@ -0,0 +1,280 @@
+//! Status replies sent over the control connection with `STAT`.
+
+use std::time::Duration;
+
+use crate::Command;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::Response;
+use crate::Result;
+use crate::SYSTEM;
+
+/// Session status reported by the server for a bare `STAT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionStatus {
+    summary: String,
+    lines: Vec<String>,
+}
+
+impl SessionStatus {
+    /// Text of the first reply line, e.g. `FTP server status:`.
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+
+    /// Status lines between the first and the last reply line, trimmed.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Value of a `key: value` line, with `key` compared case-insensitively.
+    ///
+    /// # Example
+    /// ```
+    /// # use simpleftp::{Response, SessionStatus};
+    /// let reply = "-FTP server status:\r\n     TYPE: BINARY\r\n     Logged in as ftp\r\n211 End\r\n";
+    /// let status = SessionStatus::from_reply(&Response::new(211, reply));
+    /// assert_eq!(status.value("type"), Some("BINARY"));
+    /// assert_eq!(status.lines().len(), 2);
+    /// ```
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case(key)
+                .then_some(value.trim())
+        })
+    }
+
+    /// Idle timeout of the session when the server tells it, e.g. vsftpd's
+    /// `Session timeout in seconds is 300`.
+    ///
+    /// # Example
+    /// ```
+    /// # use simpleftp::{Response, SessionStatus};
+    /// # use std::time::Duration;
+    /// let reply = "-FTP server status:\r\n     Session timeout in seconds is 300\r\n211 End\r\n";
+    /// let status = SessionStatus::from_reply(&Response::new(211, reply));
+    /// assert_eq!(status.idle_timeout(), Some(Duration::from_secs(300)));
+    /// ```
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.lines.iter().find_map(|line| idle_timeout(line))
+    }
+
+    /// Parse the reply to a bare `STAT`.
+    pub fn from_reply(response: &Response) -> Self {
+        let lines = reply_lines(response);
+        Self {
+            summary: lines.first().cloned().unwrap_or_default(),
+            lines: lines
+                .iter()
+                .skip(1)
+                .filter(|line| !line.is_empty())
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Lines of a reply without the closing `code text` line of multi-line replies.
+pub(crate) fn reply_lines(response: &Response) -> &[String] {
+    match response.line_codes() {
+        [_, .., Some(last)] if *last == response.code() => {
+            &response.lines()[..response.lines().len() - 1]
+        }
+        _ => response.lines(),
+    }
+}
+
+/// Idle timeout stated by a line such as `Session timeout in seconds is 300`
+/// or `Current IDLE time limit is 900 seconds; max 7200`. The first number of
+/// the line is taken, in the unit of the word right after it, else in minutes
+/// if the line spoke of them before the number, else in seconds.
+fn idle_timeout(line: &str) -> Option<Duration> {
+    let lower = line.to_ascii_lowercase();
+    if !lower.contains("timeout") && !lower.contains("idle") {
+        return None;
+    }
+    let start = lower.find(|c: char| c.is_ascii_digit())?;
+    let end = lower[start..]
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(lower.len(), |length| start + length);
+    let value: u64 = lower[start..end].parse().ok()?;
+    let unit = lower[end..]
+        .trim_start()
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .next()
+        .unwrap_or_default();
+    let minutes = match unit {
+        "m" | "min" | "mins" | "minute" | "minutes" => true,
+        "s" | "sec" | "secs" | "second" | "seconds" => false,
+        _ => lower[..start].contains("minute"),
+    };
+    if minutes {
+        return Some(Duration::from_secs(value.saturating_mul(60)));
+    }
+    Some(Duration::from_secs(value))
+}
+
+impl FtpClient {
+    /// Get the status of the session with a bare `STAT`: connection details,
+    /// transfer settings and counters, depending on the server.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// for line in client.status_session().unwrap().lines() {
+    ///     println!("{}", line);
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// When the server refuses STAT or on connection failure.
+    pub fn status_session(&mut self) -> Result<SessionStatus> {
+        let response = self.write_cmd(Command::Stat(""))?;
+        match response.code {
+            SYSTEM => Ok(SessionStatus::from_reply(&response)),
+            _other => Err(self.refused(FtpError::CommandError(format!(
+                "Invalid response {}",
+                response.message
+            )))),
+        }
+    }
+
+    /// Ask the server to drop the session after `secs` seconds without commands,
+    /// with the `SITE IDLE` command of wu-ftpd and ProFTPD.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::FtpClient;
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// client.set_idle_timeout(1800).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When the server lacks `SITE IDLE` or refuses the value, e.g. above its maximum.
+    pub fn set_idle_timeout(&mut self, secs: u64) -> Result<()> {
+        self.expect(
+            Command::Site(&format!("IDLE {}", secs)),
+            FtpError::CommandError,
+        )?;
+        self.idle_limit = Some(Duration::from_secs(secs));
+        Ok(())
+    }
+
+    /// Idle timeout advertised by the server, from the reply to a bare
+    /// `SITE IDLE` or else from the status lines of `STAT`, to choose how often
+    /// a long-lived session must send commands. `None` when neither tells.
+    ///
+    /// # Errors
+    /// On connection failure.
+    pub fn idle_timeout(&mut self) -> Result<Option<Duration>> {
+        let command = Command::Site("IDLE");
+        let response = self.write_cmd(command)?;
+        if self.accepts(&command, &response) {
+            if let Some(timeout) = response.lines().iter().find_map(|line| idle_timeout(line)) {
+                return Ok(Some(timeout));
+            }
+        }
+        match self.status_session() {
+            Ok(status) => Ok(status.idle_timeout()),
+            Err(FtpError::CommandError(_) | FtpError::Unsupported(_)) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::scripted_server;
+    use crate::FtpClient;
+
+    #[test]
+    fn session_status_replies() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let replies = [
+            // vsftpd
+            "211-FTP server status:\r\n     Connected to 127.0.0.1\r\n     Logged in as ftp\r\n\
+             \x20    TYPE: ASCII\r\n     Session timeout in seconds is 300\r\n\r\n\
+             211 End of status\r\n",
+            // continuation lines repeating the code
+            "211-Status of 'ProFTPD Server'\r\n211-Connected from 10.0.0.5\r\n\
+             211-TYPE: BINARY, STRUcture: File, Mode: Stream\r\n211 End of status\r\n",
+            "211 Nothing to report\r\n",
+            "502 Command not implemented\r\n",
+        ];
+        let sent = AtomicUsize::new(0);
+        let address = scripted_server(move |command, _| match command {
+            "STAT" => replies[sent.fetch_add(1, Ordering::Relaxed)].to_string(),
+            _ => "200 OK\r\n".to_string(),
+        });
+        let mut client = FtpClient::connect(address).unwrap();
+
+        let status = client.status_session().unwrap();
+        assert_eq!(status.summary(), "FTP server status:");
+        assert_eq!(
+            status.lines(),
+            [
+                "Connected to 127.0.0.1",
+                "Logged in as ftp",
+                "TYPE: ASCII",
+                "Session timeout in seconds is 300"
+            ]
+        );
+        assert_eq!(status.value("Type"), Some("ASCII"));
+        assert_eq!(status.idle_timeout(), Some(Duration::from_secs(300)));
+
+        let status = client.status_session().unwrap();
+        assert_eq!(status.summary(), "Status of 'ProFTPD Server'");
+        assert_eq!(status.lines().len(), 2);
+        assert_eq!(
+            status.value("type"),
+            Some("BINARY, STRUcture: File, Mode: Stream")
+        );
+        assert_eq!(status.idle_timeout(), None);
+
+        let status = client.status_session().unwrap();
+        assert_eq!(status.summary(), "Nothing to report");
+        assert!(status.lines().is_empty());
+
+        assert!(client.status_session().is_err());
+    }
+
+    #[test]
+    fn idle_timeouts() {
+        use super::SessionStatus;
+        use crate::Response;
+        use std::time::Duration;
+
+        let timeout = |lines: &str| {
+            let reply = format!("-Status:\r\n{}\r\n211 End\r\n", lines);
+            SessionStatus::from_reply(&Response::new(211, reply)).idle_timeout()
+        };
+        assert_eq!(
+            timeout("     Session timeout in seconds is 300"),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(
+            timeout(" TYPE: BINARY\r\n Idle timeout: 15 minutes"),
+            Some(Duration::from_secs(900))
+        );
+        assert_eq!(
+            timeout(" Idle timeout is 900 seconds (15 minutes)"),
+            Some(Duration::from_secs(900))
+        );
+        assert_eq!(
+            timeout(" Idle timeout in minutes: 10"),
+            Some(Duration::from_secs(600))
+        );
+        assert_eq!(
+            timeout(" Idle timeout: 307445734561825861 minutes"),
+            Some(Duration::from_secs(u64::MAX))
+        );
+        assert_eq!(timeout(" Connected to 10.0.0.1"), None);
+    }
+}
@@ -0,0 +1,48 @@
+//! Transport abstraction for control and data connections.
+
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::TcpStream;
+
+/// A byte stream carried over a TCP socket, possibly wrapped by another layer.
+pub(crate) trait Stream: Read + Write + Send {
+    /// The underlying socket, used for addresses, timeouts and shutdown.
+    fn socket(&self) -> &TcpStream;
+
+    /// Whether bytes go through to the socket unchanged, so the socket itself
+    /// can carry the data.
+    #[cfg_attr(not(all(feature = "zero-copy", target_os = "linux")), allow(dead_code))]
+    fn is_plain(&self) -> bool {
+        false
+    }
+
+    /// Signal the end of the data to the peer and close the connection.
+    fn close(&mut self) -> std::io::Result<()> {
+        self.socket().shutdown(Shutdown::Both)
+    }
+}
+
+impl Stream for TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+
+    fn is_plain(&self) -> bool {
+        true
+    }
+}
+
+impl Stream for Box<dyn Stream> {
+    fn socket(&self) -> &TcpStream {
+        self.as_ref().socket()
+    }
+
+    fn is_plain(&self) -> bool {
+        self.as_ref().is_plain()
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        self.as_mut().close()
+    }
+}
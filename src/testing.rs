@@ -0,0 +1,129 @@
+//! Helpers shared by the tests of several modules: embedded servers over
+//! scratch directories and scripted control connections.
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+
+#[cfg(feature = "server")]
+use crate::FtpClient;
+#[cfg(feature = "server")]
+use crate::FtpServer;
+
+/// A single control connection answering each command line with `reply`,
+/// which gets the passive listener to send listings or file content.
+/// `PASV` is answered with that listener's address, an empty reply closes
+/// the connection.
+pub(crate) fn scripted_server<F>(reply: F) -> SocketAddr
+where
+    F: Fn(&str, &TcpListener) -> String + Send + 'static,
+{
+    let control = TcpListener::bind("127.0.0.1:0").unwrap();
+    let data = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = control.local_addr().unwrap();
+    let port = data.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let (mut socket, _) = control.accept().unwrap();
+        let mut reader = BufReader::new(socket.try_clone().unwrap());
+        socket.write_all(b"220 Ready\r\n").unwrap();
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 {
+            let answer = match line.trim_end() {
+                "PASV" => format!(
+                    "227 Entering Passive Mode (127,0,0,1,{},{})\r\n",
+                    port >> 8,
+                    port & 0xFF
+                ),
+                command => reply(command, &data),
+            };
+            if answer.is_empty() || socket.write_all(answer.as_bytes()).is_err() {
+                break;
+            }
+            line.clear();
+        }
+    });
+    address
+}
+
+/// Serve `server` on a free loopback port.
+#[cfg(feature = "server")]
+pub(crate) fn start_server(server: FtpServer) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || server.serve(listener));
+    address
+}
+
+/// A scratch directory under the system temp directory, removed when
+/// dropped so a failing assertion doesn't leave it behind.
+#[cfg(feature = "server")]
+pub(crate) struct TempRoot(std::path::PathBuf);
+
+#[cfg(feature = "server")]
+impl TempRoot {
+    /// Create `simpleftp-<name>-<pid>` holding `files`. Names ending in `/`
+    /// are created as directories.
+    pub(crate) fn new(name: &str, files: &[(&str, &[u8])]) -> TempRoot {
+        let path = std::env::temp_dir().join(format!("simpleftp-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        for (name, content) in files {
+            let file = path.join(name);
+            if name.ends_with('/') {
+                std::fs::create_dir_all(file).unwrap();
+            } else {
+                std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+                std::fs::write(file, content).unwrap();
+            }
+        }
+        TempRoot(path)
+    }
+}
+
+#[cfg(feature = "server")]
+impl std::ops::Deref for TempRoot {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(feature = "server")]
+impl Drop for TempRoot {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Serve `root` to anonymous users from an embedded server.
+#[cfg(feature = "server")]
+pub(crate) fn serve(root: &std::path::Path) -> SocketAddr {
+    use crate::{AnonymousAccess, LocalStorage};
+
+    start_server(FtpServer::new(AnonymousAccess).storage(LocalStorage::new(root)))
+}
+
+/// Connect to `address` and log in anonymously.
+#[cfg(feature = "server")]
+pub(crate) fn anonymous_client(address: SocketAddr) -> FtpClient {
+    let mut client = FtpClient::connect(address).unwrap();
+    client.login("anonymous", "").unwrap();
+    client
+}
+
+/// A client logged in to an embedded server serving a fresh [`TempRoot`].
+#[cfg(feature = "server")]
+pub(crate) fn served_client(name: &str, files: &[(&str, &[u8])]) -> (FtpClient, TempRoot) {
+    let root = TempRoot::new(name, files);
+    let client = anonymous_client(serve(&root));
+    (client, root)
+}
+
+/// A self-signed certificate for `127.0.0.1`.
+#[cfg(all(feature = "server", any(feature = "rustls", feature = "native-tls")))]
+pub(crate) fn loopback_certificate() -> rcgen::CertifiedKey {
+    rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap()
+}
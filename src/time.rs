@@ -0,0 +1,129 @@
+//! Conversions of server timestamps, which are always UTC.
+
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Parse an RFC 3659 timestamp, `YYYYMMDDHHMMSS[.sss]`, as sent by MDTM and
+/// the MLSD `modify` fact.
+pub(crate) fn parse_timestamp(text: &str) -> Option<SystemTime> {
+    let text = text.trim();
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if whole.len() != 14 || !whole.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| whole[range].parse::<u32>().ok();
+    let nanos = if fraction.is_empty() {
+        0
+    } else if fraction.len() <= 9 && fraction.bytes().all(|b| b.is_ascii_digit()) {
+        fraction.parse::<u32>().ok()? * 10u32.pow(9 - fraction.len() as u32)
+    } else {
+        return None;
+    };
+    from_utc(
+        i64::from(field(0..4)?),
+        field(4..6)?,
+        field(6..8)?,
+        field(8..10)?,
+        field(10..12)?,
+        field(12..14)?,
+    )
+    .map(|time| time + Duration::from_nanos(u64::from(nanos)))
+}
+
+/// Build a time from UTC calendar fields, `None` when out of range.
+pub(crate) fn from_utc(
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+) -> Option<SystemTime> {
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    let seconds =
+        days_from_civil(year, month, day) * 86_400 + i64::from(hour * 3600 + minute * 60 + second);
+    let seconds = u64::try_from(seconds).ok()?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(seconds))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// UTC calendar fields of a time: year, month, day, hour, minute and second.
+/// Times before 1970 are clamped to it.
+pub(crate) fn to_utc(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (year, month, day) = civil_from_days(i64::try_from(seconds / 86_400).unwrap_or(0));
+    let of_day = (seconds % 86_400) as u32;
+    (
+        year,
+        month,
+        day,
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60,
+    )
+}
+
+/// Format a time as an RFC 3659 timestamp, `YYYYMMDDHHMMSS`.
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = to_utc(time);
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// Proleptic Gregorian date of a number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn mdtm_timestamps() {
+        use std::time::{Duration, UNIX_EPOCH};
+        let parse = super::parse_timestamp;
+        assert_eq!(parse("19700101000000"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse(" 20240229235959.5"),
+            Some(UNIX_EPOCH + Duration::from_millis(1_709_251_199_500))
+        );
+        assert_eq!(parse("20241301000000"), None);
+        assert_eq!(parse("2024"), None);
+    }
+}
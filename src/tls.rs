@@ -0,0 +1,471 @@
+//! FTPS support shared by the TLS backends (`rustls` or `native-tls` features).
+//!
+//! The rest of the client only deals with [`TlsConfig`], whichever backend
+//! created it.
+
+use std::io::BufReader;
+use std::sync::Arc;
+
+use crate::stream::Stream;
+use crate::Command;
+use crate::FtpClient;
+use crate::FtpError;
+use crate::Result;
+
+/// A TLS implementation able to secure an established connection.
+pub(crate) trait TlsBackend: Send + Sync {
+    /// Run the client handshake over `stream`, verifying the server as `domain`.
+    fn wrap(&self, domain: &str, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>>;
+}
+
+/// A TLS implementation able to secure connections accepted by the server.
+#[cfg(feature = "server")]
+pub(crate) trait TlsAcceptorBackend: Send + Sync {
+    /// Run the server handshake over `stream`.
+    fn accept(&self, stream: Box<dyn Stream>) -> std::io::Result<Box<dyn Stream>>;
+}
+
+/// TLS settings for explicit (`AUTH TLS`) or implicit FTPS.
+///
+/// # Example
+/// ```no_run
+/// # #[cfg(feature = "rustls")]
+/// # fn main() -> simpleftp::Result<()> {
+/// use simpleftp::{FtpClient, TlsConfig};
+/// use std::sync::Arc;
+///
+/// # let roots = rustls::RootCertStore::empty();
+/// let config = rustls::ClientConfig::builder()
+///     .with_root_certificates(roots)
+///     .with_no_client_auth();
+/// let mut client = FtpClient::builder()
+///     .tls(TlsConfig::rustls(Arc::new(config)))
+///     .connect("ftp.example.com:21")?;
+/// client.login("user", "password")?;
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "rustls"))]
+/// # fn main() {}
+/// ```
+#[derive(Clone)]
+pub struct TlsConfig {
+    backend: Arc<dyn TlsBackend>,
+    pub(crate) implicit: bool,
+}
+
+impl TlsConfig {
+    /// Use rustls with the given client configuration.
+    /// Sessions are resumed on data connections through the configuration's
+    /// session store, as many servers require.
+    #[cfg(feature = "rustls")]
+    pub fn rustls(config: Arc<rustls::ClientConfig>) -> Self {
+        Self::new(rustls_backend::RustlsBackend { config })
+    }
+
+    /// Use the platform TLS stack through native-tls.
+    #[cfg(feature = "native-tls")]
+    pub fn native_tls(connector: native_tls::TlsConnector) -> Self {
+        Self::new(native_tls_backend::NativeTlsBackend { connector })
+    }
+
+    #[allow(dead_code)]
+    fn new(backend: impl TlsBackend + 'static) -> Self {
+        Self {
+            backend: Arc::new(backend),
+            implicit: false,
+        }
+    }
+
+    /// Start TLS as soon as the connection opens (FTPS on port 990)
+    /// instead of negotiating it with `AUTH TLS`.
+    pub fn implicit(mut self, implicit: bool) -> Self {
+        self.implicit = implicit;
+        self
+    }
+
+    pub(crate) fn wrap(&self, domain: &str, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>> {
+        self.backend.wrap(domain, stream)
+    }
+}
+
+/// TLS settings of the embedded server, for explicit (`AUTH TLS`) or implicit
+/// FTPS.
+///
+/// # Example
+/// ```no_run
+/// # #[cfg(feature = "rustls")]
+/// # fn main() -> simpleftp::Result<()> {
+/// use simpleftp::{AnonymousAccess, FtpServer, ServerTlsConfig};
+/// use std::sync::Arc;
+///
+/// # let (certificates, key) = (Vec::new(), rustls::pki_types::PrivateKeyDer::Pkcs8(Vec::new().into()));
+/// let config = rustls::ServerConfig::builder()
+///     .with_no_client_auth()
+///     .with_single_cert(certificates, key)
+///     .unwrap();
+/// FtpServer::new(AnonymousAccess)
+///     .tls(ServerTlsConfig::rustls(Arc::new(config)).required(true))
+///     .listen("0.0.0.0:21")?;
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "rustls"))]
+/// # fn main() {}
+/// ```
+#[cfg(feature = "server")]
+#[derive(Clone)]
+pub struct ServerTlsConfig {
+    backend: Arc<dyn TlsAcceptorBackend>,
+    pub(crate) implicit: bool,
+    pub(crate) required: bool,
+}
+
+#[cfg(feature = "server")]
+impl ServerTlsConfig {
+    /// Use rustls with the given server configuration.
+    #[cfg(feature = "rustls")]
+    pub fn rustls(config: Arc<rustls::ServerConfig>) -> Self {
+        Self::new(rustls_backend::RustlsAcceptor { config })
+    }
+
+    /// Use the platform TLS stack through native-tls.
+    #[cfg(feature = "native-tls")]
+    pub fn native_tls(acceptor: native_tls::TlsAcceptor) -> Self {
+        Self::new(native_tls_backend::NativeTlsAcceptor { acceptor })
+    }
+
+    #[allow(dead_code)]
+    fn new(backend: impl TlsAcceptorBackend + 'static) -> Self {
+        Self {
+            backend: Arc::new(backend),
+            implicit: false,
+            required: false,
+        }
+    }
+
+    /// Start TLS as soon as a connection is accepted (FTPS on port 990)
+    /// instead of waiting for `AUTH TLS`.
+    pub fn implicit(mut self, implicit: bool) -> Self {
+        self.implicit = implicit;
+        self
+    }
+
+    /// Refuse logins before `AUTH TLS` and transfers before `PROT P`.
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    pub(crate) fn accept(&self, stream: Box<dyn Stream>) -> std::io::Result<Box<dyn Stream>> {
+        self.backend.accept(stream)
+    }
+}
+
+#[allow(dead_code)]
+fn handshake_error(error: impl std::fmt::Display) -> FtpError {
+    FtpError::ConnectionError(format!("TLS handshake failed: {}", error))
+}
+
+#[cfg(feature = "rustls")]
+mod rustls_backend {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::Shutdown;
+    use std::net::TcpStream;
+    use std::ops::Deref;
+    use std::ops::DerefMut;
+    use std::sync::Arc;
+
+    use rustls::ConnectionCommon;
+    use rustls::SideData;
+
+    use super::handshake_error;
+    use super::TlsBackend;
+    use crate::stream::Stream;
+    use crate::Result;
+
+    pub(super) struct RustlsBackend {
+        pub(super) config: Arc<rustls::ClientConfig>,
+    }
+
+    impl TlsBackend for RustlsBackend {
+        fn wrap(&self, domain: &str, mut stream: Box<dyn Stream>) -> Result<Box<dyn Stream>> {
+            let name = rustls::pki_types::ServerName::try_from(domain.to_string())
+                .map_err(handshake_error)?;
+            let mut connection = rustls::ClientConnection::new(self.config.clone(), name)
+                .map_err(handshake_error)?;
+            while connection.is_handshaking() {
+                connection
+                    .complete_io(&mut stream)
+                    .map_err(handshake_error)?;
+            }
+            let stream = rustls::StreamOwned::new(connection, stream);
+            Ok(Box::new(RustlsStream(stream)))
+        }
+    }
+
+    #[cfg(feature = "server")]
+    pub(super) struct RustlsAcceptor {
+        pub(super) config: Arc<rustls::ServerConfig>,
+    }
+
+    #[cfg(feature = "server")]
+    impl super::TlsAcceptorBackend for RustlsAcceptor {
+        fn accept(&self, mut stream: Box<dyn Stream>) -> std::io::Result<Box<dyn Stream>> {
+            let mut connection = rustls::ServerConnection::new(self.config.clone())
+                .map_err(std::io::Error::other)?;
+            while connection.is_handshaking() {
+                connection.complete_io(&mut stream)?;
+            }
+            let stream = rustls::StreamOwned::new(connection, stream);
+            Ok(Box::new(RustlsStream(stream)))
+        }
+    }
+
+    /// A client or server connection.
+    struct RustlsStream<C>(rustls::StreamOwned<C, Box<dyn Stream>>);
+
+    impl<C, S> Read for RustlsStream<C>
+    where
+        C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+        S: SideData,
+    {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.read(buf) {
+                // Many servers close data connections without close_notify.
+                Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => Ok(0),
+                other => other,
+            }
+        }
+    }
+
+    impl<C, S> Write for RustlsStream<C>
+    where
+        C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+        S: SideData,
+    {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl<C, S> Stream for RustlsStream<C>
+    where
+        C: DerefMut + Deref<Target = ConnectionCommon<S>> + Send,
+        S: SideData,
+    {
+        fn socket(&self) -> &TcpStream {
+            self.0.get_ref().socket()
+        }
+
+        fn close(&mut self) -> std::io::Result<()> {
+            self.0.conn.send_close_notify();
+            self.0.flush()?;
+            self.socket().shutdown(Shutdown::Both)
+        }
+    }
+}
+
+#[cfg(feature = "native-tls")]
+mod native_tls_backend {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::Shutdown;
+    use std::net::TcpStream;
+
+    use super::handshake_error;
+    use super::TlsBackend;
+    use crate::stream::Stream;
+    use crate::Result;
+
+    pub(super) struct NativeTlsBackend {
+        pub(super) connector: native_tls::TlsConnector,
+    }
+
+    impl TlsBackend for NativeTlsBackend {
+        fn wrap(&self, domain: &str, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>> {
+            let stream = self
+                .connector
+                .connect(domain, stream)
+                .map_err(|error| match error {
+                    native_tls::HandshakeError::Failure(error) => handshake_error(error),
+                    native_tls::HandshakeError::WouldBlock(_) => handshake_error("interrupted"),
+                })?;
+            Ok(Box::new(NativeTlsStream(stream)))
+        }
+    }
+
+    #[cfg(feature = "server")]
+    pub(super) struct NativeTlsAcceptor {
+        pub(super) acceptor: native_tls::TlsAcceptor,
+    }
+
+    #[cfg(feature = "server")]
+    impl super::TlsAcceptorBackend for NativeTlsAcceptor {
+        fn accept(&self, stream: Box<dyn Stream>) -> std::io::Result<Box<dyn Stream>> {
+            let stream = self.acceptor.accept(stream).map_err(|error| match error {
+                native_tls::HandshakeError::Failure(error) => std::io::Error::other(error),
+                native_tls::HandshakeError::WouldBlock(_) => {
+                    std::io::Error::from(std::io::ErrorKind::Interrupted)
+                }
+            })?;
+            Ok(Box::new(NativeTlsStream(stream)))
+        }
+    }
+
+    struct NativeTlsStream(native_tls::TlsStream<Box<dyn Stream>>);
+
+    impl Read for NativeTlsStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for NativeTlsStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl Stream for NativeTlsStream {
+        fn socket(&self) -> &TcpStream {
+            self.0.get_ref().socket()
+        }
+
+        fn close(&mut self) -> std::io::Result<()> {
+            self.0.shutdown()?;
+            self.socket().shutdown(Shutdown::Both)
+        }
+    }
+}
+
+impl FtpClient {
+    /// Negotiate TLS on the control connection with `AUTH TLS` and protect
+    /// data connections with `PBSZ 0` and `PROT P`. With implicit TLS the control
+    /// connection is already secured and only data protection is negotiated.
+    pub(crate) fn secure(&mut self, tls: &TlsConfig, domain: &str) -> Result<()> {
+        if !tls.implicit {
+            let command = Command::Auth("TLS");
+            let response = self.write_cmd(command)?;
+            if !self.accepts(&command, &response) {
+                return Err(self.refused(FtpError::ConnectionError(format!(
+                    "Server refused AUTH TLS: {}",
+                    response.message
+                ))));
+            }
+            let placeholder: Box<dyn Stream> =
+                Box::new(self.reader.get_ref().socket().try_clone()?);
+            let control = std::mem::replace(&mut self.reader, BufReader::new(placeholder));
+            self.reader = BufReader::new(tls.wrap(domain, control.into_inner())?);
+        }
+        for command in [Command::Pbsz(0), Command::Prot("P")] {
+            let response = self.write_cmd(command)?;
+            if !self.accepts(&command, &response) {
+                return Err(self.refused(FtpError::ConnectionError(format!(
+                    "Server refused {}: {}",
+                    command.logged(),
+                    response.message
+                ))));
+            }
+        }
+        self.data_protected = true;
+        Ok(())
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "server",
+    any(feature = "rustls", feature = "native-tls")
+))]
+mod tests {
+    use super::ServerTlsConfig;
+    use super::TlsConfig;
+    use crate::testing::loopback_certificate;
+    use crate::testing::start_server;
+    use crate::testing::TempRoot;
+    use crate::FtpClient;
+
+    /// Log in over TLS to a server serving a fresh directory and transfer a
+    /// file both ways over protected data connections.
+    fn check_ftps(name: &str, server_tls: ServerTlsConfig, client_tls: TlsConfig) {
+        use crate::{AnonymousAccess, FtpServer, LocalStorage};
+
+        let root = TempRoot::new(name, &[]);
+        let server = FtpServer::new(AnonymousAccess)
+            .storage(LocalStorage::new(root.to_path_buf()))
+            .tls(server_tls.required(true));
+        let address = start_server(server);
+        let mut client = FtpClient::builder()
+            .tls(client_tls)
+            .connect(address.to_string())
+            .unwrap();
+        client.login("anonymous", "").unwrap();
+        client.put("/secret.txt", &mut &b"sealed"[..]).unwrap();
+        assert_eq!(std::fs::read(root.join("secret.txt")).unwrap(), b"sealed");
+        let mut copy = Vec::new();
+        client.get("/secret.txt", &mut copy).unwrap();
+        assert_eq!(copy, b"sealed");
+    }
+
+    #[cfg(feature = "rustls")]
+    #[test]
+    fn server_speaks_ftps_with_rustls() {
+        use rustls::pki_types::PrivateKeyDer;
+        use std::sync::Arc;
+
+        let certified = loopback_certificate();
+        let key = PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into());
+        let server = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![certified.cert.der().clone()], key)
+            .unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let client = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let (server, client) = (Arc::new(server), Arc::new(client));
+        for implicit in [false, true] {
+            check_ftps(
+                &format!("rustls-{}", implicit),
+                ServerTlsConfig::rustls(server.clone()).implicit(implicit),
+                TlsConfig::rustls(client.clone()).implicit(implicit),
+            );
+        }
+    }
+
+    #[cfg(feature = "native-tls")]
+    #[test]
+    fn server_speaks_ftps_with_native_tls() {
+        use native_tls::{Certificate, Identity, TlsAcceptor, TlsConnector};
+
+        let certified = loopback_certificate();
+        let certificate = certified.cert.pem();
+        let identity = Identity::from_pkcs8(
+            certificate.as_bytes(),
+            certified.key_pair.serialize_pem().as_bytes(),
+        )
+        .unwrap();
+        let root = Certificate::from_pem(certificate.as_bytes()).unwrap();
+        for implicit in [false, true] {
+            let connector = TlsConnector::builder()
+                .add_root_certificate(root.clone())
+                .build()
+                .unwrap();
+            check_ftps(
+                &format!("native-tls-{}", implicit),
+                ServerTlsConfig::native_tls(TlsAcceptor::new(identity.clone()).unwrap())
+                    .implicit(implicit),
+                TlsConfig::native_tls(connector).implicit(implicit),
+            );
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Spans describing the client activity (`tracing` feature). Without the
+//! feature the spans do nothing and cost nothing.
+
+use crate::Command;
+
+/// A span around a control command or a data transfer.
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Span {
+    /// Span of a control command and its reply.
+    pub(crate) fn command(command: &Command) -> Self {
+        #[cfg(feature = "tracing")]
+        {
+            let span = tracing::debug_span!(
+                "ftp_command",
+                command = command.name(),
+                path = tracing::field::Empty,
+                code = tracing::field::Empty,
+            );
+            if let Some(path) = command.path() {
+                span.record("path", path);
+            }
+            Self { span }
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = command;
+            Self {}
+        }
+    }
+
+    /// Span of a data transfer, enclosing the spans of its commands.
+    pub(crate) fn transfer(verb: &str, path: &str) -> Self {
+        #[cfg(feature = "tracing")]
+        {
+            Self {
+                span: tracing::info_span!(
+                    "ftp_transfer",
+                    command = verb,
+                    path = path,
+                    code = tracing::field::Empty,
+                    bytes = tracing::field::Empty,
+                ),
+            }
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = (verb, path);
+            Self {}
+        }
+    }
+
+    pub(crate) fn in_scope<T>(&self, operation: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "tracing")]
+        return self.span.in_scope(operation);
+        #[cfg(not(feature = "tracing"))]
+        operation()
+    }
+
+    /// Record the reply code ending the command or transfer.
+    pub(crate) fn record_code(&self, code: usize) {
+        #[cfg(feature = "tracing")]
+        self.span.record("code", code);
+        #[cfg(not(feature = "tracing"))]
+        let _ = code;
+    }
+}
+
+/// Record the bytes moved on the transfer span being run, if any.
+pub(crate) fn record_bytes(bytes: u64) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("bytes", bytes);
+    #[cfg(not(feature = "tracing"))]
+    let _ = bytes;
+}
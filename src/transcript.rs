@@ -0,0 +1,123 @@
+//! Sanitized session log for auditing.
+
+use std::io::Write;
+use std::time::Instant;
+
+use crate::events::redact;
+use crate::Result;
+
+/// Records commands, replies, timings and transfer sizes to a writer.
+/// Credentials sent with `PASS` and `ACCT` are never written.
+///
+/// Each line is prefixed with the time elapsed since recording started, e.g.
+/// ```text
+/// [0.000s] > USER demo
+/// [0.041s] < 331 Password required for demo (41ms)
+/// [0.042s] > PASS ****
+/// ```
+pub(crate) struct Transcript {
+    writer: Box<dyn Write + Send>,
+    enabled: bool,
+    started: Instant,
+    last_command: Instant,
+}
+
+impl Transcript {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        let now = Instant::now();
+        Self {
+            writer,
+            enabled: true,
+            started: now,
+            last_command: now,
+        }
+    }
+
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub(crate) fn into_inner(self) -> Box<dyn Write + Send> {
+        self.writer
+    }
+
+    pub(crate) fn command(&mut self, command: &str) -> Result<()> {
+        self.last_command = Instant::now();
+        self.line(format_args!("> {}", redact(command)))
+    }
+
+    pub(crate) fn reply(&mut self, code: usize, message: &str) -> Result<()> {
+        let elapsed = self.last_command.elapsed().as_millis();
+        self.line(format_args!(
+            "< {}{} ({}ms)",
+            code,
+            message.trim_end().replace("\r\n", " | "),
+            elapsed
+        ))
+    }
+
+    pub(crate) fn transfer(&mut self, bytes: u64) -> Result<()> {
+        self.line(format_args!("= {} bytes transferred", bytes))
+    }
+
+    fn line(&mut self, text: std::fmt::Arguments) -> Result<()> {
+        if self.enabled {
+            let elapsed = self.started.elapsed().as_secs_f64();
+            writeln!(self.writer, "[{:.3}s] {}", elapsed, text)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::scripted_server;
+    use crate::FtpClient;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// A writer whose output stays readable by the test.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transcripts_hide_credentials() {
+        let address = scripted_server(|command, data| match command {
+            "USER user" => "331 Password required\r\n".to_string(),
+            "PASS hunter2" | "ACCT billing" => "230 Logged in\r\n".to_string(),
+            "RETR /a.txt" => {
+                data.accept().unwrap().0.write_all(b"hello").unwrap();
+                "150 Opening\r\n226 Done\r\n".to_string()
+            }
+            _ => "200 OK\r\n".to_string(),
+        });
+        let mut client = FtpClient::connect(address).unwrap();
+        let output = Shared::default();
+        client.record_transcript(output.clone());
+        client.login("user", "hunter2").unwrap();
+        client.account("billing").unwrap();
+        client.set_transcript_enabled(false);
+        client.noop().unwrap();
+        client.set_transcript_enabled(true);
+        client.get("/a.txt", &mut Vec::new()).unwrap();
+
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(text.contains("] > USER user\n"), "{}", text);
+        assert!(text.contains("] < 331 Password required ("), "{}", text);
+        assert!(text.contains("] > PASS ****\n") && text.contains("] > ACCT ****\n"));
+        assert!(text.contains("] = 5 bytes transferred\n"), "{}", text);
+        assert!(!text.contains("hunter2") && !text.contains("billing"));
+        assert!(!text.contains("NOOP"));
+        assert!(client.stop_transcript().is_some());
+    }
+}
@@ -0,0 +1,201 @@
+//! Recursive traversal of remote directory trees.
+
+use std::collections::HashSet;
+
+use crate::listing::matches;
+use crate::EntryKind;
+use crate::FtpClient;
+use crate::FtpEntry;
+use crate::FtpError;
+use crate::ListOrder;
+use crate::RemotePath;
+use crate::Result;
+
+/// What the walker does with symbolic links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Leave links out of the results.
+    Skip,
+    /// Report links as [`EntryKind::Symlink`] entries without following them.
+    #[default]
+    Report,
+    /// Report links and descend into those pointing to directories.
+    /// Each directory is visited once, so link cycles are harmless.
+    Follow,
+}
+
+/// Settings of [`FtpClient::walk`].
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    symlinks: SymlinkPolicy,
+    max_depth: Option<usize>,
+    order: Option<ListOrder>,
+    pattern: Option<String>,
+}
+
+impl WalkOptions {
+    /// Report symbolic links and walk the whole tree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How to treat symbolic links.
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Don't descend more than `depth` levels below the root, `0` only lists the root.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Visit the entries of each directory sorted by `order` rather than in
+    /// listing order.
+    pub fn sort_by(mut self, order: ListOrder) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    /// Only report files, links and other non-directories whose name matches
+    /// `pattern`, see [`crate::ListOptions::pattern`]. Directories are still
+    /// reported and walked.
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+}
+
+/// An entry found by [`FtpClient::walk`].
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    /// Full remote path of the entry.
+    pub path: RemotePath,
+    /// The entry as listed in its directory.
+    pub entry: FtpEntry,
+    /// Number of directories between the root and the entry, `0` for the root's content.
+    pub depth: usize,
+    /// What a symbolic link points to: a directory when the server lets the
+    /// client enter it, a file when it reports its size, `None` for broken
+    /// links and for entries that aren't links.
+    pub link_kind: Option<EntryKind>,
+}
+
+impl FtpClient {
+    /// List the tree below `root`, depth first, parents before their content.
+    ///
+    /// MLSD is used when the server supports it, LIST otherwise.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use simpleftp::{FtpClient, SymlinkPolicy, WalkOptions};
+    /// let mut client = FtpClient::connect("127.0.0.1:21").unwrap();
+    /// client.login("user", "password").unwrap();
+    /// let options = WalkOptions::new().symlinks(SymlinkPolicy::Skip).max_depth(3);
+    /// for found in client.walk("/pub", &options).unwrap() {
+    ///     println!("{} {:?}", found.path, found.entry.size());
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// Errors on connection failure or when a directory can't be listed.
+    pub fn walk(&mut self, root: impl AsRef<str>, options: &WalkOptions) -> Result<Vec<WalkEntry>> {
+        let root = RemotePath::new(root.as_ref());
+        let mut found = Vec::new();
+        let mut visited = HashSet::new();
+        let mut use_mlsd = !self.config.disable_mlsd;
+        let mut pending = vec![(root.clone(), 0)];
+        visited.insert(root.normalize());
+
+        while let Some((dir, depth)) = pending.pop() {
+            let mut entries = self.walk_list(&dir, &mut use_mlsd)?;
+            if let Some(order) = options.order {
+                entries.sort_by(|a, b| order.compare(a, b));
+            }
+            let mut subdirs = Vec::new();
+            for entry in entries {
+                if entry.name() == "." || entry.name() == ".." {
+                    continue;
+                }
+                let path = dir.join(entry.name());
+                let mut link_kind = None;
+                let descend = if entry.is_dir() {
+                    visited.insert(path.normalize())
+                } else if entry.is_symlink() {
+                    if options.symlinks == SymlinkPolicy::Skip {
+                        continue;
+                    }
+                    link_kind = self.link_kind(&path)?;
+                    options.symlinks == SymlinkPolicy::Follow
+                        && link_kind == Some(EntryKind::Directory)
+                        && visited.insert(link_target(&dir, &path, &entry))
+                } else {
+                    false
+                };
+                if descend && options.max_depth.is_none_or(|max| depth < max) {
+                    subdirs.push((path.clone(), depth + 1));
+                }
+                let unmatched = options
+                    .pattern
+                    .as_ref()
+                    .is_some_and(|pattern| !matches(pattern, entry.name()));
+                if unmatched && !entry.is_dir() {
+                    continue;
+                }
+                found.push(WalkEntry {
+                    path,
+                    entry,
+                    depth,
+                    link_kind,
+                });
+            }
+            // reversed so the stack pops them in listing order
+            pending.extend(subdirs.into_iter().rev());
+        }
+        Ok(found)
+    }
+
+    /// List `dir` with MLSD, switching to LIST for good once the server refuses it.
+    pub(crate) fn walk_list(
+        &mut self,
+        dir: &RemotePath,
+        use_mlsd: &mut bool,
+    ) -> Result<Vec<FtpEntry>> {
+        if *use_mlsd {
+            match self.mlsd(dir) {
+                Err(FtpError::CommandError(_) | FtpError::Unsupported(_)) => *use_mlsd = false,
+                listing => return listing,
+            }
+        }
+        self.list_entries(dir)
+    }
+
+    /// What the link at `path` points to, see [`WalkEntry::link_kind`].
+    fn link_kind(&mut self, path: &RemotePath) -> Result<Option<EntryKind>> {
+        let current = self.current_dir()?;
+        match self.change_dir(path) {
+            Ok(()) => {
+                self.change_dir(&current)?;
+                return Ok(Some(EntryKind::Directory));
+            }
+            Err(error @ (FtpError::ConnectionError(_) | FtpError::SessionExpired(_))) => {
+                return Err(error)
+            }
+            Err(_) => {}
+        }
+        match self.size(path) {
+            Ok(_) => Ok(Some(EntryKind::File)),
+            Err(error @ (FtpError::ConnectionError(_) | FtpError::SessionExpired(_))) => Err(error),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// Where the link at `path`, listed in `dir`, leads.
+fn link_target(dir: &RemotePath, path: &RemotePath, entry: &FtpEntry) -> RemotePath {
+    match entry.link_target() {
+        Some(target) => dir.join(target).normalize(),
+        None => path.normalize(),
+    }
+}
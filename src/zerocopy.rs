@@ -0,0 +1,153 @@
+//! Transfers between local files and plain data connections copied by the
+//! kernel (`zero-copy` feature, Linux only), without going through user space.
+
+use std::fs::File;
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+use std::time::Instant;
+
+use crate::FtpClient;
+use crate::ProtocolEvent;
+use crate::Result;
+
+/// Bytes moved per system call.
+const CHUNK: usize = 1 << 20;
+
+/// Result of a system call returning a byte count.
+fn check(result: isize) -> std::io::Result<usize> {
+    if result < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(result as usize)
+    }
+}
+
+impl FtpClient {
+    /// Send the rest of `file` over `socket` with `sendfile(2)`.
+    pub(crate) fn send_file(&mut self, file: &File, socket: &TcpStream) -> Result<u64> {
+        let (deadline, watched) = self.transfer_watch();
+        let started = Instant::now();
+        let mut last_progress = started;
+        let mut total = 0u64;
+        loop {
+            if watched {
+                self.limit_data_timeouts(socket, deadline)?;
+            }
+            // SAFETY: both descriptors stay open for the call, a null offset uses
+            // and advances the file position.
+            let sent = check(unsafe {
+                libc::sendfile(
+                    socket.as_raw_fd(),
+                    file.as_raw_fd(),
+                    std::ptr::null_mut(),
+                    CHUNK,
+                )
+            });
+            let count = match sent {
+                Ok(0) => break,
+                Ok(count) => count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(self.data_error(error, deadline, last_progress)),
+            };
+            self.check_size_limits(total + count as u64)?;
+            last_progress = Instant::now();
+            total += count as u64;
+            self.session_bytes += count as u64;
+            self.emit(ProtocolEvent::TransferProgress { bytes: total });
+        }
+        self.finish_transfer(total, started)
+    }
+
+    /// Write everything received on `socket` to `file` with `splice(2)`
+    /// through a pipe.
+    pub(crate) fn splice_to_file(&mut self, socket: &TcpStream, file: &File) -> Result<u64> {
+        let (deadline, watched) = self.transfer_watch();
+        let (pipe_read, pipe_write) = pipe()?;
+        let started = Instant::now();
+        let mut last_progress = started;
+        let mut total = 0u64;
+        loop {
+            if watched {
+                self.limit_data_timeouts(socket, deadline)?;
+            }
+            let received = splice(socket.as_raw_fd(), pipe_write.as_raw_fd(), CHUNK);
+            let count = match received {
+                Ok(0) => break,
+                Ok(count) => count,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(self.data_error(error, deadline, last_progress)),
+            };
+            self.check_size_limits(total + count as u64)?;
+            let mut pending = count;
+            while pending > 0 {
+                match splice(pipe_read.as_raw_fd(), file.as_raw_fd(), pending) {
+                    Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero).into()),
+                    Ok(written) => pending -= written,
+                    Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                    Err(error) => return Err(error.into()),
+                }
+            }
+            last_progress = Instant::now();
+            total += count as u64;
+            self.session_bytes += count as u64;
+            self.emit(ProtocolEvent::TransferProgress { bytes: total });
+        }
+        self.finish_transfer(total, started)
+    }
+}
+
+fn pipe() -> std::io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0 as RawFd; 2];
+    // SAFETY: `fds` has room for the two descriptors written by the call.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: the descriptors were just created and are owned by nobody else.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+fn splice(from: RawFd, to: RawFd, length: usize) -> std::io::Result<usize> {
+    // SAFETY: the descriptors are open, null offsets use the current positions.
+    check(unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            length,
+            libc::SPLICE_F_MOVE,
+        )
+    })
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::testing::served_client;
+
+    #[test]
+    fn files_round_trip_through_the_kernel() {
+        // several chunks and a partial one
+        let content: Vec<u8> = (0..super::CHUNK * 2 + 1000)
+            .map(|index| (index % 251) as u8)
+            .collect();
+        let files: &[(&str, &[u8])] = &[("local/big.bin", &content), ("local/empty.bin", b"")];
+        let (mut client, root) = served_client("zero-copy", files);
+        for name in ["big.bin", "empty.bin"] {
+            let local = root.join("local").join(name);
+            let remote = format!("/{}", name);
+            client.put_from_path(&local, &remote).unwrap();
+            assert_eq!(
+                std::fs::read(root.join(name)).unwrap(),
+                std::fs::read(&local).unwrap()
+            );
+            let copy = root.join(format!("copy-{}", name));
+            client.get_to_path(&remote, &copy).unwrap();
+            assert_eq!(std::fs::read(copy).unwrap(), std::fs::read(&local).unwrap());
+        }
+        assert_eq!(client.last_transfer().map(|stats| stats.bytes), Some(0));
+    }
+}